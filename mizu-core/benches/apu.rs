@@ -0,0 +1,44 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use mizu_core::{ApuQuality, GameBoy, GameBoyConfig};
+
+mod common;
+
+/// A rom that plays a note on pulse 1 on both speakers, then loops forever
+fn note_rom() -> Vec<u8> {
+    #[rustfmt::skip]
+    const PROGRAM: &[u8] = &[
+        0x3E, 0xFF, // LD A, 0xFF
+        0xE0, 0x24, // LDH (NR50), A
+        0xE0, 0x25, // LDH (NR51), A
+        0x3E, 0xF0, // LD A, 0xF0
+        0xE0, 0x12, // LDH (NR12), A
+        0x3E, 0x87, // LD A, 0x87
+        0xE0, 0x14, // LDH (NR14), A
+        0x18, 0xFE, // JR -2
+    ];
+
+    common::build_rom(PROGRAM)
+}
+
+fn apu_quality(c: &mut Criterion) {
+    let mut group = c.benchmark_group("apu_quality");
+    for apu_quality in [ApuQuality::Accurate, ApuQuality::Fast] {
+        let config = GameBoyConfig {
+            apu_quality,
+            render_video: false,
+            ..GameBoyConfig::default()
+        };
+        let mut gb = GameBoy::from_rom_bytes(note_rom(), config).unwrap();
+
+        group.bench_function(format!("{:?}", apu_quality), |b| {
+            b.iter(|| {
+                gb.clock_for_frame();
+                drop(gb.audio_buffers());
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, apu_quality);
+criterion_main!(benches);
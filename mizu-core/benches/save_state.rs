@@ -0,0 +1,55 @@
+use std::io::Cursor;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use mizu_core::{GameBoy, GameBoyConfig};
+
+mod common;
+
+/// A rom that increments `A` and writes it to the background tile map
+/// in a loop
+fn counter_rom() -> Vec<u8> {
+    #[rustfmt::skip]
+    const PROGRAM: &[u8] = &[
+        0x3C,             // INC A
+        0xEA, 0x00, 0x98, // LD (0x9800), A
+        0x18, 0xFA,       // JR -6
+    ];
+
+    common::build_rom(PROGRAM)
+}
+
+fn save_state(c: &mut Criterion) {
+    let mut gb = GameBoy::from_rom_bytes(counter_rom(), GameBoyConfig::default()).unwrap();
+    gb.clock_for_frame();
+
+    let mut group = c.benchmark_group("save_state");
+    for compressed in [true, false] {
+        let name = if compressed {
+            "compressed"
+        } else {
+            "uncompressed"
+        };
+        let save = |gb: &GameBoy, state: &mut Vec<u8>| {
+            state.clear();
+            if compressed {
+                gb.save_state(state).unwrap();
+            } else {
+                gb.save_state_uncompressed(state).unwrap();
+            }
+        };
+
+        let mut state = Vec::new();
+        group.bench_function(format!("save_{}", name), |b| {
+            b.iter(|| save(&gb, &mut state))
+        });
+
+        save(&gb, &mut state);
+        group.bench_function(format!("load_{}", name), |b| {
+            b.iter(|| gb.load_state(Cursor::new(&state)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, save_state);
+criterion_main!(benches);
@@ -7,16 +7,110 @@ mod wave_channel;
 use bitflags::bitflags;
 use save_state::Savable;
 
+use crate::save_migration::StateMigration;
 use crate::GameBoyConfig;
 use channel::{ApuChannel, Dac, LengthCountedChannel};
 use noise_channel::NoiseChannel;
 use pulse_channel::PulseChannel;
 use wave_channel::WaveChannel;
 
+/// Number of stereo samples passed to the [`AudioCallback`] in every call
+pub const AUDIO_CALLBACK_BLOCK_SAMPLES: usize = 256;
+
+/// Callback called with a block of [`AUDIO_CALLBACK_BLOCK_SAMPLES`] mixed
+/// stereo samples, in the same layout as [`AudioBuffers::all`]
+pub type AudioCallback = Box<dyn FnMut(&[f32])>;
+
+/// The quality of the audio mixing of the APU.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Savable)]
+pub enum ApuQuality {
+    /// Emulates the high-pass filter (capacitor) of each channel's DAC and
+    /// fills all the per-channel buffers of [`AudioBuffers`].
+    #[default]
+    Accurate,
+    /// Skips the high-pass filter, so the output will have a DC offset
+    /// (which is not audible, but can produce clicks when channels are
+    /// enabled/disabled), and only fills [`AudioBuffers::all`], the
+    /// per-channel buffers will be empty.
+    ///
+    /// Useful for performance-sensitive usages like headless batch rendering.
+    Fast,
+}
+
+/// The layout of the per-channel buffers of [`AudioBuffers`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AudioStemMode {
+    /// Interleaved stereo, right then left, with the `NR51` panning and
+    /// `NR50` volume of each side applied, same as [`AudioBuffers::all`].
+    #[default]
+    Stereo,
+    /// One value per sample, the average of the right and left outputs of
+    /// the channel.
+    Mono,
+}
+
+/// A sound channel of the APU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApuChannelId {
+    Pulse1,
+    Pulse2,
+    Wave,
+    Noise,
+}
+
+/// Internal state of a single sound channel, part of [`ApuRegisters`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApuChannelState {
+    /// The 11-bit frequency value for the pulse and wave channels,
+    /// for the noise channel, this is the period of its frequency timer
+    /// (derived from `NR43`).
+    pub frequency: u16,
+    /// The current envelope volume (`0-15`) for the pulse and noise channels,
+    /// for the wave channel, this is the 2-bit volume code of `NR32`.
+    pub volume: u8,
+    /// Remaining length counter, the channel is disabled when it reaches `0`
+    /// if length is enabled.
+    pub length_counter: u16,
+    /// The channel is playing (the bits reported in `NR52`).
+    pub enabled: bool,
+    /// The DAC of the channel is powered, a channel with its DAC off
+    /// outputs nothing and can't be enabled.
+    pub dac_enabled: bool,
+}
+
+/// A snapshot of the APU registers and its channels state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApuRegisters {
+    /// Values of the registers `NR10-NR52` (`0xFF10-0xFF26`) as read by the CPU,
+    /// indexed by `addr - 0xFF10`, unused addresses read `0xFF`.
+    pub nr: [u8; 0x17],
+    /// The APU is powered on (bit 7 of `NR52`).
+    pub power: bool,
+    pub pulse1: ApuChannelState,
+    pub pulse2: ApuChannelState,
+    pub wave: ApuChannelState,
+    pub noise: ApuChannelState,
+}
+
+impl ApuRegisters {
+    /// Value of the register at `addr` (`0xFF10-0xFF26`).
+    pub fn register(&self, addr: u16) -> u8 {
+        self.nr[(addr - 0xFF10) as usize]
+    }
+}
+
 /// Contains the flushed output buffer of the `APU`.
-/// The main buffer `all` is the summation of all of the other buffers/channels.
-/// If you want a combination of different channels, you can just add them together.
+/// The main buffer `all` is the summation of the audible channels, taking into
+/// account the muted and soloed channels (see
+/// [`GameBoy::set_channel_muted`](crate::GameBoy::set_channel_muted)), while
+/// the per-channel buffers are always filled with the output of their channel
+/// regardless of muting. If you want a combination of different channels,
+/// you can just add them together.
 /// All volume control is done before pushing to the buffers.
+///
+/// `all` and the per-channel buffers are interleaved stereo, with the right
+/// sample first, `left` and `right` contain the same mixed output of `all`
+/// but split into separate buffers.
 pub struct AudioBuffers<'a> {
     pulse1: &'a mut Vec<f32>,
     pulse2: &'a mut Vec<f32>,
@@ -24,28 +118,64 @@ pub struct AudioBuffers<'a> {
     noise: &'a mut Vec<f32>,
 
     all: &'a mut Vec<f32>,
+    left: &'a mut Vec<f32>,
+    right: &'a mut Vec<f32>,
 }
 
 impl AudioBuffers<'_> {
+    /// The output of the pulse 1 channel, the layout is controlled by
+    /// [`GameBoy::set_audio_stem_mode`](crate::GameBoy::set_audio_stem_mode)
+    ///
+    /// The per-channel buffers are empty with [`ApuQuality::Fast`].
     pub fn pulse1(&self) -> &[f32] {
         self.pulse1
     }
 
+    /// The output of the pulse 2 channel, same layout as [`AudioBuffers::pulse1`]
     pub fn pulse2(&self) -> &[f32] {
         self.pulse2
     }
 
+    /// The output of the wave channel, same layout as [`AudioBuffers::pulse1`]
     pub fn wave(&self) -> &[f32] {
         self.wave
     }
 
+    /// The output of the noise channel, same layout as [`AudioBuffers::pulse1`]
     pub fn noise(&self) -> &[f32] {
         self.noise
     }
 
+    /// The mixed output of the audible channels, so switching the muted
+    /// and soloed channels changes it without mixing the channels again
     pub fn all(&self) -> &[f32] {
         self.all
     }
+
+    /// The mixed output of the left speaker, after `NR51` panning
+    /// and `NR50` left volume
+    pub fn left(&self) -> &[f32] {
+        self.left
+    }
+
+    /// The mixed output of the right speaker, after `NR51` panning
+    /// and `NR50` right volume
+    pub fn right(&self) -> &[f32] {
+        self.right
+    }
+
+    /// Moves the samples out of the APU buffers into an [`OwnedAudioBuffers`]
+    pub fn into_owned(self) -> OwnedAudioBuffers {
+        OwnedAudioBuffers {
+            pulse1: std::mem::take(self.pulse1),
+            pulse2: std::mem::take(self.pulse2),
+            wave: std::mem::take(self.wave),
+            noise: std::mem::take(self.noise),
+            all: std::mem::take(self.all),
+            left: std::mem::take(self.left),
+            right: std::mem::take(self.right),
+        }
+    }
 }
 
 impl Drop for AudioBuffers<'_> {
@@ -55,9 +185,28 @@ impl Drop for AudioBuffers<'_> {
         self.wave.clear();
         self.noise.clear();
         self.all.clear();
+        self.left.clear();
+        self.right.clear();
     }
 }
 
+/// The same buffers as [`AudioBuffers`], but owned, so they can be sent to
+/// another thread, see [`GameBoy::take_audio_buffers`](crate::GameBoy::take_audio_buffers).
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OwnedAudioBuffers {
+    pub pulse1: Vec<f32>,
+    pub pulse2: Vec<f32>,
+    pub wave: Vec<f32>,
+    pub noise: Vec<f32>,
+
+    /// The mixed output of both speakers, see [`AudioBuffers::all`]
+    pub all: Vec<f32>,
+    /// The mixed output of the left speaker, see [`AudioBuffers::left`]
+    pub left: Vec<f32>,
+    /// The mixed output of the right speaker, see [`AudioBuffers::right`]
+    pub right: Vec<f32>,
+}
+
 bitflags! {
     #[derive(Savable)]
     #[savable(bitflags)]
@@ -111,6 +260,10 @@ pub struct Apu {
     #[savable(skip)]
     buffer: Vec<f32>,
     #[savable(skip)]
+    left_buffer: Vec<f32>,
+    #[savable(skip)]
+    right_buffer: Vec<f32>,
+    #[savable(skip)]
     pulse1_buffers: Vec<f32>,
     #[savable(skip)]
     pulse2_buffers: Vec<f32>,
@@ -119,6 +272,43 @@ pub struct Apu {
     #[savable(skip)]
     noise_buffers: Vec<f32>,
 
+    /// Called with `(left, right)` for every output sample generated
+    #[savable(skip)]
+    sample_callback: Option<Box<dyn FnMut(f32, f32)>>,
+
+    /// Called with the new step (`0-7`) on every frame sequencer step
+    #[savable(skip)]
+    sequencer_callback: Option<Box<dyn FnMut(u8)>>,
+
+    /// When set, the mixed output is collected in `audio_callback_block`
+    /// and passed to it instead of the buffers
+    #[savable(skip)]
+    audio_callback: Option<AudioCallback>,
+    #[savable(skip)]
+    audio_callback_block: Vec<f32>,
+
+    /// Muted channels do not contribute to the mixed output, indexed by [`ApuChannelId`]
+    #[savable(skip)]
+    channels_muted: [bool; 4],
+    /// If any channel is soloed, only soloed channels contribute to the mixed output
+    #[savable(skip)]
+    channels_solo: [bool; 4],
+    /// Volume scaling applied on top of `NR50`, in `0.0..=1.0`
+    #[savable(skip)]
+    master_volume: f32,
+    /// Volume scaling of each channel, indexed by [`ApuChannelId`], in `0.0..=1.0`
+    #[savable(skip)]
+    channels_volume: [f32; 4],
+    #[savable(skip)]
+    stem_mode: AudioStemMode,
+    /// When disabled, the APU is clocked normally but no samples are generated
+    #[savable(skip)]
+    output_enabled: bool,
+    /// The emulation speed relative to real time, the samples are spread
+    /// over this many times more clocks
+    #[savable(skip)]
+    speed_multiplier: f32,
+
     /// Stores the value of the 4th bit (5th in double speed mode) of the divider
     /// as sequencer clocks are controlled by the divider
     divider_sequencer_clock_bit: bool,
@@ -145,12 +335,26 @@ impl Apu {
             channels_selection: ChannelsSelection::from_bits_truncate(0),
             power: false,
             buffer: Vec::new(),
+            left_buffer: Vec::new(),
+            right_buffer: Vec::new(),
 
             pulse1_buffers: Vec::new(),
             pulse2_buffers: Vec::new(),
             wave_buffers: Vec::new(),
             noise_buffers: Vec::new(),
 
+            sample_callback: None,
+            sequencer_callback: None,
+            audio_callback: None,
+            audio_callback_block: Vec::new(),
+            channels_muted: [false; 4],
+            channels_solo: [false; 4],
+            master_volume: 1.,
+            channels_volume: [1.; 4],
+            stem_mode: AudioStemMode::Stereo,
+            output_enabled: true,
+            speed_multiplier: 1.,
+
             sample_counter: 0.,
             pulse1: Dac::new(LengthCountedChannel::new(PulseChannel::default(), 64)),
             pulse2: Dac::new(LengthCountedChannel::new(PulseChannel::default(), 64)),
@@ -164,6 +368,27 @@ impl Apu {
         }
     }
 
+    /// Migrates the saved state of the APU to the next version, see
+    /// [`StateMigration`]
+    pub fn migrate_state(migration: &mut StateMigration) -> save_state::Result<()> {
+        let apu = Self::new(GameBoyConfig::default());
+
+        migration.copy(apu.pulse1)?;
+        migration.copy(apu.pulse2)?;
+        // the configuration is the last field of the wave channel
+        migration.copy_bytes(apu.wave.save_size()? - apu.config.save_size()?)?;
+        migration.config()?;
+        migration.copy(apu.noise)?;
+        migration.copy(apu.channels_control)?;
+        migration.copy(apu.channels_selection)?;
+        migration.copy(apu.power)?;
+        migration.copy(apu.sample_counter)?;
+        migration.copy(apu.divider_sequencer_clock_bit)?;
+        migration.copy(apu.sequencer_position)?;
+        migration.copy(apu.clocks_counter)?;
+        migration.config()
+    }
+
     pub fn new_skip_boot_rom(config: GameBoyConfig) -> Self {
         let mut apu = Self::new(config);
 
@@ -183,16 +408,16 @@ impl Apu {
         apu
     }
 
-    pub fn read_register(&mut self, addr: u16) -> u8 {
+    pub fn read_register(&self, addr: u16) -> u8 {
         match addr {
-            0xFF10 => 0x80 | self.pulse1.channel_mut().read_sweep_register(),
-            0xFF11 => 0x3F | (self.pulse1.channel_mut().read_pattern_duty() << 6),
+            0xFF10 => 0x80 | self.pulse1.channel().read_sweep_register(),
+            0xFF11 => 0x3F | (self.pulse1.channel().read_pattern_duty() << 6),
             0xFF12 => self.pulse1.channel().envelope().read_envelope_register(),
             0xFF13 => 0xFF,
             0xFF14 => 0xBF | ((self.pulse1.read_length_enable() as u8) << 6),
 
             0xFF15 => 0xFF,
-            0xFF16 => 0x3F | (self.pulse2.channel_mut().read_pattern_duty() << 6),
+            0xFF16 => 0x3F | (self.pulse2.channel().read_pattern_duty() << 6),
             0xFF17 => self.pulse2.channel().envelope().read_envelope_register(),
             0xFF18 => 0xFF,
             0xFF19 => 0xBF | ((self.pulse2.read_length_enable() as u8) << 6),
@@ -227,10 +452,12 @@ impl Apu {
     }
 
     pub fn write_register(&mut self, addr: u16, data: u8) {
-        // `addr % 5 != 2` will be true if its not a length counter register,
-        // as these are not affected by power off, but `addr % 5 != 2` also
-        // includes `0xFF25` and we don't want to be able to write to it
-        if !self.power && addr <= 0xFF25 && (addr % 5 != 2 || addr == 0xFF25) {
+        // `addr % 5 == 2` will be true for the length counter registers,
+        // but also for `0xFF25`. On DMG, length counters are not affected
+        // by power off and can still be written, on CGB they are read-only
+        // like the rest of the registers
+        let is_length_register = addr % 5 == 2 && addr != 0xFF25;
+        if !self.power && addr <= 0xFF25 && !(self.config.is_dmg && is_length_register) {
             return;
         }
 
@@ -371,6 +598,80 @@ impl Apu {
         }
     }
 
+    /// Reads the wave RAM the same way the CPU reads `0xFF30-0xFF3F`
+    pub fn wave_ram(&self) -> [u8; 16] {
+        std::array::from_fn(|i| self.wave.channel().read_buffer(i as u8))
+    }
+
+    /// The APU is powered on (bit 7 of `NR52`)
+    pub fn is_powered(&self) -> bool {
+        self.power
+    }
+
+    /// Snapshot of the sound registers and the internal state of the channels,
+    /// reading the registers here has no side effects
+    pub fn registers(&self) -> ApuRegisters {
+        ApuRegisters {
+            nr: std::array::from_fn(|i| self.read_register(0xFF10 + i as u16)),
+            power: self.power,
+            pulse1: ApuChannelState {
+                frequency: self.pulse1.channel().frequency(),
+                volume: self.pulse1.channel().envelope().current_volume(),
+                length_counter: self.pulse1.length_counter(),
+                enabled: self.pulse1.enabled(),
+                dac_enabled: self.pulse1.dac_enabled(),
+            },
+            pulse2: ApuChannelState {
+                frequency: self.pulse2.channel().frequency(),
+                volume: self.pulse2.channel().envelope().current_volume(),
+                length_counter: self.pulse2.length_counter(),
+                enabled: self.pulse2.enabled(),
+                dac_enabled: self.pulse2.dac_enabled(),
+            },
+            wave: ApuChannelState {
+                frequency: self.wave.channel().frequency(),
+                volume: self.wave.channel().read_volume(),
+                length_counter: self.wave.length_counter(),
+                enabled: self.wave.enabled(),
+                dac_enabled: self.wave.dac_enabled(),
+            },
+            noise: ApuChannelState {
+                frequency: self.noise.channel().frequency(),
+                volume: self.noise.channel().envelope().current_volume(),
+                length_counter: self.noise.length_counter(),
+                enabled: self.noise.enabled(),
+                dac_enabled: self.noise.dac_enabled(),
+            },
+        }
+    }
+
+    pub fn channel_frequency(&self, channel: ApuChannelId) -> Option<f32> {
+        let (enabled, frequency) = match channel {
+            ApuChannelId::Pulse1 => (
+                self.pulse1.enabled(),
+                131072. / (2048 - self.pulse1.channel().frequency()) as f32,
+            ),
+            ApuChannelId::Pulse2 => (
+                self.pulse2.enabled(),
+                131072. / (2048 - self.pulse2.channel().frequency()) as f32,
+            ),
+            ApuChannelId::Wave => (
+                self.wave.enabled(),
+                65536. / (2048 - self.wave.channel().frequency()) as f32,
+            ),
+            ApuChannelId::Noise => (self.noise.enabled(), self.noise.channel().shift_rate()),
+        };
+
+        (self.power && enabled).then_some(frequency)
+    }
+
+    /// Writes the wave RAM the same way the CPU writes `0xFF30-0xFF3F`
+    pub fn set_wave_ram(&mut self, data: [u8; 16]) {
+        for (i, byte) in data.into_iter().enumerate() {
+            self.wave.channel_mut().write_buffer(i as u8, byte);
+        }
+    }
+
     pub fn read_pcm12(&self) -> u8 {
         let p1 = self.pulse1.output() & 0xF;
         let p2 = self.pulse2.output() & 0xF;
@@ -393,13 +694,41 @@ impl Apu {
             noise: &mut self.noise_buffers,
 
             all: &mut self.buffer,
+            left: &mut self.left_buffer,
+            right: &mut self.right_buffer,
         }
     }
 
-    /// The APU is clocked by the divider, on the falling edge of the bit 12
-    /// of the divider, this is needed since the divider can be clocked manually
-    /// by resetting it to 0 on write
-    pub fn clock(&mut self, double_speed: bool, divider: u8) {
+    /// Appends the mixed output to `out`, and clears all the buffers
+    pub fn drain_buffer_into(&mut self, out: &mut Vec<f32>) {
+        out.extend_from_slice(self.get_buffers().all());
+    }
+
+    /// Appends the output of `channel` to `out`, and clears all the buffers
+    pub fn drain_channel_buffer_into(&mut self, channel: ApuChannelId, out: &mut Vec<f32>) {
+        let buffers = self.get_buffers();
+        out.extend_from_slice(match channel {
+            ApuChannelId::Pulse1 => buffers.pulse1(),
+            ApuChannelId::Pulse2 => buffers.pulse2(),
+            ApuChannelId::Wave => buffers.wave(),
+            ApuChannelId::Noise => buffers.noise(),
+        });
+    }
+
+    /// Clocks the APU while the CPU is in `STOP` mode, the channels are not
+    /// clocked, but the output samples are still generated, so that
+    /// the output sample rate stays constant.
+    pub fn clock_stopped(&mut self, double_speed: bool) {
+        if self.clock_output(double_speed) && self.power {
+            self.store_previous_inputs();
+        }
+    }
+
+    /// Generates output samples at the configured sample rate, the APU runs
+    /// at the same speed in normal and double speed.
+    ///
+    /// Returns `true` if the APU should be clocked in this cycle.
+    fn clock_output(&mut self, double_speed: bool) -> bool {
         // 2 in normal speed, 1 in double speed
         let clocks = (!double_speed) as u8 + 1;
 
@@ -408,23 +737,150 @@ impl Apu {
             self.clocks_counter -= 2;
         } else {
             // don't do anything, wait for the next cycle
-            return;
+            return false;
         }
 
-        const SAMPLE_RATE: f64 = 44100.;
-        const SAMPLE_EVERY_N_CLOCKS: f64 = (((16384 * 256) / 4) as f64) / SAMPLE_RATE;
+        let sample_every_n_clocks = self.sample_every_n_clocks();
 
         self.sample_counter += 1.;
-        if self.sample_counter >= SAMPLE_EVERY_N_CLOCKS {
-            self.push_output();
+        if self.sample_counter >= sample_every_n_clocks {
+            self.sample_counter -= sample_every_n_clocks;
 
-            self.sample_counter -= SAMPLE_EVERY_N_CLOCKS;
+            // the exact sample point is `sample_counter` clocks ago, between
+            // the previous and the current output of the channels
+            if self.output_enabled {
+                self.push_output(self.sample_counter as f32);
+            }
         }
 
-        if !self.power {
+        true
+    }
+
+    /// The configured sample rate, `0` is clamped to `1` to not divide by zero
+    fn sample_rate(&self) -> f64 {
+        self.config.sample_rate.max(1) as f64
+    }
+
+    fn sample_every_n_clocks(&self) -> f64 {
+        const APU_CLOCKS_PER_SECOND: f64 = ((16384 * 256) / 4) as f64;
+        APU_CLOCKS_PER_SECOND * self.speed_multiplier as f64 / self.sample_rate()
+    }
+
+    /// How much of the DAC capacitor charge is kept every sample, the
+    /// capacitor keeps `0.999958` of its charge every T-cycle, so this
+    /// depends on the number of cycles between samples, it is `~0.996` for
+    /// `44100Hz` at normal speed.
+    fn high_pass_charge_factor(&self) -> f32 {
+        0.999958f64.powf(self.sample_every_n_clocks() * 4.) as f32
+    }
+
+    /// The number of samples that will be generated in the next `cycles`
+    /// (in normal speed T-cycles), including the fraction of a sample
+    /// accumulated so far
+    pub fn expected_samples(&self, cycles: u32) -> usize {
+        if !self.output_enabled {
+            return 0;
+        }
+        let clocks = (cycles / 4) as f64;
+        ((self.sample_counter + clocks) / self.sample_every_n_clocks()) as usize
+    }
+
+    fn store_previous_inputs(&mut self) {
+        self.pulse1.store_previous_input();
+        self.pulse2.store_previous_input();
+        self.wave.store_previous_input();
+        self.noise.store_previous_input();
+    }
+
+    pub fn set_sequencer_callback(&mut self, callback: Option<Box<dyn FnMut(u8)>>) {
+        self.sequencer_callback = callback;
+    }
+
+    /// The current step of the frame sequencer (`0-7`), the step is
+    /// advanced at 512Hz, and length is clocked on even steps, sweep on
+    /// steps `2` and `6` and envelope on step `7`
+    pub fn frame_sequencer_step(&self) -> u8 {
+        // `sequencer_position` is one ahead of the step, and the last step
+        // (envelope) wraps it to `0`, it is `-1` when the first step is
+        // delayed after power on
+        (self.sequencer_position - 1).rem_euclid(8) as u8
+    }
+
+    pub fn set_sample_callback(&mut self, callback: Option<Box<dyn FnMut(f32, f32)>>) {
+        self.sample_callback = callback;
+    }
+
+    /// Sets the block callback, samples of an incomplete block from the
+    /// previous callback are dropped
+    pub fn set_audio_callback(&mut self, callback: Option<AudioCallback>) {
+        self.audio_callback = callback;
+        self.audio_callback_block.clear();
+        self.audio_callback_block
+            .reserve(AUDIO_CALLBACK_BLOCK_SAMPLES * 2);
+    }
+
+    pub fn set_channel_muted(&mut self, channel: ApuChannelId, muted: bool) {
+        self.channels_muted[channel as usize] = muted;
+    }
+
+    pub fn set_channel_solo(&mut self, channel: ApuChannelId, solo: bool) {
+        self.channels_solo[channel as usize] = solo;
+    }
+
+    pub fn set_master_volume(&mut self, volume: f32) {
+        self.master_volume = volume.clamp(0., 1.);
+    }
+
+    pub fn set_channel_volume(&mut self, channel: ApuChannelId, volume: f32) {
+        self.channels_volume[channel as usize] = volume.clamp(0., 1.);
+    }
+
+    pub fn set_stem_mode(&mut self, mode: AudioStemMode) {
+        self.stem_mode = mode;
+    }
+
+    /// At least `0.1`, so that there is at most one sample per clock
+    pub fn set_speed_multiplier(&mut self, multiplier: f32) {
+        self.speed_multiplier = multiplier.max(0.1);
+    }
+
+    pub fn speed_multiplier(&self) -> f32 {
+        self.speed_multiplier
+    }
+
+    /// The number of samples generated every emulated second
+    pub fn samples_per_second(&self) -> f64 {
+        self.sample_rate() / self.speed_multiplier as f64
+    }
+
+    /// Enables or disables generating output samples, the buffered samples
+    /// are discarded when disabling
+    pub fn set_output_enabled(&mut self, enabled: bool) {
+        self.output_enabled = enabled;
+        if !enabled {
+            // clears the buffers when dropped
+            self.get_buffers();
+        }
+    }
+
+    /// Returns `true` if the channel should be included in the mixed output
+    fn is_channel_audible(&self, channel: ApuChannelId) -> bool {
+        let any_solo = self.channels_solo.iter().any(|&s| s);
+
+        !self.channels_muted[channel as usize]
+            && (!any_solo || self.channels_solo[channel as usize])
+    }
+
+    /// The APU is clocked by the divider, on the falling edge of the bit 12
+    /// of the divider, this is needed since the divider can be clocked manually
+    /// by resetting it to 0 on write
+    pub fn clock(&mut self, double_speed: bool, divider: u8) {
+        if !self.clock_output(double_speed) || !self.power {
             return;
         }
 
+        self.store_previous_inputs();
+
         self.pulse1.channel_mut().clock();
         self.pulse2.channel_mut().clock();
         self.wave.channel_mut().clock();
@@ -462,19 +918,33 @@ impl Apu {
                 0 | 2 | 4 | 6 => {}
                 _ => unreachable!(),
             }
+
+            let step = self.frame_sequencer_step();
+            if let Some(callback) = self.sequencer_callback.as_mut() {
+                callback(step);
+            }
         }
     }
 }
 
 impl Apu {
-    fn push_output(&mut self) {
-        let right_vol = self.channels_control.vol_right() as f32 + 1.;
-        let left_vol = self.channels_control.vol_left() as f32 + 1.;
-
-        let pulse1 = self.pulse1.dac_output() / 8.;
-        let pulse2 = self.pulse2.dac_output() / 8.;
-        let wave = self.wave.dac_output() / 8.;
-        let noise = self.noise.dac_output() / 8.;
+    /// Pushes one sample to the buffers, `previous_weight` is used to
+    /// interpolate between the channels outputs before and after the last clock
+    fn push_output(&mut self, previous_weight: f32) {
+        let right_vol = (self.channels_control.vol_right() as f32 + 1.) * self.master_volume;
+        let left_vol = (self.channels_control.vol_left() as f32 + 1.) * self.master_volume;
+
+        let accurate = self.config.apu_quality == ApuQuality::Accurate;
+        let high_pass = accurate.then(|| self.high_pass_charge_factor());
+
+        let pulse1 = self.pulse1.dac_output(high_pass, previous_weight) / 8.
+            * self.channels_volume[ApuChannelId::Pulse1 as usize];
+        let pulse2 = self.pulse2.dac_output(high_pass, previous_weight) / 8.
+            * self.channels_volume[ApuChannelId::Pulse2 as usize];
+        let wave = self.wave.dac_output(high_pass, previous_weight) / 8.
+            * self.channels_volume[ApuChannelId::Wave as usize];
+        let noise = self.noise.dac_output(high_pass, previous_weight) / 8.
+            * self.channels_volume[ApuChannelId::Noise as usize];
 
         let right_pulse1 = if self
             .channels_selection
@@ -559,22 +1029,53 @@ impl Apu {
         let right_noise = right_noise / 5.0;
         let left_noise = left_noise / 5.0;
 
-        self.pulse1_buffers.push(right_pulse1);
-        self.pulse1_buffers.push(left_pulse1);
-
-        self.pulse2_buffers.push(right_pulse2);
-        self.pulse2_buffers.push(left_pulse2);
-
-        self.wave_buffers.push(right_wave);
-        self.wave_buffers.push(left_wave);
+        if accurate && self.audio_callback.is_none() {
+            for (buffer, right, left) in [
+                (&mut self.pulse1_buffers, right_pulse1, left_pulse1),
+                (&mut self.pulse2_buffers, right_pulse2, left_pulse2),
+                (&mut self.wave_buffers, right_wave, left_wave),
+                (&mut self.noise_buffers, right_noise, left_noise),
+            ] {
+                match self.stem_mode {
+                    AudioStemMode::Stereo => {
+                        buffer.push(right);
+                        buffer.push(left);
+                    }
+                    AudioStemMode::Mono => buffer.push((right + left) / 2.),
+                }
+            }
+        }
 
-        self.noise_buffers.push(right_noise);
-        self.noise_buffers.push(left_noise);
+        let mut right_sample = 0.;
+        let mut left_sample = 0.;
+        for (channel, right, left) in [
+            (ApuChannelId::Pulse1, right_pulse1, left_pulse1),
+            (ApuChannelId::Pulse2, right_pulse2, left_pulse2),
+            (ApuChannelId::Wave, right_wave, left_wave),
+            (ApuChannelId::Noise, right_noise, left_noise),
+        ] {
+            if self.is_channel_audible(channel) {
+                right_sample += right;
+                left_sample += left;
+            }
+        }
+        if let Some(callback) = self.audio_callback.as_mut() {
+            self.audio_callback_block.push(right_sample);
+            self.audio_callback_block.push(left_sample);
+            if self.audio_callback_block.len() == AUDIO_CALLBACK_BLOCK_SAMPLES * 2 {
+                callback(&self.audio_callback_block);
+                self.audio_callback_block.clear();
+            }
+        } else {
+            self.buffer.push(right_sample);
+            self.buffer.push(left_sample);
+            self.right_buffer.push(right_sample);
+            self.left_buffer.push(left_sample);
+        }
 
-        let right_sample = right_pulse1 + right_pulse2 + right_wave + right_noise;
-        let left_sample = left_pulse1 + left_pulse2 + left_wave + left_noise;
-        self.buffer.push(right_sample);
-        self.buffer.push(left_sample);
+        if let Some(callback) = self.sample_callback.as_mut() {
+            callback(left_sample, right_sample);
+        }
     }
 
     fn power_off(&mut self) {
@@ -643,3 +1144,852 @@ impl Apu {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Apu, ApuChannelId, ApuChannelState, ApuQuality, AudioStemMode, AUDIO_CALLBACK_BLOCK_SAMPLES,
+    };
+    use crate::cpu::CpuState;
+    use crate::tests::{build_cgb_test_rom, build_test_rom, test_cgb_gameboy, test_gameboy};
+    use crate::GameBoyConfig;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn sample_callback_called_for_every_sample() {
+        let mut apu = Apu::new_skip_boot_rom(GameBoyConfig::default());
+
+        let count = Rc::new(Cell::new(0usize));
+        let count_clone = count.clone();
+        apu.set_sample_callback(Some(Box::new(move |_, _| {
+            count_clone.set(count_clone.get() + 1);
+        })));
+
+        // one frame in machine cycles
+        const CYCLES_PER_FRAME: u32 = 70224 / 4;
+        for i in 0..CYCLES_PER_FRAME {
+            apu.clock(false, (i / 64) as u8);
+        }
+
+        // 44100 samples per second, ~59.73 frames per second
+        let expected = 44100. * 70224. / 4194304.;
+        assert!((count.get() as f64 - expected).abs() <= 1.);
+
+        // the buffered API is still filled with the same samples
+        let buffers = apu.get_buffers();
+        assert_eq!(buffers.all().len(), count.get() * 2);
+    }
+
+    #[test]
+    fn audio_callback_blocks() {
+        let mut apu = Apu::new_skip_boot_rom(GameBoyConfig::default());
+
+        let blocks = Rc::new(Cell::new(0usize));
+        let blocks_clone = blocks.clone();
+        apu.set_audio_callback(Some(Box::new(move |block| {
+            assert_eq!(block.len(), AUDIO_CALLBACK_BLOCK_SAMPLES * 2);
+            blocks_clone.set(blocks_clone.get() + 1);
+        })));
+
+        // one second in machine cycles
+        for i in 0..4194304 / 4 {
+            apu.clock(false, (i / 64) as u8);
+        }
+        assert_eq!(blocks.get(), 44100 / AUDIO_CALLBACK_BLOCK_SAMPLES);
+        // the buffers are not filled
+        let buffers = apu.get_buffers();
+        assert!(buffers.all().is_empty());
+        assert!(buffers.pulse1().is_empty());
+        drop(buffers);
+
+        apu.set_audio_callback(None);
+        for i in 0..0x100 {
+            apu.clock(false, (i / 64) as u8);
+        }
+        assert!(!apu.get_buffers().all().is_empty());
+    }
+
+    #[test]
+    fn output_disabled() {
+        let mut apu = Apu::new_skip_boot_rom(GameBoyConfig::default());
+        let count = Rc::new(Cell::new(0usize));
+        let count_clone = count.clone();
+        apu.set_sample_callback(Some(Box::new(move |_, _| {
+            count_clone.set(count_clone.get() + 1);
+        })));
+
+        // pulse 1 with the shortest length
+        apu.write_register(0xFF11, 0x3F);
+        apu.write_register(0xFF12, 0xF0);
+        apu.write_register(0xFF14, 0xC7);
+
+        for i in 0..0x100 {
+            apu.clock(false, (i / 64) as u8);
+        }
+        assert_ne!(count.get(), 0);
+        apu.set_output_enabled(false);
+        assert!(apu.get_buffers().all().is_empty());
+        count.set(0);
+
+        // one frame in machine cycles
+        for i in 0..70224 / 4 {
+            apu.clock(false, (i / 64) as u8);
+        }
+        assert_eq!(count.get(), 0);
+        let buffers = apu.get_buffers();
+        assert!(buffers.all().is_empty());
+        assert!(buffers.pulse1().is_empty());
+        drop(buffers);
+        // the length counter is still clocked
+        assert!(!apu.registers().pulse1.enabled);
+
+        apu.set_output_enabled(true);
+        for i in 0..0x100 {
+            apu.clock(false, (i / 64) as u8);
+        }
+        assert_ne!(count.get(), 0);
+        assert!(!apu.get_buffers().all().is_empty());
+    }
+
+    #[test]
+    fn configurable_sample_rate() {
+        for sample_rate in [22050, 44100, 48000, 96000] {
+            let config = GameBoyConfig {
+                sample_rate,
+                ..GameBoyConfig::default()
+            };
+            let mut apu = Apu::new_skip_boot_rom(config);
+            apu.write_register(0xFF12, 0xF0);
+            apu.write_register(0xFF14, 0x87);
+
+            // one second
+            for i in 0..(4194304 / 4) {
+                apu.clock(false, (i / 64) as u8);
+            }
+
+            let buffers = apu.get_buffers();
+            let samples = buffers.all().len() as i64 / 2;
+            assert!((samples - sample_rate as i64).abs() <= 1);
+            // all channels are sampled at the same points
+            assert_eq!(buffers.pulse1().len(), buffers.all().len());
+            assert_eq!(buffers.noise().len(), buffers.all().len());
+            assert!(buffers.all().iter().any(|&s| s != 0.));
+        }
+
+        // `0` is clamped to `1`
+        let mut apu = Apu::new_skip_boot_rom(GameBoyConfig {
+            sample_rate: 0,
+            ..GameBoyConfig::default()
+        });
+        assert_eq!(apu.samples_per_second(), 1.);
+        for i in 0..(4194304 / 4) {
+            apu.clock(false, (i / 64) as u8);
+        }
+        assert!(apu.get_buffers().all().len() <= 4);
+    }
+
+    #[test]
+    fn speed_multiplier() {
+        for multiplier in [0.5, 2., 4.] {
+            let mut apu = Apu::new_skip_boot_rom(GameBoyConfig::default());
+            apu.set_speed_multiplier(multiplier);
+
+            // one emulated second
+            for i in 0..(4194304 / 4) {
+                apu.clock(false, (i / 64) as u8);
+            }
+
+            let samples = apu.get_buffers().all().len() as f32 / 2.;
+            assert!((samples - 44100. / multiplier).abs() <= 1.);
+        }
+    }
+
+    #[test]
+    fn high_pass_removes_dc_offset() {
+        for sample_rate in [22050, 44100, 48000, 96000] {
+            let config = GameBoyConfig {
+                sample_rate,
+                ..GameBoyConfig::default()
+            };
+            let mut apu = Apu::new_skip_boot_rom(config);
+            if sample_rate == 44100 {
+                assert!((apu.high_pass_charge_factor() - 0.996).abs() < 0.0001);
+            }
+
+            // constant wave output at full volume
+            for addr in 0xFF30..=0xFF3F {
+                apu.write_register(addr, 0xFF);
+            }
+            apu.write_register(0xFF1A, 0x80);
+            apu.write_register(0xFF1C, 0x20);
+            apu.write_register(0xFF1E, 0x80);
+
+            // 100ms
+            for i in 0..(4194304 / 4 / 10) {
+                apu.clock(false, (i / 64) as u8);
+            }
+
+            let buffers = apu.get_buffers();
+            let wave = buffers.wave();
+            assert!(wave.iter().any(|&s| s.abs() > 0.01));
+            // the DC offset decays at the same rate for all sample rates
+            assert!(wave[wave.len() - 2..].iter().all(|&s| s.abs() < 0.001));
+        }
+    }
+
+    /// Plays a note on pulse1 and clocks the APU for some frames
+    fn play_note(apu_quality: ApuQuality) -> Apu {
+        play_note_with(apu_quality, |_| {})
+    }
+
+    /// Same as [`play_note`], but runs `setup` on the APU before playing
+    fn play_note_with(apu_quality: ApuQuality, setup: impl FnOnce(&mut Apu)) -> Apu {
+        let config = GameBoyConfig {
+            apu_quality,
+            ..GameBoyConfig::default()
+        };
+        let mut apu = Apu::new_skip_boot_rom(config);
+        setup(&mut apu);
+        apu.write_register(0xFF12, 0xF0);
+        apu.write_register(0xFF13, 0x00);
+        apu.write_register(0xFF14, 0x87);
+
+        const CYCLES_PER_FRAME: u32 = 70224 / 4;
+        for i in 0..CYCLES_PER_FRAME * 10 {
+            apu.clock(false, (i / 64) as u8);
+        }
+
+        apu
+    }
+
+    #[test]
+    fn apu_quality_output() {
+        assert_eq!(GameBoyConfig::default().apu_quality, ApuQuality::Accurate);
+
+        let mut accurate = play_note(ApuQuality::Accurate);
+        let accurate_buffers = accurate.get_buffers();
+        assert!(accurate_buffers.all().iter().any(|&s| s != 0.));
+        // `all` is the mix of the channels
+        for (i, &sample) in accurate_buffers.all().iter().enumerate() {
+            let mix = accurate_buffers.pulse1()[i]
+                + accurate_buffers.pulse2()[i]
+                + accurate_buffers.wave()[i]
+                + accurate_buffers.noise()[i];
+            assert_eq!(sample, mix);
+        }
+
+        let mut fast = play_note(ApuQuality::Fast);
+        let fast_buffers = fast.get_buffers();
+        assert_eq!(fast_buffers.all().len(), accurate_buffers.all().len());
+        assert!(fast_buffers.all().iter().any(|&s| s != 0.));
+        assert!(fast_buffers.pulse1().is_empty());
+    }
+
+    #[test]
+    fn mute_and_solo_channels() {
+        /// Plays a note on pulse1 and noise, and returns the mixed output
+        fn play(setup: impl FnOnce(&mut Apu)) -> (Apu, Vec<f32>) {
+            let mut apu = Apu::new_skip_boot_rom(GameBoyConfig::default());
+            setup(&mut apu);
+            apu.write_register(0xFF25, 0xFF);
+            apu.write_register(0xFF12, 0xF0);
+            apu.write_register(0xFF14, 0x87);
+            apu.write_register(0xFF21, 0xF0);
+            apu.write_register(0xFF23, 0x80);
+
+            const CYCLES_PER_FRAME: u32 = 70224 / 4;
+            for i in 0..CYCLES_PER_FRAME * 4 {
+                apu.clock(false, (i / 64) as u8);
+            }
+            let all = apu.get_buffers().all().to_vec();
+            (apu, all)
+        }
+
+        let (_, normal) = play(|_| {});
+        assert!(normal.iter().any(|&s| s != 0.));
+
+        // muting a channel removes it from the mix, but keeps it running
+        let (mut apu, muted_pulse1) = play(|apu| apu.set_channel_muted(ApuChannelId::Pulse1, true));
+        assert_ne!(muted_pulse1, normal);
+        assert!(apu.read_register(0xFF26) & 1 != 0);
+        let buffers = apu.get_buffers();
+        for (i, &sample) in buffers.all().iter().enumerate() {
+            assert_eq!(
+                sample,
+                buffers.pulse2()[i] + buffers.wave()[i] + buffers.noise()[i]
+            );
+        }
+        drop(buffers);
+
+        // solo only keeps the soloed channels
+        let (mut apu, solo_pulse1) = play(|apu| apu.set_channel_solo(ApuChannelId::Pulse1, true));
+        assert!(solo_pulse1.iter().any(|&s| s != 0.));
+        let buffers = apu.get_buffers();
+        assert_eq!(buffers.all(), buffers.pulse1());
+        drop(buffers);
+
+        // mute has priority over solo
+        let (_, muted_solo) = play(|apu| {
+            apu.set_channel_solo(ApuChannelId::Pulse1, true);
+            apu.set_channel_muted(ApuChannelId::Pulse1, true);
+        });
+        assert!(muted_solo.iter().all(|&s| s == 0.));
+    }
+
+    #[test]
+    fn frame_sequencer_step_callback() {
+        let mut apu = Apu::new_skip_boot_rom(GameBoyConfig::default());
+        let steps = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let steps_clone = steps.clone();
+        apu.set_sequencer_callback(Some(Box::new(move |step| {
+            steps_clone.borrow_mut().push(step)
+        })));
+
+        // restart the sequencer
+        apu.write_register(0xFF26, 0x00);
+        apu.write_register(0xFF26, 0x80);
+        assert_eq!(apu.frame_sequencer_step(), 7);
+
+        // 10 falling edges of bit 4 of the divider
+        for divider in 0..=0x140u16 {
+            apu.clock(false, divider as u8);
+            apu.clock(false, divider as u8);
+        }
+        assert_eq!(*steps.borrow(), vec![0, 1, 2, 3, 4, 5, 6, 7, 0, 1]);
+        assert_eq!(apu.frame_sequencer_step(), 1);
+    }
+
+    #[test]
+    fn channel_frequency() {
+        let mut apu = Apu::new_skip_boot_rom(GameBoyConfig::default());
+        // pulse 1 is still playing after the boot rom
+        apu.write_register(0xFF26, 0x00);
+        apu.write_register(0xFF26, 0x80);
+        assert_eq!(apu.channel_frequency(ApuChannelId::Pulse1), None);
+
+        // pulse 1: frequency 0x783 (A5, 880Hz)
+        apu.write_register(0xFF12, 0xF0);
+        apu.write_register(0xFF13, 0x83);
+        apu.write_register(0xFF14, 0x87);
+        assert_eq!(
+            apu.channel_frequency(ApuChannelId::Pulse1),
+            Some(131072. / 125.)
+        );
+        // wave: DAC off, so it is not playing
+        apu.write_register(0xFF1A, 0x00);
+        apu.write_register(0xFF1E, 0x87);
+        assert_eq!(apu.channel_frequency(ApuChannelId::Wave), None);
+        apu.write_register(0xFF1A, 0x80);
+        apu.write_register(0xFF1D, 0x00);
+        apu.write_register(0xFF1E, 0x84);
+        assert_eq!(apu.channel_frequency(ApuChannelId::Wave), Some(64.));
+        // noise: divisor code 0, shift 2
+        apu.write_register(0xFF21, 0xF0);
+        apu.write_register(0xFF22, 0x20);
+        apu.write_register(0xFF23, 0x80);
+        assert_eq!(apu.channel_frequency(ApuChannelId::Noise), Some(131072.));
+
+        apu.write_register(0xFF26, 0x00);
+        assert_eq!(apu.channel_frequency(ApuChannelId::Pulse1), None);
+    }
+
+    #[test]
+    fn mono_stems() {
+        let mut stereo = Apu::new_skip_boot_rom(GameBoyConfig::default());
+        let mut mono = Apu::new_skip_boot_rom(GameBoyConfig::default());
+        mono.set_stem_mode(AudioStemMode::Mono);
+        for apu in [&mut stereo, &mut mono] {
+            // pulse1 on the left only
+            apu.write_register(0xFF25, 0x10);
+            apu.write_register(0xFF24, 0x77);
+            apu.write_register(0xFF12, 0xF0);
+            apu.write_register(0xFF14, 0x87);
+            for i in 0..70224 {
+                apu.clock(false, (i / 256) as u8);
+            }
+        }
+
+        let stereo = stereo.get_buffers();
+        let mono = mono.get_buffers();
+        assert_eq!(mono.all(), stereo.all());
+        assert_eq!(mono.pulse1().len(), stereo.pulse1().len() / 2);
+        assert_eq!(mono.noise().len(), mono.pulse1().len());
+        assert!(stereo.pulse1().iter().step_by(2).all(|&right| right == 0.));
+        for (&mono, stereo) in mono.pulse1().iter().zip(stereo.pulse1().chunks(2)) {
+            assert_eq!(mono, stereo[1] / 2.);
+        }
+    }
+
+    #[test]
+    fn stereo_panning() {
+        let mut apu = Apu::new_skip_boot_rom(GameBoyConfig::default());
+        // pulse1 on the left only, left volume 7, right volume 7
+        apu.write_register(0xFF25, 0x10);
+        apu.write_register(0xFF24, 0x77);
+        apu.write_register(0xFF12, 0xF0);
+        apu.write_register(0xFF14, 0x87);
+
+        const CYCLES_PER_FRAME: u32 = 70224 / 4;
+        for i in 0..CYCLES_PER_FRAME * 4 {
+            apu.clock(false, (i / 64) as u8);
+        }
+
+        let buffers = apu.get_buffers();
+        assert_eq!(buffers.left().len(), buffers.all().len() / 2);
+        assert_eq!(buffers.right().len(), buffers.all().len() / 2);
+        assert!(buffers.left().iter().any(|&s| s != 0.));
+        assert!(buffers.right().iter().all(|&s| s == 0.));
+        for (i, (&right, &left)) in buffers.right().iter().zip(buffers.left()).enumerate() {
+            assert_eq!(buffers.all()[i * 2], right);
+            assert_eq!(buffers.all()[i * 2 + 1], left);
+        }
+        let left_loud = buffers.left().to_vec();
+        drop(buffers);
+
+        // same note with left volume 3, should be half as loud
+        let mut apu = Apu::new_skip_boot_rom(GameBoyConfig::default());
+        apu.write_register(0xFF25, 0x10);
+        apu.write_register(0xFF24, 0x37);
+        apu.write_register(0xFF12, 0xF0);
+        apu.write_register(0xFF14, 0x87);
+        for i in 0..CYCLES_PER_FRAME * 4 {
+            apu.clock(false, (i / 64) as u8);
+        }
+        let buffers = apu.get_buffers();
+        for (&quiet, &loud) in buffers.left().iter().zip(&left_loud) {
+            assert!((quiet * 2. - loud).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn master_and_channel_volume() {
+        fn play(setup: impl FnOnce(&mut Apu)) -> Vec<f32> {
+            let mut apu = play_note_with(ApuQuality::Accurate, setup);
+            let all = apu.get_buffers().all().to_vec();
+            all
+        }
+
+        let normal = play(|_| {});
+        assert!(normal.iter().any(|&s| s != 0.));
+
+        let half_master = play(|apu| apu.set_master_volume(0.5));
+        let half_pulse1 = play(|apu| apu.set_channel_volume(ApuChannelId::Pulse1, 0.5));
+        // only pulse1 is playing
+        for ((&normal, &master), &channel) in normal.iter().zip(&half_master).zip(&half_pulse1) {
+            assert!((normal * 0.5 - master).abs() < 1e-6);
+            assert!((normal * 0.5 - channel).abs() < 1e-6);
+        }
+
+        // other channels volume does not affect pulse1
+        let other_channel = play(|apu| apu.set_channel_volume(ApuChannelId::Noise, 0.));
+        assert_eq!(other_channel, normal);
+
+        // clamped to `0.0..=1.0`
+        let loud = play(|apu| apu.set_master_volume(2.));
+        assert_eq!(loud, normal);
+        let silent = play(|apu| apu.set_master_volume(-1.));
+        assert!(silent.iter().all(|&s| s == 0.));
+    }
+
+    #[test]
+    fn writes_while_powered_off() {
+        for is_dmg in [false, true] {
+            let config = GameBoyConfig {
+                is_dmg,
+                ..GameBoyConfig::default()
+            };
+            let mut apu = Apu::new_skip_boot_rom(config);
+            assert!(apu.is_powered());
+
+            apu.write_register(0xFF26, 0x00);
+            assert!(!apu.is_powered());
+            // length 1 for all channels
+            apu.write_register(0xFF11, 0x3F);
+            apu.write_register(0xFF16, 0x3F);
+            apu.write_register(0xFF1B, 0xFF);
+            apu.write_register(0xFF20, 0x3F);
+            apu.write_register(0xFF12, 0xF0);
+            apu.write_register(0xFF25, 0xFF);
+            apu.write_register(0xFF26, 0x80);
+            assert!(apu.is_powered());
+
+            let registers = apu.registers();
+            // the rest of the registers are read-only on both
+            assert_eq!(registers.nr[0x02], 0x00);
+            assert_eq!(registers.nr[0x15], 0x00);
+            // duty is not written
+            assert_eq!(registers.nr[0x01], 0x3F);
+
+            let expected_lengths = if is_dmg { [1; 4] } else { [64, 64, 256, 64] };
+            let lengths = [
+                registers.pulse1.length_counter,
+                registers.pulse2.length_counter,
+                registers.wave.length_counter,
+                registers.noise.length_counter,
+            ];
+            assert_eq!(lengths, expected_lengths);
+        }
+    }
+
+    #[test]
+    fn wave_ram_access() {
+        // rising from 0 to 14, so that no byte is `0xFF`
+        let sample = |k: usize| (k * 15 / 32) as u8;
+        let ramp: [u8; 16] = std::array::from_fn(|i| sample(i * 2) << 4 | sample(i * 2 + 1));
+
+        for is_dmg in [false, true] {
+            let config = GameBoyConfig {
+                is_dmg,
+                ..GameBoyConfig::default()
+            };
+            let mut apu = Apu::new_skip_boot_rom(config);
+            apu.set_wave_ram(ramp);
+            assert_eq!(apu.wave_ram(), ramp);
+            for (i, &byte) in ramp.iter().enumerate() {
+                assert_eq!(apu.read_register(0xFF30 + i as u16), byte);
+            }
+
+            // play the wave, only the byte being played can be accessed
+            apu.write_register(0xFF1A, 0x80);
+            apu.write_register(0xFF1D, 0xFF);
+            apu.write_register(0xFF1E, 0x87);
+            let mut accessed_bytes = Vec::new();
+            for _ in 0..100 {
+                apu.clock(false, 0);
+                let wave_ram = apu.wave_ram();
+                if wave_ram[0] != 0xFF {
+                    // all the reads are from the same byte
+                    assert!(wave_ram.iter().all(|&byte| byte == wave_ram[0]));
+                    assert!(ramp.contains(&wave_ram[0]));
+                    accessed_bytes.push(wave_ram[0]);
+                }
+            }
+            assert!(!accessed_bytes.is_empty());
+            if !is_dmg {
+                assert_eq!(accessed_bytes.len(), 100);
+            }
+
+            // stopping the channel (DAC off) gives access to the whole RAM again
+            apu.write_register(0xFF1A, 0x00);
+            assert_eq!(apu.wave_ram(), ramp);
+        }
+    }
+
+    #[test]
+    fn frame_sequencer_clocked_by_divider_falling_edge() {
+        for double_speed in [false, true] {
+            let mut apu = Apu::new_skip_boot_rom(GameBoyConfig::default());
+            let bit = if double_speed { 0x20 } else { 0x10 };
+            // in normal speed, the APU is clocked every 2 clocks
+            let clock = |apu: &mut Apu, divider: u8| {
+                apu.clock(double_speed, divider);
+                apu.clock(double_speed, divider);
+            };
+
+            // restart the sequencer from position 0, the next event clocks length
+            apu.write_register(0xFF26, 0x00);
+            apu.write_register(0xFF26, 0x80);
+            // pulse 2 with length 1, length enabled and triggered
+            apu.write_register(0xFF16, 0x3F);
+            apu.write_register(0xFF17, 0xF0);
+            apu.write_register(0xFF19, 0xC0);
+            let pulse2_on = |apu: &Apu| apu.read_register(0xFF26) & 0x02 != 0;
+            assert!(pulse2_on(&apu));
+
+            // the other bit does not clock the sequencer
+            clock(&mut apu, bit ^ 0x30);
+            clock(&mut apu, 0);
+            assert!(pulse2_on(&apu));
+
+            // stays set while the divider is counting
+            clock(&mut apu, bit);
+            for divider in bit..bit + 0x0F {
+                clock(&mut apu, divider);
+            }
+            assert!(pulse2_on(&apu));
+
+            // writing to DIV resets it, falling edge, clock length
+            clock(&mut apu, 0);
+            assert!(!pulse2_on(&apu));
+        }
+    }
+
+    #[test]
+    fn registers_snapshot() {
+        let mut apu = Apu::new_skip_boot_rom(GameBoyConfig::default());
+
+        // pulse 1: volume 12, length 64 - 0x30, frequency 0x6A3, triggered
+        apu.write_register(0xFF11, 0x80 | 0x30);
+        apu.write_register(0xFF12, 0xC0);
+        apu.write_register(0xFF13, 0xA3);
+        apu.write_register(0xFF14, 0xC6);
+        // wave: DAC off, so triggering it does not enable it
+        apu.write_register(0xFF1A, 0x00);
+        apu.write_register(0xFF1C, 0x40);
+        apu.write_register(0xFF1E, 0x80);
+
+        let regs = apu.registers();
+        assert!(regs.power);
+        assert_eq!(regs.register(0xFF11), 0xBF);
+        assert_eq!(regs.register(0xFF12), 0xC0);
+        assert_eq!(regs.register(0xFF13), 0xFF);
+        assert_eq!(regs.register(0xFF14), 0xFF);
+        assert_eq!(regs.register(0xFF15), 0xFF);
+        assert_eq!(regs.register(0xFF26), apu.read_register(0xFF26));
+
+        assert_eq!(
+            regs.pulse1,
+            ApuChannelState {
+                frequency: 0x6A3,
+                volume: 12,
+                length_counter: 0x10,
+                enabled: true,
+                dac_enabled: true,
+            }
+        );
+        assert!(!regs.wave.enabled);
+        assert!(!regs.wave.dac_enabled);
+        assert_eq!(regs.wave.volume, 2);
+
+        // taking a snapshot does not affect the state
+        assert_eq!(apu.registers(), regs);
+    }
+
+    #[test]
+    fn expected_samples_per_frame() {
+        let mut gb = test_gameboy(&[0x18, 0xFE]);
+        // align to the start of a frame
+        gb.clock_for_frame();
+        gb.take_audio_buffers();
+
+        let mut total = 0;
+        for _ in 0..600 {
+            let expected = gb.expected_samples_this_frame();
+            gb.clock_for_frame();
+            let samples = gb.take_audio_buffers().all.len() / 2;
+            assert!(samples.abs_diff(expected) <= 1);
+            total += samples;
+        }
+
+        // 600 frames of 70224 cycles at 4194304Hz, slightly more than 10 seconds
+        let exact = 600. * 70224. / 4194304. * 44100.;
+        assert!((total as f64 - exact).abs() <= 1.);
+    }
+
+    #[test]
+    fn drain_audio_reuses_buffer() {
+        use crate::ApuChannelId;
+
+        let mut gb = test_gameboy(&[0x18, 0xFE]);
+
+        // the first frame after power on is partial
+        gb.clock_for_frame();
+        gb.drain_audio_into(&mut Vec::new());
+
+        let mut out = Vec::with_capacity(0x1000);
+        let ptr = out.as_ptr();
+        for _ in 0..10 {
+            out.clear();
+            gb.clock_for_frame();
+            gb.drain_audio_into(&mut out);
+
+            // one frame (~59.7 fps) of stereo samples
+            assert!((735..=740).contains(&(out.len() / 2)));
+            assert!(gb.audio_buffers().all().is_empty());
+            // no reallocation happened
+            assert_eq!(out.as_ptr(), ptr);
+        }
+
+        let mut wave = Vec::new();
+        gb.clock_for_frame();
+        gb.drain_audio_channel_into(ApuChannelId::Wave, &mut wave);
+        assert!((735..=740).contains(&(wave.len() / 2)));
+        assert!(gb.audio_buffers().all().is_empty());
+    }
+
+    #[test]
+    fn audio_only_rendering() {
+        // `LD A, 0xFF`, `LDH (NR51), A`, `LDH (NR50), A`, `LD A, 0xF0`, `LDH (NR12), A`,
+        // `LD A, 0x87`, `LDH (NR14), A`, `JR -2` (pulse 1 playing forever)
+        let rom = build_test_rom(
+            0,
+            0,
+            &[
+                0x3E, 0xFF, 0xE0, 0x25, 0xE0, 0x24, 0x3E, 0xF0, 0xE0, 0x12, 0x3E, 0x87, 0xE0, 0x14,
+                0x18, 0xFE,
+            ],
+        );
+        let mut gb = crate::GameBoy::new_audio_only(rom).unwrap();
+
+        // one second of audio, in blocks of 1024 cycles
+        let mut out = Vec::new();
+        let mut executed = 0;
+        while executed < 4194304 {
+            executed += gb.clock_for_cycles(1024);
+            gb.drain_audio_into(&mut out);
+        }
+        assert!((44000..=44200).contains(&(out.len() / 2)));
+        assert!(out.iter().any(|&s| s != 0.));
+
+        // the PPU still runs, but nothing is rendered
+        assert!(gb.frame_count() >= 59);
+        assert!(gb.screen_buffer().iter().all(|&p| p == 0xFF));
+    }
+
+    #[test]
+    fn take_audio_buffers_send_to_thread() {
+        let mut gb = test_gameboy(&[0x18, 0xFE]);
+
+        let (sender, receiver) = std::sync::mpsc::channel::<crate::OwnedAudioBuffers>();
+        let audio_thread = std::thread::spawn(move || {
+            receiver
+                .iter()
+                .map(|buffers| {
+                    assert_eq!(buffers.left.len(), buffers.all.len() / 2);
+                    assert_eq!(buffers.pulse1.len(), buffers.all.len());
+                    buffers.all.len()
+                })
+                .collect::<Vec<_>>()
+        });
+
+        for _ in 0..3 {
+            gb.clock_for_frame();
+            sender.send(gb.take_audio_buffers()).unwrap();
+            assert!(gb.audio_buffers().all().is_empty());
+        }
+        drop(sender);
+
+        let lengths = audio_thread.join().unwrap();
+        assert_eq!(lengths.len(), 3);
+        assert!(lengths.iter().all(|&len| len > 0));
+    }
+
+    #[test]
+    fn speed_multiplier_audio_samples() {
+        let mut gb = test_gameboy(&[0x18, 0xFE]);
+        // the first frame after power on is partial
+        gb.clock_for_frame();
+
+        let mut samples = Vec::new();
+        for multiplier in [1., 2., 4.] {
+            gb.set_speed_multiplier(multiplier);
+            gb.drain_audio_into(&mut Vec::new());
+            assert_eq!(gb.speed_multiplier(), multiplier);
+
+            let expected = gb.audio_samples_per_frame();
+            assert!((expected - 44100. / 59.7275 / multiplier as f64).abs() < 0.01);
+            for _ in 0..4 {
+                samples.clear();
+                gb.clock_for_frame();
+                gb.drain_audio_into(&mut samples);
+                assert!((samples.len() as f64 / 2. - expected).abs() <= 1.);
+            }
+        }
+    }
+
+    #[test]
+    fn double_speed_audio_sample_rate() {
+        #[rustfmt::skip]
+        const PROGRAM: [u8; 26] = [
+            0x01, 0x00, 0x00, // LD BC, 0
+            0x0B,             // DEC BC
+            0x78,             // LD A, B
+            0xB1,             // OR C
+            0x20, 0xFB,       // JR NZ, -5
+            0x3E, 0x01,       // LD A, 1
+            0xE0, 0x4D,       // LDH (KEY1), A
+            0x10, 0x00,       // STOP (switch speed)
+            0x01, 0x00, 0x00, // LD BC, 0
+            0x0B,             // DEC BC
+            0x78,             // LD A, B
+            0xB1,             // OR C
+            0x20, 0xFB,       // JR NZ, -5
+            0x10, 0x00,       // STOP
+            0x18, 0xFE,       // JR -2
+        ];
+        let mut gb = test_cgb_gameboy(&PROGRAM);
+
+        // the first frame after power on is partial
+        let mut samples = Vec::new();
+        gb.clock_for_frame();
+        gb.drain_audio_into(&mut samples);
+
+        // delay loop in normal speed, then in double speed, then stopped
+        let mut frames_per_speed = [0; 2];
+        let mut stopped_frames = 0;
+        for _ in 0..60 {
+            samples.clear();
+            let state = gb.clock_for_frame();
+            gb.drain_audio_into(&mut samples);
+
+            // ~59.7 fps, with stereo samples
+            assert!((1470..=1480).contains(&samples.len()));
+
+            let double_speed = gb.bus.read_not_ticked(0xFF4D, None) >> 7;
+            frames_per_speed[double_speed as usize] += 1;
+            stopped_frames += (state == CpuState::Stopped) as u32;
+        }
+        assert!(frames_per_speed[0] > 10);
+        assert!(frames_per_speed[1] > 10);
+        assert!(stopped_frames > 10);
+    }
+
+    #[test]
+    fn audio_during_speed_switch_and_stop() {
+        use crate::{ApuQuality, JoypadButton};
+
+        #[rustfmt::skip]
+        const PROGRAM: [u8; 21] = [
+            0xAF,             // XOR A
+            0xE0, 0x00,       // LDH (P1), A (select all buttons)
+            0x3E, 0xE0,       // LD A, 0xE0
+            0xE0, 0x13,       // LDH (NR13), A
+            0x3E, 0x87,       // LD A, 0x87
+            0xE0, 0x14,       // LDH (NR14), A (pulse 1 at 4096Hz)
+            0x3E, 0x01,       // LD A, 1
+            0xE0, 0x4D,       // LDH (KEY1), A
+            0x10, 0x00,       // STOP (switch speed)
+            0x10, 0x00,       // STOP
+            0x18, 0xFE,       // JR -2
+        ];
+        let rom = build_cgb_test_rom(&PROGRAM);
+        // no high-pass filter, so the output stays constant while stopped
+        let config = GameBoyConfig {
+            apu_quality: ApuQuality::Fast,
+            ..GameBoyConfig::default()
+        };
+        let mut gb = crate::GameBoy::from_rom_bytes(rom, config).unwrap();
+
+        while gb.cpu.next_instruction(&mut gb.bus) != CpuState::SpeedSwitching {}
+        gb.drain_audio_into(&mut Vec::new());
+
+        // the pulse keeps playing during the switch
+        let mut samples = Vec::new();
+        while gb.cpu.next_instruction(&mut gb.bus) == CpuState::SpeedSwitching {
+            assert!(!gb.is_stopped());
+        }
+        gb.drain_audio_into(&mut samples);
+        assert!(samples.iter().any(|&s| s != samples[0]));
+
+        while gb.cpu.next_instruction(&mut gb.bus) != CpuState::Stopped {}
+        assert!(gb.is_stopped());
+        for _ in 0..100 {
+            gb.cpu.next_instruction(&mut gb.bus);
+        }
+        gb.drain_audio_into(&mut Vec::new());
+
+        // the output is held while stopped, but the samples are still generated
+        samples.clear();
+        for _ in 0..10000 {
+            assert_eq!(gb.cpu.next_instruction(&mut gb.bus), CpuState::Stopped);
+        }
+        gb.drain_audio_into(&mut samples);
+        assert!(samples.len() > 100);
+        assert!(samples.iter().all(|&s| s == samples[0]));
+
+        gb.press_joypad(JoypadButton::A);
+        gb.cpu.next_instruction(&mut gb.bus);
+        assert!(!gb.is_stopped());
+    }
+}
@@ -54,6 +54,10 @@ impl<C: ApuChannel> LengthCountedChannel<C> {
         self.counter_decrease_enable
     }
 
+    pub fn length_counter(&self) -> u16 {
+        self.current_counter
+    }
+
     pub fn clock_length_counter(&mut self) {
         if self.counter_decrease_enable {
             if self.current_counter == 0 {
@@ -130,6 +134,10 @@ impl<C: ApuChannel> ApuChannel for LengthCountedChannel<C> {
 #[derive(Savable)]
 pub struct Dac<C: ApuChannel> {
     capacitor: f32,
+    /// The input of the DAC before the last channel clock, used for
+    /// interpolating between the two values when sampling
+    #[savable(skip)]
+    previous_input: f32,
     channel: C,
 }
 
@@ -137,18 +145,38 @@ impl<C: ApuChannel> Dac<C> {
     pub fn new(channel: C) -> Self {
         Self {
             capacitor: 0.,
+            previous_input: 0.,
             channel,
         }
     }
 
-    pub fn dac_output(&mut self) -> f32 {
+    /// Must be called before clocking the channel, to keep the previous
+    /// value of the input for interpolation
+    pub fn store_previous_input(&mut self) {
+        self.previous_input = self.channel.output() as f32 / 15.;
+    }
+
+    /// Returns the output of the DAC, `high_pass` enables the capacitor
+    /// high-pass filter, which removes the DC offset of the output, with
+    /// the given charge factor (how much of the capacitor charge is kept
+    /// every sample).
+    ///
+    /// The input is linearly interpolated between the current and previous
+    /// values, `previous_weight` (`0.0..1.0`) is the weight of the previous value.
+    pub fn dac_output(&mut self, high_pass: Option<f32>, previous_weight: f32) -> f32 {
         if self.channel.muted() {
             0.
         } else {
-            let dac_in = self.channel.output() as f32 / 15.;
+            let current_input = self.channel.output() as f32 / 15.;
+            let dac_in =
+                current_input * (1. - previous_weight) + self.previous_input * previous_weight;
+            let Some(charge_factor) = high_pass else {
+                return dac_in;
+            };
+
             let dac_out = dac_in - self.capacitor;
 
-            self.capacitor = dac_in - dac_out * 0.996;
+            self.capacitor = dac_in - dac_out * charge_factor;
 
             dac_out
         }
@@ -31,6 +31,15 @@ impl NoiseChannel {
         (self.shift_clock_frequency << 4) | ((self.step_mode_7_bits as u8) << 3) | self.divisor_code
     }
 
+    pub fn frequency(&self) -> u16 {
+        self.get_frequency()
+    }
+
+    /// The rate in Hz at which the LFSR is shifted
+    pub fn shift_rate(&self) -> f32 {
+        4194304. / ((self.base_divisor() as u32) << self.shift_clock_frequency) as f32
+    }
+
     pub fn envelope(&self) -> &EnvelopGenerator {
         &self.envelope
     }
@@ -1,16 +1,26 @@
+#[cfg(feature = "zip")]
+mod archive;
 mod error;
 mod mappers;
 
 use sha2::{Digest, Sha256};
 
+use std::cell::Cell;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
-pub use error::CartridgeError;
+pub use error::{CartridgeError, SramError};
+pub use mappers::{
+    MapperType, RtcSaveFormat, RtcTime, RumbleCallback, CAMERA_HEIGHT, CAMERA_WIDTH,
+};
 
-use error::SramError;
-use mappers::{Mapper, MapperType, MappingResult};
+/// Callback called when the battery backed RAM is first written after
+/// being saved, see [`GameBoy::set_sram_dirty_callback`](crate::GameBoy::set_sram_dirty_callback)
+pub type SramDirtyCallback = Box<dyn FnMut()>;
+
+use crate::save_migration::StateMigration;
+use mappers::{Mapper, MappingResult};
 use save_state::Savable;
 
 const NINTENDO_LOGO_DATA: &[u8; 48] = &[
@@ -155,10 +165,27 @@ impl CartridgeType {
                 ram: true,
                 battery: true,
             }),
+            0xFC => Some(Self {
+                mapper_type: MapperType::PocketCamera,
+                ram: true,
+                battery: true,
+            }),
             _ => None,
         }
     }
 
+    /// Parses the cartridge type from the header of `data`, including the
+    /// MBC1 multicart detection
+    fn detect(data: &[u8]) -> Result<Self, CartridgeError> {
+        let mut cartridge_type =
+            Self::from_byte(data[0x147]).ok_or(CartridgeError::InvalidCartridgeType)?;
+
+        // checks if its mbc1 multicart and update the mapper type
+        cartridge_type.update_mbc1_multicart(data);
+
+        Ok(cartridge_type)
+    }
+
     /// Checks if the cartridge is MBC1 multicart and update the `mapper` value
     fn update_mbc1_multicart(&mut self, data: &[u8]) {
         if let MapperType::Mbc1 { ref mut multicart } = self.mapper_type {
@@ -185,6 +212,8 @@ impl CartridgeType {
             MapperType::Mbc2 => Box::<mappers::Mbc2>::default(),
             MapperType::Mbc3 { timer } => Box::new(mappers::Mbc3::new(timer)),
             MapperType::Mbc5 { rumble } => Box::new(mappers::Mbc5::new(rumble)),
+            MapperType::Mbc7 => Box::new(mappers::Mbc7::new()),
+            MapperType::PocketCamera => Box::<mappers::PocketCamera>::default(),
             _ => return None,
         };
 
@@ -192,62 +221,401 @@ impl CartridgeType {
     }
 }
 
+/// The parsed header of the cartridge, located at `0x100-0x14F` in the ROM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    /// The game title, stored in `0x134-0x143`, but may be shorter in newer
+    /// cartridges as the last bytes are used for the manufacturer code
+    /// and CGB flag.
+    pub title: String,
+    /// The manufacturer code, stored in `0x13F-0x142`, only used in newer
+    /// cartridges and is part of the title in older ones.
+    pub manufacturer_code: [u8; 4],
+    /// The CGB flag byte at `0x143`.
+    pub cgb_flag: u8,
+    /// The SGB flag byte at `0x146` is `0x03`.
+    pub sgb_supported: bool,
+    pub mapper_type: MapperType,
+    /// The size of the ROM in bytes.
+    pub rom_size: usize,
+    /// The size of the cartridge RAM in bytes, not including the MBC2
+    /// internal RAM.
+    pub ram_size: usize,
+    /// The destination code at `0x14A`, `0x00` for Japan and `0x01` for overseas.
+    pub destination_code: u8,
+    /// The header checksum at `0x14D` matches the computed checksum.
+    ///
+    /// Note that cartridges with invalid header checksums are rejected when
+    /// loading (as the boot rom would lock up), so this is always `true` for
+    /// a loaded cartridge.
+    pub header_checksum_valid: bool,
+    /// The global checksum at `0x14E-0x14F` matches the computed checksum
+    /// of the whole ROM, this is not checked by the hardware, and some
+    /// cartridges have invalid values.
+    pub global_checksum_valid: bool,
+}
+
+impl CartridgeHeader {
+    /// The cartridge supports CGB functions, but may work on DMG as well.
+    pub fn supports_cgb(&self) -> bool {
+        self.cgb_flag & 0x80 != 0
+    }
+
+    /// The cartridge works on CGB only.
+    pub fn cgb_only(&self) -> bool {
+        self.cgb_flag == 0xC0
+    }
+}
+
+/// The cartridge as seen by the [`GameBoy`](crate::GameBoy), implement this
+/// to plug in a custom cartridge (i.e. flash carts or test fixtures) with
+/// [`GameBoyBuilder::cartridge`](crate::GameBoyBuilder::cartridge).
+///
+/// The cartridges created from ROM data by the builder use the built-in
+/// implementation, which handles all the supported mappers.
+pub trait CartridgeInterface {
+    /// Read from `0x0000-0x3FFF`
+    fn read_rom0(&self, addr: u16) -> u8;
+
+    /// Read from `0x4000-0x7FFF`
+    fn read_romx(&self, addr: u16) -> u8;
+
+    /// Write to `0x0000-0x7FFF`, which is used to control the mapper
+    fn write_to_bank_controller(&mut self, _addr: u16, _data: u8) {
+        // ignored
+    }
+
+    /// Write to `0x0000-0x7FFF` when [`GameBoyConfig::writable_rom`](crate::GameBoyConfig::writable_rom)
+    /// is set, cartridges without a mapper should modify the ROM in memory,
+    /// otherwise this should behave like [`write_to_bank_controller`](Self::write_to_bank_controller).
+    fn write_rom(&mut self, addr: u16, data: u8) {
+        self.write_to_bank_controller(addr, data);
+    }
+
+    /// Read from `0xA000-0xBFFF`
+    fn read_ram(&mut self, _addr: u16) -> u8 {
+        0xFF
+    }
+
+    /// Write to `0xA000-0xBFFF`
+    fn write_ram(&mut self, _addr: u16, _data: u8) {
+        // ignored
+    }
+
+    /// A way to sync bus/emulator to the mapper, main purpose is to sync
+    /// MBC3's RTC clock, this is clocked 4194304 / 2 times per second
+    /// regardless of the CPU clock speed (double or normal)
+    fn clock_mapper(&mut self) {
+        // ignored
+    }
+
+    fn header(&self) -> &CartridgeHeader;
+
+    /// A hash identifying the cartridge, used to make sure save states are
+    /// loaded into the same cartridge
+    fn hash(&self) -> &[u8; 32];
+
+    /// The cartridge supports CGB functions, used to select the mode of the
+    /// CGB when starting without a boot rom
+    fn is_cartridge_color(&self) -> bool {
+        self.header().supports_cgb()
+    }
+
+    /// The ROM banks mapped to `0x0000-0x3FFF` and `0x4000-0x7FFF`
+    /// respectively, for debugging
+    fn rom_banks(&self) -> (u16, u16) {
+        (0, 1)
+    }
+
+    /// The RAM bank mapped to `0xA000-0xBFFF`, for debugging
+    fn ram_bank(&self) -> u8 {
+        0
+    }
+
+    /// The ROM file path, `None` if the cartridge is not backed by a file
+    fn file_path(&self) -> Option<&Path> {
+        None
+    }
+
+    /// The cartridge has a battery to keep its RAM (and clock) data
+    fn has_battery(&self) -> bool {
+        false
+    }
+
+    /// The size of the battery backed data, as produced by
+    /// [`save_sram`](Self::save_sram), `0` if there is no battery
+    fn sram_size(&self) -> usize {
+        0
+    }
+
+    /// Loads the battery backed data from `reader`, the data must be the
+    /// exact expected size, same as what [`save_sram`](Self::save_sram)
+    /// produces.
+    fn load_sram(&mut self, reader: &mut dyn Read) -> Result<(), CartridgeError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        if !data.is_empty() {
+            return Err(CartridgeError::SramSizeMismatch {
+                expected: 0,
+                got: data.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Writes the battery backed data into `writer`
+    fn save_sram(&self, _writer: &mut dyn Write) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// The battery backed data was written since the last
+    /// [`save_sram`](Self::save_sram)
+    fn sram_dirty(&self) -> bool {
+        false
+    }
+
+    /// Sets the dirty state of the battery backed data without calling the
+    /// dirty callback, used to restore it when a failed load is undone
+    fn set_sram_dirty(&mut self, _dirty: bool) {
+        // ignored
+    }
+
+    /// Writes the battery backed data to its backing storage now, and
+    /// disables any save that would happen when the cartridge is dropped
+    fn flush_sram(&mut self) -> Result<(), SramError> {
+        Ok(())
+    }
+
+    /// Sets the callback to be called when the battery backed data becomes
+    /// dirty, see [`sram_dirty`](Self::sram_dirty)
+    fn set_sram_dirty_callback(&mut self, _callback: Option<SramDirtyCallback>) {
+        // ignored
+    }
+
+    /// The current time of the real time clock, if the cartridge has one
+    fn rtc(&self) -> Option<RtcTime> {
+        None
+    }
+
+    fn set_rtc(&mut self, _time: RtcTime) {
+        // ignored
+    }
+
+    fn set_rtc_frozen(&mut self, _frozen: bool) {
+        // ignored
+    }
+
+    /// Sets the format of the real time clock data in the SRAM, used by
+    /// [`save_sram`](Self::save_sram) and [`load_sram`](Self::load_sram)
+    fn set_rtc_save_format(&mut self, _format: RtcSaveFormat) {
+        // ignored
+    }
+
+    fn set_rumble_callback(&mut self, _callback: Option<RumbleCallback>) {
+        // ignored
+    }
+
+    /// Sets the current acceleration of the accelerometer in `g`
+    fn set_accelerometer(&mut self, _x: f32, _y: f32) {
+        // ignored
+    }
+
+    /// Sets the image seen by the camera sensor, [`CAMERA_WIDTH`] *
+    /// [`CAMERA_HEIGHT`] grayscale pixels
+    fn set_camera_frame(&mut self, _frame: &[u8]) {
+        // ignored
+    }
+
+    /// Set while loading a state with [`GameBoy::load_state_forced`](crate::GameBoy::load_state_forced),
+    /// the hash in the state header is not checked then, and the cartridge
+    /// should accept a state of a different hash as well.
+    fn set_ignore_state_hash(&mut self, _ignore: bool) {
+        // ignored
+    }
+
+    // `Savable` cannot be used in a trait object, as it has generic methods
+    fn save_state(&self, writer: &mut dyn Write) -> save_state::Result<()>;
+
+    fn load_state(&mut self, reader: &mut dyn Read) -> save_state::Result<()>;
+}
+
+impl Savable for Box<dyn CartridgeInterface> {
+    fn save<W: Write>(&self, writer: &mut W) -> save_state::Result<()> {
+        self.save_state(writer)
+    }
+
+    fn load<R: Read>(&mut self, reader: &mut R) -> save_state::Result<()> {
+        self.load_state(reader)
+    }
+}
+
 pub struct Cartridge {
-    file_path: Box<Path>,
-    sram_file_path: Box<Path>,
+    file_path: Option<Box<Path>>,
+    sram_file_path: Option<Box<Path>>,
     save_on_shutdown: bool,
-    game_title: String,
+    header: CartridgeHeader,
     cartridge_type: CartridgeType,
     target_device: TargetDevice,
     mapper: Box<dyn Mapper>,
     hash: [u8; 32],
+    /// Allow loading a save state of a different cartridge hash,
+    /// used for forced loading
+    ignore_state_hash: bool,
     rom: Vec<u8>,
     ram: Vec<u8>,
+    /// The RAM was written since it was last saved, only tracked for
+    /// cartridges with battery
+    sram_dirty: Cell<bool>,
+    sram_dirty_callback: Option<SramDirtyCallback>,
 }
 
 impl Cartridge {
+    /// Detects the mapper type from the header of the ROM `data`, without
+    /// validating the rest of the ROM
+    pub fn detect_mapper(data: &[u8]) -> Result<MapperType, CartridgeError> {
+        if data.len() < 0x150 {
+            return Err(CartridgeError::InvalidRomSize {
+                expected: 0x8000,
+                actual: data.len(),
+            });
+        }
+
+        CartridgeType::detect(data).map(|cartridge_type| cartridge_type.mapper_type)
+    }
+
     pub fn from_file<RomP: AsRef<Path>, SavP: AsRef<Path>>(
         file_path: RomP,
         sram_file_path: Option<SavP>,
         save_on_shutdown: bool,
+        rtc_save_format: RtcSaveFormat,
     ) -> Result<Self, CartridgeError> {
+        let (file_path, data) = Self::read_rom_file(file_path)?;
+
+        // the SRAM file is still named after the archive
+        let sram_file_path = if let Some(sram_file_path) = sram_file_path {
+            sram_file_path.as_ref().to_path_buf().into_boxed_path()
+        } else {
+            Self::get_save_file(&file_path).into_boxed_path()
+        };
+
+        Self::from_data(
+            data,
+            Some(file_path),
+            Some(sram_file_path),
+            save_on_shutdown,
+            rtc_save_format,
+        )
+    }
+
+    /// Same as [`from_file`](Self::from_file), but the SRAM is kept only in
+    /// memory, it is not loaded from or saved to any file.
+    pub fn from_file_in_memory_sram<RomP: AsRef<Path>>(
+        file_path: RomP,
+        rtc_save_format: RtcSaveFormat,
+    ) -> Result<Self, CartridgeError> {
+        let (file_path, data) = Self::read_rom_file(file_path)?;
+
+        Self::from_data(data, Some(file_path), None, false, rtc_save_format)
+    }
+
+    /// Reads the ROM file at `file_path` after checking its extension, and
+    /// extracts it if it's an archive
+    fn read_rom_file<RomP: AsRef<Path>>(
+        file_path: RomP,
+    ) -> Result<(Box<Path>, Vec<u8>), CartridgeError> {
         let extension = file_path
             .as_ref()
             .extension()
             .ok_or(CartridgeError::ExtensionError)?;
 
-        if extension != "gbc" && extension != "gb" {
+        let is_archive_extension =
+            cfg!(feature = "zip") && (extension == "zip" || extension == "gz");
+        if extension != "gbc" && extension != "gb" && !is_archive_extension {
             return Err(CartridgeError::ExtensionError);
         }
 
         let file_path = file_path.as_ref().to_path_buf().into_boxed_path();
-        let sram_file_path = if let Some(sram_file_path) = sram_file_path {
-            sram_file_path.as_ref().to_path_buf().into_boxed_path()
-        } else {
-            Self::get_save_file(&file_path).into_boxed_path()
-        };
 
         let mut file = File::open(&file_path)?;
 
         let mut data = Vec::new();
         file.read_to_end(&mut data)?;
 
+        #[cfg(feature = "zip")]
+        let data = archive::extract_rom(data)?;
+
+        Ok((file_path, data))
+    }
+
+    /// Reads the ROM data from `reader`, from its current position to the end.
+    pub fn read_rom<R: Read + Seek>(mut reader: R) -> std::io::Result<Vec<u8>> {
+        let start = reader.stream_position()?;
+        let end = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(start))?;
+
+        let mut data = Vec::with_capacity(end.saturating_sub(start) as usize);
+        reader.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Creates a cartridge from ROM data without a backing file, the SRAM is
+    /// loaded from and saved to `sram_file_path` if provided.
+    pub fn from_bytes_with_sram<SavP: AsRef<Path>>(
+        data: Vec<u8>,
+        sram_file_path: Option<SavP>,
+        save_on_shutdown: bool,
+        rtc_save_format: RtcSaveFormat,
+    ) -> Result<Self, CartridgeError> {
+        let sram_file_path =
+            sram_file_path.map(|path| path.as_ref().to_path_buf().into_boxed_path());
+
+        Self::from_data(
+            data,
+            None,
+            sram_file_path,
+            save_on_shutdown,
+            rtc_save_format,
+        )
+    }
+
+    /// Creates a cartridge from the ROM data in memory.
+    ///
+    /// Since there is no backing file, SRAM is not loaded and is not saved
+    /// on shutdown.
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, CartridgeError> {
+        Self::from_data(data, None, None, false, RtcSaveFormat::Native)
+    }
+
+    fn from_data(
+        data: Vec<u8>,
+        file_path: Option<Box<Path>>,
+        sram_file_path: Option<Box<Path>>,
+        save_on_shutdown: bool,
+        rtc_save_format: RtcSaveFormat,
+    ) -> Result<Self, CartridgeError> {
         let hash: [u8; 32] = Sha256::digest(&data).into();
 
-        if data.len() < 0x8000 || data.len() % 0x4000 != 0 {
-            eprintln!(
-                "[WARN]: the cartridge contain invalid rom size {:X}",
-                data.len()
-            );
+        // the header is needed to know the ROM size
+        if data.len() < 0x150 {
+            return Err(CartridgeError::InvalidRomSize {
+                expected: 0x8000,
+                actual: data.len(),
+            });
         }
 
-        // extend the data, as some roms don't follow the rules :(
-        if data.len() < 0x8000 {
-            data.extend_from_slice(&vec![0; 0x8000 - data.len()]);
-        }
-        if data.len() % 0x4000 != 0 {
-            data.extend_from_slice(&vec![0; 0x4000 - (data.len() % 0x4000)]);
-        }
+        let header_checksum = data[0x134..=0x14c]
+            .iter()
+            .fold(0u8, |acc, &b| acc.wrapping_sub(b).wrapping_sub(1));
+        let header_checksum_valid = header_checksum == data[0x14d];
+        // the sum of all the bytes of the ROM as supplied, except the
+        // checksum itself
+        let global_checksum = data
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != 0x14e && *i != 0x14f)
+            .fold(0u16, |acc, (_, &b)| acc.wrapping_add(b as u16));
+        let global_checksum_valid =
+            global_checksum == u16::from_be_bytes([data[0x14e], data[0x14f]]);
 
         if &data[0x104..=0x133] != NINTENDO_LOGO_DATA {
             return Err(CartridgeError::InvalidNintendoLogo);
@@ -270,11 +638,7 @@ impl Cartridge {
 
         println!("target gameboy {:?}", target_device);
 
-        let mut cartridge_type =
-            CartridgeType::from_byte(data[0x147]).ok_or(CartridgeError::InvalidCartridgeType)?;
-
-        // checks if its mbc1 multicart and update the mapper type
-        cartridge_type.update_mbc1_multicart(&data);
+        let cartridge_type = CartridgeType::detect(&data)?;
 
         let num_rom_banks = data[0x148];
         if num_rom_banks > 8 {
@@ -284,9 +648,10 @@ impl Cartridge {
         let mut rom_size = 0x8000 << num_rom_banks;
 
         if rom_size != data.len() {
-            // try to fix it, sometimes the rom will have `0` as the num_rom_banks
+            // try to fix it, sometimes the rom will have `0` as the num_rom_banks,
+            // but only if the file has a valid number of banks
             let mut fixed = false;
-            if rom_size < data.len() && data.len() % rom_size == 0 {
+            if rom_size < data.len() && data.len().is_multiple_of(rom_size) {
                 let div = data.len() / rom_size;
                 if div.is_power_of_two() && div.ilog2() < 8 {
                     println!(
@@ -300,7 +665,10 @@ impl Cartridge {
             }
 
             if !fixed {
-                return Err(CartridgeError::InvalidRomSize(rom_size));
+                return Err(CartridgeError::InvalidRomSize {
+                    expected: rom_size,
+                    actual: data.len(),
+                });
             }
         }
 
@@ -325,18 +693,26 @@ impl Cartridge {
 
         let mut ram = vec![0; ram_size];
 
-        let mut checksum = 0u8;
-        for &i in data[0x134..=0x14c].iter() {
-            checksum = checksum.wrapping_sub(i).wrapping_sub(1);
-        }
-
-        if checksum != data[0x14d] {
+        if !header_checksum_valid {
             return Err(CartridgeError::InvalidChecksum {
-                got: checksum,
+                got: header_checksum,
                 expected: data[0x14d],
             });
         }
 
+        let header = CartridgeHeader {
+            title: game_title,
+            manufacturer_code: data[0x13f..=0x142].try_into().unwrap(),
+            cgb_flag: data[0x143],
+            sgb_supported: data[0x146] == 0x03,
+            mapper_type: cartridge_type.mapper_type,
+            rom_size,
+            ram_size,
+            destination_code: data[0x14a],
+            header_checksum_valid,
+            global_checksum_valid,
+        };
+
         println!("LOG: {:?}", cartridge_type);
 
         let mut mapper =
@@ -347,9 +723,10 @@ impl Cartridge {
                 ))?;
 
         mapper.init((rom_size / 0x4000) as u16, ram_size);
+        mapper.set_rtc_save_format(rtc_save_format);
 
-        if cartridge_type.battery {
-            match Self::load_sram_file(&sram_file_path, ram_size, mapper.save_battery_size()) {
+        if let (true, Some(sram_file_path)) = (cartridge_type.battery, &sram_file_path) {
+            match Self::load_sram_file(sram_file_path, ram_size, mapper.save_battery_size()) {
                 Ok((saved_ram, extra)) => {
                     ram = saved_ram;
                     mapper.load_battery(&extra);
@@ -362,38 +739,63 @@ impl Cartridge {
             file_path,
             sram_file_path,
             save_on_shutdown,
-            game_title,
+            header,
             cartridge_type,
             target_device,
             mapper,
             hash,
+            ignore_state_hash: false,
             rom: data,
             ram,
+            sram_dirty: Cell::new(false),
+            sram_dirty_callback: None,
         })
     }
+}
 
-    /// 0x0000-0x3FFF
-    pub fn read_rom0(&self, addr: u16) -> u8 {
+impl CartridgeInterface for Cartridge {
+    fn read_rom0(&self, addr: u16) -> u8 {
         let addr = self.mapper.map_read_rom0(addr);
 
         self.rom[addr]
     }
 
-    // TODO: implement mapper
-    /// 0x4000-0x7FFF
-    pub fn read_romx(&self, addr: u16) -> u8 {
+    fn read_romx(&self, addr: u16) -> u8 {
         let addr = self.mapper.map_read_romx(addr);
 
         self.rom[addr]
     }
 
-    /// 0x0000-0x7FFF
-    pub fn write_to_bank_controller(&mut self, addr: u16, data: u8) {
+    fn write_to_bank_controller(&mut self, addr: u16, data: u8) {
         self.mapper.write_bank_controller_register(addr, data);
     }
 
-    /// 0xA000-0xBFFF
-    pub fn read_ram(&mut self, addr: u16) -> u8 {
+    fn write_rom(&mut self, addr: u16, data: u8) {
+        if self.cartridge_type.mapper_type != MapperType::NoMapper {
+            self.write_to_bank_controller(addr, data);
+            return;
+        }
+
+        let addr = if addr < 0x4000 {
+            self.mapper.map_read_rom0(addr)
+        } else {
+            self.mapper.map_read_romx(addr)
+        };
+        self.rom[addr] = data;
+    }
+
+    fn rom_banks(&self) -> (u16, u16) {
+        let rom0 = self.mapper.map_read_rom0(0x0000) / 0x4000;
+        let romx = self.mapper.map_read_romx(0x4000) / 0x4000;
+
+        (rom0 as u16, romx as u16)
+    }
+
+    fn ram_bank(&self) -> u8 {
+        self.mapper.ram_bank()
+    }
+
+    fn read_ram(&mut self, addr: u16) -> u8 {
         match self.mapper.map_ram_read(addr) {
             MappingResult::Addr(addr) => self.ram[addr],
             MappingResult::Value(value) => value,
@@ -401,41 +803,156 @@ impl Cartridge {
         }
     }
 
-    /// 0xA000-0xBFFF
-    pub fn write_ram(&mut self, addr: u16, data: u8) {
+    fn write_ram(&mut self, addr: u16, data: u8) {
         match self.mapper.map_ram_write(addr, data) {
-            MappingResult::Addr(addr) => self.ram[addr] = data,
+            MappingResult::Addr(addr) => {
+                self.ram[addr] = data;
+                self.mark_sram_dirty();
+            }
             MappingResult::NotMapped | MappingResult::Value(_) => {}
         }
     }
 
-    /// A way to sync bus/emulator to the mapper, main purpose is to sync
-    /// MBC3's RTC clock, the number of clocks for one second is 4194304 / 2
-    ///
-    /// The bus should clock this in 4194304 / 2 clocks per second regardless
-    /// of the CPU clock speed (double or normal)
-    pub fn clock_mapper(&mut self) {
-        self.mapper.clock();
+    fn clock_mapper(&mut self) {
+        self.mapper.clock(&mut self.ram);
     }
 
-    pub fn is_cartridge_color(&self) -> bool {
+    fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    fn hash(&self) -> &[u8; 32] {
+        &self.hash
+    }
+
+    fn is_cartridge_color(&self) -> bool {
         self.target_device == TargetDevice::Color
     }
 
-    pub fn game_title(&self) -> &str {
-        &self.game_title
+    fn file_path(&self) -> Option<&Path> {
+        self.file_path.as_deref()
     }
 
-    pub fn hash(&self) -> &[u8; 32] {
-        &self.hash
+    fn has_battery(&self) -> bool {
+        self.cartridge_type.battery
     }
 
-    pub fn file_path(&self) -> &Path {
-        &self.file_path
+    fn sram_size(&self) -> usize {
+        if self.cartridge_type.battery {
+            self.ram.len() + self.mapper.save_battery_size()
+        } else {
+            0
+        }
+    }
+
+    /// The SRAM data is the RAM of the cartridge followed by the extra
+    /// battery data of the mapper (i.e. the MBC3 RTC), and is empty if the
+    /// cartridge has no battery.
+    fn load_sram(&mut self, reader: &mut dyn Read) -> Result<(), CartridgeError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        let expected = self.sram_size();
+        if data.len() != expected {
+            return Err(CartridgeError::SramSizeMismatch {
+                expected,
+                got: data.len(),
+            });
+        }
+
+        if self.cartridge_type.battery {
+            let (ram, extra) = data.split_at(self.ram.len());
+            self.ram.copy_from_slice(ram);
+            self.mapper.load_battery(extra);
+            self.sram_dirty.set(false);
+        }
+
+        Ok(())
+    }
+
+    fn save_sram(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        if self.cartridge_type.battery {
+            writer.write_all(&self.ram)?;
+            writer.write_all(&self.mapper.save_battery())?;
+            self.sram_dirty.set(false);
+        }
+        Ok(())
+    }
+
+    fn sram_dirty(&self) -> bool {
+        self.sram_dirty.get()
+    }
+
+    fn set_sram_dirty(&mut self, dirty: bool) {
+        self.sram_dirty.set(dirty && self.cartridge_type.battery);
+    }
+
+    fn flush_sram(&mut self) -> Result<(), SramError> {
+        // saved explicitly, don't save again on drop
+        self.save_on_shutdown = false;
+        if self.cartridge_type.battery {
+            self.save_sram_file()?;
+            self.sram_dirty.set(false);
+        }
+        Ok(())
+    }
+
+    fn set_sram_dirty_callback(&mut self, callback: Option<SramDirtyCallback>) {
+        self.sram_dirty_callback = callback;
+    }
+
+    fn rtc(&self) -> Option<RtcTime> {
+        self.mapper.rtc()
+    }
+
+    fn set_rtc(&mut self, time: RtcTime) {
+        self.mapper.set_rtc(time);
+    }
+
+    fn set_rtc_frozen(&mut self, frozen: bool) {
+        self.mapper.set_rtc_frozen(frozen);
+    }
+
+    fn set_rtc_save_format(&mut self, format: RtcSaveFormat) {
+        self.mapper.set_rtc_save_format(format);
+    }
+
+    fn set_rumble_callback(&mut self, callback: Option<RumbleCallback>) {
+        self.mapper.set_rumble_callback(callback);
+    }
+
+    fn set_accelerometer(&mut self, x: f32, y: f32) {
+        self.mapper.set_accelerometer(x, y);
+    }
+
+    fn set_camera_frame(&mut self, frame: &[u8]) {
+        self.mapper.set_camera_frame(frame);
+    }
+
+    fn set_ignore_state_hash(&mut self, ignore: bool) {
+        self.ignore_state_hash = ignore;
+    }
+
+    fn save_state(&self, mut writer: &mut dyn Write) -> save_state::Result<()> {
+        Savable::save(self, &mut writer)
+    }
+
+    fn load_state(&mut self, mut reader: &mut dyn Read) -> save_state::Result<()> {
+        Savable::load(self, &mut reader)
     }
 }
 
 impl Cartridge {
+    /// Marks the battery backed data as dirty, and calls the callback if it
+    /// was not dirty before
+    fn mark_sram_dirty(&mut self) {
+        if self.cartridge_type.battery && !self.sram_dirty.replace(true) {
+            if let Some(callback) = self.sram_dirty_callback.as_mut() {
+                callback();
+            }
+        }
+    }
+
     fn get_save_file<P: AsRef<Path>>(path: P) -> PathBuf {
         let extension = path.as_ref().extension().unwrap().to_str().unwrap();
         path.as_ref().with_extension(format!("{}.sav", extension))
@@ -467,8 +984,9 @@ impl Cartridge {
     }
 
     fn save_sram_file(&self) -> Result<(), SramError> {
-        //let path = Self::get_save_file(&self.file_path);
-        let sram_file_path = &self.sram_file_path;
+        let Some(sram_file_path) = &self.sram_file_path else {
+            return Ok(());
+        };
         println!("Writing SRAM file data to {:?}", sram_file_path);
 
         let mut file = File::create(sram_file_path)?;
@@ -507,6 +1025,22 @@ impl Drop for Cartridge {
     }
 }
 
+impl Cartridge {
+    /// Migrates the saved state of the cartridge to the next version, see
+    /// [`StateMigration`]
+    pub(crate) fn migrate_state(migration: &mut StateMigration) -> save_state::Result<()> {
+        migration.copy([0u8; 32])?;
+        let cartridge_type = migration.copy(CartridgeType::from_byte(0).unwrap())?;
+        let mapper = cartridge_type
+            .get_mapper()
+            .ok_or(save_state::Error::CorruptField("Cartridge::cartridge_type"))?;
+        mapper.migrate_state(migration)?;
+        migration.copy(Vec::<u8>::new())?;
+
+        Ok(())
+    }
+}
+
 impl Savable for Cartridge {
     fn save<W: Write>(&self, mut writer: &mut W) -> save_state::Result<()> {
         self.hash.save(&mut writer)?;
@@ -522,19 +1056,684 @@ impl Savable for Cartridge {
         // this check should be done at the beginning, here is another check
         let mut hash = [0u8; 32];
         hash.load(&mut reader)?;
-        assert_eq!(hash, self.hash);
+        if !self.ignore_state_hash && hash != self.hash {
+            return Err(save_state::Error::CorruptField("Cartridge::hash"));
+        }
 
         // make a copy here, so we can fill it without changing the original one
         let mut cartridge_type = self.cartridge_type;
         cartridge_type.load(&mut reader)?;
-        assert_eq!(self.cartridge_type, cartridge_type);
+        if self.cartridge_type != cartridge_type {
+            return Err(save_state::Error::CorruptField("Cartridge::cartridge_type"));
+        }
 
+        let battery_before = self.mapper.save_battery();
         let size = self.mapper.save_state_size()? as usize;
         let mut data = vec![0; size];
         reader.read_exact(&mut data)?;
         self.mapper.load_state(&data)?;
-        self.ram.load(&mut reader)?;
+        let mut ram = Vec::new();
+        ram.load(&mut reader)?;
+
+        // the loaded data may not be the one saved in the SRAM, restoring
+        // the same data (i.e. rewinding) does not make it dirty
+        let changed = ram != self.ram || self.mapper.save_battery() != battery_before;
+        self.ram = ram;
+        if changed {
+            self.mark_sram_dirty();
+        }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{CartridgeError, CartridgeInterface};
+    use crate::tests::{build_test_rom, fix_header_checksum, test_gameboy};
+    use crate::{GameBoyConfig, RtcSaveFormat};
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn detect_mapper() {
+        use crate::MapperType;
+
+        let rom = build_test_rom(0x1B, 2, &[0x18, 0xFE]);
+        let mapper_type = crate::GameBoy::detect_mapper(&rom).unwrap();
+        assert_eq!(mapper_type, MapperType::Mbc5 { rumble: false });
+        assert!(mapper_type.is_implemented());
+
+        let gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+        assert_eq!(gb.mapper_type(), mapper_type);
+
+        // detected even if it can't be loaded
+        let rom = build_test_rom(0x20, 2, &[0x18, 0xFE]);
+        let mapper_type = crate::GameBoy::detect_mapper(&rom).unwrap();
+        assert_eq!(mapper_type, MapperType::Mbc6);
+        assert!(!mapper_type.is_implemented());
+        assert!(matches!(
+            crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()),
+            Err(crate::CartridgeError::MapperNotImplemented(
+                MapperType::Mbc6
+            ))
+        ));
+
+        assert!(matches!(
+            crate::GameBoy::detect_mapper(&[0; 0x100]),
+            Err(crate::CartridgeError::InvalidRomSize { .. })
+        ));
+    }
+
+    #[test]
+    fn current_rom_and_ram_bank() {
+        // MBC5 with 4 ROM banks and 4 RAM banks
+        let mut rom = build_test_rom(0x1B, 3, &[0x18, 0xFE]);
+        rom[0x148] = 1;
+        fix_header_checksum(&mut rom);
+        rom.resize(0x10000, 0);
+        let mut gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+        assert_eq!(gb.current_rom_bank(), 1);
+        assert_eq!(gb.current_ram_bank(), 0);
+
+        gb.bus.cartridge_mut().write_to_bank_controller(0x2000, 3);
+        gb.bus.cartridge_mut().write_to_bank_controller(0x4000, 2);
+        assert_eq!(gb.current_rom_bank(), 3);
+        assert_eq!(gb.current_ram_bank(), 2);
+
+        // wraps around the number of banks
+        gb.bus.cartridge_mut().write_to_bank_controller(0x4000, 6);
+        assert_eq!(gb.current_ram_bank(), 2);
+
+        let gb = test_gameboy(&[0x18, 0xFE]);
+        assert_eq!(gb.current_rom_bank(), 1);
+        assert_eq!(gb.current_ram_bank(), 0);
+    }
+
+    #[test]
+    fn cartridge_hash() {
+        let rom = build_test_rom(0, 0, &[0x18, 0xFE]);
+        let hash: [u8; 32] = Sha256::digest(&rom).into();
+
+        let gb = crate::GameBoy::from_rom_bytes(rom.clone(), GameBoyConfig::default()).unwrap();
+        assert_eq!(gb.cartridge_hash(), hash);
+        let other = crate::GameBoy::from_rom_bytes(rom.clone(), GameBoyConfig::default()).unwrap();
+        assert_eq!(other.cartridge_hash(), hash);
+
+        // the whole ROM is hashed, not only the header
+        let mut modified = rom;
+        modified[0x7FFF] ^= 0xFF;
+        let other = crate::GameBoy::from_rom_bytes(modified, GameBoyConfig::default()).unwrap();
+        assert_eq!(other.cartridge_header(), gb.cartridge_header());
+        assert_ne!(other.cartridge_hash(), hash);
+    }
+
+    #[test]
+    fn cartridge_header() {
+        // MBC3+TIMER+RAM+BATTERY, 32KB RAM
+        let mut rom = build_test_rom(0x10, 3, &[0x18, 0xFE]);
+        // CGB only, with SGB support
+        rom[0x143] = 0xC0;
+        rom[0x146] = 0x03;
+        rom[0x14a] = 0x01;
+        rom[0x13f..=0x142].copy_from_slice(b"ABCD");
+        fix_header_checksum(&mut rom);
+
+        let gb = crate::GameBoy::from_rom_bytes(rom.clone(), GameBoyConfig::default()).unwrap();
+        let header = gb.cartridge_header();
+        assert_eq!(header.title, "TEST");
+        assert_eq!(&header.manufacturer_code, b"ABCD");
+        assert!(header.supports_cgb());
+        assert!(header.cgb_only());
+        assert!(header.sgb_supported);
+        assert_eq!(header.mapper_type, crate::MapperType::Mbc3 { timer: true });
+        assert_eq!(header.rom_size, 0x8000);
+        assert_eq!(header.ram_size, 0x8000);
+        assert_eq!(header.destination_code, 0x01);
+        assert!(header.header_checksum_valid);
+        assert!(!header.global_checksum_valid);
+
+        // fix the global checksum
+        let global_checksum = rom
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != 0x14e && *i != 0x14f)
+            .fold(0u16, |acc, (_, &b)| acc.wrapping_add(b as u16));
+        rom[0x14e..=0x14f].copy_from_slice(&global_checksum.to_be_bytes());
+        let gb = crate::GameBoy::from_rom_bytes(rom.clone(), GameBoyConfig::default()).unwrap();
+        assert!(gb.cartridge_header().global_checksum_valid);
+
+        // the checksum covers all the supplied data, even if the header
+        // indicates a smaller ROM
+        rom.resize(0x10000, 0);
+        rom[0x8000] = 1;
+        let gb = crate::GameBoy::from_rom_bytes(rom.clone(), GameBoyConfig::default()).unwrap();
+        assert_eq!(gb.cartridge_header().rom_size, 0x10000);
+        assert!(!gb.cartridge_header().global_checksum_valid);
+        rom[0x14f] = rom[0x14f].wrapping_add(1);
+        let gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+        assert!(gb.cartridge_header().global_checksum_valid);
+
+        // DMG only
+        let gb = test_gameboy(&[0x18, 0xFE]);
+        assert!(!gb.cartridge_header().supports_cgb());
+        assert!(!gb.cartridge_header().cgb_only());
+    }
+
+    #[test]
+    fn invalid_rom_and_ram_sizes() {
+        let load = |rom: Vec<u8>| crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default());
+        let with_header = |rom_size_index: u8, ram_size_index: u8, len: usize| {
+            // MBC5+RAM
+            let mut rom = build_test_rom(0x1A, ram_size_index, &[0x18, 0xFE]);
+            rom[0x148] = rom_size_index;
+            fix_header_checksum(&mut rom);
+            rom.resize(len, 0);
+            rom
+        };
+
+        // truncated
+        assert!(matches!(
+            load(with_header(2, 2, 0x10000)),
+            Err(CartridgeError::InvalidRomSize {
+                expected: 0x20000,
+                actual: 0x10000
+            })
+        ));
+        assert!(matches!(
+            load(with_header(0, 2, 0x7000)),
+            Err(CartridgeError::InvalidRomSize {
+                expected: 0x8000,
+                actual: 0x7000
+            })
+        ));
+        assert!(matches!(
+            load(with_header(0, 2, 0x100)),
+            Err(CartridgeError::InvalidRomSize {
+                expected: 0x8000,
+                actual: 0x100
+            })
+        ));
+        // not a power of two number of banks
+        assert!(matches!(
+            load(with_header(0, 2, 0x18000)),
+            Err(CartridgeError::InvalidRomSize {
+                expected: 0x8000,
+                actual: 0x18000
+            })
+        ));
+        assert!(matches!(
+            load(with_header(9, 2, 0x8000)),
+            Err(CartridgeError::InvalidRomSizeIndex(9))
+        ));
+        assert!(matches!(
+            load(with_header(0, 6, 0x8000)),
+            Err(CartridgeError::InvalidRamSizeIndex(6))
+        ));
+
+        // a larger power of two file with a wrong header is still accepted
+        let gb = load(with_header(0, 2, 0x20000)).unwrap();
+        assert_eq!(gb.cartridge_header().rom_size, 0x20000);
+        load(with_header(2, 2, 0x20000)).unwrap();
+    }
+
+    #[test]
+    fn mbc3_rtc() {
+        // MBC3+TIMER+BATTERY
+        let rom = build_test_rom(0x0F, 0, &[0x18, 0xFE]);
+        let mut gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+
+        let time = crate::RtcTime {
+            seconds: 59,
+            minutes: 59,
+            hours: 23,
+            days: 10,
+            halt: false,
+            day_counter_carry: false,
+        };
+        gb.set_rtc(time);
+        assert_eq!(gb.rtc(), Some(time));
+
+        // a bit more than one second
+        for _ in 0..60 {
+            gb.clock_for_frame();
+        }
+        assert_eq!(
+            gb.rtc(),
+            Some(crate::RtcTime {
+                seconds: 0,
+                minutes: 0,
+                hours: 0,
+                days: 11,
+                ..time
+            })
+        );
+
+        gb.set_rtc(time);
+        gb.set_rtc_frozen(true);
+        for _ in 0..60 {
+            gb.clock_for_frame();
+        }
+        assert_eq!(gb.rtc(), Some(time));
+
+        // no RTC in MBC1
+        let rom = build_test_rom(0x01, 0, &[0x18, 0xFE]);
+        let mut gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+        gb.set_rtc(time);
+        assert_eq!(gb.rtc(), None);
+    }
+
+    #[test]
+    fn mbc5_rumble_callback() {
+        // MBC5+RUMBLE+RAM, 32KB RAM
+        let rom = build_test_rom(0x1D, 3, &[0x18, 0xFE]);
+        let mut gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+
+        let states = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let states_clone = states.clone();
+        gb.set_rumble_callback(Box::new(move |on| states_clone.borrow_mut().push(on)));
+
+        let cartridge = gb.bus.cartridge_mut();
+        cartridge.write_to_bank_controller(0x0000, 0x0A);
+        // RAM bank 1, rumble on
+        cartridge.write_to_bank_controller(0x4000, 0x09);
+        cartridge.write_ram(0xA000, 0x55);
+        // still on, no change
+        cartridge.write_to_bank_controller(0x4000, 0x08);
+        assert_eq!(cartridge.read_ram(0xA000), 0);
+        // RAM bank 1, rumble off, the rumble bit is not part of the RAM bank
+        cartridge.write_to_bank_controller(0x4000, 0x01);
+        assert_eq!(cartridge.read_ram(0xA000), 0x55);
+
+        assert_eq!(*states.borrow(), vec![true, false]);
+    }
+
+    #[test]
+    fn writable_rom() {
+        use crate::cpu::CpuBusProvider;
+
+        let config = GameBoyConfig {
+            writable_rom: true,
+            ..GameBoyConfig::default()
+        };
+
+        let rom = build_test_rom(0, 0, &[0x18, 0xFE]);
+        let mut gb = crate::GameBoy::from_rom_bytes(rom.clone(), config).unwrap();
+        gb.bus.write(0x0150, 0x12);
+        gb.bus.write(0x7FFF, 0x34);
+        assert_eq!(gb.bus.read_not_ticked(0x0150, None), 0x12);
+        assert_eq!(gb.bus.read_not_ticked(0x7FFF, None), 0x34);
+
+        // off by default
+        let mut gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+        gb.bus.write(0x7FFF, 0x12);
+        assert_eq!(gb.bus.read_not_ticked(0x7FFF, None), 0x00);
+
+        // writes still go to the mapper for cartridges with mappers
+        let mut rom = build_test_rom(0x19, 0, &[0x18, 0xFE]);
+        rom[0x3000] = 0x56;
+        let mut gb = crate::GameBoy::from_rom_bytes(rom, config).unwrap();
+        // MBC5 can map bank 0 to `0x4000-0x7FFF`
+        gb.bus.write(0x2000, 0);
+        assert_eq!(gb.bus.read_not_ticked(0x7000, None), 0x56);
+        assert_eq!(gb.bus.read_not_ticked(0x2000, None), 0x00);
+    }
+
+    #[test]
+    fn shutdown_flushes_sram() {
+        use crate::cpu::CpuBusProvider;
+        use std::io::Cursor;
+
+        let rom = build_test_rom(0x03, 2, &[0x18, 0xFE]);
+        let path = std::env::temp_dir().join("mizu-test-shutdown.sav");
+        let _ = std::fs::remove_file(&path);
+
+        let mut gb = crate::GameBoy::builder_from_reader(Cursor::new(rom.clone()))
+            .sram_file(&path)
+            .save_on_shutdown(false)
+            .build()
+            .unwrap();
+        gb.bus.write(0x0000, 0x0A);
+        gb.bus.write(0xA000, 0x42);
+        assert!(gb.sram_is_dirty());
+        gb.shutdown().unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(data.len(), 0x2000);
+        assert_eq!(data[0], 0x42);
+        std::fs::remove_file(&path).unwrap();
+
+        // errors are returned instead of printed
+        let path = std::env::temp_dir()
+            .join("mizu-test-missing-dir")
+            .join("shutdown.sav");
+        let gb = crate::GameBoy::builder_from_reader(Cursor::new(rom))
+            .sram_file(&path)
+            .build()
+            .unwrap();
+        assert!(matches!(gb.shutdown(), Err(crate::SaveError::SramError(_))));
+    }
+
+    #[test]
+    fn in_memory_sram() {
+        use crate::cpu::CpuBusProvider;
+
+        let dir = std::env::temp_dir().join("mizu-test-in-memory-sram");
+        std::fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("rom.gb");
+        let sav_path = dir.join("rom.gb.sav");
+        let _ = std::fs::remove_file(&sav_path);
+        std::fs::write(&rom_path, build_test_rom(0x03, 2, &[0x18, 0xFE])).unwrap();
+
+        let mut gb = crate::GameBoy::builder(&rom_path)
+            .save_on_shutdown(true)
+            .in_memory_sram(true)
+            .build()
+            .unwrap();
+        assert!(gb.has_battery());
+        assert_eq!(gb.file_path(), Some(rom_path.as_path()));
+        gb.bus.write(0x0000, 0x0A);
+        gb.bus.write(0xA000, 0x42);
+
+        let mut saved = Vec::new();
+        gb.save_sram(&mut saved).unwrap();
+        assert_eq!(saved[0], 0x42);
+        gb.shutdown().unwrap();
+        assert!(!sav_path.exists());
+
+        // an existing file is not loaded either
+        std::fs::write(&sav_path, vec![0x11; 0x2000]).unwrap();
+        let mut gb = crate::GameBoy::builder(&rom_path)
+            .sram_file(&sav_path)
+            .in_memory_sram(true)
+            .build()
+            .unwrap();
+        gb.load_sram(saved.as_slice()).unwrap();
+        gb.bus.write(0x0000, 0x0A);
+        assert_eq!(gb.bus.read_not_ticked(0xA000, None), 0x42);
+        drop(gb);
+        assert_eq!(std::fs::read(&sav_path).unwrap(), vec![0x11; 0x2000]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rtc_compatible_sav_format() {
+        use crate::RtcTime;
+
+        let config = GameBoyConfig {
+            rtc_save_format: RtcSaveFormat::Compatible,
+            ..GameBoyConfig::default()
+        };
+        let rom = build_test_rom(0x10, 2, &[0x18, 0xFE]);
+        let mut gb = crate::GameBoy::from_rom_bytes(rom.clone(), config).unwrap();
+
+        // a save written by VBA-M/BGB two hours ago, at day 0x105 10:20:30
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let mut sav = vec![0x55; 0x2000];
+        for value in [30u32, 20, 10, 0x05, 0x01, 0, 0, 0, 0, 0] {
+            sav.extend_from_slice(&value.to_le_bytes());
+        }
+        sav.extend_from_slice(&(now - 2 * 60 * 60).to_le_bytes());
+        gb.load_sram(sav.as_slice()).unwrap();
+
+        let time = gb.rtc().unwrap();
+        assert_eq!((time.days, time.hours, time.minutes), (0x105, 12, 20));
+        assert!((30..=31).contains(&time.seconds));
+
+        let mut saved = Vec::new();
+        gb.save_sram(&mut saved).unwrap();
+        assert_eq!(saved.len(), 0x2000 + 48);
+        assert_eq!(saved[..0x2000], sav[..0x2000]);
+        assert_eq!(
+            saved[0x2000 + 8..0x2000 + 20],
+            [12, 0, 0, 0, 5, 0, 0, 0, 1, 0, 0, 0]
+        );
+
+        // halted clocks do not advance
+        gb.set_rtc(RtcTime {
+            hours: 3,
+            halt: true,
+            ..RtcTime::default()
+        });
+        let mut saved = Vec::new();
+        gb.save_sram(&mut saved).unwrap();
+        saved[0x2000 + 40..].copy_from_slice(&(now - 1000).to_le_bytes());
+        gb.load_sram(saved.as_slice()).unwrap();
+        assert_eq!(gb.rtc().unwrap().hours, 3);
+        assert_eq!(gb.rtc().unwrap().minutes, 0);
+
+        // the native format has a different size
+        let mut gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+        assert!(matches!(
+            gb.load_sram(sav.as_slice()),
+            Err(crate::CartridgeError::SramSizeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn load_and_save_sram() {
+        // MBC1+RAM+BATTERY, 8KB RAM
+        let rom = build_test_rom(0x03, 2, &[0x18, 0xFE]);
+        let mut gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+
+        let sram = (0..0x2000).map(|i| i as u8).collect::<Vec<_>>();
+        gb.load_sram(sram.as_slice()).unwrap();
+
+        // enable RAM and read it from the CPU side
+        gb.bus
+            .cartridge_mut()
+            .write_to_bank_controller(0x0000, 0x0A);
+        assert_eq!(gb.bus.read_not_ticked(0xA123, None), 0x23);
+
+        let mut saved = Vec::new();
+        gb.save_sram(&mut saved).unwrap();
+        assert_eq!(saved, sram);
+
+        // wrong size is rejected and the RAM is not changed
+        assert!(matches!(
+            gb.load_sram(&[0u8; 0x1000][..]),
+            Err(CartridgeError::SramSizeMismatch {
+                expected: 0x2000,
+                got: 0x1000
+            })
+        ));
+        assert_eq!(gb.bus.read_not_ticked(0xA123, None), 0x23);
+    }
+
+    #[test]
+    fn sram_size_and_battery() {
+        // MBC1+RAM+BATTERY, 8KB RAM
+        let rom = build_test_rom(0x03, 2, &[0x18, 0xFE]);
+        let gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+        assert!(gb.has_battery());
+        assert_eq!(gb.sram_size(), 0x2000);
+
+        // MBC3+TIMER+RAM+BATTERY, 8KB RAM, the RTC data is included
+        let rom = build_test_rom(0x10, 2, &[0x18, 0xFE]);
+        let gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+        assert!(gb.has_battery());
+        let mut saved = Vec::new();
+        gb.save_sram(&mut saved).unwrap();
+        assert_eq!(gb.sram_size(), saved.len());
+        assert!(gb.sram_size() > 0x2000);
+
+        // MBC1+RAM, no battery, the RAM is not saved
+        let rom = build_test_rom(0x02, 2, &[0x18, 0xFE]);
+        let mut gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+        assert!(!gb.has_battery());
+        assert_eq!(gb.sram_size(), 0);
+        let mut saved = Vec::new();
+        gb.save_sram(&mut saved).unwrap();
+        assert_eq!(saved.len(), gb.sram_size());
+        gb.load_sram(saved.as_slice()).unwrap();
+        assert!(matches!(
+            gb.load_sram([0; 0x2000].as_slice()),
+            Err(CartridgeError::SramSizeMismatch {
+                expected: 0,
+                got: 0x2000
+            })
+        ));
+
+        // ROM only
+        let rom = build_test_rom(0x00, 0, &[0x18, 0xFE]);
+        let gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+        assert!(!gb.has_battery());
+        assert_eq!(gb.sram_size(), 0);
+    }
+
+    #[test]
+    fn sram_dirty_tracking() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        // MBC1+RAM+BATTERY, 8KB RAM
+        let rom = build_test_rom(0x03, 2, &[0x18, 0xFE]);
+        let mut gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+        let calls = Rc::new(Cell::new(0));
+        let calls_clone = calls.clone();
+        gb.set_sram_dirty_callback(Box::new(move || calls_clone.set(calls_clone.get() + 1)));
+        assert!(!gb.sram_is_dirty());
+
+        // RAM is disabled, so nothing is written
+        gb.bus.cartridge_mut().write_ram(0xA000, 0x12);
+        assert!(!gb.sram_is_dirty());
+
+        gb.bus
+            .cartridge_mut()
+            .write_to_bank_controller(0x0000, 0x0A);
+        gb.bus.cartridge_mut().write_ram(0xA000, 0x12);
+        gb.bus.cartridge_mut().write_ram(0xA001, 0x34);
+        assert!(gb.sram_is_dirty());
+        assert_eq!(calls.get(), 1);
+
+        gb.save_sram(std::io::sink()).unwrap();
+        assert!(!gb.sram_is_dirty());
+        gb.bus.cartridge_mut().write_ram(0xA002, 0x56);
+        assert!(gb.sram_is_dirty());
+        assert_eq!(calls.get(), 2);
+
+        // loading a state with the same SRAM data does not change it
+        let mut state = Vec::new();
+        gb.save_state(&mut state).unwrap();
+        let snapshot = gb.snapshot();
+        gb.save_sram(std::io::sink()).unwrap();
+        gb.load_state(std::io::Cursor::new(&state)).unwrap();
+        gb.restore(&snapshot);
+        assert!(!gb.sram_is_dirty());
+        assert_eq!(calls.get(), 2);
+
+        // loading a state replaces the SRAM data
+        gb.bus.cartridge_mut().write_ram(0xA000, 0x78);
+        gb.save_sram(std::io::sink()).unwrap();
+        gb.load_state(std::io::Cursor::new(&state)).unwrap();
+        assert!(gb.sram_is_dirty());
+        assert_eq!(calls.get(), 4);
+
+        // no battery, not tracked
+        // MBC1+RAM, 8KB RAM
+        let rom = build_test_rom(0x02, 2, &[0x18, 0xFE]);
+        let mut gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+        gb.bus
+            .cartridge_mut()
+            .write_to_bank_controller(0x0000, 0x0A);
+        gb.bus.cartridge_mut().write_ram(0xA000, 0x12);
+        assert!(!gb.sram_is_dirty());
+    }
+
+    #[test]
+    fn custom_cartridge() {
+        use crate::{CartridgeHeader, MapperType};
+        use save_state::Savable;
+        use std::io::{Read, Write};
+
+        /// ROM only cartridge with a single byte register in the SRAM area
+        struct RegisterCartridge {
+            rom: Vec<u8>,
+            header: CartridgeHeader,
+            register: u8,
+        }
+
+        impl CartridgeInterface for RegisterCartridge {
+            fn read_rom0(&self, addr: u16) -> u8 {
+                self.rom[addr as usize]
+            }
+
+            fn read_romx(&self, addr: u16) -> u8 {
+                self.rom[addr as usize]
+            }
+
+            fn read_ram(&mut self, _addr: u16) -> u8 {
+                self.register
+            }
+
+            fn write_ram(&mut self, _addr: u16, data: u8) {
+                self.register = data;
+            }
+
+            fn header(&self) -> &CartridgeHeader {
+                &self.header
+            }
+
+            fn hash(&self) -> &[u8; 32] {
+                &[1; 32]
+            }
+
+            fn save_state(&self, mut writer: &mut dyn Write) -> save_state::Result<()> {
+                self.register.save(&mut writer)
+            }
+
+            fn load_state(&mut self, mut reader: &mut dyn Read) -> save_state::Result<()> {
+                self.register.load(&mut reader)
+            }
+        }
+
+        // LD A, (0xA000); INC A; LD (0xA000), A; JR loop
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x108].copy_from_slice(&[0xFA, 0x00, 0xA0, 0x3C, 0xEA, 0x00, 0xA0, 0x18]);
+        rom[0x108] = 0xF7;
+        let cartridge = RegisterCartridge {
+            rom,
+            header: CartridgeHeader {
+                title: String::from("CUSTOM"),
+                manufacturer_code: [0; 4],
+                cgb_flag: 0,
+                sgb_supported: false,
+                mapper_type: MapperType::NoMapper,
+                rom_size: 0x8000,
+                ram_size: 0,
+                destination_code: 0,
+                header_checksum_valid: true,
+                global_checksum_valid: true,
+            },
+            register: 0,
+        };
+
+        let mut gb = crate::GameBoy::builder_from_cartridge(Box::new(cartridge))
+            .build()
+            .unwrap();
+        assert_eq!(gb.game_title(), "CUSTOM");
+        assert_eq!(gb.file_path(), None);
+
+        for _ in 0..4 * 10 {
+            gb.cpu.next_instruction(&mut gb.bus);
+        }
+        assert_eq!(gb.bus.read_not_ticked(0xA000, None), 10);
+
+        let mut state = Vec::new();
+        gb.save_state(&mut state).unwrap();
+        for _ in 0..4 * 10 {
+            gb.cpu.next_instruction(&mut gb.bus);
+        }
+        assert_eq!(gb.bus.read_not_ticked(0xA000, None), 20);
+        gb.load_state(std::io::Cursor::new(&state)).unwrap();
+        assert_eq!(gb.bus.read_not_ticked(0xA000, None), 10);
+
+        // the cartridge does not have battery data by default
+        let mut sram = Vec::new();
+        gb.save_sram(&mut sram).unwrap();
+        assert!(sram.is_empty());
+    }
+}
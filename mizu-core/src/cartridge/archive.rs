@@ -0,0 +1,188 @@
+//! Extraction of ROMs stored inside `.zip` and `.gz` archives.
+
+use std::io::{Cursor, Read};
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+use super::CartridgeError;
+
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+/// The largest ROM size that can be indicated in the cartridge header,
+/// extraction stops after it so small archives cannot fill the memory
+const MAX_ROM_SIZE: usize = 0x8000 << 8;
+
+/// Extracts the ROM from `data` if it is an archive (detected by the magic bytes),
+/// otherwise `data` is returned as is.
+pub fn extract_rom(data: Vec<u8>) -> Result<Vec<u8>, CartridgeError> {
+    if data.starts_with(GZIP_MAGIC) {
+        read_rom(GzDecoder::new(data.as_slice()))
+    } else if data.starts_with(ZIP_MAGIC) {
+        unzip(&data)
+    } else {
+        Ok(data)
+    }
+}
+
+/// Reads the extracted ROM from `reader`, which checks the CRC of the
+/// data at the end of the stream
+fn read_rom<R: Read>(reader: R) -> Result<Vec<u8>, CartridgeError> {
+    let mut data = Vec::new();
+    reader
+        .take(MAX_ROM_SIZE as u64 + 1)
+        .read_to_end(&mut data)
+        .map_err(|_| CartridgeError::InvalidArchive)?;
+
+    if data.len() > MAX_ROM_SIZE {
+        return Err(CartridgeError::ArchiveRomTooLarge(MAX_ROM_SIZE));
+    }
+
+    Ok(data)
+}
+
+fn is_rom_name(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.ends_with(".gb") || name.ends_with(".gbc")
+}
+
+/// Extracts the only file in the zip archive, or the first ROM file if
+/// there are multiple files
+fn unzip(data: &[u8]) -> Result<Vec<u8>, CartridgeError> {
+    let mut archive =
+        ZipArchive::new(Cursor::new(data)).map_err(|_| CartridgeError::InvalidArchive)?;
+
+    let mut files = Vec::with_capacity(archive.len());
+    for i in 0..archive.len() {
+        let file = archive
+            .by_index_raw(i)
+            .map_err(|_| CartridgeError::InvalidArchive)?;
+        if !file.is_dir() {
+            files.push((i, is_rom_name(file.name())));
+        }
+    }
+
+    let index = match files.as_slice() {
+        [] => return Err(CartridgeError::InvalidArchive),
+        [(index, _)] => *index,
+        _ => files
+            .iter()
+            .find(|(_, is_rom)| *is_rom)
+            .map(|(index, _)| *index)
+            .ok_or(CartridgeError::AmbiguousArchive)?,
+    };
+
+    let file = archive
+        .by_index(index)
+        .map_err(|_| CartridgeError::InvalidArchive)?;
+    read_rom(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::build_test_rom;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use zip::write::FileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (i, (name, data)) in files.iter().enumerate() {
+            // test both the stored and deflate methods
+            let method = if i % 2 == 0 {
+                CompressionMethod::Deflated
+            } else {
+                CompressionMethod::Stored
+            };
+            if name.ends_with('/') {
+                writer.add_directory(*name, FileOptions::default()).unwrap();
+            } else {
+                writer
+                    .start_file(*name, FileOptions::default().compression_method(method))
+                    .unwrap();
+                writer.write_all(data).unwrap();
+            }
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn extract_gzip() {
+        let text = b"mizu mizu mizu gameboy".repeat(20);
+        let gz = gzip(&text);
+        assert_eq!(extract_rom(gz.clone()).unwrap(), text);
+
+        // the CRC is in the trailer before the size
+        let mut corrupted = gz;
+        let len = corrupted.len();
+        corrupted[len - 5] ^= 1;
+        assert!(matches!(
+            extract_rom(corrupted),
+            Err(CartridgeError::InvalidArchive)
+        ));
+    }
+
+    #[test]
+    fn extract_zip() {
+        // a single file is used regardless of its name
+        let archive = zip(&[("game.bin", b"rom data")]);
+        assert_eq!(extract_rom(archive).unwrap(), b"rom data");
+
+        let archive = zip(&[
+            ("readme.txt", b"text"),
+            ("dir/", b""),
+            ("dir/Game.GBC", b"rom data"),
+            ("other.gb", b"other rom"),
+        ]);
+        assert_eq!(extract_rom(archive).unwrap(), b"rom data");
+
+        let archive = zip(&[("readme.txt", b"text"), ("game.bin", b"rom data")]);
+        assert!(matches!(
+            extract_rom(archive),
+            Err(CartridgeError::AmbiguousArchive)
+        ));
+
+        // not an archive
+        assert_eq!(extract_rom(b"rom data".to_vec()).unwrap(), b"rom data");
+    }
+
+    #[test]
+    fn extract_larger_than_max_rom_size() {
+        let data = vec![0; MAX_ROM_SIZE + 1];
+        assert!(matches!(
+            extract_rom(gzip(&data)),
+            Err(CartridgeError::ArchiveRomTooLarge(MAX_ROM_SIZE))
+        ));
+        assert!(matches!(
+            extract_rom(zip(&[("game.gb", &data)])),
+            Err(CartridgeError::ArchiveRomTooLarge(MAX_ROM_SIZE))
+        ));
+
+        assert_eq!(extract_rom(gzip(&data[1..])).unwrap().len(), MAX_ROM_SIZE);
+    }
+
+    #[test]
+    fn load_rom_from_gzip_file() {
+        let rom = build_test_rom(0, 0, &[0x18, 0xFE]);
+
+        let path = std::env::temp_dir().join(format!("mizu-test-rom-{}.gb.gz", std::process::id()));
+        std::fs::write(&path, gzip(&rom)).unwrap();
+        let gb = crate::GameBoy::builder(&path)
+            .save_on_shutdown(false)
+            .build()
+            .unwrap();
+        assert_eq!(gb.file_path(), Some(path.as_path()));
+        assert_eq!(gb.game_title(), "TEST");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -12,6 +12,16 @@ pub enum CartridgeError {
     /// The ROM file does not have valid extension.
     #[error("The file ends with an invalid extension, should end with '.gb' or '.gbc'")]
     ExtensionError,
+    /// The ROM archive contains multiple files, and none of them is a ROM.
+    #[error("The archive contains multiple files, and none of them is a '.gb' or '.gbc' ROM")]
+    AmbiguousArchive,
+    /// The ROM archive is corrupted or uses an unsupported compression method.
+    #[error("The archive is corrupted or uses an unsupported compression method")]
+    InvalidArchive,
+    /// The ROM extracted from the archive is larger than the largest ROM size
+    /// that can be indicated in the cartridge header.
+    #[error("The extracted rom is larger than the maximum rom size {0} bytes")]
+    ArchiveRomTooLarge(usize),
     /// The rom file does not contain a valid Nintendo logo data at `0x104`.
     #[error("The rom file does not contain a valid Nintendo logo data at 0x104")]
     InvalidNintendoLogo,
@@ -27,9 +37,10 @@ pub enum CartridgeError {
     /// The rom file header contain invalid `ram_size` value.
     #[error("The ram size index {0} is invalid")]
     InvalidRamSizeIndex(u8),
-    /// The rom file size does not match the rom size provided in the rom header.
-    #[error("The file size does not match the rom size {0} bytes indicated inside the header")]
-    InvalidRomSize(usize),
+    /// The rom file size does not match the rom size provided in the rom header,
+    /// i.e. the file is truncated, or is not a power-of-two number of banks.
+    #[error("The rom file size {actual} bytes does not match the rom size {expected} bytes indicated inside the header")]
+    InvalidRomSize { expected: usize, actual: usize },
     /// The cartridge type suggest the cartridge has ram, but it is not present.
     #[error("The cartridge type suggest the cartridge has ram, but it is not present")]
     RamNotPresentError,
@@ -42,6 +53,30 @@ pub enum CartridgeError {
     /// The mapper type is not supported by the emulator.
     #[error("The mapper {0:?} is not yet implemented")]
     MapperNotImplemented(MapperType),
+    /// The boot rom size is not one of the sizes expected for the model,
+    /// `0x100` for DMG and `0x900` for CGB, see
+    /// [`GameBoyConfig::boot_rom_len`](crate::GameBoyConfig::boot_rom_len).
+    /// `expected` contains both when the model is detected from the boot rom.
+    #[error(
+        "The boot rom size {got:#X} does not match the expected {}",
+        .expected.iter().map(|len| format!("{:#X}", len)).collect::<Vec<_>>().join(" or ")
+    )]
+    InvalidBootRomSize { expected: Vec<usize>, got: usize },
+    /// The provided SRAM data size does not match the size of the cartridge
+    /// RAM and the extra battery data of the mapper, which is `0` for
+    /// cartridges without a battery.
+    #[error("The SRAM data size {got} does not match the expected {expected}")]
+    SramSizeMismatch { expected: usize, got: usize },
+    /// The cartridge header marks the game as CGB only, but the emulator is
+    /// configured to run as DMG, see
+    /// [`GameBoyConfig::auto_detect_mode`](crate::GameBoyConfig::auto_detect_mode).
+    #[error("The game requires a GameBoy Color, but the emulator is configured as DMG")]
+    RequiresCgb,
+    /// The boot rom is a DMG boot rom, but the game is CGB only and
+    /// [`GameBoyConfig::auto_detect_mode`](crate::GameBoyConfig::auto_detect_mode)
+    /// switched the model to CGB, which needs a CGB boot rom.
+    #[error("The game requires a GameBoy Color, but the boot rom is for a DMG")]
+    BootRomModelMismatch,
 }
 
 impl From<ioError> for CartridgeError {
@@ -50,6 +85,7 @@ impl From<ioError> for CartridgeError {
     }
 }
 
+/// An error that may occur when loading or saving the cartridge SRAM file.
 #[derive(thiserror::Error, Debug)]
 pub enum SramError {
     #[error("Could not load cartridge save file")]
@@ -70,6 +70,14 @@ impl Mapper for Mbc1 {
         bank * 0x4000 + addr as usize
     }
 
+    fn ram_bank(&self) -> u8 {
+        if self.ram_banks == 0 {
+            0
+        } else {
+            (if self.mode { self.two_bit_bank2 } else { 0 }) % self.ram_banks
+        }
+    }
+
     fn map_ram_read(&mut self, addr: u16) -> MappingResult {
         if !self.ram_enable {
             return MappingResult::NotMapped;
@@ -1,4 +1,4 @@
-use super::{Mapper, MappingResult, ONE_SECOND_MAPPER_CLOCKS};
+use super::{Mapper, MappingResult, RtcSaveFormat, RtcTime, ONE_SECOND_MAPPER_CLOCKS};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use save_state::Savable;
 use std::io::Cursor;
@@ -11,7 +11,7 @@ fn system_time_now() -> u64 {
         .as_secs()
 }
 
-#[derive(Savable)]
+#[derive(Clone, Savable)]
 struct RtcRegister {
     /// A full second is ONE_SECOND_MAPPER_CLOCKS, which is synced to the bus
     sub_second: u32,
@@ -28,6 +28,11 @@ struct RtcRegister {
     latched: bool,
 
     current_time_secs: u64,
+
+    /// Stops the clock from advancing, this is not the `halt` flag
+    /// controlled by the game, but an external control of the emulator
+    #[savable(skip)]
+    frozen: bool,
 }
 
 impl Default for RtcRegister {
@@ -45,6 +50,7 @@ impl Default for RtcRegister {
 
             sub_second: 0,
             current_time_secs: system_time,
+            frozen: false,
         }
     }
 }
@@ -186,8 +192,93 @@ impl RtcRegister {
         self.current_time_secs += system_time_diff;
     }
 
+    fn save_battery_compatible(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(48);
+
+        let day_high = |time: &RtcTime| {
+            ((time.day_counter_carry as u32) << 7)
+                | ((time.halt as u32) << 6)
+                | ((time.days >> 8) & 1) as u32
+        };
+        let latched = RtcTime {
+            seconds: self.seconds,
+            minutes: self.minutes,
+            hours: self.hours,
+            days: self.days,
+            halt: self.halt,
+            day_counter_carry: self.day_counter_carry,
+        };
+        for time in [self.time(), latched] {
+            for value in [
+                time.seconds as u32,
+                time.minutes as u32,
+                time.hours as u32,
+                (time.days & 0xFF) as u32,
+                day_high(&time),
+            ] {
+                result.write_u32::<LittleEndian>(value).unwrap();
+            }
+        }
+        result.write_u64::<LittleEndian>(system_time_now()).unwrap();
+
+        result
+    }
+
+    fn load_battery_compatible(&mut self, data: &[u8]) {
+        let mut cur = Cursor::new(data);
+
+        // only the current registers are used, the latched registers are
+        // updated from them
+        let mut current = [0; 5];
+        for value in &mut current {
+            *value = cur.read_u32::<LittleEndian>().unwrap();
+        }
+        cur.set_position(40);
+        let timestamp = cur.read_u64::<LittleEndian>().unwrap();
+
+        self.seconds = current[0] as u8 & 0x3F;
+        self.minutes = current[1] as u8 & 0x3F;
+        self.hours = current[2] as u8 & 0x1F;
+        self.days = ((current[4] as u16 & 1) << 8) | (current[3] as u16 & 0xFF);
+        self.halt = (current[4] >> 6) & 1 == 1;
+        self.day_counter_carry = (current[4] >> 7) & 1 == 1;
+        self.sub_second = 0;
+
+        // the time passed since saving is added on the next update
+        let elapsed = system_time_now().saturating_sub(timestamp);
+        self.last_latched_time = self.current_time_secs.saturating_sub(elapsed);
+    }
+
+    fn time(&self) -> RtcTime {
+        // the registers may be latched or not yet updated, so use a copy
+        // to compute the current time
+        let mut rtc = self.clone();
+        rtc.update_registers();
+
+        RtcTime {
+            seconds: rtc.seconds,
+            minutes: rtc.minutes,
+            hours: rtc.hours,
+            days: rtc.days,
+            halt: rtc.halt,
+            day_counter_carry: rtc.day_counter_carry,
+        }
+    }
+
+    fn set_time(&mut self, time: RtcTime) {
+        self.seconds = time.seconds & 0x3F;
+        self.minutes = time.minutes & 0x3F;
+        self.hours = time.hours & 0x1F;
+        self.days = time.days & 0x1FF;
+        self.halt = time.halt;
+        self.day_counter_carry = time.day_counter_carry;
+        self.sub_second = 0;
+        // start counting from the new time
+        self.last_latched_time = self.current_time_secs;
+    }
+
     fn clock_second_part(&mut self) {
-        if !self.halt {
+        if !self.halt && !self.frozen {
             self.sub_second += 1;
 
             if self.sub_second == ONE_SECOND_MAPPER_CLOCKS {
@@ -215,6 +306,9 @@ pub struct Mbc3 {
 
     ram_block_enable: bool,
     is_reading_ram: bool,
+
+    #[savable(skip)]
+    rtc_save_format: RtcSaveFormat,
 }
 
 impl Mbc3 {
@@ -273,6 +367,14 @@ impl Mapper for Mbc3 {
         bank as usize * 0x4000 + addr as usize
     }
 
+    fn ram_bank(&self) -> u8 {
+        if self.ram_banks == 0 {
+            0
+        } else {
+            self.ram_bank % self.ram_banks
+        }
+    }
+
     fn map_ram_read(&mut self, addr: u16) -> MappingResult {
         if self.ram_block_enable {
             if self.is_reading_ram {
@@ -335,28 +437,48 @@ impl Mapper for Mbc3 {
     }
 
     fn save_battery_size(&self) -> usize {
-        if self.rtc_present {
-            self.rtc_register.save_battery_size()
-        } else {
-            0
+        match (self.rtc_present, self.rtc_save_format) {
+            (false, _) => 0,
+            (true, RtcSaveFormat::Native) => self.rtc_register.save_battery_size(),
+            (true, RtcSaveFormat::Compatible) => 48,
         }
     }
 
     fn save_battery(&self) -> Vec<u8> {
-        if self.rtc_present {
-            self.rtc_register.save_battery()
-        } else {
-            Vec::new()
+        match (self.rtc_present, self.rtc_save_format) {
+            (false, _) => Vec::new(),
+            (true, RtcSaveFormat::Native) => self.rtc_register.save_battery(),
+            (true, RtcSaveFormat::Compatible) => self.rtc_register.save_battery_compatible(),
         }
     }
 
     fn load_battery(&mut self, data: &[u8]) {
+        match (self.rtc_present, self.rtc_save_format) {
+            (false, _) => {}
+            (true, RtcSaveFormat::Native) => self.rtc_register.load_battery(data),
+            (true, RtcSaveFormat::Compatible) => self.rtc_register.load_battery_compatible(data),
+        }
+    }
+
+    fn set_rtc_save_format(&mut self, format: RtcSaveFormat) {
+        self.rtc_save_format = format;
+    }
+
+    fn rtc(&self) -> Option<RtcTime> {
+        self.rtc_present.then(|| self.rtc_register.time())
+    }
+
+    fn set_rtc(&mut self, time: RtcTime) {
         if self.rtc_present {
-            self.rtc_register.load_battery(data)
+            self.rtc_register.set_time(time);
         }
     }
 
-    fn clock(&mut self) {
+    fn set_rtc_frozen(&mut self, frozen: bool) {
+        self.rtc_register.frozen = frozen;
+    }
+
+    fn clock(&mut self, _ram: &mut [u8]) {
         self.rtc_register.clock_second_part();
     }
 
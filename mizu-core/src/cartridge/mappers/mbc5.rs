@@ -1,4 +1,5 @@
-use super::{Mapper, MappingResult};
+use super::{Mapper, MappingResult, RumbleCallback};
+use crate::save_migration::StateMigration;
 use save_state::Savable;
 
 #[derive(Default, Savable)]
@@ -11,14 +12,19 @@ pub struct Mbc5 {
     ram_bank: u8,
     rom_bank: u16,
 
-    // TODO: use this idk how
-    _rumble: bool,
+    /// The cartridge has a rumble motor, which is controlled by bit 3
+    /// of the RAM bank register
+    rumble: bool,
+    rumble_on: bool,
+
+    #[savable(skip)]
+    rumble_callback: Option<RumbleCallback>,
 }
 
 impl Mbc5 {
     pub fn new(rumble: bool) -> Self {
         Self {
-            _rumble: rumble,
+            rumble,
             rom_bank: 1,
             ..Self::default()
         }
@@ -45,6 +51,14 @@ impl Mapper for Mbc5 {
         bank as usize * 0x4000 + addr as usize
     }
 
+    fn ram_bank(&self) -> u8 {
+        if self.ram_banks == 0 {
+            0
+        } else {
+            self.ram_bank % self.ram_banks
+        }
+    }
+
     fn map_ram_read(&mut self, addr: u16) -> MappingResult {
         if !self.ram_enable {
             return MappingResult::NotMapped;
@@ -77,11 +91,39 @@ impl Mapper for Mbc5 {
                 self.rom_bank |= ((data & 1) as u16) << 8;
             }
             0x4000..=0x5FFF => {
-                self.ram_bank = data & 0xF;
+                if self.rumble {
+                    self.ram_bank = data & 0x7;
+
+                    let rumble_on = data & 0x8 != 0;
+                    if rumble_on != self.rumble_on {
+                        self.rumble_on = rumble_on;
+                        if let Some(callback) = self.rumble_callback.as_mut() {
+                            callback(rumble_on);
+                        }
+                    }
+                } else {
+                    self.ram_bank = data & 0xF;
+                }
             }
             _ => {}
         }
     }
 
+    fn set_rumble_callback(&mut self, callback: Option<RumbleCallback>) {
+        self.rumble_callback = callback;
+    }
+
     save_state_fns!();
+
+    fn migrate_state(&self, migration: &mut StateMigration) -> save_state::Result<()> {
+        migration.copy(self.rom_banks)?;
+        migration.copy(self.is_2k_ram)?;
+        migration.copy(self.ram_banks)?;
+        migration.copy(self.ram_enable)?;
+        migration.copy(self.ram_bank)?;
+        migration.copy(self.rom_bank)?;
+        migration.copy(self.rumble)?;
+        migration.copy_since(3, self.rumble_on)?;
+        Ok(())
+    }
 }
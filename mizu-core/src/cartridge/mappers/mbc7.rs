@@ -0,0 +1,417 @@
+use super::{Mapper, MappingResult};
+use save_state::Savable;
+
+/// The value of the accelerometer when it is flat (no tilt)
+const ACCELEROMETER_CENTER: u16 = 0x81D0;
+/// The difference in the accelerometer value for `1g` of acceleration
+const ACCELEROMETER_GRAVITY: f32 = 0x70 as f32;
+
+#[derive(Default, Clone, Copy, PartialEq, Savable)]
+enum EepromState {
+    /// Waiting for the start bit
+    #[default]
+    Idle,
+    /// Receiving the opcode and address bits
+    Command,
+    /// Shifting out the data of a `READ` command
+    Read,
+    /// Receiving the data of a `WRITE` or `WRALL` command
+    Write,
+    /// The command is finished, waiting for `CS` to go low
+    Done,
+}
+
+/// The 93LC56 serial EEPROM, 128 words of 16 bits.
+#[derive(Savable)]
+struct Eeprom {
+    data: [u16; 128],
+
+    cs: bool,
+    clk: bool,
+    di: bool,
+    do_: bool,
+
+    state: EepromState,
+    /// Shift register for the input command/data and the output data
+    shift: u16,
+    /// Number of bits received/sent in the current state
+    bits: u8,
+    /// The address of the current `WRITE` command
+    address: u8,
+    /// The current write command is `WRALL`
+    write_all: bool,
+    write_enabled: bool,
+}
+
+impl Default for Eeprom {
+    fn default() -> Self {
+        Self {
+            data: [0xFFFF; 128],
+            cs: false,
+            clk: false,
+            di: false,
+            do_: true,
+            state: EepromState::Idle,
+            shift: 0,
+            bits: 0,
+            address: 0,
+            write_all: false,
+            write_enabled: false,
+        }
+    }
+}
+
+impl Eeprom {
+    fn read(&self) -> u8 {
+        ((self.cs as u8) << 7) | ((self.clk as u8) << 6) | ((self.di as u8) << 1) | self.do_ as u8
+    }
+
+    fn write(&mut self, data: u8) {
+        let cs = data & 0x80 != 0;
+        let clk = data & 0x40 != 0;
+        self.di = data & 0x02 != 0;
+
+        if !cs {
+            // deselecting the chip cancels any command
+            self.state = EepromState::Idle;
+        } else if !self.clk && clk {
+            self.clock_bit();
+        }
+
+        self.cs = cs;
+        self.clk = clk;
+    }
+
+    /// Handles one bit on the rising edge of `CLK`
+    fn clock_bit(&mut self) {
+        match self.state {
+            EepromState::Idle => {
+                if self.di {
+                    self.state = EepromState::Command;
+                    self.shift = 0;
+                    self.bits = 0;
+                }
+            }
+            EepromState::Command => {
+                self.shift = (self.shift << 1) | self.di as u16;
+                self.bits += 1;
+
+                // 2 bits opcode, 8 bits address (only 7 are used)
+                if self.bits == 10 {
+                    self.run_command();
+                }
+            }
+            EepromState::Read => {
+                self.do_ = self.shift & 0x8000 != 0;
+                self.shift <<= 1;
+                self.bits += 1;
+
+                if self.bits == 16 {
+                    // sequential read, continue to the next word
+                    self.address = (self.address + 1) & 0x7F;
+                    self.shift = self.data[self.address as usize];
+                    self.bits = 0;
+                }
+            }
+            EepromState::Write => {
+                self.shift = (self.shift << 1) | self.di as u16;
+                self.bits += 1;
+
+                if self.bits == 16 {
+                    if self.write_enabled {
+                        if self.write_all {
+                            self.data.fill(self.shift);
+                        } else {
+                            self.data[self.address as usize] = self.shift;
+                        }
+                    }
+                    // ready
+                    self.do_ = true;
+                    self.state = EepromState::Done;
+                }
+            }
+            EepromState::Done => {}
+        }
+    }
+
+    fn run_command(&mut self) {
+        let opcode = (self.shift >> 8) & 3;
+        self.address = (self.shift & 0x7F) as u8;
+        self.bits = 0;
+        self.state = EepromState::Done;
+
+        match opcode {
+            // READ
+            0b10 => {
+                self.shift = self.data[self.address as usize];
+                // dummy zero bit before the data
+                self.do_ = false;
+                self.state = EepromState::Read;
+            }
+            // WRITE
+            0b01 => {
+                self.write_all = false;
+                self.shift = 0;
+                self.state = EepromState::Write;
+            }
+            // ERASE
+            0b11 => {
+                if self.write_enabled {
+                    self.data[self.address as usize] = 0xFFFF;
+                }
+                self.do_ = true;
+            }
+            _ => match (self.shift >> 6) & 3 {
+                // EWDS
+                0b00 => self.write_enabled = false,
+                // WRAL
+                0b01 => {
+                    self.write_all = true;
+                    self.shift = 0;
+                    self.state = EepromState::Write;
+                }
+                // ERAL
+                0b10 => {
+                    if self.write_enabled {
+                        self.data.fill(0xFFFF);
+                    }
+                    self.do_ = true;
+                }
+                // EWEN
+                0b11 => self.write_enabled = true,
+                _ => unreachable!(),
+            },
+        }
+    }
+}
+
+#[derive(Default, Savable)]
+pub struct Mbc7 {
+    rom_banks: u16,
+
+    /// the bank number to use in the memory [0x4000..=0x7FFF]
+    rom_bank_4000: u8,
+
+    /// both must be enabled to access the registers in 0xA000-0xAFFF
+    ram_enable_1: bool,
+    ram_enable_2: bool,
+
+    /// the accelerometer can only be latched after being erased
+    accelerometer_erased: bool,
+    latched_x: u16,
+    latched_y: u16,
+
+    /// The current value of the accelerometer, controlled by the frontend
+    #[savable(skip)]
+    accelerometer_x: u16,
+    #[savable(skip)]
+    accelerometer_y: u16,
+
+    eeprom: Eeprom,
+}
+
+impl Mbc7 {
+    pub fn new() -> Self {
+        Self {
+            rom_bank_4000: 1,
+            latched_x: 0x8000,
+            latched_y: 0x8000,
+            accelerometer_x: ACCELEROMETER_CENTER,
+            accelerometer_y: ACCELEROMETER_CENTER,
+            ..Self::default()
+        }
+    }
+
+    fn registers_enabled(&self) -> bool {
+        self.ram_enable_1 && self.ram_enable_2
+    }
+}
+
+impl Mapper for Mbc7 {
+    fn init(&mut self, rom_banks: u16, _ram_size: usize) {
+        assert!(rom_banks <= 128);
+        self.rom_banks = rom_banks;
+    }
+
+    fn map_read_rom0(&self, addr: u16) -> usize {
+        addr as usize
+    }
+
+    fn map_read_romx(&self, addr: u16) -> usize {
+        let addr = addr & 0x3FFF;
+
+        let bank = self.rom_bank_4000 as u16 % self.rom_banks;
+
+        bank as usize * 0x4000 + addr as usize
+    }
+
+    fn map_ram_read(&mut self, addr: u16) -> MappingResult {
+        if !self.registers_enabled() || addr >= 0xB000 {
+            return MappingResult::NotMapped;
+        }
+
+        let value = match (addr >> 4) & 0xF {
+            2 => self.latched_x as u8,
+            3 => (self.latched_x >> 8) as u8,
+            4 => self.latched_y as u8,
+            5 => (self.latched_y >> 8) as u8,
+            6 => 0x00,
+            8 => self.eeprom.read(),
+            _ => 0xFF,
+        };
+
+        MappingResult::Value(value)
+    }
+
+    fn map_ram_write(&mut self, addr: u16, data: u8) -> MappingResult {
+        if !self.registers_enabled() || addr >= 0xB000 {
+            return MappingResult::NotMapped;
+        }
+
+        match (addr >> 4) & 0xF {
+            0 if data == 0x55 => {
+                self.accelerometer_erased = true;
+                self.latched_x = 0x8000;
+                self.latched_y = 0x8000;
+            }
+            1 if data == 0xAA && self.accelerometer_erased => {
+                self.accelerometer_erased = false;
+                self.latched_x = self.accelerometer_x;
+                self.latched_y = self.accelerometer_y;
+            }
+            8 => self.eeprom.write(data),
+            _ => {}
+        }
+
+        MappingResult::NotMapped
+    }
+
+    fn write_bank_controller_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enable_1 = data == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank_4000 = data & 0x7F,
+            0x4000..=0x5FFF => self.ram_enable_2 = data == 0x40,
+            _ => {}
+        }
+    }
+
+    fn save_battery_size(&self) -> usize {
+        256
+    }
+
+    fn save_battery(&self) -> Vec<u8> {
+        self.eeprom
+            .data
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect()
+    }
+
+    fn load_battery(&mut self, data: &[u8]) {
+        assert!(data.len() == 256);
+
+        for (word, bytes) in self.eeprom.data.iter_mut().zip(data.chunks_exact(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+    }
+
+    fn set_accelerometer(&mut self, x: f32, y: f32) {
+        let to_value = |g: f32| (ACCELEROMETER_CENTER as f32 + g * ACCELEROMETER_GRAVITY) as u16;
+
+        self.accelerometer_x = to_value(x);
+        self.accelerometer_y = to_value(y);
+    }
+
+    save_state_fns!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mapper, MappingResult, Mbc7};
+
+    fn read(mbc: &mut Mbc7, addr: u16) -> u8 {
+        match mbc.map_ram_read(addr) {
+            MappingResult::Value(value) => value,
+            _ => 0xFF,
+        }
+    }
+
+    fn enabled_mbc7() -> Mbc7 {
+        let mut mbc = Mbc7::new();
+        mbc.init(4, 0);
+        mbc.write_bank_controller_register(0x0000, 0x0A);
+        mbc.write_bank_controller_register(0x4000, 0x40);
+        mbc
+    }
+
+    /// Sends `count` bits of `value` (MSB first) to the EEPROM, and returns
+    /// the `DO` bits after every rising edge
+    fn eeprom_send(mbc: &mut Mbc7, value: u32, count: u8) -> u32 {
+        let mut out = 0;
+        for i in (0..count).rev() {
+            let di = ((value >> i) & 1) as u8;
+            mbc.map_ram_write(0xA080, 0x80 | (di << 1));
+            mbc.map_ram_write(0xA080, 0xC0 | (di << 1));
+            out = (out << 1) | (read(mbc, 0xA080) & 1) as u32;
+        }
+        out
+    }
+
+    fn eeprom_command(mbc: &mut Mbc7, command: u32, count: u8) -> u32 {
+        // select
+        mbc.map_ram_write(0xA080, 0x00);
+        mbc.map_ram_write(0xA080, 0x80);
+        let out = eeprom_send(mbc, command, count);
+        // deselect
+        mbc.map_ram_write(0xA080, 0x00);
+        out
+    }
+
+    #[test]
+    fn accelerometer_latch() {
+        let mut mbc = enabled_mbc7();
+        mbc.set_accelerometer(1., -0.5);
+
+        // cannot latch before erasing
+        mbc.map_ram_write(0xA010, 0xAA);
+        assert_eq!(read(&mut mbc, 0xA020), 0x00);
+        assert_eq!(read(&mut mbc, 0xA030), 0x80);
+
+        mbc.map_ram_write(0xA000, 0x55);
+        mbc.map_ram_write(0xA010, 0xAA);
+        let x = u16::from_le_bytes([read(&mut mbc, 0xA020), read(&mut mbc, 0xA030)]);
+        let y = u16::from_le_bytes([read(&mut mbc, 0xA040), read(&mut mbc, 0xA050)]);
+        assert_eq!(x, 0x81D0 + 0x70);
+        assert_eq!(y, 0x81D0 - 0x38);
+
+        // registers are not accessible when disabled
+        mbc.write_bank_controller_register(0x4000, 0x00);
+        assert_eq!(read(&mut mbc, 0xA020), 0xFF);
+    }
+
+    #[test]
+    fn eeprom_write_and_read() {
+        let mut mbc = enabled_mbc7();
+
+        // writes are ignored before EWEN
+        eeprom_command(&mut mbc, (0b101 << 24) | (0x05 << 16) | 0x1234, 27);
+        assert_eq!(mbc.save_battery()[10..12], [0xFF, 0xFF]);
+
+        // EWEN
+        eeprom_command(&mut mbc, (0b100 << 8) | 0xC0, 11);
+        // WRITE 0x1234 to address 5
+        eeprom_command(&mut mbc, (0b101 << 24) | (0x05 << 16) | 0x1234, 27);
+        assert_eq!(mbc.save_battery()[10..12], [0x34, 0x12]);
+
+        // READ address 5, 11 command bits, then a dummy 0 and 16 data bits
+        let out = eeprom_command(&mut mbc, (0b110 << 24) | (0x05 << 16), 27);
+        assert_eq!(out & 0x1FFFF, 0x1234);
+
+        // battery round trip
+        let saved = mbc.save_battery();
+        let mut other = enabled_mbc7();
+        other.load_battery(&saved);
+        let out = eeprom_command(&mut other, (0b110 << 24) | (0x05 << 16), 27);
+        assert_eq!(out & 0x1FFFF, 0x1234);
+    }
+}
@@ -22,20 +22,27 @@ mod mbc1;
 mod mbc2;
 mod mbc3;
 mod mbc5;
+mod mbc7;
 mod no_mapper;
+mod pocket_camera;
 
 pub(super) use mbc1::Mbc1;
 pub(super) use mbc2::Mbc2;
 pub(super) use mbc3::Mbc3;
 pub(super) use mbc5::Mbc5;
+pub(super) use mbc7::Mbc7;
 pub(super) use no_mapper::NoMapper;
+pub(super) use pocket_camera::PocketCamera;
+pub use pocket_camera::{CAMERA_HEIGHT, CAMERA_WIDTH};
 
+use crate::save_migration::StateMigration;
 use save_state::Savable;
 
 /// The number of clocks needed from the bus to complete one second
 pub const ONE_SECOND_MAPPER_CLOCKS: u32 = 4194304 / 2;
 
-#[derive(Debug, Clone, Copy, PartialEq, Savable)]
+/// The memory bank controller type of the cartridge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Savable)]
 pub enum MapperType {
     NoMapper,
     Mbc1 { multicart: bool },
@@ -45,8 +52,60 @@ pub enum MapperType {
     Mmm01,
     Mbc6,
     Mbc7,
+    PocketCamera,
 }
 
+impl MapperType {
+    /// Returns `true` if the mapper is supported by the emulator, cartridges
+    /// with unsupported mappers fail to load with
+    /// [`CartridgeError::MapperNotImplemented`](crate::CartridgeError::MapperNotImplemented).
+    pub fn is_implemented(&self) -> bool {
+        !matches!(self, Self::Mmm01 | Self::Mbc6)
+    }
+}
+
+/// The time of the MBC3 real time clock.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RtcTime {
+    /// `0-59`
+    pub seconds: u8,
+    /// `0-59`
+    pub minutes: u8,
+    /// `0-23`
+    pub hours: u8,
+    /// `0-511`
+    pub days: u16,
+    /// The clock is stopped
+    pub halt: bool,
+    /// The days counter overflowed
+    pub day_counter_carry: bool,
+}
+
+/// The format of the real time clock data appended after the SRAM in the
+/// `.sav` file, for cartridges with an MBC3 timer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RtcSaveFormat {
+    /// The format used by mizu, 29 bytes, all little endian:
+    /// - `u8` seconds, `u8` minutes, `u8` hours, `u16` days.
+    /// - `u64` the internal clock time in seconds when the registers
+    ///   were last updated.
+    /// - `u64` how far the internal clock is ahead of the system time.
+    #[default]
+    Native,
+    /// The format used by BGB and VBA-M, 48 bytes, all little endian:
+    /// - 5 `u32`: the current seconds, minutes, hours, days (low 8 bits), and
+    ///   the day high register (bit 0: day bit 8, bit 6: halt, bit 7: carry).
+    /// - 5 `u32`: the latched registers, in the same order.
+    /// - `u64` the unix timestamp of when the file was saved.
+    ///
+    /// When loading, the clock is advanced by the time passed since the
+    /// timestamp, unless it is halted.
+    Compatible,
+}
+
+/// Called with the new state of the rumble motor when it changes
+pub type RumbleCallback = Box<dyn FnMut(bool)>;
+
 pub enum MappingResult {
     Addr(usize),
     Value(u8),
@@ -64,6 +123,11 @@ pub trait Mapper {
 
     fn map_ram_write(&mut self, addr: u16, data: u8) -> MappingResult;
 
+    /// The RAM bank mapped to `0xA000-0xBFFF`, for debugging
+    fn ram_bank(&self) -> u8 {
+        0
+    }
+
     fn write_bank_controller_register(&mut self, _addr: u16, _data: u8) {
         // ignored
     }
@@ -80,11 +144,49 @@ pub trait Mapper {
         // ignored
     }
 
+    /// Sets the format of the real time clock data in
+    /// [`Mapper::save_battery`] and [`Mapper::load_battery`]
+    fn set_rtc_save_format(&mut self, _format: RtcSaveFormat) {
+        // ignored
+    }
+
+    /// The current time of the real time clock, if the mapper has one
+    fn rtc(&self) -> Option<RtcTime> {
+        None
+    }
+
+    fn set_rtc(&mut self, _time: RtcTime) {
+        // ignored
+    }
+
+    /// Stops the real time clock from advancing with [`Mapper::clock`]
+    fn set_rtc_frozen(&mut self, _frozen: bool) {
+        // ignored
+    }
+
+    fn set_rumble_callback(&mut self, _callback: Option<RumbleCallback>) {
+        // ignored
+    }
+
+    /// Sets the current acceleration of the accelerometer in `g`
+    fn set_accelerometer(&mut self, _x: f32, _y: f32) {
+        // ignored
+    }
+
+    /// Sets the image seen by the camera sensor, `CAMERA_WIDTH * CAMERA_HEIGHT`
+    /// grayscale pixels
+    fn set_camera_frame(&mut self, _frame: &[u8]) {
+        // ignored
+    }
+
     /// Fixed-timed updates from the bus, the main purpose is to be used to
     /// sync the MBC3 RTC clock to emulation in case emulation speed changed
     ///
     /// the number of clocks for one second is (ONE_SECOND_MAPPER_CLOCKS) 4194304 / 2
-    fn clock(&mut self) {
+    ///
+    /// `ram` is the cartridge RAM, for mappers that write to it on their own,
+    /// i.e. the camera when finishing a capture
+    fn clock(&mut self, _ram: &mut [u8]) {
         // ignore
     }
 
@@ -97,4 +199,10 @@ pub trait Mapper {
     fn save_state_size(&self) -> save_state::Result<u64>;
     fn save_state(&self) -> save_state::Result<Vec<u8>>;
     fn load_state(&mut self, data: &[u8]) -> save_state::Result<()>;
+
+    /// Migrates the saved state of the mapper to the next version, see
+    /// [`StateMigration`], mappers that did not change copy it as is
+    fn migrate_state(&self, migration: &mut StateMigration) -> save_state::Result<()> {
+        migration.copy_bytes(self.save_state_size()?)
+    }
 }
@@ -0,0 +1,332 @@
+use super::{Mapper, MappingResult};
+use save_state::Savable;
+
+/// The width of the image captured by the camera sensor
+pub const CAMERA_WIDTH: usize = 128;
+/// The height of the image captured by the camera sensor
+pub const CAMERA_HEIGHT: usize = 112;
+
+/// The captured image is written as tiles to this offset in RAM bank 0
+const CAPTURE_RAM_OFFSET: usize = 0x100;
+/// The size of the captured image in RAM, 2 bits per pixel
+const CAPTURE_RAM_SIZE: usize = CAMERA_WIDTH * CAMERA_HEIGHT / 4;
+/// The number of registers, `A000-A035`
+const REGISTERS_SIZE: usize = 0x36;
+/// The first of the dither matrix registers, `4x4` entries of 3 thresholds
+const DITHER_MATRIX_START: usize = 0x06;
+
+#[derive(Savable)]
+pub struct PocketCamera {
+    rom_banks: u16,
+    ram_banks: u8,
+
+    ram_enable: bool,
+    /// bit 4 selects the camera registers instead of RAM
+    ram_bank: u8,
+    rom_bank: u8,
+
+    registers: [u8; REGISTERS_SIZE],
+    /// Mapper clocks until the current capture is finished, `0` if
+    /// there is no capture in progress
+    capture_clocks_remaining: u32,
+
+    /// The image seen by the sensor, controlled by the frontend
+    #[savable(skip)]
+    frame: Vec<u8>,
+}
+
+impl Default for PocketCamera {
+    fn default() -> Self {
+        Self {
+            rom_banks: 0,
+            ram_banks: 0,
+            ram_enable: false,
+            ram_bank: 0,
+            rom_bank: 1,
+            registers: [0; REGISTERS_SIZE],
+            capture_clocks_remaining: 0,
+            frame: vec![0; CAMERA_WIDTH * CAMERA_HEIGHT],
+        }
+    }
+}
+
+impl PocketCamera {
+    fn registers_selected(&self) -> bool {
+        self.ram_bank & 0x10 != 0
+    }
+
+    fn exposure(&self) -> u16 {
+        u16::from_be_bytes([self.registers[2], self.registers[3]])
+    }
+
+    fn start_capture(&mut self) {
+        // approximation of the sensor timing, the exposure time is
+        // in units of 16 microseconds
+        let n_bit_clocks = if self.registers[1] & 0x80 == 0 {
+            1024
+        } else {
+            0
+        };
+        self.capture_clocks_remaining = 64896 + n_bit_clocks + self.exposure() as u32 * 32;
+    }
+
+    /// Processes the sensor image with the exposure, invert and dither
+    /// matrix registers, and returns the color index (`0` white - `3` black)
+    fn pixel_color(&self, x: usize, y: usize) -> u8 {
+        let value = self.frame[y * CAMERA_WIDTH + x] as u32;
+        // an exposure of `0x1000` keeps the image as is
+        let mut value = (value * self.exposure() as u32 / 0x1000).min(0xFF) as u8;
+        if self.registers[4] & 0x08 != 0 {
+            value = !value;
+        }
+
+        let matrix_index = DITHER_MATRIX_START + ((y & 3) * 4 + (x & 3)) * 3;
+        let thresholds = &self.registers[matrix_index..matrix_index + 3];
+
+        if value < thresholds[0] {
+            3
+        } else if value < thresholds[1] {
+            2
+        } else if value < thresholds[2] {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// Writes the processed image into RAM bank 0 as `16x14` tiles
+    fn finish_capture(&mut self, ram: &mut [u8]) {
+        self.registers[0] &= !1;
+
+        // the RAM from the cartridge header may be too small for the image
+        let Some(capture_ram) =
+            ram.get_mut(CAPTURE_RAM_OFFSET..CAPTURE_RAM_OFFSET + CAPTURE_RAM_SIZE)
+        else {
+            return;
+        };
+
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let color = self.pixel_color(x, y);
+                let tile = (y / 8) * (CAMERA_WIDTH / 8) + x / 8;
+                let addr = tile * 16 + (y % 8) * 2;
+                let bit = 0x80 >> (x % 8);
+
+                if color & 1 != 0 {
+                    capture_ram[addr] |= bit;
+                } else {
+                    capture_ram[addr] &= !bit;
+                }
+                if color & 2 != 0 {
+                    capture_ram[addr + 1] |= bit;
+                } else {
+                    capture_ram[addr + 1] &= !bit;
+                }
+            }
+        }
+    }
+}
+
+impl Mapper for PocketCamera {
+    fn init(&mut self, rom_banks: u16, ram_size: usize) {
+        assert!(rom_banks <= 64);
+        assert!(ram_size <= 0x20000);
+        self.rom_banks = rom_banks;
+        self.ram_banks = (ram_size / 0x2000) as u8;
+    }
+
+    fn map_read_rom0(&self, addr: u16) -> usize {
+        addr as usize
+    }
+
+    fn map_read_romx(&self, addr: u16) -> usize {
+        let addr = addr & 0x3FFF;
+
+        let bank = self.rom_bank as u16 % self.rom_banks;
+
+        bank as usize * 0x4000 + addr as usize
+    }
+
+    fn ram_bank(&self) -> u8 {
+        if self.ram_banks == 0 {
+            0
+        } else {
+            self.ram_bank % self.ram_banks
+        }
+    }
+
+    fn map_ram_read(&mut self, addr: u16) -> MappingResult {
+        if self.registers_selected() {
+            // only the capture register can be read
+            let value = if addr & 0x7F == 0 {
+                self.registers[0] & 0x07
+            } else {
+                0x00
+            };
+            return MappingResult::Value(value);
+        }
+
+        if self.ram_banks == 0 {
+            return MappingResult::NotMapped;
+        }
+
+        // reading the RAM does not need it to be enabled
+        let addr = addr & 0x1FFF;
+        let bank = self.ram_bank % self.ram_banks;
+        MappingResult::Addr(bank as usize * 0x2000 + addr as usize)
+    }
+
+    fn map_ram_write(&mut self, addr: u16, data: u8) -> MappingResult {
+        if self.registers_selected() {
+            let register = (addr & 0x7F) as usize;
+            if register == 0 {
+                if data & 1 != 0 && self.capture_clocks_remaining == 0 {
+                    self.start_capture();
+                }
+                // the busy bit stays set until the capture is finished
+                self.registers[0] = (data & 0x06) | (self.capture_clocks_remaining != 0) as u8;
+            } else if register < REGISTERS_SIZE {
+                self.registers[register] = data;
+            }
+            return MappingResult::NotMapped;
+        }
+
+        if !self.ram_enable {
+            return MappingResult::NotMapped;
+        }
+
+        self.map_ram_read(addr)
+    }
+
+    fn write_bank_controller_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enable = data & 0xF == 0xA,
+            0x2000..=0x3FFF => self.rom_bank = data & 0x3F,
+            0x4000..=0x5FFF => self.ram_bank = data & 0x1F,
+            _ => {}
+        }
+    }
+
+    fn set_camera_frame(&mut self, frame: &[u8]) {
+        self.frame.copy_from_slice(frame);
+    }
+
+    fn clock(&mut self, ram: &mut [u8]) {
+        if self.capture_clocks_remaining != 0 {
+            self.capture_clocks_remaining -= 1;
+            if self.capture_clocks_remaining == 0 {
+                self.finish_capture(ram);
+            }
+        }
+    }
+
+    save_state_fns!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mapper, MappingResult, PocketCamera, CAMERA_HEIGHT, CAMERA_WIDTH};
+
+    fn read(camera: &mut PocketCamera, ram: &[u8], addr: u16) -> u8 {
+        match camera.map_ram_read(addr) {
+            MappingResult::Addr(addr) => ram[addr],
+            MappingResult::Value(value) => value,
+            MappingResult::NotMapped => 0xFF,
+        }
+    }
+
+    #[test]
+    fn capture_dithered_image() {
+        let mut camera = PocketCamera::default();
+        camera.init(64, 0x20000);
+        let mut ram = vec![0xAA; 0x20000];
+
+        // left half is black, right half is white
+        let frame = (0..CAMERA_WIDTH * CAMERA_HEIGHT)
+            .map(|i| if i % CAMERA_WIDTH < 64 { 0x00 } else { 0xFF })
+            .collect::<Vec<_>>();
+        camera.set_camera_frame(&frame);
+
+        camera.write_bank_controller_register(0x4000, 0x10);
+        // exposure `0x1000`
+        camera.map_ram_write(0xA002, 0x10);
+        camera.map_ram_write(0xA003, 0x00);
+        // same thresholds for the whole matrix
+        for i in 0..16 {
+            camera.map_ram_write(0xA006 + i * 3, 0x40);
+            camera.map_ram_write(0xA007 + i * 3, 0x80);
+            camera.map_ram_write(0xA008 + i * 3, 0xC0);
+        }
+
+        camera.map_ram_write(0xA000, 0x01);
+        assert_eq!(read(&mut camera, &ram, 0xA000), 0x01);
+        // other registers are not readable
+        assert_eq!(read(&mut camera, &ram, 0xA002), 0x00);
+
+        let mut clocks = 0;
+        while read(&mut camera, &ram, 0xA000) & 1 != 0 {
+            camera.clock(&mut ram);
+            clocks += 1;
+        }
+        assert_eq!(clocks, 64896 + 1024 + 0x1000 * 32);
+
+        // the first tile is black, and the last tile of the row is white
+        camera.write_bank_controller_register(0x4000, 0x00);
+        assert_eq!(read(&mut camera, &ram, 0xA100), 0xFF);
+        assert_eq!(read(&mut camera, &ram, 0xA101), 0xFF);
+        assert_eq!(read(&mut camera, &ram, 0xA100 + 15 * 16), 0x00);
+        assert_eq!(read(&mut camera, &ram, 0xA101 + 15 * 16), 0x00);
+        // outside the capture area is not changed
+        assert_eq!(read(&mut camera, &ram, 0xA0FF), 0xAA);
+        assert_eq!(read(&mut camera, &ram, 0xAF00), 0xAA);
+
+        // invert the output
+        camera.write_bank_controller_register(0x4000, 0x10);
+        camera.map_ram_write(0xA004, 0x08);
+        camera.map_ram_write(0xA000, 0x01);
+        while read(&mut camera, &ram, 0xA000) & 1 != 0 {
+            camera.clock(&mut ram);
+        }
+        camera.write_bank_controller_register(0x4000, 0x00);
+        assert_eq!(read(&mut camera, &ram, 0xA100), 0x00);
+        assert_eq!(read(&mut camera, &ram, 0xA100 + 15 * 16), 0xFF);
+    }
+
+    #[test]
+    fn capture_with_small_ram() {
+        let mut camera = PocketCamera::default();
+        camera.init(64, 0x800);
+        let mut ram = vec![0xAA; 0x800];
+
+        camera.write_bank_controller_register(0x4000, 0x10);
+        camera.map_ram_write(0xA000, 0x01);
+        while read(&mut camera, &ram, 0xA000) & 1 != 0 {
+            camera.clock(&mut ram);
+        }
+
+        // the image does not fit, so nothing is written
+        assert!(ram.iter().all(|&byte| byte == 0xAA));
+    }
+
+    #[test]
+    fn ram_write_enable() {
+        let mut camera = PocketCamera::default();
+        camera.init(64, 0x20000);
+
+        camera.write_bank_controller_register(0x4000, 0x03);
+        assert!(matches!(
+            camera.map_ram_write(0xA010, 0x12),
+            MappingResult::NotMapped
+        ));
+        assert!(matches!(
+            camera.map_ram_read(0xA010),
+            MappingResult::Addr(0x6010)
+        ));
+
+        camera.write_bank_controller_register(0x0000, 0x0A);
+        assert!(matches!(
+            camera.map_ram_write(0xA010, 0x12),
+            MappingResult::Addr(0x6010)
+        ));
+    }
+}
@@ -4,21 +4,38 @@ mod instructions_table;
 use bitflags::bitflags;
 use save_state::Savable;
 
+use std::collections::HashSet;
+use std::io::Write;
+
 use crate::memory::InterruptType;
+use crate::save_migration::StateMigration;
 use crate::GameBoyConfig;
 use instruction::{Condition, Instruction, Opcode, OperandType};
 
 pub trait CpuBusProvider {
     fn read(&mut self, addr: u16) -> u8;
     fn write(&mut self, addr: u16, data: u8);
+    /// Reads without clocking the bus, used for logging
+    fn peek(&mut self, addr: u16) -> u8;
+    /// Clocks the bus for one machine cycle without accessing memory, used
+    /// for the internal cycles of instructions
+    fn idle(&mut self) {
+        self.read(0);
+    }
 
     fn take_next_interrupt(&mut self) -> Option<InterruptType>;
     fn peek_next_interrupt(&mut self) -> Option<InterruptType>;
 
+    /// Returns the first write `(addr, value)` to a watched address since
+    /// the last call
+    fn take_watchpoint_hit(&mut self) -> Option<(u16, u8)>;
+
     fn is_hdma_running(&mut self) -> bool;
 
     fn enter_stop_mode(&mut self);
     fn stopped(&self) -> bool;
+    /// The CPU is halted while the speed switch (started by `STOP`) is in progress
+    fn switching_speed(&self) -> bool;
 
     /// Triggers oam_bug without clock, this is used for inc/dec instructions
     fn trigger_write_oam_bug(&mut self, addr: u16);
@@ -31,6 +48,10 @@ pub trait CpuBusProvider {
 
 const INTERRUPTS_VECTOR: [u16; 5] = [0x40, 0x48, 0x50, 0x58, 0x60];
 
+/// Callback called with the CPU registers before executing every instruction
+pub type TraceCallback = Box<dyn FnMut(&CpuRegisters, &Instruction)>;
+
+/// A snapshot of the CPU registers
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct CpuRegisters {
     pub a: u8,
@@ -45,15 +66,55 @@ pub struct CpuRegisters {
     pub pc: u16,
 }
 
+impl CpuRegisters {
+    /// A line in the log format of Gameboy Doctor, `pc_mem` are the 4 bytes
+    /// starting at `pc`
+    pub(crate) fn doctor_log_line(&self, pc_mem: [u8; 4]) -> String {
+        format!(
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} \
+             SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            self.a,
+            self.f,
+            self.b,
+            self.c,
+            self.d,
+            self.e,
+            self.h,
+            self.l,
+            self.sp,
+            self.pc,
+            pc_mem[0],
+            pc_mem[1],
+            pc_mem[2],
+            pc_mem[3],
+        )
+    }
+}
+
+/// The state of the CPU after executing a step
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum CpuState {
     Normal,
+    /// Executed a jump to itself
     InfiniteLoop,
     Halting,
     RunningHDMA,
+    /// In `STOP` mode
     Stopped,
+    /// Halted while switching between normal and double speed (CGB only)
+    SpeedSwitching,
     RunningInterrupt(InterruptType),
+    /// Stopped at a breakpoint, either from an address breakpoint
+    /// or the `LD B, B` instruction
     Breakpoint(CpuRegisters),
+    /// A watched address was written by the last step,
+    /// see [`GameBoy::add_write_watchpoint`](crate::GameBoy::add_write_watchpoint)
+    Watchpoint {
+        addr: u16,
+        value: u8,
+    },
+    /// Executed an illegal opcode, the CPU is locked up
+    IllegalInstruction(u8),
 }
 
 bitflags! {
@@ -67,14 +128,29 @@ bitflags! {
     }
 }
 
-#[derive(Savable, PartialEq)]
-enum HaltMode {
+/// The `HALT` state of the CPU
+#[derive(Savable, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HaltMode {
     NotHalting,
+    /// Halted with `IME=1`, the next interrupt will be serviced
     HaltRunInterrupt,
+    /// Halted with `IME=0`, execution continues after the `HALT` when
+    /// an interrupt is requested, without servicing it
     HaltNoRunInterrupt,
+    /// `HALT` was executed with `IME=0` while an interrupt was pending,
+    /// the byte after the `HALT` will be read twice
     HaltBug,
 }
 
+/// The internal state of the CPU behind the `HALT` bug and the `EI` delay,
+/// which is not visible in the registers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuQuirkState {
+    pub halt_mode: HaltMode,
+    /// `EI` was executed, and `IME` will be set before the next instruction
+    pub ei_pending: bool,
+}
+
 #[derive(Savable)]
 pub struct Cpu {
     reg_a: u8,
@@ -94,6 +170,22 @@ pub struct Cpu {
     ime: bool,
     halt_mode: HaltMode,
 
+    /// PC addresses that will stop execution before fetching the instruction
+    #[savable(skip)]
+    breakpoints: HashSet<u16>,
+    /// The address of the last breakpoint that was hit, used to not stop
+    /// again at the same breakpoint when execution is resumed, cleared when
+    /// a state is loaded
+    #[savable(skip, default)]
+    breakpoint_hit: Option<u16>,
+
+    /// Called before executing every instruction
+    #[savable(skip)]
+    trace_callback: Option<TraceCallback>,
+    /// Gameboy Doctor log lines are written here before every instruction
+    #[savable(skip)]
+    doctor_log: Option<Box<dyn Write>>,
+
     config: GameBoyConfig,
 }
 
@@ -115,10 +207,37 @@ impl Cpu {
             ime: false,
             halt_mode: HaltMode::NotHalting,
 
+            breakpoints: HashSet::new(),
+            breakpoint_hit: None,
+
+            trace_callback: None,
+            doctor_log: None,
+
             config,
         }
     }
 
+    /// Migrates the saved state of the CPU to the next version, see
+    /// [`StateMigration`]
+    pub fn migrate_state(migration: &mut StateMigration) -> save_state::Result<()> {
+        let cpu = Self::new(GameBoyConfig::default());
+
+        migration.copy(cpu.reg_a)?;
+        migration.copy(cpu.reg_b)?;
+        migration.copy(cpu.reg_c)?;
+        migration.copy(cpu.reg_d)?;
+        migration.copy(cpu.reg_e)?;
+        migration.copy(cpu.reg_h)?;
+        migration.copy(cpu.reg_l)?;
+        migration.copy(cpu.reg_f)?;
+        migration.copy(cpu.reg_sp)?;
+        migration.copy(cpu.reg_pc)?;
+        migration.copy(cpu.enable_interrupt_next)?;
+        migration.copy(cpu.ime)?;
+        migration.copy(cpu.halt_mode)?;
+        migration.config()
+    }
+
     /// create a new cpu, with states that match the ones the CPU would have
     /// if the boot-rom would run (default values for registers)
     pub fn new_without_boot_rom(config: GameBoyConfig, is_cart_cgb: bool) -> Self {
@@ -150,12 +269,49 @@ impl Cpu {
         cpu
     }
 
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+        self.breakpoint_hit = None;
+    }
+
+    pub fn set_trace_callback(&mut self, callback: Option<TraceCallback>) {
+        self.trace_callback = callback;
+    }
+
+    pub fn set_doctor_log(&mut self, writer: Option<Box<dyn Write>>) {
+        self.doctor_log = writer;
+    }
+
     pub fn next_instruction<P: CpuBusProvider>(&mut self, bus: &mut P) -> CpuState {
+        let cpu_state = self.step(bus);
+
+        // the write is reported after the step that performed it is complete
+        if let Some((addr, value)) = bus.take_watchpoint_hit() {
+            return CpuState::Watchpoint { addr, value };
+        }
+
+        cpu_state
+    }
+
+    fn step<P: CpuBusProvider>(&mut self, bus: &mut P) -> CpuState {
         if bus.stopped() {
             self.advance_bus(bus);
             return CpuState::Stopped;
         }
 
+        if bus.switching_speed() {
+            self.advance_bus(bus);
+            return CpuState::SpeedSwitching;
+        }
+
         if bus.is_hdma_running() {
             self.advance_bus(bus);
             return CpuState::RunningHDMA;
@@ -209,11 +365,23 @@ impl Cpu {
             return cpu_state;
         }
 
+        // stop before fetching the instruction, when resuming from the same
+        // breakpoint, the instruction should be executed normally
+        let resumed_from = self.breakpoint_hit.take();
+        if resumed_from != Some(self.reg_pc) && self.breakpoints.contains(&self.reg_pc) {
+            self.breakpoint_hit = Some(self.reg_pc);
+            return CpuState::Breakpoint(self.registers());
+        }
+
         if self.enable_interrupt_next {
             self.ime = true;
             self.enable_interrupt_next = false;
         }
 
+        if self.doctor_log.is_some() {
+            self.write_doctor_log(bus);
+        }
+
         let pc = self.reg_pc;
         let mut instruction = Instruction::from_byte(self.fetch_next_pc(bus), pc);
 
@@ -286,7 +454,7 @@ impl Cpu {
         self.reg_f.set(flag, value);
     }
 
-    fn registers(&self) -> CpuRegisters {
+    pub fn registers(&self) -> CpuRegisters {
         CpuRegisters {
             a: self.reg_a,
             b: self.reg_b,
@@ -301,6 +469,38 @@ impl Cpu {
         }
     }
 
+    /// The interrupt master enable flag
+    pub fn ime(&self) -> bool {
+        self.ime
+    }
+
+    pub fn quirk_state(&self) -> CpuQuirkState {
+        CpuQuirkState {
+            halt_mode: self.halt_mode,
+            ei_pending: self.enable_interrupt_next,
+        }
+    }
+
+    /// Sets the interrupt master enable flag immediately, cancelling any
+    /// pending enable from `EI`
+    pub fn set_ime(&mut self, ime: bool) {
+        self.ime = ime;
+        self.enable_interrupt_next = false;
+    }
+
+    /// Writes the state before executing the next instruction to the doctor
+    /// log, the log is removed if writing fails
+    fn write_doctor_log<P: CpuBusProvider>(&mut self, bus: &mut P) {
+        let pc_mem = [0, 1, 2, 3].map(|i| bus.peek(self.reg_pc.wrapping_add(i)));
+        let line = self.registers().doctor_log_line(pc_mem);
+
+        if let Some(log) = self.doctor_log.as_mut() {
+            if writeln!(log, "{}", line).is_err() {
+                self.doctor_log = None;
+            }
+        }
+    }
+
     fn fetch_next_pc<P: CpuBusProvider>(&mut self, bus: &mut P) -> u8 {
         let result = bus.read(self.reg_pc);
         bus.trigger_read_write_oam_bug(self.reg_pc);
@@ -409,7 +609,7 @@ impl Cpu {
 
     /// advances the bus and all other components by one machine cycle
     fn advance_bus<P: CpuBusProvider>(&mut self, bus: &mut P) {
-        bus.read(0);
+        bus.idle();
     }
 
     fn stack_push<P: CpuBusProvider>(&mut self, data: u16, bus: &mut P) {
@@ -450,6 +650,23 @@ impl Cpu {
         instruction: Instruction,
         bus: &mut P,
     ) -> CpuState {
+        if let Some(callback) = self.trace_callback.as_mut() {
+            // `reg_pc` is already after the opcode, report the instruction address
+            let registers = CpuRegisters {
+                a: self.reg_a,
+                b: self.reg_b,
+                c: self.reg_c,
+                d: self.reg_d,
+                e: self.reg_e,
+                f: self.reg_f.bits(),
+                h: self.reg_h,
+                l: self.reg_l,
+                sp: self.reg_sp,
+                pc: instruction.pc,
+            };
+            callback(&registers, &instruction);
+        }
+
         let src = self.read_operand(instruction.src, bus);
 
         let mut cpu_state = CpuState::Normal;
@@ -919,11 +1136,23 @@ impl Cpu {
                 0
             }
             Opcode::Stop => {
-                // TODO: respect wait time for speed switch
+                // if a speed switch is prepared, the CPU will be halted
+                // until the switch is done
                 bus.enter_stop_mode();
                 0
             }
-            Opcode::Illegal => todo!(),
+            Opcode::Illegal(byte) => {
+                // The CPU locks up on illegal opcodes, interrupts are
+                // not serviced anymore, and the PC does not advance,
+                // so the same instruction is executed again.
+                self.reg_pc = instruction.pc;
+                self.ime = false;
+                self.enable_interrupt_next = false;
+
+                cpu_state = CpuState::IllegalInstruction(byte);
+
+                0
+            }
             Opcode::Prefix => unreachable!(),
         };
 
@@ -938,3 +1167,397 @@ impl Cpu {
         cpu_state
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Cpu, CpuBusProvider, CpuState};
+    use crate::memory::InterruptType;
+    use crate::tests::{build_cgb_test_rom, test_cgb_gameboy, test_gameboy};
+    use crate::GameBoyConfig;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// A flat 64KB memory bus without any components, used to test the CPU alone
+    struct TestBus {
+        memory: Box<[u8; 0x10000]>,
+    }
+
+    impl TestBus {
+        fn new(program: &[u8]) -> Self {
+            let mut memory = Box::new([0; 0x10000]);
+            memory[0x100..0x100 + program.len()].copy_from_slice(program);
+            Self { memory }
+        }
+    }
+
+    impl CpuBusProvider for TestBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.memory[addr as usize]
+        }
+
+        fn write(&mut self, addr: u16, data: u8) {
+            self.memory[addr as usize] = data;
+        }
+
+        fn peek(&mut self, addr: u16) -> u8 {
+            self.memory[addr as usize]
+        }
+
+        fn take_next_interrupt(&mut self) -> Option<InterruptType> {
+            None
+        }
+
+        fn peek_next_interrupt(&mut self) -> Option<InterruptType> {
+            None
+        }
+
+        fn take_watchpoint_hit(&mut self) -> Option<(u16, u8)> {
+            None
+        }
+
+        fn is_hdma_running(&mut self) -> bool {
+            false
+        }
+
+        fn enter_stop_mode(&mut self) {}
+
+        fn stopped(&self) -> bool {
+            false
+        }
+
+        fn switching_speed(&self) -> bool {
+            false
+        }
+
+        fn trigger_write_oam_bug(&mut self, _addr: u16) {}
+
+        fn trigger_read_write_oam_bug(&mut self, _addr: u16) {}
+
+        fn read_no_oam_bug(&mut self, addr: u16) -> u8 {
+            self.read(addr)
+        }
+    }
+
+    #[test]
+    fn address_breakpoint() {
+        // NOP; NOP; INC A; NOP
+        let mut bus = TestBus::new(&[0x00, 0x00, 0x3C, 0x00]);
+        let mut cpu = Cpu::new_without_boot_rom(GameBoyConfig::default(), true);
+        cpu.add_breakpoint(0x102);
+
+        assert_eq!(cpu.next_instruction(&mut bus), CpuState::Normal);
+        assert_eq!(cpu.next_instruction(&mut bus), CpuState::Normal);
+
+        // stops before executing `INC A`
+        let a = cpu.reg_a;
+        let state = save_state::save_object(&cpu).unwrap();
+        match cpu.next_instruction(&mut bus) {
+            CpuState::Breakpoint(regs) => {
+                assert_eq!(regs.pc, 0x102);
+                assert_eq!(regs.a, a);
+            }
+            state => panic!("expected breakpoint, got {:?}", state),
+        }
+
+        // a loaded state stops at the breakpoint again
+        save_state::load_object(&mut cpu, &state).unwrap();
+        assert!(matches!(
+            cpu.next_instruction(&mut bus),
+            CpuState::Breakpoint(_)
+        ));
+
+        // resuming executes the instruction at the breakpoint
+        assert_eq!(cpu.next_instruction(&mut bus), CpuState::Normal);
+        assert_eq!(cpu.reg_a, a.wrapping_add(1));
+        assert_eq!(cpu.reg_pc, 0x103);
+
+        cpu.remove_breakpoint(0x102);
+        cpu.reg_pc = 0x102;
+        assert_eq!(cpu.next_instruction(&mut bus), CpuState::Normal);
+    }
+
+    #[test]
+    fn trace_callback() {
+        // NOP; LD A, $42; INC A; NOP
+        let mut bus = TestBus::new(&[0x00, 0x3E, 0x42, 0x3C, 0x00]);
+        let mut cpu = Cpu::new_without_boot_rom(GameBoyConfig::default(), true);
+
+        let traced = Rc::new(RefCell::new(Vec::new()));
+        let traced_clone = traced.clone();
+        cpu.set_trace_callback(Some(Box::new(move |regs, instruction| {
+            traced_clone
+                .borrow_mut()
+                .push((regs.pc, regs.a, instruction.to_string()));
+        })));
+
+        for _ in 0..3 {
+            cpu.next_instruction(&mut bus);
+        }
+
+        assert_eq!(
+            *traced.borrow(),
+            vec![
+                (0x100, 0x11, "NOP".to_string()),
+                (0x101, 0x11, "LD A, d8".to_string()),
+                (0x103, 0x42, "INC A".to_string()),
+            ]
+        );
+
+        cpu.set_trace_callback(None);
+        cpu.next_instruction(&mut bus);
+        assert_eq!(traced.borrow().len(), 3);
+    }
+
+    #[test]
+    fn doctor_log() {
+        struct SharedLog(Rc<RefCell<Vec<u8>>>);
+
+        impl std::io::Write for SharedLog {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.borrow_mut().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        // LD A, $42; JP $0100
+        let mut bus = TestBus::new(&[0x3E, 0x42, 0xC3, 0x00, 0x01]);
+        let mut cpu = Cpu::new_without_boot_rom(GameBoyConfig::default(), true);
+        let log = Rc::new(RefCell::new(Vec::new()));
+        cpu.set_doctor_log(Some(Box::new(SharedLog(log.clone()))));
+
+        for _ in 0..3 {
+            cpu.next_instruction(&mut bus);
+        }
+
+        let regs = cpu.registers();
+        let flags = format!(
+            "F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X}",
+            regs.f, regs.b, regs.c, regs.d, regs.e, regs.h, regs.l
+        );
+        let log = String::from_utf8(log.borrow().clone()).unwrap();
+        let lines = log.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                format!("A:11 {} SP:FFFE PC:0100 PCMEM:3E,42,C3,00", flags),
+                format!("A:42 {} SP:FFFE PC:0102 PCMEM:C3,00,01,00", flags),
+                format!("A:42 {} SP:FFFE PC:0100 PCMEM:3E,42,C3,00", flags),
+            ]
+        );
+    }
+
+    #[test]
+    fn illegal_instruction_locks_cpu() {
+        // EI; NOP; illegal 0xD3
+        let mut bus = TestBus::new(&[0xFB, 0x00, 0xD3]);
+        let mut cpu = Cpu::new_without_boot_rom(GameBoyConfig::default(), true);
+
+        cpu.next_instruction(&mut bus);
+        cpu.next_instruction(&mut bus);
+
+        for _ in 0..10 {
+            assert_eq!(
+                cpu.next_instruction(&mut bus),
+                CpuState::IllegalInstruction(0xD3)
+            );
+            assert_eq!(cpu.reg_pc, 0x102);
+            assert!(!cpu.ime);
+        }
+    }
+
+    #[test]
+    fn disassemble_range() {
+        #[rustfmt::skip]
+        let mut gb = test_cgb_gameboy(&[
+            0x3E, 0x12,       // LD A, 0x12
+            0xCB, 0x37,       // SWAP A
+            0xC3, 0x50, 0x01, // JP 0x0150
+        ]);
+
+        let lines = gb.disassemble_range(0x150, 3);
+        let summary = lines
+            .iter()
+            .map(|line| (line.address, line.bytes.as_slice(), line.text.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                (0x150, &[0x3E, 0x12][..], "LD A, $12"),
+                (0x152, &[0xCB, 0x37][..], "SWAP A"),
+                (0x154, &[0xC3, 0x50, 0x01][..], "JP $0150"),
+            ]
+        );
+
+        // `IE` is the last byte of memory
+        let lines = gb.disassemble_range(0xFFFF, 5);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].address, 0xFFFF);
+        assert_eq!(lines[0].bytes.len(), 1);
+    }
+
+    #[test]
+    fn known_entry_points() {
+        let gb = test_gameboy(&[0x18, 0xFE]);
+
+        assert_eq!(
+            gb.known_entry_points(),
+            [0x100, 0x40, 0x48, 0x50, 0x58, 0x60, 0x00, 0x08, 0x10, 0x18, 0x20, 0x28, 0x30, 0x38]
+        );
+    }
+
+    #[test]
+    fn dump_cpu_log_line() {
+        let mut gb = test_gameboy(&[0x18, 0xFE]);
+        let regs = gb.cpu.registers();
+
+        assert_eq!(
+            gb.dump_cpu_log_line(),
+            format!(
+                "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} \
+                 SP:FFFE PC:0100 PCMEM:C3,50,01,00",
+                regs.a, regs.f, regs.b, regs.c, regs.d, regs.e, regs.h, regs.l,
+            )
+        );
+    }
+
+    #[test]
+    fn request_interrupt_runs_handler() {
+        use crate::InterruptType;
+
+        // `LD A, 0x04`, `LDH (IE), A`, `EI`, `JR -2` (timer interrupt enabled)
+        let mut rom = build_cgb_test_rom(&[0x3E, 0x04, 0xE0, 0xFF, 0xFB, 0x18, 0xFE]);
+        // timer handler: `INC B`, `RETI`
+        rom[0x50] = 0x04;
+        rom[0x51] = 0xD9;
+        let mut gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+
+        for _ in 0..10 {
+            gb.cpu.next_instruction(&mut gb.bus);
+        }
+        let b = gb.cpu.registers().b;
+        let (interrupt_flags, interrupt_enable) = gb.pending_interrupts();
+        assert_eq!(interrupt_flags & 0x04, 0);
+        assert_eq!(interrupt_enable, 0x04);
+
+        gb.request_interrupt(InterruptType::Timer);
+        assert_eq!(gb.pending_interrupts().0 & 0x04, 0x04);
+
+        for _ in 0..10 {
+            gb.cpu.next_instruction(&mut gb.bus);
+        }
+        assert_eq!(gb.cpu.registers().b, b.wrapping_add(1));
+        assert_eq!(gb.pending_interrupts().0 & 0x04, 0);
+    }
+
+    #[test]
+    fn cpu_quirk_state() {
+        use crate::HaltMode;
+
+        // `DI`, `LD A, 1`, `LDH (IE), A`, `LDH (IF), A`, `HALT`, `INC A`,
+        // `XOR A`, `LDH (IF), A`, `EI`, `NOP`, `HALT`
+        let mut gb = test_gameboy(&[
+            0xF3, 0x3E, 0x01, 0xE0, 0xFF, 0xE0, 0x0F, 0x76, 0x3C, 0xAF, 0xE0, 0x0F, 0xFB, 0x00,
+            0x76,
+        ]);
+        // `JP 0x150` and the first 5 instructions
+        for _ in 0..6 {
+            gb.cpu.next_instruction(&mut gb.bus);
+        }
+        // `HALT` with a pending interrupt and `IME=0`
+        let state = gb.cpu_quirk_state();
+        assert_eq!(state.halt_mode, HaltMode::HaltBug);
+        assert!(!state.ei_pending);
+
+        // `INC A` is executed twice
+        gb.cpu.next_instruction(&mut gb.bus);
+        assert_eq!(gb.cpu_quirk_state().halt_mode, HaltMode::NotHalting);
+        assert_eq!(gb.cpu.registers().pc, 0x158);
+        gb.cpu.next_instruction(&mut gb.bus);
+        assert_eq!(gb.cpu.registers().a, 3);
+
+        for _ in 0..3 {
+            gb.cpu.next_instruction(&mut gb.bus);
+        }
+        // after `EI`
+        assert!(gb.cpu_quirk_state().ei_pending);
+        assert!(!gb.ime());
+
+        // `NOP`
+        gb.cpu.next_instruction(&mut gb.bus);
+        assert!(!gb.cpu_quirk_state().ei_pending);
+        assert!(gb.ime());
+
+        gb.cpu.next_instruction(&mut gb.bus);
+        assert_eq!(gb.cpu_quirk_state().halt_mode, HaltMode::HaltRunInterrupt);
+    }
+
+    #[test]
+    fn force_ime() {
+        use crate::InterruptType;
+
+        // `LD A, 0x04`, `LDH (IE), A`, `JR -2` (timer interrupt enabled, no `EI`)
+        let mut rom = build_cgb_test_rom(&[0x3E, 0x04, 0xE0, 0xFF, 0x18, 0xFE]);
+        // timer handler: `INC B`, `RETI`
+        rom[0x50] = 0x04;
+        rom[0x51] = 0xD9;
+        let mut gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+
+        for _ in 0..10 {
+            gb.cpu.next_instruction(&mut gb.bus);
+        }
+        assert!(!gb.ime());
+        let b = gb.cpu.registers().b;
+
+        gb.request_interrupt(InterruptType::Timer);
+        for _ in 0..10 {
+            gb.cpu.next_instruction(&mut gb.bus);
+        }
+        assert_eq!(gb.cpu.registers().b, b);
+
+        gb.set_ime(true);
+        assert!(gb.ime());
+        for _ in 0..10 {
+            gb.cpu.next_instruction(&mut gb.bus);
+        }
+        assert_eq!(gb.cpu.registers().b, b.wrapping_add(1));
+        // `RETI` enables interrupts again
+        assert!(gb.ime());
+
+        gb.set_ime(false);
+        assert!(!gb.ime());
+    }
+
+    #[test]
+    fn write_watchpoints() {
+        // `LD A, 0x42`, `LD (0xC000), A`, `LDH (0x80), A`, `LD (0xC001), A`, `JR -2`
+        let mut gb = test_cgb_gameboy(&[
+            0x3E, 0x42, 0xEA, 0x00, 0xC0, 0xE0, 0x80, 0xEA, 0x01, 0xC0, 0x18, 0xFE,
+        ]);
+        gb.add_write_watchpoint(0xC000);
+        gb.add_write_watchpoint_range(0xFF80, 0xFFFE);
+        gb.add_write_watchpoint(0xC001);
+        gb.remove_write_watchpoint(0xC001);
+
+        assert_eq!(
+            gb.clock_for_frame(),
+            CpuState::Watchpoint {
+                addr: 0xC000,
+                value: 0x42
+            }
+        );
+        // the write is already done
+        assert_eq!(gb.bus.read_not_ticked(0xC000, None), 0x42);
+        assert_eq!(
+            gb.clock_for_frame(),
+            CpuState::Watchpoint {
+                addr: 0xFF80,
+                value: 0x42
+            }
+        );
+        assert_eq!(gb.clock_for_frame(), CpuState::InfiniteLoop);
+        assert_eq!(gb.bus.read_not_ticked(0xC001, None), 0x42);
+    }
+}
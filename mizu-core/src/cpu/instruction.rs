@@ -1,12 +1,32 @@
 use super::instructions_table;
 use std::fmt::Display;
 
-#[derive(Debug)]
-pub(super) struct Instruction {
+/// A decoded CPU instruction.
+///
+/// Can be displayed in assembly form, e.g. `LD A, (HL)` or `JP NZ, $1234`.
+#[derive(Debug, Clone, Copy)]
+pub struct Instruction {
     pub pc: u16,
     pub opcode: Opcode,
     pub src: OperandType,
     pub dest: OperandType,
+    /// The value of the immediate operand (if any), this is only filled
+    /// when the instruction is decoded with [`Instruction::decode`],
+    /// in normal execution, the CPU reads the operands itself.
+    pub imm: Option<u16>,
+}
+
+/// A decoded instruction in memory, see
+/// [`GameBoy::disassemble_range`](crate::GameBoy::disassemble_range).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisassembledLine {
+    /// The address of the first byte of the instruction
+    pub address: u16,
+    /// The bytes of the instruction, including the prefix and the
+    /// immediate operand
+    pub bytes: Vec<u8>,
+    /// The instruction in assembly form, e.g. `JP NZ, $1234`
+    pub text: String,
 }
 
 /// This is the location the operands will come from,
@@ -150,7 +170,7 @@ pub enum Opcode {
     Res(u8),
     Set(u8),
 
-    Illegal,
+    Illegal(u8),
 
     Halt,
 }
@@ -164,6 +184,7 @@ impl Instruction {
             opcode,
             src: operand_types.1,
             dest: operand_types.0,
+            imm: None,
         }
     }
 
@@ -175,11 +196,85 @@ impl Instruction {
             opcode,
             src: operand_types.1,
             dest: operand_types.0,
+            imm: None,
+        }
+    }
+
+    /// Decodes the instruction at `addr` including its immediate operand,
+    /// using `read` to read the bytes.
+    ///
+    /// Returns the instruction and its length in bytes.
+    pub(crate) fn decode<F: FnMut(u16) -> u8>(addr: u16, mut read: F) -> (Self, u16) {
+        let mut instruction = Self::from_byte(read(addr), addr);
+        let mut len = 1;
+
+        if instruction.opcode == Opcode::Prefix {
+            instruction = Self::from_prefix(read(addr.wrapping_add(1)), addr);
+            len += 1;
         }
+
+        let imm_len = instruction.src.imm_len().max(instruction.dest.imm_len());
+
+        let mut imm = 0;
+        for i in 0..imm_len {
+            let byte = read(addr.wrapping_add(len + i)) as u16;
+            imm |= byte << (i * 8);
+        }
+        if imm_len != 0 {
+            instruction.imm = Some(imm);
+        }
+
+        (instruction, len + imm_len)
     }
 }
 
-fn operand_str(operand: OperandType) -> String {
+/// Returns the length in bytes of the instruction starting with `opcode`,
+/// including its immediate operand, without decoding the operand.
+///
+/// If `is_cb` is `true`, `opcode` is the byte following the `0xCB` prefix,
+/// prefixed instructions are always 2 bytes. Otherwise `0xCB` itself
+/// returns `2` as well. The lengths are the same as the ones used by the CPU
+/// and [`GameBoy::disassemble`](crate::GameBoy::disassemble).
+pub fn instruction_length(opcode: u8, is_cb: bool) -> u8 {
+    if is_cb {
+        return 2;
+    }
+
+    let instruction = Instruction::from_byte(opcode, 0);
+    if instruction.opcode == Opcode::Prefix {
+        return 2;
+    }
+
+    1 + instruction.src.imm_len().max(instruction.dest.imm_len()) as u8
+}
+
+impl OperandType {
+    /// The number of bytes following the opcode needed for this operand
+    fn imm_len(self) -> u16 {
+        match self {
+            OperandType::Imm8 | OperandType::Imm8Signed | OperandType::HighAddr8 => 1,
+            OperandType::Imm16 | OperandType::Addr16 | OperandType::Addr16Val16 => 2,
+            _ => 0,
+        }
+    }
+}
+
+fn operand_str(operand: OperandType, imm: Option<u16>) -> String {
+    match (operand, imm) {
+        (OperandType::Imm8, Some(imm)) => return format!("${:02X}", imm),
+        (OperandType::Imm8Signed, Some(imm)) => {
+            let imm = imm as u8 as i8;
+            let sign = if imm < 0 { '-' } else { '+' };
+            return format!("{}${:02X}", sign, imm.unsigned_abs());
+        }
+        (OperandType::Imm16, Some(imm)) => return format!("${:04X}", imm),
+        (OperandType::HighAddr8, Some(imm)) => return format!("(${:04X})", 0xFF00 | imm),
+        (OperandType::Addr16 | OperandType::Addr16Val16, Some(imm)) => {
+            return format!("(${:04X})", imm)
+        }
+        _ => {}
+    }
+
     match operand {
         OperandType::RegA => "A".into(),
         OperandType::RegB => "B".into(),
@@ -202,7 +297,7 @@ fn operand_str(operand: OperandType) -> String {
         OperandType::Imm8Signed => "r8".into(),
         OperandType::Imm16 => "d16".into(),
         OperandType::HighAddr8 => "(a8)".into(),
-        OperandType::HighAddrC => "(C)".into(),
+        OperandType::HighAddrC => "($FF00+C)".into(),
         OperandType::Addr16 => "(a16)".into(),
         OperandType::Addr16Val16 => "(a16)".into(),
         OperandType::Implied => "".into(),
@@ -211,80 +306,149 @@ fn operand_str(operand: OperandType) -> String {
 
 impl Display for Instruction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let opcode: String = match self.opcode {
-            Opcode::Nop => "NOP".into(),
-            Opcode::Stop => "STOP".into(),
-            Opcode::Ld => "LD".into(),
-            Opcode::LdSPHL => "LD SP, HL".into(),
-            Opcode::LdHLSPSigned8 => "LDHLSP".into(),
-            Opcode::LdBB => "LD B,B".into(),
-            Opcode::Push => "PUSH".into(),
-            Opcode::Pop => "POP".into(),
-            Opcode::Inc => "INC".into(),
-            Opcode::Inc16 => "INC".into(),
-            Opcode::Dec => "DEC".into(),
-            Opcode::Dec16 => "DEC".into(),
-            Opcode::Add => "ADD".into(),
-            Opcode::Add16 => "ADD".into(),
-            Opcode::AddSPSigned8 => "ADD".into(),
-            Opcode::Adc => "ADC".into(),
-            Opcode::Cp => "CP".into(),
-            Opcode::Sub => "SUB".into(),
-            Opcode::Sbc => "SBC".into(),
-            Opcode::And => "AND".into(),
-            Opcode::Xor => "XOR".into(),
-            Opcode::Or => "OR".into(),
-            Opcode::Jp(Condition::Unconditional) => "JP".into(),
-            Opcode::Jp(cond) => format!("JP {:?},", cond),
-            Opcode::JpHL => "JP".into(),
-            Opcode::Jr(Condition::Unconditional) => "JR".into(),
-            Opcode::Jr(cond) => format!("JR {:?},", cond),
-            Opcode::Call(Condition::Unconditional) => "CALL".into(),
-            Opcode::Call(cond) => format!("CALL {:?},", cond),
-            Opcode::Ret(Condition::Unconditional) => "RET".into(),
-            Opcode::Ret(cond) => format!("RET {:?},", cond),
-            Opcode::Reti => "RETI".into(),
-            Opcode::Rst(loc) => format!("RST {:02X}", loc),
-            Opcode::Di => "DI".into(),
-            Opcode::Ei => "EI".into(),
-            Opcode::Ccf => "CCF".into(),
-            Opcode::Scf => "SCF".into(),
-            Opcode::Daa => "DAA".into(),
-            Opcode::Cpl => "CPL".into(),
-            Opcode::Rlca => "RLCA".into(),
-            Opcode::Rla => "RLA".into(),
-            Opcode::Rrca => "RRCA".into(),
-            Opcode::Rra => "RRA".into(),
-            Opcode::Prefix => "PREFIX".into(),
-            Opcode::Rlc => "RLC".into(),
-            Opcode::Rrc => "RRC".into(),
-            Opcode::Rl => "RL".into(),
-            Opcode::Rr => "RR".into(),
-            Opcode::Sla => "SLA".into(),
-            Opcode::Sra => "SRA".into(),
-            Opcode::Swap => "SWAP".into(),
-            Opcode::Srl => "SRL".into(),
-            Opcode::Bit(n) => format!("BIT {},", n),
-            Opcode::Res(n) => format!("RES {},", n),
-            Opcode::Set(n) => format!("SET {},", n),
-            Opcode::Illegal => "ILLEGAL".into(),
-            Opcode::Halt => "HALT".into(),
+        let mut operands = Vec::with_capacity(3);
+
+        let opcode = match self.opcode {
+            Opcode::Nop => "NOP",
+            Opcode::Stop => "STOP",
+            Opcode::Ld => "LD",
+            Opcode::LdSPHL => {
+                operands.push("SP".into());
+                operands.push("HL".into());
+                "LD"
+            }
+            Opcode::LdHLSPSigned8 => {
+                // special form `LD HL, SP+r8`
+                let offset = operand_str(self.src, self.imm);
+                return write!(f, "LD HL, SP{}", offset);
+            }
+            Opcode::LdBB => {
+                operands.push("B".into());
+                operands.push("B".into());
+                "LD"
+            }
+            Opcode::Push => "PUSH",
+            Opcode::Pop => "POP",
+            Opcode::Inc | Opcode::Inc16 => "INC",
+            Opcode::Dec | Opcode::Dec16 => "DEC",
+            Opcode::Add | Opcode::Add16 | Opcode::AddSPSigned8 => "ADD",
+            Opcode::Adc => "ADC",
+            Opcode::Cp => "CP",
+            Opcode::Sub => "SUB",
+            Opcode::Sbc => "SBC",
+            Opcode::And => "AND",
+            Opcode::Xor => "XOR",
+            Opcode::Or => "OR",
+            Opcode::Jp(cond) | Opcode::Jr(cond) | Opcode::Call(cond) | Opcode::Ret(cond) => {
+                if cond != Condition::Unconditional {
+                    operands.push(format!("{:?}", cond));
+                }
+                match self.opcode {
+                    Opcode::Jp(_) => "JP",
+                    Opcode::Jr(_) => "JR",
+                    Opcode::Call(_) => "CALL",
+                    _ => "RET",
+                }
+            }
+            Opcode::JpHL => "JP",
+            Opcode::Reti => "RETI",
+            Opcode::Rst(loc) => {
+                operands.push(format!("${:02X}", loc));
+                "RST"
+            }
+            Opcode::Di => "DI",
+            Opcode::Ei => "EI",
+            Opcode::Ccf => "CCF",
+            Opcode::Scf => "SCF",
+            Opcode::Daa => "DAA",
+            Opcode::Cpl => "CPL",
+            Opcode::Rlca => "RLCA",
+            Opcode::Rla => "RLA",
+            Opcode::Rrca => "RRCA",
+            Opcode::Rra => "RRA",
+            Opcode::Prefix => "PREFIX",
+            Opcode::Rlc => "RLC",
+            Opcode::Rrc => "RRC",
+            Opcode::Rl => "RL",
+            Opcode::Rr => "RR",
+            Opcode::Sla => "SLA",
+            Opcode::Sra => "SRA",
+            Opcode::Swap => "SWAP",
+            Opcode::Srl => "SRL",
+            Opcode::Bit(n) | Opcode::Res(n) | Opcode::Set(n) => {
+                operands.push(n.to_string());
+                match self.opcode {
+                    Opcode::Bit(_) => "BIT",
+                    Opcode::Res(_) => "RES",
+                    _ => "SET",
+                }
+            }
+            Opcode::Illegal(byte) => {
+                operands.push(format!("${:02X}", byte));
+                "ILLEGAL"
+            }
+            Opcode::Halt => "HALT",
         };
 
-        let mut operands = operand_str(self.dest);
-        if operands.is_empty() {
-            operands = operand_str(self.src);
-        } else if !operand_str(self.src).is_empty() {
-            operands += &format!(",{}", operand_str(self.src));
+        // read-modify-write instructions like `INC A` have the same operand
+        // as source and destination, and `SUB`/`AND`/`XOR`/`OR` always use `A`
+        // as destination, in both cases, only one operand is shown
+        let single_operand = matches!(
+            self.opcode,
+            Opcode::Inc
+                | Opcode::Inc16
+                | Opcode::Dec
+                | Opcode::Dec16
+                | Opcode::Rlc
+                | Opcode::Rrc
+                | Opcode::Rl
+                | Opcode::Rr
+                | Opcode::Sla
+                | Opcode::Sra
+                | Opcode::Swap
+                | Opcode::Srl
+                | Opcode::Res(_)
+                | Opcode::Set(_)
+        );
+        let skip_dest = matches!(
+            self.opcode,
+            Opcode::Sub | Opcode::And | Opcode::Xor | Opcode::Or
+        );
+
+        let shown_operands = if single_operand {
+            [self.dest, OperandType::Implied]
+        } else if skip_dest {
+            [OperandType::Implied, self.src]
+        } else {
+            [self.dest, self.src]
+        };
+
+        for operand in shown_operands {
+            let operand = match (self.opcode, operand, self.imm) {
+                // show the jump target instead of the relative offset
+                (Opcode::Jr(_), OperandType::Imm8Signed, Some(imm)) => {
+                    let target = self.pc.wrapping_add(2).wrapping_add(imm as u8 as i8 as u16);
+                    format!("${:04X}", target)
+                }
+                _ => operand_str(operand, self.imm),
+            };
+
+            if !operand.is_empty() {
+                operands.push(operand);
+            }
         }
 
-        write!(f, "{} {}", opcode, operands)
+        if operands.is_empty() {
+            write!(f, "{}", opcode)
+        } else {
+            write!(f, "{} {}", opcode, operands.join(", "))
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Instruction;
+    use super::{instruction_length, Instruction};
 
     #[test]
     fn available_instructions() {
@@ -299,4 +463,47 @@ mod tests {
             Instruction::from_prefix(i, 0);
         }
     }
+
+    fn decode(bytes: &[u8], addr: u16) -> (String, u16) {
+        let (instruction, len) =
+            Instruction::decode(addr, |a| bytes[a.wrapping_sub(addr) as usize]);
+        (instruction.to_string(), len)
+    }
+
+    #[test]
+    fn display_instructions() {
+        assert_eq!(decode(&[0x00], 0), ("NOP".into(), 1));
+        assert_eq!(decode(&[0x7E], 0), ("LD A, (HL)".into(), 1));
+        assert_eq!(decode(&[0xC2, 0x34, 0x12], 0), ("JP NZ, $1234".into(), 3));
+        assert_eq!(decode(&[0xC3, 0x50, 0x01], 0), ("JP $0150".into(), 3));
+        assert_eq!(decode(&[0x3E, 0x42], 0), ("LD A, $42".into(), 2));
+        assert_eq!(decode(&[0xE0, 0x40], 0), ("LD ($FF40), A".into(), 2));
+        assert_eq!(decode(&[0x08, 0x00, 0xC0], 0), ("LD ($C000), SP".into(), 3));
+        assert_eq!(decode(&[0x20, 0xFE], 0x200), ("JR NZ, $0200".into(), 2));
+        assert_eq!(decode(&[0xF8, 0xFF], 0), ("LD HL, SP-$01".into(), 2));
+        assert_eq!(decode(&[0xCB, 0x7C], 0), ("BIT 7, H".into(), 2));
+        assert_eq!(decode(&[0xFF], 0), ("RST $38".into(), 1));
+        assert_eq!(decode(&[0x3C], 0), ("INC A".into(), 1));
+        assert_eq!(decode(&[0x7F], 0), ("LD A, A".into(), 1));
+        assert_eq!(decode(&[0x80], 0), ("ADD A, B".into(), 1));
+        assert_eq!(decode(&[0x90], 0), ("SUB B".into(), 1));
+        assert_eq!(decode(&[0xE6, 0x0F], 0), ("AND $0F".into(), 2));
+        assert_eq!(decode(&[0xCB, 0xC6], 0), ("SET 0, (HL)".into(), 2));
+    }
+
+    #[test]
+    fn instruction_length_matches_decode() {
+        for i in 0..=255u8 {
+            let (_, len) = Instruction::decode(0, |a| if a == 0 { i } else { 0 });
+            assert_eq!(instruction_length(i, false) as u16, len, "opcode {:02X}", i);
+
+            let (_, len) = Instruction::decode(0, |a| if a == 0 { 0xCB } else { i });
+            assert_eq!(
+                instruction_length(i, true) as u16,
+                len,
+                "opcode CB {:02X}",
+                i
+            );
+        }
+    }
 }
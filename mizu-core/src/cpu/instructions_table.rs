@@ -216,7 +216,7 @@ pub(super) const INSTRUCTIONS: &[(Opcode, (OperandType, OperandType)); 256] = &[
     (Ret(NC), (Implied, Implied)),
     (Pop, (RegDE, Implied)),
     (Jp(NC), (Implied, Imm16)),
-    (Illegal, (Implied, Implied)),
+    (Illegal(0xD3), (Implied, Implied)),
     (Call(NC), (Implied, Imm16)),
     (Push, (Implied, RegDE)),
     (Sub, (RegA, Imm8)),
@@ -224,32 +224,32 @@ pub(super) const INSTRUCTIONS: &[(Opcode, (OperandType, OperandType)); 256] = &[
     (Ret(C), (Implied, Implied)),
     (Reti, (Implied, Implied)),
     (Jp(C), (Implied, Imm16)),
-    (Illegal, (Implied, Implied)),
+    (Illegal(0xDB), (Implied, Implied)),
     (Call(C), (Implied, Imm16)),
-    (Illegal, (Implied, Implied)),
+    (Illegal(0xDD), (Implied, Implied)),
     (Sbc, (RegA, Imm8)),
     (Rst(24), (Implied, Implied)),
     (Ld, (HighAddr8, RegA)),
     (Pop, (RegHL, Implied)),
     (Ld, (HighAddrC, RegA)),
-    (Illegal, (Implied, Implied)),
-    (Illegal, (Implied, Implied)),
+    (Illegal(0xE3), (Implied, Implied)),
+    (Illegal(0xE4), (Implied, Implied)),
     (Push, (Implied, RegHL)),
     (And, (RegA, Imm8)),
     (Rst(32), (Implied, Implied)),
     (AddSPSigned8, (RegSP, Imm8Signed)),
     (JpHL, (Implied, RegHL)),
     (Ld, (Addr16, RegA)),
-    (Illegal, (Implied, Implied)),
-    (Illegal, (Implied, Implied)),
-    (Illegal, (Implied, Implied)),
+    (Illegal(0xEB), (Implied, Implied)),
+    (Illegal(0xEC), (Implied, Implied)),
+    (Illegal(0xED), (Implied, Implied)),
     (Xor, (RegA, Imm8)),
     (Rst(40), (Implied, Implied)),
     (Ld, (RegA, HighAddr8)),
     (Pop, (RegAF, Implied)),
     (Ld, (RegA, HighAddrC)),
     (Di, (Implied, Implied)),
-    (Illegal, (Implied, Implied)),
+    (Illegal(0xF4), (Implied, Implied)),
     (Push, (Implied, RegAF)),
     (Or, (RegA, Imm8)),
     (Rst(48), (Implied, Implied)),
@@ -257,8 +257,8 @@ pub(super) const INSTRUCTIONS: &[(Opcode, (OperandType, OperandType)); 256] = &[
     (LdSPHL, (Implied, Implied)),
     (Ld, (RegA, Addr16)),
     (Ei, (Implied, Implied)),
-    (Illegal, (Implied, Implied)),
-    (Illegal, (Implied, Implied)),
+    (Illegal(0xFC), (Implied, Implied)),
+    (Illegal(0xFD), (Implied, Implied)),
     (Cp, (Implied, Imm8)),
     (Rst(56), (Implied, Implied)),
 ];
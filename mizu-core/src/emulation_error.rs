@@ -0,0 +1,12 @@
+/// An error that stops the emulation, returned by
+/// [`GameBoy::try_clock_for_frame`](crate::GameBoy::try_clock_for_frame).
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmulationError {
+    /// The CPU executed an illegal opcode at `pc`, and is locked up.
+    #[error("Illegal instruction {opcode:#04X} at {pc:#06X}")]
+    IllegalInstruction { opcode: u8, pc: u16 },
+    /// The CPU is stuck in a jump to itself at `pc`, with interrupts
+    /// disabled (either `IME` or `IE`), so it can never exit.
+    #[error("The CPU is stuck in an infinite loop at {pc:#06X} with interrupts disabled")]
+    Deadlock { pc: u16 },
+}
@@ -0,0 +1,82 @@
+use save_state::Savable;
+
+/// Callback called with the new state of the infrared LED of the gameboy
+/// (`true` when lit), see [`crate::GameBoy::set_ir_led_callback`]
+pub type IrLedCallback = Box<dyn FnMut(bool)>;
+
+/// The infrared communication port (`RP` register) of the CGB
+#[derive(Default, Savable)]
+pub struct Infrared {
+    led_on: bool,
+    read_enable: u8,
+
+    #[savable(skip)]
+    input_lit: bool,
+    #[savable(skip)]
+    led_callback: Option<IrLedCallback>,
+}
+
+impl Infrared {
+    pub fn read_register(&self) -> u8 {
+        // bit 1 is `0` when receiving a signal, and it reads `1` if
+        // reading is disabled
+        let receiving = self.read_enable == 3 && self.input_lit;
+
+        (self.read_enable << 6) | 0x3C | ((!receiving as u8) << 1) | self.led_on as u8
+    }
+
+    pub fn write_register(&mut self, data: u8) {
+        self.read_enable = data >> 6;
+
+        let led_on = data & 1 != 0;
+        if led_on != self.led_on {
+            self.led_on = led_on;
+
+            if let Some(callback) = self.led_callback.as_mut() {
+                callback(led_on);
+            }
+        }
+    }
+
+    pub fn set_input(&mut self, lit: bool) {
+        self.input_lit = lit;
+    }
+
+    pub fn set_led_callback(&mut self, callback: Option<IrLedCallback>) {
+        self.led_callback = callback;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Infrared;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn led_and_input() {
+        let mut infrared = Infrared::default();
+        let led_changes = Rc::new(RefCell::new(Vec::new()));
+        let led_changes_clone = led_changes.clone();
+        infrared.set_led_callback(Some(Box::new(move |lit| {
+            led_changes_clone.borrow_mut().push(lit)
+        })));
+
+        assert_eq!(infrared.read_register(), 0x3E);
+
+        // reading is disabled, input is ignored
+        infrared.set_input(true);
+        assert_eq!(infrared.read_register(), 0x3E);
+
+        infrared.write_register(0xC0);
+        assert_eq!(infrared.read_register(), 0xFC);
+        infrared.set_input(false);
+        assert_eq!(infrared.read_register(), 0xFE);
+
+        infrared.write_register(0xC1);
+        infrared.write_register(0xC1);
+        infrared.write_register(0x00);
+        assert_eq!(infrared.read_register(), 0x3E);
+        assert_eq!(led_changes.borrow().as_slice(), &[true, false]);
+    }
+}
@@ -5,7 +5,11 @@ use std::convert::From;
 
 use crate::memory::{InterruptManager, InterruptType};
 
+/// The number of frames per second of the GameBoy, used for autofire timing
+const FRAMES_PER_SECOND: f32 = 4194304.0 / 70224.0;
+
 /// Joypad button types of the GameBoy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum JoypadButton {
     Start,
     Select,
@@ -17,9 +21,24 @@ pub enum JoypadButton {
     Right,
 }
 
+/// When the joypad interrupt is requested, see
+/// [`GameBoy::set_joypad_interrupt_mode`](crate::GameBoy::set_joypad_interrupt_mode).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum JoypadIntMode {
+    /// On the high to low transition of any of the selected `P1` input
+    /// lines, i.e. when a selected button is pressed, as in the hardware
+    #[default]
+    Hardware,
+    /// On any change of the selected `P1` input lines, i.e. when a selected
+    /// button is pressed or released, for debugging
+    AnyChange,
+}
+
 bitflags! {
-    #[derive(Default)]
-    struct JoypadButtons:u8 {
+    /// The state of all the joypad buttons, a set bit means the button
+    /// is pressed.
+    #[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct JoypadState:u8 {
         const START  = 1 << 7;
         const SELECT = 1 << 6;
         const B      = 1 << 5;
@@ -31,7 +50,43 @@ bitflags! {
     }
 }
 
-impl From<JoypadButton> for JoypadButtons {
+impl JoypadButton {
+    /// Returns all the buttons, in the order of their bits in [`JoypadState`]
+    /// from the highest to the lowest
+    pub fn all() -> [JoypadButton; 8] {
+        [
+            Self::Start,
+            Self::Select,
+            Self::B,
+            Self::A,
+            Self::Down,
+            Self::Up,
+            Self::Left,
+            Self::Right,
+        ]
+    }
+
+    /// Returns the bit of this button in a [`JoypadState`] mask
+    pub fn bit_mask(&self) -> u8 {
+        JoypadState::from(*self).bits()
+    }
+
+    /// Returns the buttons which have their bits set in `mask`
+    pub fn from_mask(mask: u8) -> impl Iterator<Item = JoypadButton> {
+        Self::all()
+            .into_iter()
+            .filter(move |button| mask & button.bit_mask() != 0)
+    }
+
+    /// Returns a [`JoypadState`] mask with the bits of all `buttons` set
+    pub fn to_mask(buttons: &[JoypadButton]) -> u8 {
+        buttons
+            .iter()
+            .fold(0, |mask, button| mask | button.bit_mask())
+    }
+}
+
+impl From<JoypadButton> for JoypadState {
     fn from(button: JoypadButton) -> Self {
         match button {
             JoypadButton::Start => Self::START,
@@ -48,12 +103,26 @@ impl From<JoypadButton> for JoypadButtons {
 
 #[derive(Savable)]
 pub struct Joypad {
+    /// The buttons held by the user
     #[savable(skip)]
-    buttons: JoypadButtons,
+    buttons: JoypadState,
     selecting_directions: bool,
     selecting_start: bool,
 
     old_p1: u8,
+
+    /// Autofire rate in Hz of each button, indexed by the bit of the button
+    /// in [`JoypadState`]
+    #[savable(skip)]
+    autofire_rates: [Option<f32>; 8],
+    /// Number of frames each autofire button has been held
+    #[savable(skip)]
+    autofire_held_frames: [u32; 8],
+    /// Held autofire buttons that are in the released part of the cycle
+    #[savable(skip)]
+    autofire_released: JoypadState,
+    #[savable(skip)]
+    interrupt_mode: JoypadIntMode,
 }
 
 impl Default for Joypad {
@@ -63,6 +132,10 @@ impl Default for Joypad {
             selecting_directions: true,
             selecting_start: true,
             old_p1: 0,
+            autofire_rates: [None; 8],
+            autofire_held_frames: [0; 8],
+            autofire_released: JoypadState::empty(),
+            interrupt_mode: JoypadIntMode::Hardware,
         }
     }
 }
@@ -71,12 +144,13 @@ impl Joypad {
     /// returns the lower 4 bits of P1 (joypad register)
     pub fn get_keys_pressed(&self) -> u8 {
         let mut result = 0xF;
+        let buttons = self.buttons - self.autofire_released;
 
         if self.selecting_start {
-            result &= !self.buttons.bits() >> 4;
+            result &= !buttons.bits() >> 4;
         }
         if self.selecting_directions {
-            result &= !self.buttons.bits();
+            result &= !buttons.bits();
         }
 
         result
@@ -98,7 +172,11 @@ impl Joypad {
     pub fn update_interrupts<I: InterruptManager>(&mut self, interrupt: &mut I) {
         let new_p1 = self.get_keys_pressed();
 
-        let should_interrupt = (self.old_p1 ^ new_p1) & self.old_p1 != 0;
+        let changed = self.old_p1 ^ new_p1;
+        let should_interrupt = match self.interrupt_mode {
+            JoypadIntMode::Hardware => changed & self.old_p1 != 0,
+            JoypadIntMode::AnyChange => changed != 0,
+        };
 
         if should_interrupt {
             interrupt.request_interrupt(InterruptType::Joypad);
@@ -107,6 +185,10 @@ impl Joypad {
         self.old_p1 = new_p1;
     }
 
+    pub fn set_interrupt_mode(&mut self, mode: JoypadIntMode) {
+        self.interrupt_mode = mode;
+    }
+
     pub fn press_joypad(&mut self, button: JoypadButton) {
         self.buttons.insert(button.into())
     }
@@ -114,4 +196,216 @@ impl Joypad {
     pub fn release_joypad(&mut self, button: JoypadButton) {
         self.buttons.remove(button.into())
     }
+
+    pub fn state(&self) -> JoypadState {
+        self.buttons
+    }
+
+    pub fn set_state(&mut self, state: JoypadState) {
+        self.buttons = state;
+    }
+
+    pub fn set_autofire(&mut self, button: JoypadButton, rate_hz: Option<f32>) {
+        let index = button.bit_mask().trailing_zeros() as usize;
+        let button = JoypadState::from(button);
+
+        self.autofire_rates[index] = rate_hz.filter(|&rate| rate > 0.0);
+        self.autofire_held_frames[index] = 0;
+        self.autofire_released.remove(button);
+    }
+
+    /// Advances the autofire cycle of the held buttons, called at the
+    /// start of every frame
+    pub fn clock_autofire(&mut self) {
+        for (index, rate) in self.autofire_rates.iter().enumerate() {
+            let Some(rate) = rate else {
+                continue;
+            };
+            let button = JoypadState::from_bits_truncate(1 << index);
+
+            if self.buttons.contains(button) {
+                // starts pressed, and released for the second half of the cycle
+                let cycles = self.autofire_held_frames[index] as f32 * rate / FRAMES_PER_SECOND;
+                self.autofire_released.set(button, cycles.fract() >= 0.5);
+                self.autofire_held_frames[index] += 1;
+            } else {
+                self.autofire_released.remove(button);
+                self.autofire_held_frames[index] = 0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Joypad, JoypadButton, JoypadIntMode, JoypadState};
+    use crate::memory::{InterruptManager, InterruptType};
+    use crate::tests::test_gameboy;
+
+    #[derive(Default)]
+    struct JoypadInterrupt(u32);
+
+    impl InterruptManager for JoypadInterrupt {
+        fn request_interrupt(&mut self, interrupt: InterruptType) {
+            if interrupt == InterruptType::Joypad {
+                self.0 += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn set_state_interrupts_on_net_transition() {
+        let mut joypad = Joypad::default();
+        let mut interrupt = JoypadInterrupt::default();
+        // select the buttons only
+        joypad.write_joypad(0x10);
+        joypad.update_interrupts(&mut interrupt);
+        assert_eq!(interrupt.0, 0);
+
+        joypad.set_state(JoypadState::A | JoypadState::RIGHT);
+        assert_eq!(joypad.state(), JoypadState::A | JoypadState::RIGHT);
+        joypad.update_interrupts(&mut interrupt);
+        assert_eq!(interrupt.0, 1);
+
+        // pressing and releasing in between does not change anything
+        joypad.set_state(JoypadState::A | JoypadState::START);
+        joypad.set_state(JoypadState::A | JoypadState::RIGHT);
+        joypad.update_interrupts(&mut interrupt);
+        assert_eq!(interrupt.0, 1);
+
+        // only releases, and direction changes are not selected
+        joypad.set_state(JoypadState::LEFT);
+        joypad.update_interrupts(&mut interrupt);
+        assert_eq!(interrupt.0, 1);
+
+        joypad.set_state(JoypadState::B | JoypadState::START);
+        joypad.update_interrupts(&mut interrupt);
+        assert_eq!(interrupt.0, 2);
+        assert_eq!(joypad.read_joypad() & 0xF, 0b0101);
+    }
+
+    #[test]
+    fn autofire_toggles_held_button() {
+        let mut joypad = Joypad::default();
+        // select the buttons only
+        joypad.write_joypad(0x10);
+        let a_pressed = |joypad: &Joypad| joypad.get_keys_pressed() & 1 == 0;
+
+        // ~4 frames per cycle
+        joypad.set_autofire(JoypadButton::A, Some(15.));
+        joypad.clock_autofire();
+        assert!(!a_pressed(&joypad));
+
+        joypad.press_joypad(JoypadButton::A);
+        let mut pattern = Vec::new();
+        for _ in 0..8 {
+            joypad.clock_autofire();
+            pattern.push(a_pressed(&joypad));
+        }
+        assert_eq!(
+            pattern,
+            [true, true, false, false, true, true, false, false]
+        );
+        // the held state is not affected
+        assert_eq!(joypad.state(), JoypadState::A);
+
+        // releasing restarts the cycle
+        joypad.clock_autofire();
+        joypad.clock_autofire();
+        joypad.release_joypad(JoypadButton::A);
+        joypad.clock_autofire();
+        assert!(!a_pressed(&joypad));
+        joypad.press_joypad(JoypadButton::A);
+        joypad.clock_autofire();
+        assert!(a_pressed(&joypad));
+
+        joypad.clock_autofire();
+        joypad.clock_autofire();
+        joypad.set_autofire(JoypadButton::A, None);
+        for _ in 0..8 {
+            joypad.clock_autofire();
+            assert!(a_pressed(&joypad));
+        }
+    }
+
+    #[test]
+    fn any_change_interrupt_mode() {
+        let mut joypad = Joypad::default();
+        let mut interrupt = JoypadInterrupt::default();
+        joypad.write_joypad(0x10);
+        joypad.update_interrupts(&mut interrupt);
+        joypad.set_interrupt_mode(JoypadIntMode::AnyChange);
+
+        joypad.press_joypad(JoypadButton::A);
+        joypad.update_interrupts(&mut interrupt);
+        assert_eq!(interrupt.0, 1);
+
+        joypad.release_joypad(JoypadButton::A);
+        joypad.update_interrupts(&mut interrupt);
+        assert_eq!(interrupt.0, 2);
+
+        // no change, no interrupt
+        joypad.update_interrupts(&mut interrupt);
+        assert_eq!(interrupt.0, 2);
+
+        // directions are not selected
+        joypad.press_joypad(JoypadButton::Left);
+        joypad.update_interrupts(&mut interrupt);
+        assert_eq!(interrupt.0, 2);
+
+        // releasing in hardware mode does not interrupt
+        joypad.set_interrupt_mode(JoypadIntMode::Hardware);
+        joypad.press_joypad(JoypadButton::B);
+        joypad.update_interrupts(&mut interrupt);
+        joypad.release_joypad(JoypadButton::B);
+        joypad.update_interrupts(&mut interrupt);
+        assert_eq!(interrupt.0, 3);
+    }
+
+    #[test]
+    fn button_masks() {
+        let all = JoypadButton::all();
+        for (i, button) in all.iter().enumerate() {
+            assert_eq!(button.bit_mask(), 0x80 >> i);
+            assert_eq!(JoypadState::from(*button).bits(), button.bit_mask());
+        }
+        assert_eq!(JoypadButton::to_mask(&all), 0xFF);
+        assert_eq!(JoypadButton::to_mask(&[]), 0);
+        assert_eq!(JoypadButton::from_mask(0xFF).collect::<Vec<_>>(), all);
+
+        let buttons = [JoypadButton::A, JoypadButton::Left];
+        let mask = JoypadButton::to_mask(&buttons);
+        assert_eq!(mask, (JoypadState::A | JoypadState::LEFT).bits());
+        assert_eq!(JoypadButton::from_mask(mask).collect::<Vec<_>>(), buttons);
+    }
+
+    #[test]
+    fn joypad_snapshot_interrupts() {
+        use crate::{JoypadButton, JoypadState};
+
+        let mut gb = test_gameboy(&[0x18, 0xFE]);
+        gb.clock_for_frame();
+        assert_eq!(gb.interrupts_fired_this_frame().joypad, 0);
+
+        gb.apply_joypad_snapshot(&[JoypadButton::A, JoypadButton::Start, JoypadButton::Up]);
+        gb.clock_for_scanline();
+        assert_eq!(gb.interrupts_fired_this_frame().joypad, 1);
+        assert_eq!(
+            gb.joypad_state(),
+            JoypadState::A | JoypadState::START | JoypadState::UP
+        );
+
+        // no-op snapshots, in any order
+        gb.apply_joypad_snapshot(&[JoypadButton::Up, JoypadButton::A, JoypadButton::Start]);
+        gb.clock_for_scanline();
+        gb.apply_joypad_snapshot(&[JoypadButton::A, JoypadButton::Start, JoypadButton::Up]);
+        gb.clock_for_scanline();
+        assert_eq!(gb.interrupts_fired_this_frame().joypad, 1);
+
+        // releasing does not request an interrupt
+        gb.apply_joypad_snapshot(&[JoypadButton::A]);
+        gb.clock_for_scanline();
+        assert_eq!(gb.interrupts_fired_this_frame().joypad, 1);
+        assert_eq!(gb.joypad_state(), JoypadState::A);
+    }
 }
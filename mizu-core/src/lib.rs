@@ -1,53 +1,313 @@
 mod apu;
 mod cartridge;
 mod cpu;
+mod emulation_error;
+mod infrared;
 mod joypad;
+mod link;
 mod memory;
 mod ppu;
 mod printer;
 mod save_error;
+mod save_migration;
 mod serial;
+mod sgb;
 mod timer;
 
 #[cfg(test)]
 mod tests;
 
 use std::cell::RefCell;
-use std::fs::File;
+use std::collections::VecDeque;
 use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use save_state::Savable;
 
 use cartridge::Cartridge;
-use cpu::Cpu;
+use cpu::{Cpu, CpuBusProvider};
 use memory::Bus;
+use save_migration::StateMigration;
 
-pub use apu::AudioBuffers;
-pub use cartridge::CartridgeError;
-pub use joypad::JoypadButton;
-pub use printer::Printer;
+pub use apu::{
+    ApuChannelId, ApuChannelState, ApuQuality, ApuRegisters, AudioBuffers, AudioCallback,
+    AudioStemMode, OwnedAudioBuffers, AUDIO_CALLBACK_BLOCK_SAMPLES,
+};
+pub use cartridge::{
+    CartridgeError, CartridgeHeader, CartridgeInterface, MapperType, RtcSaveFormat, RtcTime,
+    RumbleCallback, SramDirtyCallback, SramError, CAMERA_HEIGHT, CAMERA_WIDTH,
+};
+pub use cpu::instruction::{
+    instruction_length, Condition, DisassembledLine, Instruction, Opcode, OperandType,
+};
+pub use cpu::{CpuQuirkState, CpuRegisters, CpuState, HaltMode, TraceCallback};
+pub use emulation_error::EmulationError;
+pub use infrared::IrLedCallback;
+pub use joypad::{JoypadButton, JoypadIntMode, JoypadState};
+pub use link::LinkCable;
+pub use memory::{AccessProfile, DmaStatus, InterruptCounts, InterruptType, RamFillPattern};
+pub use ppu::{
+    Color, ColorCorrection, FrameCallback, PipelineState, PpuLayer, PpuQuirkReport,
+    ScanlineCallback, TilemapRender, TILEMAP_RENDER_SIZE, VRAM_TILES_PER_BANK,
+};
+pub use printer::{PrintCallback, PrintedImage, Printer};
 pub use save_error::SaveError;
-pub use serial::SerialDevice;
+pub use serial::{ByteSerialDevice, SerialByteCallback, SerialDevice, SerialRole, SerialStats};
+pub use sgb::{SgbBorder, SGB_BORDER_HEIGHT, SGB_BORDER_WIDTH};
+pub use timer::TimerState;
 
 /// The current version of state saved/loaded by
 /// [`GameBoy::save_state`] / [`GameBoy::load_state`].
 ///
-/// Loading a state that is not compatible with this version, results
-/// in [`SaveError::UnmatchedSaveErrorVersion`]
-pub const SAVE_STATE_VERSION: usize = 2;
+/// Loading a state of a newer version results in
+/// [`SaveError::UnmatchedSaveErrorVersion`], and a state of an older version
+/// is migrated to this version if possible, otherwise results in
+/// [`SaveError::UnsupportedMigration`].
+pub const SAVE_STATE_VERSION: usize = 3;
 const SAVE_STATE_MAGIC: &[u8; 4] = b"MST\xee";
 const SAVE_STATE_ZSTD_COMPRESSION_LEVEL: i32 = 0; // default compression
+/// Set in the version of the header for states saved with
+/// [`GameBoy::save_state_uncompressed`], it is not part of the version itself
+const SAVE_STATE_UNCOMPRESSED_FLAG: usize = 1 << 31;
+
+/// Number of in-memory save state slots, see [`GameBoy::quick_save`]
+pub const QUICK_SAVE_SLOTS: usize = 10;
+
+const PPU_CYCLES_PER_SCANLINE: u32 = 456;
+const PPU_CYCLES_PER_FRAME: u32 = PPU_CYCLES_PER_SCANLINE * 154;
 
 /// Custom configuration for the [`GameBoy`] emulation inner workings
-#[derive(Debug, Default, Clone, Copy, Savable)]
+#[derive(Debug, Clone, Copy)]
 pub struct GameBoyConfig {
     /// Should the gameboy run in DMG mode? default is in CGB mode
+    ///
+    /// This emulates the DMG hardware, so CGB features are not available
+    /// at all, see [`GameBoyConfig::force_dmg_mode_on_cgb`] to run CGB
+    /// games in the DMG compatibility mode of the CGB instead.
     pub is_dmg: bool,
+    /// The colors of the 4 DMG shades from lightest to darkest, used for
+    /// the background and both sprite palettes when running DMG games.
+    /// Default is grayscale.
+    pub dmg_palette: [Color; 4],
+    /// The quality of the audio mixing, [`ApuQuality::Fast`] trades accuracy
+    /// of the output for performance, and stops filling the per-channel
+    /// buffers of [`AudioBuffers`], only [`AudioBuffers::all`] (and the
+    /// left and right mixes) are filled. Default is [`ApuQuality::Accurate`].
+    ///
+    /// This is a setting of the host, and is not stored in save states.
+    pub apu_quality: ApuQuality,
+    /// The sample rate of the audio output in [`AudioBuffers`], the APU
+    /// output is resampled to it using linear interpolation, `0` is treated
+    /// as `1`. Default is `44100`.
+    ///
+    /// This is a setting of the host, and is not stored in save states.
+    pub sample_rate: u32,
+    /// The content of the RAM (WRAM, HRAM, VRAM and OAM) on power on.
+    /// Use [`RamFillPattern::Seeded`] to emulate the garbage RAM of the
+    /// hardware while keeping the runs reproducible. Default is
+    /// [`RamFillPattern::Zero`].
+    pub ram_fill: RamFillPattern,
+    /// The color correction applied to the screen buffer output, this is
+    /// only an output transform, and is not stored in save states.
+    /// Default is [`ColorCorrection::CgbLcd`].
+    pub color_correction: ColorCorrection,
+    /// If `false`, the PPU is still clocked for its timing and interrupts,
+    /// but the pixels are not written to the screen buffers, which saves
+    /// time when the screen is not needed, such as rendering audio only.
+    /// The screen buffer stays blank. Default is `true`.
+    pub render_video: bool,
+    /// Emulate the Super GameBoy command packets sent through the joypad
+    /// register, which changes the joypad behavior (multiplayer joypads).
+    /// Only has effect in DMG mode, and for cartridges that support SGB.
+    /// Default is `false`.
+    ///
+    /// Only the palette commands (coloring the whole screen with SGB
+    /// palette `0`), multiplayer and the border are supported,
+    /// see [`GameBoy::sgb_border`].
+    pub sgb: bool,
+    /// Run CGB games in the DMG compatibility mode of the CGB, as if the
+    /// cartridge was a DMG cartridge. Default is `false`.
+    ///
+    /// Unlike [`GameBoyConfig::is_dmg`], this is still the CGB hardware,
+    /// so the CGB registers are locked the same way they are for DMG games,
+    /// and the DMG palettes are colored by the CGB. When running with a boot
+    /// rom, the boot rom sees the CGB flag of the cartridge header cleared.
+    ///
+    /// Has no effect on DMG games or when `is_dmg` is set.
+    pub force_dmg_mode_on_cgb: bool,
+    /// Emulate the hardware timing quirks of the STAT mode interrupts, which
+    /// differ between DMG and CGB. If `false`, a simplified model is used
+    /// where each mode interrupt triggers at the start of its mode, which is
+    /// enough for most games, but fails timing sensitive test roms.
+    /// Default is `true`.
+    pub stat_quirks: bool,
+    /// Allow the CPU to write to the ROM (`0x0000-0x7FFF`) of cartridges
+    /// without a mapper, the ROM is only modified in memory and is not saved
+    /// to the file. Useful for homebrew that expects a writable flash
+    /// cartridge. Cartridges with a mapper are not affected. Default is `false`.
+    pub writable_rom: bool,
+    /// The format of the MBC3 real time clock data in the `.sav` file, and
+    /// in [`GameBoy::save_sram`] and [`GameBoy::load_sram`], see
+    /// [`RtcSaveFormat`]. Use [`RtcSaveFormat::Compatible`] to share saves
+    /// with other emulators. This is not stored in save states.
+    /// Default is [`RtcSaveFormat::Native`].
+    pub rtc_save_format: RtcSaveFormat,
+    /// When [`GameBoyConfig::is_dmg`] is set and the cartridge header marks
+    /// the game as CGB only, run in CGB mode instead of failing with
+    /// [`CartridgeError::RequiresCgb`].
+    ///
+    /// A boot rom given to [`GameBoyBuilder::boot_rom_bytes`] or
+    /// [`GameBoyBuilder::boot_rom_file`] selects the model from its size
+    /// first, a DMG boot rom (`0x100` bytes) can't run a CGB only game, so
+    /// the build fails with [`CartridgeError::BootRomModelMismatch`]. With
+    /// [`GameBoyBuilder::allow_any_boot_rom_size`], the model is not
+    /// detected from the boot rom, and it is resized to the length of the
+    /// CGB boot rom instead.
+    ///
+    /// This is not stored in save states. Default is `false`.
+    pub auto_detect_mode: bool,
+}
+
+impl Default for GameBoyConfig {
+    fn default() -> Self {
+        Self {
+            is_dmg: false,
+            dmg_palette: [
+                Color::from_rgb(0xFF, 0xFF, 0xFF),
+                Color::from_rgb(0xAD, 0xAD, 0xAD),
+                Color::from_rgb(0x52, 0x52, 0x52),
+                Color::from_rgb(0x00, 0x00, 0x00),
+            ],
+            apu_quality: ApuQuality::Accurate,
+            sample_rate: 44100,
+            ram_fill: RamFillPattern::Zero,
+            color_correction: ColorCorrection::CgbLcd,
+            render_video: true,
+            sgb: false,
+            force_dmg_mode_on_cgb: false,
+            stat_quirks: true,
+            writable_rom: false,
+            rtc_save_format: RtcSaveFormat::Native,
+            auto_detect_mode: false,
+        }
+    }
+}
+
+/// The configuration is stored in multiple components of the save state,
+/// its fields are saved in a length prefixed block, so that states saved
+/// before a field was added can still be loaded, with the default value of
+/// the missing fields.
+///
+/// New fields must be added at the end of the block, and fields that are
+/// not stored in save states (output options) are kept as they are.
+impl Savable for GameBoyConfig {
+    fn save<W: Write>(&self, writer: &mut W) -> save_state::Result<()> {
+        let mut data = Vec::new();
+        self.is_dmg.save(&mut data)?;
+        self.dmg_palette.save(&mut data)?;
+        self.ram_fill.save(&mut data)?;
+        self.sgb.save(&mut data)?;
+        self.force_dmg_mode_on_cgb.save(&mut data)?;
+        self.stat_quirks.save(&mut data)?;
+        self.writable_rom.save(&mut data)?;
+
+        (data.len() as u32).save(writer)?;
+        writer.write_all(&data)?;
+        Ok(())
+    }
+
+    fn load<R: Read>(&mut self, reader: &mut R) -> save_state::Result<()> {
+        let mut len = 0u32;
+        len.load(reader)?;
+        let mut data = vec![0; len as usize];
+        reader.read_exact(&mut data)?;
+
+        let default = Self::default();
+        let mut cursor = Cursor::new(data.as_slice());
+        let cursor = &mut cursor;
+        load_config_field(cursor, &mut self.is_dmg, default.is_dmg)?;
+        load_config_field(cursor, &mut self.dmg_palette, default.dmg_palette)?;
+        load_config_field(cursor, &mut self.ram_fill, default.ram_fill)?;
+        load_config_field(cursor, &mut self.sgb, default.sgb)?;
+        load_config_field(
+            cursor,
+            &mut self.force_dmg_mode_on_cgb,
+            default.force_dmg_mode_on_cgb,
+        )?;
+        load_config_field(cursor, &mut self.stat_quirks, default.stat_quirks)?;
+        load_config_field(cursor, &mut self.writable_rom, default.writable_rom)?;
+
+        let remaining_data_len = len as u64 - cursor.position();
+        if remaining_data_len > 0 {
+            return Err(save_state::Error::TrailingData(remaining_data_len));
+        }
+
+        Ok(())
+    }
+}
+
+/// Loads `field` from the configuration block in `cursor`, or sets it to
+/// `default` if the block ended, i.e. the field was added after the state
+/// was saved
+fn load_config_field<T: Savable>(
+    cursor: &mut Cursor<&[u8]>,
+    field: &mut T,
+    default: T,
+) -> save_state::Result<()> {
+    if cursor.position() < cursor.get_ref().len() as u64 {
+        field.load(cursor)
+    } else {
+        *field = default;
+        Ok(())
+    }
 }
 
 impl GameBoyConfig {
+    /// Migrates the configuration saved in one of the components of a save
+    /// state to the next version, see [`migrate_save_state`]
+    pub(crate) fn migrate_state(migration: &mut StateMigration) -> save_state::Result<()> {
+        let default = Self::default();
+        match migration.from() {
+            // version 2 only saved `is_dmg`, version 3 stores the fields in a
+            // length prefixed block
+            2 => {
+                let mut config = default;
+                config.is_dmg = migration.remove(default.is_dmg)?;
+                migration.insert(&config)?;
+            }
+            from => {
+                return Err(save_state::Error::IoError(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("no migration for the configuration of version {}", from),
+                )))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The cartridge should run in CGB mode, taking into account
+    /// [`force_dmg_mode_on_cgb`](Self::force_dmg_mode_on_cgb)
+    pub(crate) fn is_cartridge_cgb_mode(&self, cartridge: &dyn CartridgeInterface) -> bool {
+        cartridge.is_cartridge_color() && !self.force_dmg_mode_on_cgb
+    }
+
+    /// Checks that the cartridge can run on the configured model, switching
+    /// to CGB if [`auto_detect_mode`](Self::auto_detect_mode) is set.
+    fn resolve_model(&mut self, cartridge: &dyn CartridgeInterface) -> Result<(), CartridgeError> {
+        if self.is_dmg && cartridge.header().cgb_only() {
+            if self.auto_detect_mode {
+                self.is_dmg = false;
+            } else {
+                return Err(CartridgeError::RequiresCgb);
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn boot_rom_len(&self) -> usize {
         if self.is_dmg {
             0x100
@@ -57,25 +317,109 @@ impl GameBoyConfig {
     }
 }
 
+/// Where the ROM of a [`GameBoyBuilder`] comes from
+enum RomSource {
+    File(PathBuf),
+    /// The data is read when the source is set, so the error is kept to be
+    /// returned from [`GameBoyBuilder::build`]
+    Data(std::io::Result<Vec<u8>>),
+    Cartridge(Box<dyn CartridgeInterface>),
+}
+
+/// Where the boot rom of a [`GameBoyBuilder`] comes from
+enum BootRomSource {
+    File(PathBuf),
+    Bytes(Vec<u8>),
+}
+
 /// Builder struct container for [`GameBoy`] configurations and options.
 pub struct GameBoyBuilder {
     config: GameBoyConfig,
-    rom_file: PathBuf,
-    boot_rom_file: Option<PathBuf>,
+    rom: RomSource,
+    boot_rom: Option<BootRomSource>,
+    skip_boot_rom: bool,
+    allow_any_boot_rom_size: bool,
     sram_file: Option<PathBuf>,
     save_on_shutdown: bool,
+    in_memory_sram: bool,
 }
 
 impl GameBoyBuilder {
+    fn new(rom: RomSource) -> Self {
+        Self {
+            config: GameBoyConfig::default(),
+            rom,
+            boot_rom: None,
+            skip_boot_rom: false,
+            allow_any_boot_rom_size: false,
+            sram_file: None,
+            save_on_shutdown: true,
+            in_memory_sram: false,
+        }
+    }
+
     /// Add custom [`GameBoyConfig`]
     pub fn config(mut self, config: GameBoyConfig) -> Self {
         self.config = config;
         self
     }
 
-    /// Add boot rom file
+    /// Reads the ROM from `reader` (from its current position to the end)
+    /// instead of a file, i.e. from an archive or embedded bytes.
+    ///
+    /// Since there is no backing file, [`GameBoy::file_path`] will return
+    /// `None`, and save on shutdown is disabled. To keep the SRAM, use
+    /// [`GameBoyBuilder::sram_file`] and enable
+    /// [`GameBoyBuilder::save_on_shutdown`] after this.
+    pub fn rom_reader<R: Read + Seek>(mut self, reader: R) -> Self {
+        self.rom = RomSource::Data(Cartridge::read_rom(reader));
+        self.save_on_shutdown = false;
+        self
+    }
+
+    /// Uses a custom cartridge implementation instead of loading a ROM.
+    ///
+    /// [`GameBoyBuilder::sram_file`] and [`GameBoyBuilder::save_on_shutdown`]
+    /// do not apply to it, and the cartridge handles its own storage.
+    pub fn cartridge(mut self, cartridge: Box<dyn CartridgeInterface>) -> Self {
+        self.rom = RomSource::Cartridge(cartridge);
+        self
+    }
+
+    /// Add boot rom file, see [`GameBoyBuilder::boot_rom_bytes`].
     pub fn boot_rom_file<P: AsRef<Path>>(mut self, boot_rom_file: P) -> Self {
-        self.boot_rom_file = Some(boot_rom_file.as_ref().to_path_buf());
+        self.boot_rom = Some(BootRomSource::File(boot_rom_file.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Add boot rom data from memory, replaces [`GameBoyBuilder::boot_rom_file`].
+    ///
+    /// The model is detected from the length of the boot rom, overriding
+    /// [`GameBoyConfig::is_dmg`], `0x100` bytes for a DMG boot rom, and
+    /// `0x900` bytes for a CGB boot rom. Other lengths make
+    /// [`GameBoyBuilder::build`] fail with
+    /// [`CartridgeError::InvalidBootRomSize`], unless
+    /// [`GameBoyBuilder::allow_any_boot_rom_size`] is enabled, in which case
+    /// the model is not detected.
+    pub fn boot_rom_bytes(mut self, data: Vec<u8>) -> Self {
+        self.boot_rom = Some(BootRomSource::Bytes(data));
+        self
+    }
+
+    /// Skip the boot rom even if one is provided, and start from the
+    /// post-boot state directly. (default: false)
+    pub fn skip_boot_rom(mut self, skip_boot_rom: bool) -> Self {
+        self.skip_boot_rom = skip_boot_rom;
+        self
+    }
+
+    /// Accept boot roms of any size, which is useful for custom boot roms,
+    /// instead of failing with [`CartridgeError::InvalidBootRomSize`], the
+    /// boot rom is padded with `0xFF` or truncated to
+    /// [`GameBoyConfig::boot_rom_len`] of the configured model, which is
+    /// not detected from the boot rom. (default: false)
+    pub fn allow_any_boot_rom_size(mut self, allow: bool) -> Self {
+        self.allow_any_boot_rom_size = allow;
         self
     }
 
@@ -87,234 +431,2249 @@ impl GameBoyBuilder {
         self
     }
 
-    /// Should the SRAM be saved on shutdown? (default: true)
+    /// Should the SRAM be saved on shutdown?
+    /// (default: true, false when using [`GameBoyBuilder::rom_reader`])
     pub fn save_on_shutdown(mut self, save_on_shutdown: bool) -> Self {
         self.save_on_shutdown = save_on_shutdown;
         self
     }
 
+    /// Keep the battery backed RAM only in memory, the SRAM file is never
+    /// read or written, regardless of [`GameBoyBuilder::sram_file`], and
+    /// [`GameBoyBuilder::save_on_shutdown`] does nothing. (default: false)
+    ///
+    /// Useful when there is no writable filesystem (i.e. WASM), the SRAM
+    /// can be persisted explicitly with [`GameBoy::save_sram`] and
+    /// [`GameBoy::load_sram`]. This does not apply to
+    /// [`GameBoyBuilder::cartridge`].
+    pub fn in_memory_sram(mut self, in_memory_sram: bool) -> Self {
+        self.in_memory_sram = in_memory_sram;
+        self
+    }
+
     /// Builds a [`GameBoy`] instance.
     pub fn build(self) -> Result<GameBoy, CartridgeError> {
         GameBoy::build(self)
     }
 }
 
+/// An in-memory snapshot of the emulator state, created by
+/// [`GameBoy::snapshot`] and restored by [`GameBoy::restore`].
+///
+/// Unlike [`GameBoy::save_state`], the state is stored uncompressed and
+/// without any header, which makes it fast enough to be taken every frame,
+/// i.e. for rewinding. The buffer can be reused with [`GameBoy::snapshot_into`].
+#[derive(Default, Clone)]
+pub struct Snapshot {
+    data: Vec<u8>,
+    frame_ppu_cycles: u32,
+    input_frame_started: bool,
+    cycles_overshoot: u64,
+}
+
+/// What to do with the emulator state when loading a save state fails,
+/// see [`GameBoy::load_state_with`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LoadErrorPolicy {
+    /// Restore the state from before the load, as if nothing happened.
+    #[default]
+    RollBack,
+    /// Reset the emulator to its power on state, the battery backed
+    /// cartridge RAM is kept as it was before the load.
+    Reset,
+    /// Leave the partially loaded state as is, the emulator may be in an
+    /// inconsistent state, only useful for inspecting the failure.
+    LeavePartial,
+}
+
+/// A [`Write`] sink that hashes everything written to it with FNV-1a,
+/// used to hash the state without serializing it into a buffer
+struct StateHasher(u64);
+
+impl StateHasher {
+    const OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01B3;
+
+    fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+}
+
+impl Write for StateHasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for &byte in buf {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(Self::PRIME);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 /// The GameBoy is the main interface to the emulator.
 ///
 /// Everything regarding emulation can be controlled from here.
 pub struct GameBoy {
     cpu: Cpu,
     bus: Bus,
+
+    /// PPU cycles elapsed in the current frame, kept between calls to
+    /// [`GameBoy::clock_for_frame`] so that a frame interrupted by a
+    /// breakpoint can be completed when resuming
+    frame_ppu_cycles: u32,
+    /// The inputs of the current frame were applied, set on the first clock
+    /// of the frame, even if it stopped at a breakpoint before any cycle
+    input_frame_started: bool,
+
+    /// PPU cycles executed past the target of the last
+    /// [`GameBoy::clock_for_cycles`], deducted from the next call
+    cycles_overshoot: u64,
+
+    /// Number of frames started by [`GameBoy::clock_for_frame`]
+    frame_number: u64,
+    input_recording_start: u64,
+    input_recording: Option<Vec<(u64, JoypadState)>>,
+    input_playback_start: u64,
+    input_playback: VecDeque<(u64, JoypadState)>,
+
+    /// The state right after creation, used to reset the emulator
+    power_on_state: Snapshot,
+
+    /// Uncompressed save states of [`GameBoy::quick_save`]
+    quick_save_slots: [Option<Vec<u8>>; QUICK_SAVE_SLOTS],
 }
 
 impl GameBoy {
     /// Initiate a builder object with a cartridge file.
     pub fn builder<RomP: AsRef<Path>>(rom_file: RomP) -> GameBoyBuilder {
-        GameBoyBuilder {
-            config: GameBoyConfig::default(),
-            rom_file: rom_file.as_ref().to_path_buf(),
-            boot_rom_file: None,
-            sram_file: None,
-            save_on_shutdown: true,
-        }
+        GameBoyBuilder::new(RomSource::File(rom_file.as_ref().to_path_buf()))
+    }
+
+    /// Initiate a builder object with the ROM read from `reader`,
+    /// see [`GameBoyBuilder::rom_reader`].
+    pub fn builder_from_reader<R: Read + Seek>(reader: R) -> GameBoyBuilder {
+        GameBoyBuilder::new(RomSource::Data(Ok(Vec::new()))).rom_reader(reader)
+    }
+
+    /// Initiate a builder object with a custom cartridge,
+    /// see [`GameBoyBuilder::cartridge`].
+    pub fn builder_from_cartridge(cartridge: Box<dyn CartridgeInterface>) -> GameBoyBuilder {
+        GameBoyBuilder::new(RomSource::Cartridge(cartridge))
     }
 
     fn build(builder: GameBoyBuilder) -> Result<Self, CartridgeError> {
-        let file_path = builder.rom_file;
-        let sram_file_path = builder.sram_file;
-        let boot_rom_file_path = builder.boot_rom_file;
-        let config = builder.config;
-        let save_on_shutdown = builder.save_on_shutdown;
+        let in_memory_sram = builder.in_memory_sram;
+        let sram_file_path = builder.sram_file.filter(|_| !in_memory_sram);
+        let boot_rom = if builder.skip_boot_rom {
+            None
+        } else {
+            builder.boot_rom
+        };
+        let mut config = builder.config;
+        let save_on_shutdown = builder.save_on_shutdown && !in_memory_sram;
+        let allow_any_boot_rom_size = builder.allow_any_boot_rom_size;
 
-        let cartridge = Cartridge::from_file(file_path, sram_file_path, save_on_shutdown)?;
+        let cartridge: Box<dyn CartridgeInterface> = match builder.rom {
+            RomSource::File(file_path) if in_memory_sram => Box::new(
+                Cartridge::from_file_in_memory_sram(file_path, config.rtc_save_format)?,
+            ),
+            RomSource::File(file_path) => Box::new(Cartridge::from_file(
+                file_path,
+                sram_file_path,
+                save_on_shutdown,
+                config.rtc_save_format,
+            )?),
+            RomSource::Data(data) => Box::new(Cartridge::from_bytes_with_sram(
+                data?,
+                sram_file_path,
+                save_on_shutdown,
+                config.rtc_save_format,
+            )?),
+            RomSource::Cartridge(cartridge) => cartridge,
+        };
+
+        let mut boot_rom = match boot_rom {
+            Some(BootRomSource::File(boot_rom_file)) => Some(std::fs::read(boot_rom_file)?),
+            Some(BootRomSource::Bytes(data)) => Some(data),
+            None => None,
+        };
+
+        // the boot rom is made for one model, so its size decides the model
+        if let Some(data) = &boot_rom {
+            if !allow_any_boot_rom_size {
+                match data.len() {
+                    0x100 => config.is_dmg = true,
+                    0x900 => config.is_dmg = false,
+                    got => {
+                        return Err(CartridgeError::InvalidBootRomSize {
+                            expected: vec![0x100, 0x900],
+                            got,
+                        })
+                    }
+                }
+            }
+        }
+        let was_dmg = config.is_dmg;
+        config.resolve_model(cartridge.as_ref())?;
+        // the model detected from the boot rom can't be changed
+        if boot_rom.is_some() && !allow_any_boot_rom_size && was_dmg != config.is_dmg {
+            return Err(CartridgeError::BootRomModelMismatch);
+        }
 
-        let (bus, cpu) = if let Some(boot_rom_file) = boot_rom_file_path {
-            let mut boot_rom_file = File::open(boot_rom_file)?;
-            let mut data = vec![0; config.boot_rom_len()];
+        // make sure the boot_rom is the exact same size
+        if let Some(data) = &mut boot_rom {
+            if allow_any_boot_rom_size {
+                data.resize(config.boot_rom_len(), 0xFF);
+            } else if data.len() != config.boot_rom_len() {
+                return Err(CartridgeError::InvalidBootRomSize {
+                    expected: vec![config.boot_rom_len()],
+                    got: data.len(),
+                });
+            }
+        }
+
+        Ok(Self::from_cartridge(cartridge, boot_rom, config))
+    }
 
-            // make sure the boot_rom is the exact same size
-            assert_eq!(
-                boot_rom_file.metadata()?.len(),
-                data.len() as u64,
-                "boot_rom file size is not correct"
-            );
+    /// Creates a [`GameBoy`] from the ROM data in memory, without a boot rom.
+    ///
+    /// Since there is no backing file, [`GameBoy::file_path`] will return
+    /// `None`, and the SRAM will not be loaded or saved on shutdown.
+    pub fn from_rom_bytes(
+        data: Vec<u8>,
+        mut config: GameBoyConfig,
+    ) -> Result<Self, CartridgeError> {
+        let mut cartridge = Cartridge::from_bytes(data)?;
+        cartridge.set_rtc_save_format(config.rtc_save_format);
+        config.resolve_model(&cartridge)?;
+
+        Ok(Self::from_cartridge(Box::new(cartridge), None, config))
+    }
 
-            boot_rom_file.read_exact(&mut data)?;
+    /// Creates a [`GameBoy`] from the ROM data in memory for rendering audio
+    /// only, such as playing chiptune music offline. The CPU and the APU run
+    /// normally, but the PPU does not render pixels
+    /// (see [`GameBoyConfig::render_video`]), so the screen buffer stays
+    /// blank. Otherwise, this is the same as [`GameBoy::from_rom_bytes`] with
+    /// the default configuration.
+    ///
+    /// Use [`GameBoy::clock_for_cycles`] to clock the exact number of cycles
+    /// for each audio block, and [`GameBoy::drain_audio_into`] to collect
+    /// the samples without allocating.
+    pub fn new_audio_only(data: Vec<u8>) -> Result<Self, CartridgeError> {
+        Self::from_rom_bytes(
+            data,
+            GameBoyConfig {
+                render_video: false,
+                ..GameBoyConfig::default()
+            },
+        )
+    }
 
+    fn from_cartridge(
+        cartridge: Box<dyn CartridgeInterface>,
+        boot_rom: Option<Vec<u8>>,
+        config: GameBoyConfig,
+    ) -> Self {
+        let (bus, cpu) = if let Some(boot_rom) = boot_rom {
             (
-                Bus::new_with_boot_rom(cartridge, data, config),
+                Bus::new_with_boot_rom(cartridge, boot_rom, config),
                 Cpu::new(config),
             )
         } else {
-            let is_cartridge_color = cartridge.is_cartridge_color();
+            let is_cartridge_color = config.is_cartridge_cgb_mode(cartridge.as_ref());
             (
                 Bus::new_without_boot_rom(cartridge, config),
                 Cpu::new_without_boot_rom(config, is_cartridge_color),
             )
         };
 
-        Ok(Self { bus, cpu })
+        let mut gameboy = Self {
+            bus,
+            cpu,
+            frame_ppu_cycles: 0,
+            input_frame_started: false,
+            cycles_overshoot: 0,
+            frame_number: 0,
+            input_recording_start: 0,
+            input_recording: None,
+            input_playback_start: 0,
+            input_playback: VecDeque::new(),
+            power_on_state: Snapshot::default(),
+            quick_save_slots: Default::default(),
+        };
+        gameboy.power_on_state = gameboy.snapshot();
+
+        gameboy
     }
 
-    /// Clocks the Gameboy clock for the duration of one PPU frame.
+    /// Clocks the Gameboy clock until the end of the current PPU frame, which
+    /// is when the PPU enters VBlank, at that point the screen buffer
+    /// contains the completed frame.
+    ///
+    /// While the LCD is off or the CPU is in STOP mode, the PPU does not
+    /// produce frames, so this clocks for the duration of one frame
+    /// instead (`456 * 154` PPU cycles).
     ///
     /// This is good for timing emulation, you can call this function once
     /// and then render it.
-    pub fn clock_for_frame(&mut self) {
-        const PPU_CYCLES_PER_FRAME: u32 = 456 * 154;
-        let mut cycles = 0u32;
-        while cycles < PPU_CYCLES_PER_FRAME {
-            self.cpu.next_instruction(&mut self.bus);
-            cycles += self.bus.elapsed_ppu_cycles();
-        }
-    }
-
-    /// Return the game title string extracted from the cartridge.
-    pub fn game_title(&self) -> &str {
-        self.bus.cartridge().game_title()
-    }
-
-    /// The cartridge file path.
-    pub fn file_path(&self) -> &Path {
-        self.bus.cartridge().file_path()
+    ///
+    /// If a breakpoint (see [`GameBoy::add_breakpoint`]) is hit, this will
+    /// return [`CpuState::Breakpoint`] early before executing the instruction
+    /// at the breakpoint address. Similarly, if a watched address is written
+    /// (see [`GameBoy::add_write_watchpoint`]), this will return
+    /// [`CpuState::Watchpoint`] early after the instruction that performed
+    /// the write. Calling this function again will continue the rest of
+    /// the interrupted frame.
+    ///
+    /// Otherwise, returns [`CpuState::InfiniteLoop`], [`CpuState::Stopped`] or
+    /// [`CpuState::IllegalInstruction`] if the CPU was in one of these states
+    /// at any point during the frame (the last one is returned),
+    /// or [`CpuState::Normal`].
+    pub fn clock_for_frame(&mut self) -> CpuState {
+        self.clock_for_frame_until(None).0
     }
 
-    /// Return the pixels buffer of the PPU at the current state.
+    /// Same as [`GameBoy::clock_for_frame`], but stops early if the frame
+    /// takes more than `budget` of real time, which keeps the UI responsive
+    /// on slow machines.
     ///
-    /// The format of the pixel buffer is RGB, i.e. 3 bytes per pixel.
-    pub fn screen_buffer(&self) -> &[u8] {
-        self.bus.screen_buffer()
+    /// Returns the state like [`GameBoy::clock_for_frame`], and `true` if
+    /// the budget elapsed before the frame was completed, in which case
+    /// calling this (or [`GameBoy::clock_for_frame`]) again will continue
+    /// the rest of the frame.
+    ///
+    /// The time is only checked every few hundred instructions to keep the
+    /// overhead low, so the budget may be exceeded slightly.
+    pub fn clock_for_duration(&mut self, budget: Duration) -> (CpuState, bool) {
+        self.clock_for_frame_until(Some(Instant::now() + budget))
     }
 
-    /// Return the audio buffer of the APU at the current state.
+    /// Same as [`GameBoy::clock_for_frame`], but returns an error if the
+    /// emulation can't continue, so that the caller can stop the loop and
+    /// show the error, instead of running a locked up CPU forever.
     ///
-    /// We use `&mut` as it will also reset the buffers after using them
-    pub fn audio_buffers(&mut self) -> AudioBuffers {
-        self.bus.audio_buffers()
+    /// Returns [`EmulationError::IllegalInstruction`] if an illegal opcode
+    /// was executed, and [`EmulationError::Deadlock`] if the CPU is in a
+    /// jump to itself with interrupts disabled. Infinite loops waiting for
+    /// an interrupt are normal and are not reported.
+    ///
+    /// Breakpoints and watchpoints return `Ok` early, like
+    /// [`GameBoy::clock_for_frame`].
+    pub fn try_clock_for_frame(&mut self) -> Result<(), EmulationError> {
+        match self.clock_for_frame() {
+            CpuState::IllegalInstruction(opcode) => Err(EmulationError::IllegalInstruction {
+                opcode,
+                pc: self.cpu.registers().pc,
+            }),
+            CpuState::InfiniteLoop if self.is_deadlocked() => Err(EmulationError::Deadlock {
+                pc: self.cpu.registers().pc,
+            }),
+            _ => Ok(()),
+        }
     }
 
-    /// Change the state of the joypad button to `pressed`.
-    pub fn press_joypad(&mut self, button: JoypadButton) {
-        self.bus.press_joypad(button);
-    }
+    /// The next instruction is a jump to itself (`JR -2` or `JP pc`), and
+    /// no interrupt can be serviced to exit it
+    fn is_deadlocked(&mut self) -> bool {
+        let pc = self.cpu.registers().pc;
+        let bytes = [0, 1, 2].map(|i| self.bus.peek(pc.wrapping_add(i)));
+        let jumps_to_itself = match bytes {
+            [0x18, 0xFE, _] => true,
+            [0xC3, low, high] => u16::from_le_bytes([low, high]) == pc,
+            _ => false,
+        };
 
-    /// Change the state of the joypad button to `released`.
-    pub fn release_joypad(&mut self, button: JoypadButton) {
-        self.bus.release_joypad(button);
-    }
+        let quirks = self.cpu.quirk_state();
+        let interrupts_disabled = !(self.cpu.ime() || quirks.ei_pending);
+        let interrupts_enabled_mask = self.bus.peek(0xFFFF) & 0x1F;
 
-    // TODO: Not sure if using RefCell is the best option here
-    /// Connect a serial device to the Gameboy.
-    ///
-    /// Currently the gameboy can only be `master`, so the other device
-    /// must be implemented as `slave`.
-    pub fn connect_device(&mut self, device: Rc<RefCell<dyn SerialDevice>>) {
-        self.bus.connect_device(device);
+        jumps_to_itself && (interrupts_disabled || interrupts_enabled_mask == 0)
     }
 
-    /// Disconnects the serial device if any is connected, else, nothing is done
-    pub fn disconnect_device(&mut self) {
-        self.bus.disconnect_device();
-    }
+    /// Returns the state of the frame, and `true` if `deadline` was reached
+    /// before the frame was completed
+    fn clock_for_frame_until(&mut self, deadline: Option<Instant>) -> (CpuState, bool) {
+        // reading the time is slow compared to executing an instruction
+        const INSTRUCTIONS_PER_TIME_CHECK: u32 = 512;
 
-    /// Saves the whole current state of the emulator.
-    pub fn save_state<W: Write>(&self, mut writer: W) -> Result<(), SaveError> {
-        SAVE_STATE_MAGIC.save(&mut writer)?;
-        SAVE_STATE_VERSION.save(&mut writer)?;
-        let cartridge_hash: &[u8; 32] = self.bus.cartridge().hash();
-        cartridge_hash.save(&mut writer)?;
+        self.start_input_frame();
 
-        let mut writer = zstd::Encoder::new(&mut writer, SAVE_STATE_ZSTD_COMPRESSION_LEVEL)?;
+        let mut state = CpuState::Normal;
+        let mut instructions_until_check = INSTRUCTIONS_PER_TIME_CHECK;
+        loop {
+            let cpu_state = self.cpu.next_instruction(&mut self.bus);
+            let elapsed = self.bus.elapsed_ppu_cycles();
+            let frame_done = self.add_frame_cycles(elapsed);
 
-        self.cpu.save(&mut writer)?;
-        self.bus.save(&mut writer)?;
+            match cpu_state {
+                CpuState::Breakpoint(_) | CpuState::Watchpoint { .. } => return (cpu_state, false),
+                CpuState::InfiniteLoop | CpuState::Stopped | CpuState::IllegalInstruction(_) => {
+                    state = cpu_state
+                }
+                _ => {}
+            }
 
-        let _writer = writer.finish()?;
+            if frame_done {
+                return (state, false);
+            }
 
-        Ok(())
+            if let Some(deadline) = deadline {
+                instructions_until_check -= 1;
+                if instructions_until_check == 0 {
+                    instructions_until_check = INSTRUCTIONS_PER_TIME_CHECK;
+                    if Instant::now() >= deadline {
+                        return (state, true);
+                    }
+                }
+            }
+        }
     }
 
-    /// Loads the whole state of the emulator, if an error happened in the middle
-    /// the emulator will keep functioning like normal, as it stores a backup recovery state before
-    /// loading the new state.
-    pub fn load_state<R: Read + Seek>(&mut self, mut reader: R) -> Result<(), SaveError> {
-        // save state, so that if an error occured we will restore it back.
-        let mut recovery_save_state = Vec::new();
-        self.cpu
-            .save(&mut recovery_save_state)
-            .expect("recovery save cpu");
-        self.bus
-            .save(&mut recovery_save_state)
-            .expect("recovery save bus");
+    /// Clocks the Gameboy for `n` frames with [`GameBoy::clock_for_frame`],
+    /// and returns the final screen buffer, see [`GameBoy::screen_buffer`].
+    ///
+    /// Breakpoints and watchpoints do not stop the execution.
+    pub fn run_frames(&mut self, n: u64) -> &[u8] {
+        for _ in 0..n {
+            while let CpuState::Breakpoint(_) | CpuState::Watchpoint { .. } = self.clock_for_frame()
+            {
+            }
+        }
 
-        let mut load_routine = || {
-            let mut magic = [0u8; 4];
-            let mut version = 0usize;
-            let mut hash = [0u8; 32];
+        self.screen_buffer()
+    }
 
-            magic.load(&mut reader)?;
-            if &magic != SAVE_STATE_MAGIC {
-                return Err(SaveError::InvalidSaveStateHeader);
-            }
+    /// Clocks the Gameboy until `pred` returns `true`, or `max_frames` frames
+    /// are clocked. `pred` is called with the CPU registers after
+    /// every instruction.
+    ///
+    /// Returns the number of frames that ran, including the frame in which
+    /// `pred` returned `true`, the rest of that frame can be completed with
+    /// [`GameBoy::clock_for_frame`].
+    ///
+    /// Breakpoints do not stop the execution.
+    pub fn run_until(
+        &mut self,
+        mut pred: impl FnMut(&CpuRegisters) -> bool,
+        max_frames: u64,
+    ) -> u64 {
+        for frame in 0..max_frames {
+            self.start_input_frame();
 
-            // since there might be some possibility to migrate from different
-            // versions, we will not check here.
-            version.load(&mut reader)?;
+            loop {
+                self.cpu.next_instruction(&mut self.bus);
+                let elapsed = self.bus.elapsed_ppu_cycles();
+                let frame_done = self.add_frame_cycles(elapsed);
 
-            hash.load(&mut reader)?;
-            if &hash != self.bus.cartridge().hash() {
-                return Err(SaveError::InvalidCartridgeHash);
+                if pred(&self.cpu.registers()) {
+                    return frame + 1;
+                }
+                if frame_done {
+                    break;
+                }
             }
+        }
 
-            {
-                // use a box on read because there are two types of readers
-                // that we might use, compressed or not compressed based on the version
-                // of the save_state file
-                let mut second_stage_reader: Box<dyn Read>;
+        max_frames
+    }
 
-                if version == 1 && SAVE_STATE_VERSION == 2 {
-                    // no need to use compression
-                    second_stage_reader = Box::new(&mut reader);
-                } else if version != SAVE_STATE_VERSION {
-                    return Err(SaveError::UnmatchedSaveErrorVersion(version));
-                } else {
-                    second_stage_reader = Box::new(zstd::Decoder::new(&mut reader)?);
-                }
+    /// Clocks the Gameboy for `cycles` PPU cycles (T-cycles in normal speed,
+    /// in double speed mode the CPU runs twice as many cycles), and returns
+    /// the number of cycles actually executed.
+    ///
+    /// As instructions can't be split, the last instruction may run past the
+    /// target, the extra cycles are deducted from the next call, so that
+    /// consecutive calls stay in sync with the requested total regardless
+    /// of frame boundaries, which is useful for offline audio rendering.
+    ///
+    /// The clocked cycles are counted toward the current frame, like
+    /// [`GameBoy::clock_for_scanline`]. Breakpoints do not stop the execution.
+    pub fn clock_for_cycles(&mut self, cycles: u64) -> u64 {
+        if self.cycles_overshoot >= cycles {
+            self.cycles_overshoot -= cycles;
+            return 0;
+        }
+        let target = cycles - self.cycles_overshoot;
 
-                self.cpu.load(&mut second_stage_reader)?;
-                self.bus.load(&mut second_stage_reader)?;
-            }
+        let mut executed = 0;
+        while executed < target {
+            self.start_input_frame();
 
-            // make sure there is no more data
-            let stream_current_pos = reader.stream_position()?;
-            reader.seek(SeekFrom::End(0))?;
-            let stream_last_pos = reader.stream_position()?;
+            self.cpu.next_instruction(&mut self.bus);
+            let elapsed = self.bus.elapsed_ppu_cycles();
+            executed += elapsed as u64;
 
-            let (remaining_data_len, overflow) =
-                stream_last_pos.overflowing_sub(stream_current_pos);
-            assert!(!overflow);
+            self.add_frame_cycles(elapsed);
+        }
+        self.cycles_overshoot = executed - target;
 
-            if remaining_data_len > 0 {
-                // return seek
-                reader.seek(SeekFrom::Start(stream_current_pos))?;
+        executed
+    }
 
-                Err(SaveError::SaveStateError(save_state::Error::TrailingData(
-                    remaining_data_len,
-                )))
-            } else {
-                Ok(())
-            }
-        };
+    /// Clocks the bus (PPU, timer, DMA, APU, ...) for exactly one machine
+    /// cycle without executing the CPU, which can be used to watch the PPU
+    /// (i.e. the STAT mode transitions) change between CPU instructions.
+    ///
+    /// Instructions can't be split, so the CPU is stalled for that cycle
+    /// instead, and continues from the same instruction with the next call
+    /// that executes instructions. This shifts the CPU timing relative to the
+    /// rest of the hardware by one cycle for every call, so it should be used
+    /// for debugging only.
+    ///
+    /// The clocked cycles are counted toward the current frame, like
+    /// [`GameBoy::clock_for_scanline`].
+    pub fn step_cycle(&mut self) {
+        self.start_input_frame();
+
+        self.bus.clock_machine_cycle();
+        let elapsed = self.bus.elapsed_ppu_cycles();
+        self.add_frame_cycles(elapsed);
+    }
+
+    /// Returns the total number of PPU cycles (T-cycles in normal speed)
+    /// clocked since power on.
+    ///
+    /// Unlike the cycles counted for [`GameBoy::clock_for_frame`], this is
+    /// never reset, and it is part of the save state.
+    pub fn total_cycles(&self) -> u64 {
+        self.bus.total_ppu_cycles()
+    }
 
-        if let Err(err) = load_routine() {
-            let mut cursor = Cursor::new(&recovery_save_state);
+    /// Returns the number of frames (VBlanks) the PPU has entered since power on.
+    ///
+    /// This is never reset, and it is part of the save state. Note that
+    /// no frames are counted while the LCD is off.
+    pub fn frame_count(&self) -> u64 {
+        self.bus.frame_count()
+    }
 
-            self.cpu.load(&mut cursor).expect("recovery load cpu");
-            self.bus.load(&mut cursor).expect("recovery load bus");
+    /// Counts `cycles` toward the current frame, and returns `true` if the
+    /// frame is complete, which is when the PPU enters VBlank, or when the
+    /// PPU is not running (LCD off or STOP mode), after
+    /// [`PPU_CYCLES_PER_FRAME`] cycles.
+    fn add_frame_cycles(&mut self, cycles: u32) -> bool {
+        self.frame_ppu_cycles += cycles;
 
-            Err(err)
+        let frame_done = self.bus.take_frame_done();
+        let ppu_running = self.bus.is_lcd_on() && !self.bus.stopped();
+        if frame_done || (!ppu_running && self.frame_ppu_cycles >= PPU_CYCLES_PER_FRAME) {
+            self.frame_ppu_cycles = 0;
+            self.input_frame_started = false;
+            true
         } else {
-            Ok(())
+            false
         }
     }
+
+    /// Applies the played inputs and records the inputs for the frame that is
+    /// about to start, does nothing if the current frame already started.
+    fn start_input_frame(&mut self) {
+        if self.input_frame_started {
+            return;
+        }
+        self.input_frame_started = true;
+
+        let playback_frame = self.frame_number - self.input_playback_start;
+        while let Some(&(frame, state)) = self.input_playback.front() {
+            if frame > playback_frame {
+                break;
+            }
+            self.bus.set_joypad_state(state);
+            self.input_playback.pop_front();
+        }
+        self.bus.clock_joypad_autofire();
+
+        if let Some(log) = self.input_recording.as_mut() {
+            let state = self.bus.joypad_state();
+            // only record changes
+            if log.last().map(|&(_, last)| last) != Some(state) {
+                log.push((self.frame_number - self.input_recording_start, state));
+            }
+        }
+
+        self.frame_number += 1;
+    }
+
+    /// Starts recording the joypad state at the start of every frame
+    /// clocked by [`GameBoy::clock_for_frame`], any previous recording
+    /// is discarded.
+    ///
+    /// The log contains an entry only when the state changes, keyed by the
+    /// frame number since the start of the recording, the first entry is
+    /// always at frame `0`. See [`GameBoy::take_input_log`].
+    pub fn start_input_recording(&mut self) {
+        self.input_recording_start = self.frame_number;
+        self.input_recording = Some(Vec::new());
+    }
+
+    /// Stops the input recording, and returns the recorded log, which is empty
+    /// if there was no recording.
+    pub fn take_input_log(&mut self) -> Vec<(u64, JoypadState)> {
+        self.input_recording.take().unwrap_or_default()
+    }
+
+    /// Plays an input log recorded by [`GameBoy::start_input_recording`], the
+    /// joypad state of each entry is applied at the start of its frame during
+    /// the next calls to [`GameBoy::clock_for_frame`], counting from the next
+    /// frame as frame `0`.
+    ///
+    /// For the playback to reproduce the recording, the emulator must start
+    /// from the same state as the recording, i.e. a fresh gameboy (or a loaded
+    /// save state) with the same config, and the same SRAM and RTC state.
+    pub fn play_input_log(&mut self, log: Vec<(u64, JoypadState)>) {
+        self.input_playback_start = self.frame_number;
+        self.input_playback = log.into();
+    }
+
+    /// Returns `true` if there are still inputs from [`GameBoy::play_input_log`]
+    /// to be applied.
+    pub fn is_playing_input_log(&self) -> bool {
+        !self.input_playback.is_empty()
+    }
+
+    /// Clocks the Gameboy until the PPU moves to the next scanline, and
+    /// returns the new `LY` value.
+    ///
+    /// If the LCD is off, this will clock for the duration of one scanline
+    /// instead. This will also return early if a breakpoint or a watchpoint
+    /// is hit.
+    ///
+    /// The clocked cycles are counted toward the current frame, so the next
+    /// [`GameBoy::clock_for_frame`] will only complete the current frame.
+    pub fn clock_for_scanline(&mut self) -> u8 {
+        self.start_input_frame();
+
+        let start_scanline = self.bus.ppu_scanline();
+
+        let mut cycles = 0;
+        while cycles < PPU_CYCLES_PER_SCANLINE && self.bus.ppu_scanline() == start_scanline {
+            let cpu_state = self.cpu.next_instruction(&mut self.bus);
+            cycles += self.bus.elapsed_ppu_cycles();
+
+            if let CpuState::Breakpoint(_) | CpuState::Watchpoint { .. } = cpu_state {
+                break;
+            }
+        }
+
+        self.add_frame_cycles(cycles);
+
+        self.bus.ppu_ly()
+    }
+
+    /// Adds a breakpoint at `addr`, when the CPU is about to execute an
+    /// instruction at this address, emulation will stop and return from
+    /// [`GameBoy::clock_for_frame`].
+    ///
+    /// Calling [`GameBoy::clock_for_frame`] again will continue execution
+    /// from the breakpoint.
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.cpu.add_breakpoint(addr);
+    }
+
+    /// Removes the breakpoint at `addr` if present.
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.cpu.remove_breakpoint(addr);
+    }
+
+    /// Removes all breakpoints.
+    pub fn clear_breakpoints(&mut self) {
+        self.cpu.clear_breakpoints();
+    }
+
+    /// Adds a write watchpoint at `addr`, when the CPU writes to this address,
+    /// emulation will stop after the instruction that performed the write and
+    /// [`GameBoy::clock_for_frame`] will return [`CpuState::Watchpoint`] with
+    /// the address and the written value.
+    ///
+    /// Only CPU writes are watched, DMA transfers are not.
+    pub fn add_write_watchpoint(&mut self, addr: u16) {
+        self.bus.add_write_watchpoint_range(addr..=addr);
+    }
+
+    /// Adds a write watchpoint for the addresses `start..=end`,
+    /// see [`GameBoy::add_write_watchpoint`].
+    pub fn add_write_watchpoint_range(&mut self, start: u16, end: u16) {
+        self.bus.add_write_watchpoint_range(start..=end);
+    }
+
+    /// Removes all the write watchpoints that contain `addr`.
+    pub fn remove_write_watchpoint(&mut self, addr: u16) {
+        self.bus.remove_write_watchpoint(addr);
+    }
+
+    /// Removes all write watchpoints.
+    pub fn clear_write_watchpoints(&mut self) {
+        self.bus.clear_write_watchpoints();
+    }
+
+    /// Returns the Super GameBoy border image, `None` if SGB emulation is not
+    /// enabled (see [`GameBoyConfig::sgb`]), or the game has not transferred
+    /// a border yet.
+    pub fn sgb_border(&self) -> Option<SgbBorder> {
+        self.bus.sgb_border()
+    }
+
+    /// Starts counting the memory reads and writes to each `256` byte page,
+    /// which can be used to find the busy regions of a game, such as
+    /// busy-wait loops. Accesses by the CPU and DMA transfers are counted,
+    /// get the result with [`GameBoy::take_access_profile`].
+    pub fn enable_access_profiling(&mut self) {
+        self.bus.enable_access_profiling();
+    }
+
+    /// Returns the access counters since profiling was enabled or the last
+    /// call to this function, and resets them. All counters are `0` if
+    /// [`GameBoy::enable_access_profiling`] was not called.
+    pub fn take_access_profile(&mut self) -> AccessProfile {
+        self.bus.take_access_profile()
+    }
+
+    /// Return the game title string extracted from the cartridge.
+    pub fn game_title(&self) -> &str {
+        &self.bus.cartridge().header().title
+    }
+
+    /// Returns the current time of the cartridge real time clock, only
+    /// available for [`MapperType::Mbc3`] with timer, otherwise `None`.
+    pub fn rtc(&self) -> Option<RtcTime> {
+        self.bus.cartridge().rtc()
+    }
+
+    /// Sets the time of the cartridge real time clock, and continues
+    /// counting from it. Does nothing if the cartridge does not have a
+    /// real time clock (see [`GameBoy::rtc`]).
+    pub fn set_rtc(&mut self, time: RtcTime) {
+        self.bus.cartridge_mut().set_rtc(time);
+    }
+
+    /// Freezes the cartridge real time clock, so that it does not advance
+    /// with emulation. This is not part of the save state.
+    pub fn set_rtc_frozen(&mut self, frozen: bool) {
+        self.bus.cartridge_mut().set_rtc_frozen(frozen);
+    }
+
+    /// Sets a callback to be called with the new state of the rumble motor
+    /// whenever it is turned on or off, only used by
+    /// [`MapperType::Mbc5`] cartridges with rumble.
+    pub fn set_rumble_callback(&mut self, callback: RumbleCallback) {
+        self.bus.cartridge_mut().set_rumble_callback(Some(callback));
+    }
+
+    /// Removes the rumble callback if any is set, else, nothing is done
+    pub fn clear_rumble_callback(&mut self) {
+        self.bus.cartridge_mut().set_rumble_callback(None);
+    }
+
+    /// Sets the tilt of the accelerometer in `g` (`1.0` is the full gravity
+    /// pull), only used by [`MapperType::Mbc7`] cartridges.
+    ///
+    /// `(0.0, 0.0)` is flat, which is the default.
+    pub fn set_accelerometer(&mut self, x: f32, y: f32) {
+        self.bus.cartridge_mut().set_accelerometer(x, y);
+    }
+
+    /// Sets the image seen by the camera sensor, only used by
+    /// [`MapperType::PocketCamera`] cartridges.
+    ///
+    /// `gray` is [`CAMERA_WIDTH`] * [`CAMERA_HEIGHT`] (128x112) grayscale
+    /// pixels, `0` is black and `255` is white. The image is captured when
+    /// the game requests it, applying the exposure and dithering registers
+    /// programmed by the game. Until set, the sensor sees a black image.
+    ///
+    /// # Panics
+    /// If the length of `gray` is not [`CAMERA_WIDTH`] * [`CAMERA_HEIGHT`].
+    pub fn set_camera_frame(&mut self, gray: &[u8]) {
+        assert_eq!(gray.len(), CAMERA_WIDTH * CAMERA_HEIGHT);
+        self.bus.cartridge_mut().set_camera_frame(gray);
+    }
+
+    /// Returns the whole WRAM (`0x8000` bytes) for direct access, i.e. for
+    /// cheat engines that scan and patch memory.
+    ///
+    /// This bypasses the banking of `0xD000-0xDFFF`, bank `n` is at
+    /// `n * 0x1000..(n + 1) * 0x1000`, so bank 0 (`0xC000-0xCFFF`) is at
+    /// `0x0000-0x0FFF`, and banks `1-7` (`0xD000-0xDFFF`, selected by
+    /// `SVBK`) follow it. In DMG mode, and for DMG games, only banks `0`
+    /// and `1` are used.
+    pub fn wram_mut(&mut self) -> &mut [u8] {
+        self.bus.wram_mut()
+    }
+
+    /// Returns HRAM (`0xFF80-0xFFFE`, 127 bytes) for direct access, the
+    /// address `addr` is at index `addr - 0xFF80`.
+    pub fn hram_mut(&mut self) -> &mut [u8] {
+        self.bus.hram_mut()
+    }
+
+    /// Returns `true` if the boot rom is currently mapped, i.e. the boot
+    /// sequence is running and did not write to `0xFF50` yet.
+    pub fn boot_rom_active(&self) -> bool {
+        self.bus.boot_rom_active()
+    }
+
+    /// Returns `true` if the emulator was created with a boot rom, even if
+    /// the boot sequence has finished.
+    pub fn has_boot_rom(&self) -> bool {
+        self.bus.has_boot_rom()
+    }
+
+    /// Return the parsed header of the cartridge.
+    pub fn cartridge_header(&self) -> &CartridgeHeader {
+        self.bus.cartridge().header()
+    }
+
+    /// Return the mapper type of the cartridge.
+    pub fn mapper_type(&self) -> MapperType {
+        self.cartridge_header().mapper_type
+    }
+
+    /// Return the ROM bank currently mapped to `0x4000-0x7FFF`.
+    ///
+    /// Can be used to resolve an address in that region to an offset in the
+    /// ROM, `bank * 0x4000 + (addr - 0x4000)`.
+    pub fn current_rom_bank(&self) -> u16 {
+        self.bus.cartridge().rom_banks().1
+    }
+
+    /// Return the external RAM bank currently mapped to `0xA000-0xBFFF`,
+    /// `0` for mappers without RAM banking.
+    pub fn current_ram_bank(&self) -> u8 {
+        self.bus.cartridge().ram_bank()
+    }
+
+    /// Detects the mapper type of the ROM `data` from its header without
+    /// loading it, which can be used with [`MapperType::is_implemented`]
+    /// to check if the ROM is supported before loading it.
+    pub fn detect_mapper(data: &[u8]) -> Result<MapperType, CartridgeError> {
+        Cartridge::detect_mapper(data)
+    }
+
+    /// Returns the hash identifying the cartridge, which is also stored in
+    /// save states to check that they are loaded into the same game.
+    ///
+    /// This is the SHA-256 of the whole ROM data as loaded, not only the
+    /// header, so two dumps of the same game are only the same if they are
+    /// identical byte for byte (including any over-dumped data after the
+    /// size in the header). This can be used to key saves and configs on
+    /// the content of the ROM instead of its file path.
+    ///
+    /// For custom cartridges, this is [`CartridgeInterface::hash`].
+    pub fn cartridge_hash(&self) -> [u8; 32] {
+        *self.bus.cartridge().hash()
+    }
+
+    /// The cartridge file path, `None` if the [`GameBoy`] was created
+    /// from memory using [`GameBoy::from_rom_bytes`].
+    pub fn file_path(&self) -> Option<&Path> {
+        self.bus.cartridge().file_path()
+    }
+
+    /// Loads the cartridge SRAM from `reader`, this is the same format as
+    /// the `.sav` file, and includes the extra battery data of the
+    /// mapper (i.e. the MBC3 RTC).
+    ///
+    /// Returns [`CartridgeError::SramSizeMismatch`] if the data size does
+    /// not exactly match [`GameBoy::sram_size`].
+    pub fn load_sram<R: Read>(&mut self, mut reader: R) -> Result<(), CartridgeError> {
+        self.bus.cartridge_mut().load_sram(&mut reader)
+    }
+
+    /// Returns `true` if the cartridge has a battery to keep its RAM (and
+    /// clock) data, i.e. if there is anything to save in a `.sav` file.
+    pub fn has_battery(&self) -> bool {
+        self.bus.cartridge().has_battery()
+    }
+
+    /// Returns the size of the battery backed data in bytes, the size of
+    /// the `.sav` file, which is the cartridge RAM followed by the extra
+    /// battery data of the mapper (i.e. the MBC3 RTC).
+    ///
+    /// Cartridges without a battery return `0`, frontends should not create
+    /// `.sav` files for them.
+    pub fn sram_size(&self) -> usize {
+        self.bus.cartridge().sram_size()
+    }
+
+    /// Writes the cartridge SRAM into `writer`, in the same format as the `.sav` file,
+    /// which can be loaded back with [`GameBoy::load_sram`].
+    ///
+    /// Writes [`GameBoy::sram_size`] bytes, i.e. nothing for cartridges
+    /// without a battery.
+    pub fn save_sram<W: Write>(&self, mut writer: W) -> Result<(), SaveError> {
+        self.bus.cartridge().save_sram(&mut writer)?;
+        Ok(())
+    }
+
+    /// Shuts down the emulator, writing the cartridge SRAM to the sram file
+    /// if the cartridge has a battery and an sram file.
+    ///
+    /// Unlike the save on drop enabled by [`GameBoyBuilder::save_on_shutdown`],
+    /// which only prints errors, this returns [`SaveError::SramError`] on
+    /// failure. The SRAM is not saved again when the instance is dropped.
+    pub fn shutdown(mut self) -> Result<(), SaveError> {
+        self.bus.cartridge_mut().flush_sram()?;
+        Ok(())
+    }
+
+    /// Returns `true` if the battery backed RAM was written by the game
+    /// since the last [`GameBoy::save_sram`] or [`GameBoy::load_sram`].
+    ///
+    /// Frontends can use this to save the SRAM periodically only when
+    /// needed. Always `false` for cartridges without a battery.
+    pub fn sram_is_dirty(&self) -> bool {
+        self.bus.cartridge().sram_dirty()
+    }
+
+    /// Sets a callback to be called the first time the battery backed RAM
+    /// is written since the last [`GameBoy::save_sram`], i.e. when
+    /// [`GameBoy::sram_is_dirty`] becomes `true`.
+    pub fn set_sram_dirty_callback(&mut self, callback: SramDirtyCallback) {
+        self.bus
+            .cartridge_mut()
+            .set_sram_dirty_callback(Some(callback));
+    }
+
+    /// Removes the callback set by [`GameBoy::set_sram_dirty_callback`]
+    pub fn clear_sram_dirty_callback(&mut self) {
+        self.bus.cartridge_mut().set_sram_dirty_callback(None);
+    }
+
+    /// Return the pixels buffer of the PPU at the current state.
+    ///
+    /// The format of the pixel buffer is RGB, i.e. 3 bytes per pixel.
+    pub fn screen_buffer(&self) -> &[u8] {
+        self.bus.screen_buffer()
+    }
+
+    /// Returns `true` if a new frame was completed since the last call to
+    /// [`take_frame_ready`](Self::take_frame_ready).
+    ///
+    /// The screen buffer always holds the last complete frame, this can be
+    /// used by frontends polling with [`clock_for_scanline`](Self::clock_for_scanline)
+    /// or [`clock_for_cycles`](Self::clock_for_cycles) to only upload the
+    /// screen when it changed.
+    pub fn frame_ready(&self) -> bool {
+        self.bus.frame_ready()
+    }
+
+    /// Returns [`frame_ready`](Self::frame_ready) and clears it, should be
+    /// called when the new frame is read from the screen buffer.
+    pub fn take_frame_ready(&mut self) -> bool {
+        self.bus.take_frame_ready()
+    }
+
+    /// Returns `true` if the last completed frame in
+    /// [`screen_buffer`](Self::screen_buffer) differs from the frame
+    /// completed before it.
+    ///
+    /// Frontends can skip uploading the screen when nothing changed, i.e.
+    /// in paused games or static menus.
+    pub fn frame_changed(&self) -> bool {
+        self.bus.frame_changed()
+    }
+
+    /// Return the color index (`0-3`) of every pixel of the
+    /// [`screen_buffer`](Self::screen_buffer), 1 byte per pixel.
+    ///
+    /// The index is the one from the tile data, before applying the
+    /// palette, i.e. in DMG mode, the `BGP`/`OBP0`/`OBP1` registers are not
+    /// applied. The palette used is in
+    /// [`screen_buffer_palettes`](Self::screen_buffer_palettes).
+    pub fn screen_buffer_indices(&self) -> &[u8] {
+        self.bus.screen_buffer_indices()
+    }
+
+    /// Return the palette used by every pixel of the
+    /// [`screen_buffer`](Self::screen_buffer), 1 byte per pixel.
+    ///
+    /// Bits 0-2 are the palette number, which is the CGB palette (`0-7`),
+    /// or in DMG mode, `0` for `BGP` and `0`/`1` for `OBP0`/`OBP1`.
+    /// Bit 3 is set if the palette is a sprite palette.
+    pub fn screen_buffer_palettes(&self) -> &[u8] {
+        self.bus.screen_buffer_palettes()
+    }
+
+    /// Returns the raw content of VRAM bank `bank` (`0x2000` bytes), which
+    /// is mapped to `0x8000-0x9FFF`.
+    ///
+    /// This reads the memory directly, even when the PPU blocks access to it.
+    /// Bank `1` is only used in CGB mode, in DMG mode it is unused.
+    ///
+    /// # Panics
+    /// If `bank` is not `0` or `1`.
+    pub fn vram(&self, bank: u8) -> &[u8] {
+        self.bus.vram(bank)
+    }
+
+    /// Returns the indices of the tiles (16 bytes each, in `0x8000-0x97FF`)
+    /// written since the last call, in increasing order, and resets the set.
+    /// Tiles of bank `1` are offset by [`VRAM_TILES_PER_BANK`].
+    ///
+    /// This is cheaper than diffing [`GameBoy::vram`] every frame, and lets
+    /// tile viewers update only the changed tiles. A tile is reported even
+    /// if the written value is the same, writes to the tile maps are not
+    /// tracked, and neither are the changes from loading a state.
+    pub fn vram_dirty_tiles(&mut self) -> Vec<u16> {
+        self.bus.take_vram_dirty_tiles()
+    }
+
+    /// Returns the raw content of OAM (`0xA0` bytes), which is mapped to
+    /// `0xFE00-0xFE9F`.
+    ///
+    /// This reads the memory directly, even when the PPU blocks access to it.
+    pub fn oam(&self) -> &[u8] {
+        self.bus.oam()
+    }
+
+    /// Returns `true` if the LCD is enabled (bit 7 of `LCDC`), when the LCD
+    /// is off, the PPU does not render and the screen buffer is blank.
+    pub fn is_lcd_on(&self) -> bool {
+        self.bus.is_lcd_on()
+    }
+
+    /// Returns `true` if the CPU is in `STOP` mode waiting for a button to
+    /// be pressed, the PPU and the APU channels are not clocked in this
+    /// mode, see [`CpuState::Stopped`].
+    ///
+    /// This is `false` during a CGB speed switch, which also executes `STOP`,
+    /// but only halts the CPU, see [`CpuState::SpeedSwitching`].
+    pub fn is_stopped(&self) -> bool {
+        self.bus.stopped()
+    }
+
+    /// Returns the current PPU mode as reported in `STAT`:
+    /// `0` HBlank, `1` VBlank, `2` OAM scan, `3` drawing.
+    ///
+    /// When the LCD is off, the mode is `0`.
+    pub fn ppu_mode(&self) -> u8 {
+        self.bus.ppu_mode()
+    }
+
+    /// Returns the state of the OAM DMA and the CGB VRAM DMA (HDMA/GDMA)
+    /// transfers, this does not affect the transfers.
+    pub fn dma_status(&self) -> DmaStatus {
+        self.bus.dma_status()
+    }
+
+    /// Returns the scanline the PPU is currently processing (`0..=153`).
+    ///
+    /// This is the same as `LY`, except at scanline 153, where `LY` reads
+    /// `0` early, while this stays at `153` until the end of the scanline.
+    pub fn current_scanline(&self) -> u8 {
+        self.bus.ppu_scanline()
+    }
+
+    /// Returns the dot (PPU cycle) within the current scanline (`0..456`).
+    pub fn current_dot(&self) -> u16 {
+        self.bus.ppu_cycle()
+    }
+
+    /// Returns the internal window line counter of the PPU, which is the line
+    /// of the window that will be drawn next.
+    ///
+    /// It is different from `LY - WY`, as it is only incremented on
+    /// scanlines where the window was actually drawn (e.g. it does not
+    /// advance while the window is disabled or `WX` is offscreen), and it
+    /// is reset at the start of VBlank.
+    pub fn window_line_counter(&self) -> u8 {
+        self.bus.ppu_window_line_counter()
+    }
+
+    /// Overrides the internal window line counter of the PPU, see
+    /// [`GameBoy::window_line_counter`], the window will continue drawing
+    /// from line `counter` of the window on the next scanline it is drawn.
+    ///
+    /// This is for testing only, to set up an exact PPU state for
+    /// reproducing window edge cases. Changing it mid-frame produces a state
+    /// the hardware can't reach, and the counter is reset at the next VBlank.
+    pub fn set_window_line_counter(&mut self, counter: u8) {
+        self.bus.set_ppu_window_line_counter(counter);
+    }
+
+    /// Returns a snapshot of the pixel pipeline of the PPU, the background
+    /// fetcher and the background and sprite FIFOs.
+    ///
+    /// This is only meaningful during mode 3 (drawing), and is meant to be
+    /// polled after [`GameBoy::step_cycle`] to visualize the pipeline
+    /// dot by dot. Reading it does not affect the emulation.
+    pub fn ppu_pipeline_state(&self) -> PipelineState {
+        self.bus.ppu_pipeline_state()
+    }
+
+    /// Returns the state of the window and fine scroll handling of the PPU
+    /// at the current dot, see [`PpuQuirkReport`].
+    ///
+    /// Like [`GameBoy::ppu_pipeline_state`], this is meant to be polled after
+    /// [`GameBoy::step_cycle`] to pin down window rendering issues (i.e.
+    /// with `WX < 7`). Reading it does not affect the emulation.
+    pub fn ppu_quirk_report(&self) -> PpuQuirkReport {
+        self.bus.ppu_quirk_report()
+    }
+
+    /// Sets a callback to be called once every frame when the PPU completes
+    /// rendering it (on entering VBlank), with the RGB screen buffer of that
+    /// frame (same as [`screen_buffer`](Self::screen_buffer)).
+    ///
+    /// The callback is not called while the LCD is off.
+    pub fn set_frame_callback(&mut self, f: FrameCallback) {
+        self.bus.set_frame_callback(Some(f));
+    }
+
+    /// Removes the frame callback if any is set, else, nothing is done
+    pub fn clear_frame_callback(&mut self) {
+        self.bus.set_frame_callback(None);
+    }
+
+    /// Sets a callback to be called when the PPU completes drawing a
+    /// scanline (on entering HBlank), with the line number (`0-143`) and
+    /// the 160 RGB pixels of that line, before any frame blending.
+    ///
+    /// This allows processing the frame while it is drawn, i.e. for games
+    /// that change the palettes between scanlines. The callback is not
+    /// called during VBlank or while the LCD is off.
+    pub fn set_scanline_callback(&mut self, f: ScanlineCallback) {
+        self.bus.set_scanline_callback(Some(f));
+    }
+
+    /// Removes the scanline callback if any is set, else, nothing is done
+    pub fn clear_scanline_callback(&mut self) {
+        self.bus.set_scanline_callback(None);
+    }
+
+    /// Hides or shows a PPU layer, for debugging graphics.
+    ///
+    /// This is a debug override on top of the `LCDC` register, a hidden
+    /// layer is rendered as transparent (color 0) without affecting
+    /// the timing of the PPU. All layers are enabled by default.
+    pub fn set_layer_enabled(&mut self, layer: PpuLayer, enabled: bool) {
+        self.bus.set_layer_enabled(layer, enabled);
+    }
+
+    /// Sets the maximum number of sprites rendered per scanline, `None`
+    /// removes the limit, and all the sprites on the scanline are rendered.
+    ///
+    /// This is a debug override, the hardware limit is `10` (the default),
+    /// lifting it makes the PPU timing inaccurate, as every sprite
+    /// extends the rendering of the scanline.
+    pub fn set_sprite_limit(&mut self, limit: Option<u8>) {
+        self.bus.set_sprite_limit(limit);
+    }
+
+    /// Requests `interrupt` by setting its bit in the `IF` register, like
+    /// when the interrupt is generated by its peripheral.
+    ///
+    /// The interrupt will only be serviced if it is enabled in `IE` and
+    /// interrupts are enabled in the CPU (`IME`), but it will still wake
+    /// the CPU from `HALT` if enabled in `IE`.
+    pub fn request_interrupt(&mut self, interrupt: InterruptType) {
+        self.bus.request_interrupt(interrupt);
+    }
+
+    /// Returns the values of the `(IF, IE)` registers, the requested and
+    /// enabled interrupts respectively.
+    pub fn pending_interrupts(&self) -> (u8, u8) {
+        self.bus.pending_interrupts()
+    }
+
+    /// Returns how many times each interrupt was requested since the last
+    /// VBlank interrupt (counting it), the counters are reset when the
+    /// next VBlank is requested.
+    ///
+    /// Requests are counted even if the interrupt is not enabled or already
+    /// requested, which can be used to find STAT interrupt storms or missing
+    /// VBlanks. Note that there is no VBlank while the LCD is off, so the
+    /// counters keep accumulating then.
+    pub fn interrupts_fired_this_frame(&self) -> InterruptCounts {
+        self.bus.interrupt_counts()
+    }
+
+    /// Returns the interrupt master enable flag (`IME`) of the CPU.
+    ///
+    /// After `EI`, this only becomes `true` after the next instruction.
+    pub fn ime(&self) -> bool {
+        self.cpu.ime()
+    }
+
+    /// Sets the interrupt master enable flag (`IME`) of the CPU directly,
+    /// without the one instruction delay of `EI`, and cancelling a pending
+    /// `EI` if any.
+    ///
+    /// This is a debugging escape hatch to put the CPU in a known state,
+    /// using it while a program is running can desync its timing, as the
+    /// program would not expect interrupts to be serviced (or not) at
+    /// this point.
+    pub fn set_ime(&mut self, on: bool) {
+        self.cpu.set_ime(on);
+    }
+
+    /// Returns the internal state of the CPU that is not visible in the
+    /// registers, the current [`HaltMode`] (including the `HALT` bug) and
+    /// whether an `EI` is waiting to enable `IME` after the next instruction.
+    ///
+    /// Useful for asserting the exact `HALT`/`EI` timing transitions in
+    /// tests, reading it does not affect the emulation.
+    pub fn cpu_quirk_state(&self) -> CpuQuirkState {
+        self.cpu.quirk_state()
+    }
+
+    /// Returns the 8 background palettes of the CGB color RAM as RGB,
+    /// with the configured [`ColorCorrection`] applied.
+    ///
+    /// In DMG mode, palette `0` is the effective palette after applying
+    /// the `BGP` register, the rest of the palettes are unused.
+    pub fn bg_palettes(&self) -> [[(u8, u8, u8); 4]; 8] {
+        self.bus.bg_palettes_rgb()
+    }
+
+    /// Renders the whole background tile map (32x32 tiles) selected by
+    /// `LCDC` to a `256x256` RGB buffer, using the current tile data and
+    /// [`bg_palettes`](Self::bg_palettes), regardless of the scroll
+    /// registers, for background map viewers.
+    ///
+    /// The returned [`TilemapRender`] also contains `SCX` and `SCY`, the top
+    /// left corner of the visible `160x144` area, which wraps around the
+    /// edges of the map.
+    pub fn render_background(&self) -> TilemapRender {
+        self.bus.ppu_render_background()
+    }
+
+    /// Renders the whole window tile map (32x32 tiles) selected by `LCDC`
+    /// to a `256x256` RGB buffer, like [`render_background`](Self::render_background),
+    /// regardless of whether the window is enabled or where it is positioned,
+    /// which helps debugging HUDs drawn with the window over the background.
+    ///
+    /// The returned [`TilemapRender`] also contains `WX` and `WY`, the
+    /// position on the screen of the top left corner of the window, offset
+    /// by `7` in `WX`.
+    pub fn render_window(&self) -> TilemapRender {
+        self.bus.ppu_render_window()
+    }
+
+    /// Returns the 8 sprite palettes of the CGB color RAM as RGB,
+    /// with the configured [`ColorCorrection`] applied.
+    ///
+    /// In DMG mode, palettes `0` and `1` are the effective palettes after
+    /// applying the `OBP0` and `OBP1` registers, the rest of the palettes
+    /// are unused.
+    pub fn sprite_palettes(&self) -> [[(u8, u8, u8); 4]; 8] {
+        self.bus.sprite_palettes_rgb()
+    }
+
+    /// Writes the pixels buffer of the PPU at the current state into `out`
+    /// in RGBA format, i.e. 4 bytes per pixel with alpha set to `0xFF`.
+    ///
+    /// # Panics
+    /// If the length of `out` is not `160 * 144 * 4`.
+    pub fn screen_buffer_rgba(&self, out: &mut [u8]) {
+        self.bus.screen_buffer_rgba(out);
+    }
+
+    /// Same as [`screen_buffer_rgba`](Self::screen_buffer_rgba), but
+    /// allocates a new buffer for the result.
+    pub fn screen_buffer_rgba_vec(&self) -> Vec<u8> {
+        let mut out = vec![0; 160 * 144 * 4];
+        self.screen_buffer_rgba(&mut out);
+        out
+    }
+
+    /// Writes the pixels buffer of the PPU at the current state into `out`
+    /// in RGB565 format, i.e. one `u16` per pixel with 5 bits for red, 6 for
+    /// green and 5 for blue, for displays that use this format.
+    ///
+    /// The DMG palette and color correction are applied the same way as in
+    /// [`screen_buffer`](Self::screen_buffer). `big_endian` selects the byte
+    /// order of every pixel in memory, as displays differ in what they
+    /// expect.
+    ///
+    /// # Panics
+    /// If the length of `out` is not `160 * 144`.
+    pub fn screen_buffer_rgb565(&self, out: &mut [u16], big_endian: bool) {
+        self.bus.screen_buffer_rgb565(out, big_endian);
+    }
+
+    /// Compares the [`screen_buffer`](Self::screen_buffer) against `prev`,
+    /// and appends `(pixel_index, rgb)` to `out` for every pixel that
+    /// changed, where `pixel_index` is `y * 160 + x`.
+    ///
+    /// `prev` should be the last screen buffer the caller saw (i.e. a copy
+    /// of the last frame sent), so that applying the changes to it results
+    /// in the current screen. `out` is not cleared, and can be reused
+    /// between frames to avoid allocations.
+    ///
+    /// # Panics
+    /// If the length of `prev` is not `160 * 144 * 3`.
+    pub fn screen_diff(&self, prev: &[u8], out: &mut Vec<(u16, [u8; 3])>) {
+        let current = self.screen_buffer();
+        assert_eq!(prev.len(), current.len(), "invalid `prev` buffer size");
+
+        out.extend(
+            current
+                .chunks_exact(3)
+                .zip(prev.chunks_exact(3))
+                .enumerate()
+                .filter(|(_, (new, old))| new != old)
+                .map(|(i, (new, _))| (i as u16, [new[0], new[1], new[2]])),
+        );
+    }
+
+    /// Enables LCD ghosting emulation, which produces the motion trails of the
+    /// original LCD, and makes flickering sprites (used for transparency in
+    /// some games) look blended.
+    ///
+    /// Every frame, each pixel of [`screen_buffer`](Self::screen_buffer)
+    /// moves from its previous value toward the newly rendered one, `decay`
+    /// is how much of the previous value is kept (clamped to `0.0..=1.0`),
+    /// higher values produce longer trails.
+    ///
+    /// Use `None` to disable it (default).
+    pub fn set_lcd_ghosting(&mut self, decay: Option<f32>) {
+        self.bus.set_lcd_ghosting_decay(decay);
+    }
+
+    /// Enables frame blending, where every frame of
+    /// [`screen_buffer`](Self::screen_buffer) is blended with the previous
+    /// rendered frame, so that sprites flickering every other frame appear
+    /// solid like on the original LCD.
+    ///
+    /// `weight` is how much of the previous frame is used (clamped to
+    /// `0.0..=1.0`), `0.5` is an equal blend of the two frames. Unlike
+    /// [`set_lcd_ghosting`](Self::set_lcd_ghosting), there are no long
+    /// trails, as only the last frame is used. If both are enabled, the
+    /// blending is applied before the ghosting.
+    ///
+    /// This only affects the output, not the emulation or save states.
+    /// Use `None` to disable it (default).
+    pub fn set_lcd_frame_blend(&mut self, weight: Option<f32>) {
+        self.bus.set_lcd_frame_blend(weight);
+    }
+
+    /// Returns the number of samples (stereo pairs in [`AudioBuffers::all`])
+    /// that will be generated until the end of the current frame.
+    ///
+    /// The number of samples is not constant every frame, as a frame is not
+    /// an exact number of samples, the fraction is carried to the next frame
+    /// so that the average matches the sample rate. Frontends can use this to
+    /// size their audio queue. This assumes the frame runs for the full
+    /// duration, i.e. the LCD is not turned on or off in the middle.
+    pub fn expected_samples_this_frame(&self) -> usize {
+        self.bus
+            .apu_expected_samples(PPU_CYCLES_PER_FRAME.saturating_sub(self.frame_ppu_cycles))
+    }
+
+    /// Return the audio buffer of the APU at the current state.
+    ///
+    /// We use `&mut` as it will also reset the buffers after using them
+    pub fn audio_buffers(&mut self) -> AudioBuffers {
+        self.bus.audio_buffers()
+    }
+
+    /// Takes the audio buffers of the APU at the current state, same as
+    /// [`GameBoy::audio_buffers`], but the returned buffers are owned and
+    /// can be sent to an audio thread.
+    ///
+    /// The vectors are moved out of the APU, so the APU allocates new buffers
+    /// as it produces samples after every call. Use
+    /// [`GameBoy::drain_audio_into`] to reuse a buffer without allocating.
+    pub fn take_audio_buffers(&mut self) -> OwnedAudioBuffers {
+        self.bus.audio_buffers().into_owned()
+    }
+
+    /// Reads the wave RAM of the wave channel (channel 3), which contains
+    /// 32 4-bit samples, the high nibble of each byte is played first.
+    ///
+    /// This behaves like reads from `0xFF30-0xFF3F` by the CPU, so while
+    /// the channel is playing, only the byte being played is accessible
+    /// (and on DMG, only right when the channel reads it, otherwise `0xFF`
+    /// is returned). Stop the channel to access the whole RAM.
+    pub fn wave_ram(&self) -> [u8; 16] {
+        self.bus.wave_ram()
+    }
+
+    /// Returns a snapshot of the sound registers `NR10-NR52` and the internal
+    /// state of each channel (frequency, volume, length counter, enabled and DAC).
+    ///
+    /// Useful for debugging why a channel is silent, reading the state here
+    /// does not affect the emulation.
+    pub fn apu_registers(&self) -> ApuRegisters {
+        self.bus.apu_registers()
+    }
+
+    /// Returns `true` if the APU is powered on (bit 7 of `NR52`).
+    ///
+    /// While the APU is off, writes to the sound registers are ignored,
+    /// except for the length registers (`NR11`, `NR21`, `NR31` and `NR41`)
+    /// on DMG, which are not affected by power, on CGB they are ignored too.
+    pub fn apu_powered(&self) -> bool {
+        self.bus.apu_powered()
+    }
+
+    /// Returns the current output frequency of `channel` in Hz, or `None` if
+    /// the channel is not playing.
+    ///
+    /// For the pulse channels this is the frequency of a full duty cycle, and
+    /// for the wave channel the frequency of playing the whole wave RAM once.
+    /// For the noise channel, this is the rate at which its LFSR is shifted.
+    pub fn channel_frequency(&self, channel: ApuChannelId) -> Option<f32> {
+        self.bus.apu_channel_frequency(channel)
+    }
+
+    /// Writes the wave RAM of the wave channel (channel 3), this behaves like
+    /// writes to `0xFF30-0xFF3F` by the CPU, see [`GameBoy::wave_ram`].
+    pub fn set_wave_ram(&mut self, data: [u8; 16]) {
+        self.bus.set_wave_ram(data);
+    }
+
+    /// Appends the mixed audio output ([`AudioBuffers::all`]) to `out`, and
+    /// clears all the audio buffers of the APU.
+    ///
+    /// Unlike [`GameBoy::audio_buffers`], the samples are moved to a buffer
+    /// owned by the caller, which can be reused between frames without
+    /// allocating.
+    pub fn drain_audio_into(&mut self, out: &mut Vec<f32>) {
+        self.bus.drain_audio_into(out);
+    }
+
+    /// Same as [`GameBoy::drain_audio_into`] but appends the output of
+    /// `channel` only, all the audio buffers of the APU are still cleared.
+    pub fn drain_audio_channel_into(&mut self, channel: ApuChannelId, out: &mut Vec<f32>) {
+        self.bus.drain_audio_channel_into(channel, out);
+    }
+
+    /// Change the state of the joypad button to `pressed`.
+    pub fn press_joypad(&mut self, button: JoypadButton) {
+        self.bus.press_joypad(button);
+    }
+
+    /// Change the state of the joypad button to `released`.
+    pub fn release_joypad(&mut self, button: JoypadButton) {
+        self.bus.release_joypad(button);
+    }
+
+    /// Returns the state of all the joypad buttons.
+    pub fn joypad_state(&self) -> JoypadState {
+        self.bus.joypad_state()
+    }
+
+    /// Enables autofire for `button` at `rate_hz` presses per second,
+    /// `None` disables it.
+    ///
+    /// While the button is held (with [`GameBoy::press_joypad`]), the game
+    /// sees it pressed and released repeatedly, starting pressed. The state
+    /// is updated at the start of every frame, so the highest effective rate
+    /// is half the frame rate (~30Hz). [`GameBoy::joypad_state`] still
+    /// reports the held buttons.
+    pub fn set_autofire(&mut self, button: JoypadButton, rate_hz: Option<f32>) {
+        self.bus.set_joypad_autofire(button, rate_hz);
+    }
+
+    /// Sets the state of all the joypad buttons at once, buttons not in
+    /// `buttons` are released.
+    ///
+    /// The joypad interrupt is requested based on the transition from the
+    /// previous state, i.e. only if a selected button became pressed.
+    pub fn set_joypad_state(&mut self, buttons: JoypadState) {
+        self.bus.set_joypad_state(buttons);
+    }
+
+    /// Applies a full controller snapshot, `pressed` are the buttons
+    /// pressed, and the rest are released, which is useful for netplay and
+    /// replays that submit the whole controller state every frame.
+    ///
+    /// Unlike calling [`GameBoy::press_joypad`] and
+    /// [`GameBoy::release_joypad`] for every button, this is applied as a
+    /// single update (see [`GameBoy::set_joypad_state`]), so at most one
+    /// joypad interrupt is requested for the net transition, and a snapshot
+    /// equal to the current state does not request any.
+    pub fn apply_joypad_snapshot(&mut self, pressed: &[JoypadButton]) {
+        let state = pressed
+            .iter()
+            .fold(JoypadState::empty(), |state, &button| state | button.into());
+        self.set_joypad_state(state);
+    }
+
+    /// Sets when the joypad interrupt is requested, [`JoypadIntMode::AnyChange`]
+    /// also requests it when a button is released, which is not what the
+    /// hardware does, but can be useful for debugging input handling.
+    ///
+    /// This is not stored in save states. Default is [`JoypadIntMode::Hardware`].
+    pub fn set_joypad_interrupt_mode(&mut self, mode: JoypadIntMode) {
+        self.bus.set_joypad_interrupt_mode(mode);
+    }
+
+    // TODO: Not sure if using RefCell is the best option here
+    /// Connect a serial device to the Gameboy.
+    ///
+    /// The gameboy is the `master` or the `slave` depending on the clock
+    /// source of the transfer, see [`GameBoy::serial_role`].
+    pub fn connect_device(&mut self, device: Rc<RefCell<dyn SerialDevice>>) {
+        self.bus.connect_device(device);
+    }
+
+    /// Disconnects the serial device if any is connected, else, nothing is done.
+    ///
+    /// If the game is transferring with the external clock (driven by the
+    /// device), the transfer is completed immediately with the remaining
+    /// bits received as `1` (like an unplugged cable), and the serial
+    /// interrupt is requested, so the game does not wait forever for a clock
+    /// that will never come.
+    pub fn disconnect_device(&mut self) {
+        self.bus.disconnect_device();
+    }
+
+    /// Returns the role of the gameboy in the serial link with the connected
+    /// device, see [`SerialRole`], which the game selects for each transfer
+    /// with bit 0 of `SC`.
+    ///
+    /// In [`SerialRole::Slave`], the serial data is shifted by the clock of
+    /// the device (when the game transfers with the external clock), which
+    /// allows linking two gameboys together.
+    pub fn serial_role(&self) -> SerialRole {
+        self.bus.serial_role()
+    }
+
+    /// Returns `true` if a serial transfer is in progress, i.e. bit 7 of `SC`.
+    pub fn serial_transfer_active(&self) -> bool {
+        self.bus.serial_transfer_active()
+    }
+
+    /// Returns the value of the serial data register `SB`, while transferring,
+    /// this contains part of the sent and received bytes.
+    pub fn serial_data(&self) -> u8 {
+        self.bus.serial_data()
+    }
+
+    /// Returns the frequency of the internal serial clock in Hz, this takes
+    /// into account the CGB fast clock bit in `SC` and double speed mode.
+    pub fn serial_clock_hz(&self) -> u32 {
+        self.bus.serial_clock_hz()
+    }
+
+    /// Sets a callback to be called with every byte the game sends over the
+    /// serial port as the master (with the internal clock), once all of its
+    /// bits are shifted out. This works whether a device is connected or not,
+    /// which is useful to capture the output of test ROMs.
+    pub fn set_serial_byte_callback(&mut self, callback: SerialByteCallback) {
+        self.bus.set_serial_byte_callback(Some(callback));
+    }
+
+    /// Removes the callback set by [`GameBoy::set_serial_byte_callback`]
+    pub fn clear_serial_byte_callback(&mut self) {
+        self.bus.set_serial_byte_callback(None);
+    }
+
+    /// Sets the state of the infrared light received by the gameboy
+    /// (`true` when lit), the game can read it from the `RP` register if
+    /// reading is enabled. CGB only.
+    pub fn set_ir_input(&mut self, lit: bool) {
+        self.bus.set_ir_input(lit);
+    }
+
+    /// Sets a callback to be called when the game turns the infrared LED
+    /// on or off, this can be forwarded to [`GameBoy::set_ir_input`] of
+    /// another gameboy to connect them. CGB only.
+    pub fn set_ir_led_callback(&mut self, callback: IrLedCallback) {
+        self.bus.set_ir_led_callback(Some(callback));
+    }
+
+    /// Removes the callback set by [`GameBoy::set_ir_led_callback`]
+    pub fn clear_ir_led_callback(&mut self) {
+        self.bus.set_ir_led_callback(None);
+    }
+
+    /// Returns counters of the data exchanged with the connected serial
+    /// device, see [`SerialStats`].
+    pub fn serial_stats(&self) -> SerialStats {
+        self.bus.serial_stats()
+    }
+
+    /// Sets the full 16 bit internal divider of the timer.
+    ///
+    /// Many games use the `DIV` register as a source of randomness, this
+    /// allows controlling it precisely. Unlike writing to the `DIV` register
+    /// (`0xFF04`) which resets the whole divider to `0` (the register only
+    /// exposes the top 8 bits), this sets every bit of the internal divider
+    /// and does not trigger a timer increment.
+    pub fn set_div(&mut self, value: u16) {
+        self.bus.set_div(value);
+    }
+
+    /// Returns the state of the timer registers, including the full 16 bit
+    /// internal divider and not only the visible `DIV` register.
+    pub fn timer_state(&self) -> TimerState {
+        self.bus.timer_state()
+    }
+
+    /// Sets the state of the timer registers, which is useful to sync the
+    /// sources of randomness for TAS playback.
+    ///
+    /// Like [`GameBoy::set_div`], this does not trigger timer increments
+    /// from the change of the divider, and any pending `TIMA` overflow is
+    /// cancelled.
+    pub fn set_timer_state(&mut self, state: TimerState) {
+        self.bus.set_timer_state(state);
+    }
+
+    /// Sets a callback to be called before executing every instruction.
+    ///
+    /// The callback receives a snapshot of the CPU registers (with `pc`
+    /// pointing to the start of the instruction) and the decoded instruction.
+    /// The immediate operand of the instruction is not decoded, as the CPU
+    /// reads it during execution, use [`disassemble`](Self::disassemble) if
+    /// it is needed.
+    pub fn set_trace_callback(&mut self, f: TraceCallback) {
+        self.cpu.set_trace_callback(Some(f));
+    }
+
+    /// Removes the trace callback if any is set, else, nothing is done
+    pub fn clear_trace_callback(&mut self) {
+        self.cpu.set_trace_callback(None);
+    }
+
+    /// Writes a line to `writer` before executing every instruction, in the
+    /// format of [`dump_cpu_log_line`](Self::dump_cpu_log_line), which can
+    /// be compared line by line against Gameboy Doctor reference logs.
+    ///
+    /// Interrupt dispatches and halted cycles are not logged. Nothing is
+    /// buffered, so wrapping `writer` in a [`BufWriter`](std::io::BufWriter)
+    /// is recommended. If writing fails, the log is removed.
+    pub fn set_doctor_log(&mut self, writer: Box<dyn Write>) {
+        self.cpu.set_doctor_log(Some(writer));
+    }
+
+    /// Removes the doctor log writer if any is set, which drops it, else,
+    /// nothing is done
+    pub fn clear_doctor_log(&mut self) {
+        self.cpu.set_doctor_log(None);
+    }
+
+    /// Reads the byte at `addr` as it is stored, ignoring OAM locking by the PPU
+    /// and bus conflicts with OAM DMA.
+    ///
+    /// Reading is done without clocking the bus, see [`GameBoy::read_mem_as_cpu`]
+    /// for reading what the CPU would see.
+    pub fn read_mem(&mut self, addr: u16) -> u8 {
+        self.bus.read_raw(addr)
+    }
+
+    /// Reads the byte at `addr` as the CPU would see it at this instant,
+    /// i.e. `0xFF` from OAM while it is locked by the PPU (mode 2 and 3), or
+    /// the DMA value when reading from the bus used by an active OAM DMA.
+    ///
+    /// Reading is done without clocking the bus, so the emulation state is
+    /// not affected.
+    pub fn read_mem_as_cpu(&mut self, addr: u16) -> u8 {
+        self.bus.read_as_cpu(addr)
+    }
+
+    /// Decodes the instruction at `addr` without executing it.
+    ///
+    /// Returns the decoded instruction and its length in bytes, the instruction
+    /// implements [`Display`](std::fmt::Display) for rendering it in assembly
+    /// form. Reading is done without clocking the bus, so the emulation state
+    /// is not affected.
+    pub fn disassemble(&mut self, addr: u16) -> (Instruction, u16) {
+        Instruction::decode(addr, |addr| self.bus.peek(addr))
+    }
+
+    /// Decodes up to `count` consecutive instructions starting at `start`,
+    /// each instruction starts after the end of the previous one.
+    ///
+    /// Stops early if an instruction would extend past `0xFFFF`. Like
+    /// [`disassemble`](Self::disassemble), the emulation state is not affected.
+    pub fn disassemble_range(&mut self, start: u16, count: usize) -> Vec<DisassembledLine> {
+        let mut lines = Vec::with_capacity(count);
+
+        let mut addr = start as u32;
+        while lines.len() < count && addr <= 0xFFFF {
+            let (instruction, len) = self.disassemble(addr as u16);
+            let end = addr + len as u32;
+            if end > 0x10000 {
+                break;
+            }
+
+            let bytes = (addr..end).map(|addr| self.bus.peek(addr as u16)).collect();
+            lines.push(DisassembledLine {
+                address: addr as u16,
+                bytes,
+                text: instruction.to_string(),
+            });
+
+            addr = end;
+        }
+
+        lines
+    }
+
+    /// Returns the addresses where code is known to start in every
+    /// cartridge, which can be used to seed a recursive disassembler with
+    /// [`disassemble`](Self::disassemble).
+    ///
+    /// These are the entry point `0x0100`, the 5 interrupt vectors
+    /// (`0x40`, `0x48`, `0x50`, `0x58` and `0x60`), and the 8 `RST` targets
+    /// (`0x00-0x38`). Note that a game may not use all the interrupts or
+    /// `RST` instructions, in which case their addresses may contain data.
+    pub fn known_entry_points(&self) -> Vec<u16> {
+        let interrupt_vectors = (0..5).map(|i| 0x40 + i * 8);
+        let rst_targets = (0..8).map(|i| i * 8);
+
+        std::iter::once(0x100)
+            .chain(interrupt_vectors)
+            .chain(rst_targets)
+            .collect()
+    }
+
+    /// Returns the CPU state in the log format of
+    /// [Gameboy Doctor](https://github.com/robert/gameboy-doctor), e.g.
+    /// `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`,
+    /// which can be used to compare the execution against reference logs
+    /// when called before every instruction.
+    pub fn dump_cpu_log_line(&mut self) -> String {
+        let regs = self.cpu.registers();
+        let pc_mem = [0, 1, 2, 3].map(|i| self.bus.peek(regs.pc.wrapping_add(i)));
+
+        regs.doctor_log_line(pc_mem)
+    }
+
+    /// Returns a human readable dump of the emulation state as a JSON
+    /// object, containing the CPU registers and `IME`, the `IF`/`IE`
+    /// registers, the PPU mode and scanline, the timer registers and the
+    /// mapped ROM banks.
+    ///
+    /// This is meant for diffing against other emulators and reference logs,
+    /// use [`save_state`](Self::save_state) to save the state for resuming.
+    #[cfg(feature = "json")]
+    pub fn dump_state_json(&self) -> String {
+        let regs = self.cpu.registers();
+        let (interrupt_flags, interrupt_enable) = self.pending_interrupts();
+        let timer = self.timer_state();
+        let (rom0_bank, romx_bank) = self.bus.cartridge().rom_banks();
+
+        serde_json::json!({
+            "cpu": {
+                "a": regs.a,
+                "f": regs.f,
+                "b": regs.b,
+                "c": regs.c,
+                "d": regs.d,
+                "e": regs.e,
+                "h": regs.h,
+                "l": regs.l,
+                "sp": regs.sp,
+                "pc": regs.pc,
+                "ime": self.cpu.ime(),
+            },
+            "interrupts": {
+                "if": interrupt_flags,
+                "ie": interrupt_enable,
+            },
+            "ppu": {
+                "mode": self.ppu_mode(),
+                "scanline": self.current_scanline(),
+            },
+            "timer": {
+                "divider": timer.divider,
+                "tima": timer.tima,
+                "tma": timer.tma,
+                "tac": timer.tac,
+            },
+            "cartridge": {
+                "rom0_bank": rom0_bank,
+                "romx_bank": romx_bank,
+            },
+        })
+        .to_string()
+    }
+
+    /// Sets a callback to be called with `(left, right)` for every audio
+    /// sample generated by the APU, as soon as it is generated.
+    ///
+    /// The samples are still pushed to the buffers returned by
+    /// [`audio_buffers`](Self::audio_buffers), so that API can be used
+    /// alongside the callback.
+    pub fn set_audio_sample_callback(&mut self, callback: Box<dyn FnMut(f32, f32)>) {
+        self.bus.set_audio_sample_callback(Some(callback));
+    }
+
+    /// Removes the audio sample callback if any is set, else, nothing is done
+    pub fn clear_audio_sample_callback(&mut self) {
+        self.bus.set_audio_sample_callback(None);
+    }
+
+    /// Sets a callback to be called with the mixed stereo output (same
+    /// layout as [`AudioBuffers::all`]) whenever a block of
+    /// [`AUDIO_CALLBACK_BLOCK_SAMPLES`] samples is generated, instead of
+    /// waiting for the end of the frame.
+    ///
+    /// While the callback is set, the samples are not pushed to the buffers
+    /// returned by [`audio_buffers`](Self::audio_buffers), which stay empty.
+    pub fn set_audio_callback(&mut self, callback: AudioCallback) {
+        self.bus.set_audio_callback(Some(callback));
+    }
+
+    /// Removes the audio block callback if any is set, the samples of the
+    /// incomplete block are dropped, and the buffers are filled again
+    pub fn clear_audio_callback(&mut self) {
+        self.bus.set_audio_callback(None);
+    }
+
+    /// Returns the current step (`0-7`) of the APU frame sequencer, which is
+    /// advanced at 512Hz by the divider. Length is clocked on even steps,
+    /// sweep on steps `2` and `6`, and envelope on step `7`.
+    pub fn apu_frame_sequencer_step(&self) -> u8 {
+        self.bus.apu_frame_sequencer_step()
+    }
+
+    /// Sets a callback to be called with the new step (`0-7`) every time
+    /// the APU frame sequencer steps, see [`apu_frame_sequencer_step`](Self::apu_frame_sequencer_step).
+    ///
+    /// Useful for syncing to the internal timing of the sound hardware.
+    /// The sequencer does not step while the APU is powered off.
+    pub fn set_apu_sequencer_callback(&mut self, callback: Box<dyn FnMut(u8)>) {
+        self.bus.set_apu_sequencer_callback(Some(callback));
+    }
+
+    /// Removes the APU sequencer callback if any is set, else, nothing is done
+    pub fn clear_apu_sequencer_callback(&mut self) {
+        self.bus.set_apu_sequencer_callback(None);
+    }
+
+    /// Mutes or unmutes an audio channel.
+    ///
+    /// A muted channel does not contribute to the mixed output
+    /// ([`AudioBuffers::all`] and the sample callback), but it keeps running
+    /// normally, and its own buffer in [`AudioBuffers`] is still filled.
+    pub fn set_channel_muted(&mut self, channel: ApuChannelId, muted: bool) {
+        self.bus.set_audio_channel_muted(channel, muted);
+    }
+
+    /// Solos or unsolos an audio channel.
+    ///
+    /// If any channel is soloed, only the soloed channels contribute to the
+    /// mixed output. Muting a channel takes priority over soloing it.
+    pub fn set_channel_solo(&mut self, channel: ApuChannelId, solo: bool) {
+        self.bus.set_audio_channel_solo(channel, solo);
+    }
+
+    /// Sets the master volume of the audio output, clamped to `0.0..=1.0`.
+    ///
+    /// This is applied on top of the volume registers of the emulated
+    /// program, and is not part of the save state. Default is `1.0`.
+    pub fn set_master_volume(&mut self, volume: f32) {
+        self.bus.set_audio_master_volume(volume);
+    }
+
+    /// Sets the layout of the per-channel buffers of [`AudioBuffers`], which
+    /// can be used to render each channel separately (stems).
+    ///
+    /// With [`AudioStemMode::Stereo`], each channel keeps its own panning,
+    /// and the sum of the 4 channels is the same as [`AudioBuffers::all`]
+    /// (when no channel is muted).
+    /// The mixed buffers are not affected. This is not part of the save
+    /// state. Default is [`AudioStemMode::Stereo`].
+    pub fn set_audio_stem_mode(&mut self, mode: AudioStemMode) {
+        self.bus.set_audio_stem_mode(mode);
+    }
+
+    /// Sets the volume of an audio channel, clamped to `0.0..=1.0`.
+    ///
+    /// This is applied to the channel's output in all the buffers of
+    /// [`AudioBuffers`], and is not part of the save state. Default is `1.0`.
+    pub fn set_channel_volume(&mut self, channel: ApuChannelId, volume: f32) {
+        self.bus.set_audio_channel_volume(channel, volume);
+    }
+
+    /// Enables or disables the audio output of the APU. Default is enabled.
+    ///
+    /// When disabled, the APU is still clocked normally (length counters,
+    /// sweep, envelope, ...) so the emulation is not affected, but no samples
+    /// are generated, so [`audio_buffers`](Self::audio_buffers) returns
+    /// empty buffers and the audio sample callback is not called.
+    /// This is useful for fast-forwarding without the cost of mixing.
+    ///
+    /// Unlike [`set_channel_muted`](Self::set_channel_muted), this affects
+    /// all the buffers, and is not part of the save state.
+    pub fn set_apu_enabled(&mut self, on: bool) {
+        self.bus.set_apu_output_enabled(on);
+    }
+
+    /// Sets the speed the emulation is run at relative to real time, e.g.
+    /// `2.0` when the frontend runs two frames for every displayed frame.
+    ///
+    /// The core does not throttle itself, but the APU generates fewer
+    /// samples (`sample_rate / multiplier` every emulated second), so that
+    /// the audio output still plays in real time without overflowing the
+    /// buffers, which sounds sped up instead of broken. See
+    /// [`audio_samples_per_frame`](Self::audio_samples_per_frame).
+    ///
+    /// The multiplier is at least `0.1`, and is not part of the save state.
+    /// Default is `1.0`.
+    pub fn set_speed_multiplier(&mut self, multiplier: f32) {
+        self.bus.set_audio_speed_multiplier(multiplier);
+    }
+
+    /// Returns the speed multiplier, see
+    /// [`set_speed_multiplier`](Self::set_speed_multiplier).
+    pub fn speed_multiplier(&self) -> f32 {
+        self.bus.audio_speed_multiplier()
+    }
+
+    /// Returns the number of (stereo) audio samples generated by the APU for
+    /// a full frame at the current speed multiplier, which is what the
+    /// audio output consumes for every frame clocked by
+    /// [`clock_for_frame`](Self::clock_for_frame).
+    ///
+    /// The buffers of [`AudioBuffers`] contain 2 values per sample.
+    pub fn audio_samples_per_frame(&self) -> f64 {
+        const CPU_CLOCKS_PER_SECOND: f64 = 4194304.;
+
+        let frame_duration = PPU_CYCLES_PER_FRAME as f64 / CPU_CLOCKS_PER_SECOND;
+        self.bus.audio_samples_per_second() * frame_duration
+    }
+
+    /// Takes an in-memory [`Snapshot`] of the current state of the emulator.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut snapshot = Snapshot::default();
+        self.snapshot_into(&mut snapshot);
+        snapshot
+    }
+
+    /// Same as [`GameBoy::snapshot`], but reuses the buffer of `snapshot`
+    /// to avoid allocations.
+    pub fn snapshot_into(&self, snapshot: &mut Snapshot) {
+        snapshot.data.clear();
+        self.cpu.save(&mut snapshot.data).expect("snapshot cpu");
+        self.bus.save(&mut snapshot.data).expect("snapshot bus");
+        snapshot.frame_ppu_cycles = self.frame_ppu_cycles;
+        snapshot.input_frame_started = self.input_frame_started;
+        snapshot.cycles_overshoot = self.cycles_overshoot;
+    }
+
+    /// Returns a hash of the whole emulation state, the CPU, memory, all the
+    /// components registers and the mapper state, the same state that is
+    /// stored in [`GameBoy::save_state`], but hashed without allocating.
+    ///
+    /// Two instances that ran the same cartridge with the same inputs from
+    /// the same start state produce the same hash, so this can be compared
+    /// every frame to detect divergence (i.e. netplay desyncs).
+    ///
+    /// Anything not part of the save state is excluded, such as callbacks
+    /// and the output buffers.
+    /// The hash is stable between runs, but not between versions of the
+    /// emulator.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = StateHasher::new();
+        self.cpu.save(&mut hasher).expect("hash cpu");
+        self.bus.save(&mut hasher).expect("hash bus");
+        self.frame_ppu_cycles
+            .save(&mut hasher)
+            .expect("hash frame cycles");
+        self.cycles_overshoot
+            .save(&mut hasher)
+            .expect("hash overshoot");
+        hasher.0
+    }
+
+    /// Restores the emulator to the state of `snapshot`.
+    ///
+    /// # Panics
+    /// If the snapshot was not taken from a [`GameBoy`] running the same
+    /// cartridge with the same configuration.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        let mut cursor = Cursor::new(&snapshot.data);
+        self.cpu.load(&mut cursor).expect("restore cpu");
+        self.bus.load(&mut cursor).expect("restore bus");
+        self.frame_ppu_cycles = snapshot.frame_ppu_cycles;
+        self.input_frame_started = snapshot.input_frame_started;
+        self.cycles_overshoot = snapshot.cycles_overshoot;
+    }
+
+    /// Saves the whole current state of the emulator.
+    pub fn save_state<W: Write>(&self, writer: W) -> Result<(), SaveError> {
+        self.save_state_with_compression(writer, true)
+    }
+
+    /// Same as [`GameBoy::save_state`], but the components data is not
+    /// compressed, which is faster to save and load but results in a larger
+    /// state, useful for frequent saves (rewind) where size is not a concern.
+    ///
+    /// The state can be loaded with [`GameBoy::load_state`] like normal.
+    pub fn save_state_uncompressed<W: Write>(&self, writer: W) -> Result<(), SaveError> {
+        self.save_state_with_compression(writer, false)
+    }
+
+    fn save_state_with_compression<W: Write>(
+        &self,
+        mut writer: W,
+        compress: bool,
+    ) -> Result<(), SaveError> {
+        SAVE_STATE_MAGIC.save(&mut writer)?;
+        if compress {
+            SAVE_STATE_VERSION.save(&mut writer)?;
+        } else {
+            (SAVE_STATE_VERSION | SAVE_STATE_UNCOMPRESSED_FLAG).save(&mut writer)?;
+        }
+        let cartridge_hash: &[u8; 32] = self.bus.cartridge().hash();
+        cartridge_hash.save(&mut writer)?;
+
+        if compress {
+            let mut writer = zstd::Encoder::new(&mut writer, SAVE_STATE_ZSTD_COMPRESSION_LEVEL)?;
+
+            self.cpu.save(&mut writer)?;
+            self.bus.save(&mut writer)?;
+
+            let _writer = writer.finish()?;
+        } else {
+            self.cpu.save(&mut writer)?;
+            self.bus.save(&mut writer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Saves the whole current state of the emulator into the in-memory
+    /// `slot`, replacing the state saved there before. The slots are not
+    /// written to any file, use [`GameBoy::save_state`] for persistence.
+    ///
+    /// # Panics
+    /// If `slot` is not less than [`QUICK_SAVE_SLOTS`].
+    pub fn quick_save(&mut self, slot: u8) {
+        let mut state = self.quick_save_slots[slot as usize]
+            .take()
+            .unwrap_or_default();
+        state.clear();
+        self.save_state_uncompressed(&mut state)
+            .expect("quick save state");
+        self.quick_save_slots[slot as usize] = Some(state);
+    }
+
+    /// Loads the state saved with [`GameBoy::quick_save`] into `slot`,
+    /// returns `false` if the slot is empty, without changing the state of
+    /// the emulator. Errors are handled like [`GameBoy::load_state`].
+    ///
+    /// # Panics
+    /// If `slot` is not less than [`QUICK_SAVE_SLOTS`].
+    pub fn quick_load(&mut self, slot: u8) -> Result<bool, SaveError> {
+        let state = match self.quick_save_slots[slot as usize].take() {
+            Some(state) => state,
+            None => return Ok(false),
+        };
+        let result = self.load_state(Cursor::new(&state));
+        self.quick_save_slots[slot as usize] = Some(state);
+
+        result.map(|_| true)
+    }
+
+    /// Loads the whole state of the emulator, if an error happened in the middle
+    /// the emulator will keep functioning like normal, as it stores a backup recovery state before
+    /// loading the new state.
+    pub fn load_state<R: Read + Seek>(&mut self, reader: R) -> Result<(), SaveError> {
+        self.load_state_checked(reader, true, LoadErrorPolicy::RollBack)
+    }
+
+    /// Same as [`GameBoy::load_state`], but `on_error` controls the state
+    /// of the emulator if loading fails, the error is returned in all cases.
+    pub fn load_state_with<R: Read + Seek>(
+        &mut self,
+        reader: R,
+        on_error: LoadErrorPolicy,
+    ) -> Result<(), SaveError> {
+        self.load_state_checked(reader, true, on_error)
+    }
+
+    /// Same as [`GameBoy::load_state`], but does not check that the save state
+    /// is for the currently running cartridge, the magic and version
+    /// of the save state are still checked.
+    ///
+    /// This is useful for loading a save state of the same game but from a
+    /// different dump (region/revision) or a patched ROM.
+    ///
+    /// # Warning
+    /// If the ROMs are actually different, the emulation may desync or
+    /// behave unexpectedly after loading, as the CPU may be in the middle of
+    /// code that does not exist in the current ROM. The cartridge type
+    /// (mapper) must still match, or loading fails.
+    pub fn load_state_forced<R: Read + Seek>(&mut self, reader: R) -> Result<(), SaveError> {
+        self.load_state_checked(reader, false, LoadErrorPolicy::RollBack)
+    }
+
+    fn load_state_checked<R: Read + Seek>(
+        &mut self,
+        mut reader: R,
+        check_cartridge_hash: bool,
+        on_error: LoadErrorPolicy,
+    ) -> Result<(), SaveError> {
+        // save state, so that if an error occured we will restore it back.
+        let mut recovery_save_state = Vec::new();
+        let mut recovery_sram = Vec::new();
+        // the recovery below can change the SRAM dirty flag (i.e. `load_sram`
+        // clears it), it should stay the same if the load is undone
+        let recovery_sram_dirty = self.bus.cartridge().sram_dirty();
+        match on_error {
+            LoadErrorPolicy::RollBack => {
+                self.cpu
+                    .save(&mut recovery_save_state)
+                    .expect("recovery save cpu");
+                self.bus
+                    .save(&mut recovery_save_state)
+                    .expect("recovery save bus");
+            }
+            LoadErrorPolicy::Reset => {
+                self.bus
+                    .cartridge()
+                    .save_sram(&mut recovery_sram)
+                    .expect("recovery save sram");
+            }
+            LoadErrorPolicy::LeavePartial => {}
+        }
+
+        self.bus
+            .cartridge_mut()
+            .set_ignore_state_hash(!check_cartridge_hash);
+
+        let mut load_routine = || {
+            let mut magic = [0u8; 4];
+            let mut version = 0usize;
+            let mut hash = [0u8; 32];
+
+            magic.load(&mut reader)?;
+            if &magic != SAVE_STATE_MAGIC {
+                return Err(SaveError::InvalidSaveStateHeader);
+            }
+
+            // since there might be some possibility to migrate from different
+            // versions, we will not check here.
+            version.load(&mut reader)?;
+            let uncompressed = version & SAVE_STATE_UNCOMPRESSED_FLAG != 0;
+            version &= !SAVE_STATE_UNCOMPRESSED_FLAG;
+
+            hash.load(&mut reader)?;
+            if check_cartridge_hash && &hash != self.bus.cartridge().hash() {
+                return Err(SaveError::InvalidCartridgeHash);
+            }
+
+            if version == 0 || version > SAVE_STATE_VERSION {
+                return Err(SaveError::UnmatchedSaveErrorVersion(version));
+            }
+
+            {
+                // use a box on read because there are two types of readers
+                // that we might use, compressed or not compressed based on the version
+                // of the save_state file
+                let mut second_stage_reader: Box<dyn Read> = if version == 1 || uncompressed {
+                    // version 1 is not compressed
+                    Box::new(&mut reader)
+                } else {
+                    Box::new(zstd::Decoder::new(&mut reader)?)
+                };
+
+                if version == SAVE_STATE_VERSION {
+                    self.cpu.load(&mut second_stage_reader)?;
+                    self.bus.load(&mut second_stage_reader)?;
+                } else {
+                    let mut data = Vec::new();
+                    second_stage_reader.read_to_end(&mut data)?;
+                    let data = migrate_save_state(version, data)?;
+
+                    let mut cursor = Cursor::new(&data);
+                    self.cpu.load(&mut cursor)?;
+                    self.bus.load(&mut cursor)?;
+
+                    let remaining_data_len = data.len() as u64 - cursor.position();
+                    if remaining_data_len > 0 {
+                        return Err(SaveError::SaveStateError(save_state::Error::TrailingData(
+                            remaining_data_len,
+                        )));
+                    }
+                }
+            }
+
+            // make sure there is no more data
+            let stream_current_pos = reader.stream_position()?;
+            reader.seek(SeekFrom::End(0))?;
+            let stream_last_pos = reader.stream_position()?;
+
+            let (remaining_data_len, overflow) =
+                stream_last_pos.overflowing_sub(stream_current_pos);
+            assert!(!overflow);
+
+            if remaining_data_len > 0 {
+                // return seek
+                reader.seek(SeekFrom::Start(stream_current_pos))?;
+
+                Err(SaveError::SaveStateError(save_state::Error::TrailingData(
+                    remaining_data_len,
+                )))
+            } else {
+                Ok(())
+            }
+        };
+
+        let result = load_routine();
+        self.bus.cartridge_mut().set_ignore_state_hash(false);
+
+        if let Err(err) = result {
+            match on_error {
+                LoadErrorPolicy::RollBack => {
+                    let mut cursor = Cursor::new(&recovery_save_state);
+
+                    self.cpu.load(&mut cursor).expect("recovery load cpu");
+                    self.bus.load(&mut cursor).expect("recovery load bus");
+                    self.bus.cartridge_mut().set_sram_dirty(recovery_sram_dirty);
+                }
+                LoadErrorPolicy::Reset => {
+                    let power_on_state = std::mem::take(&mut self.power_on_state);
+                    self.restore(&power_on_state);
+                    self.power_on_state = power_on_state;
+
+                    self.bus
+                        .cartridge_mut()
+                        .load_sram(&mut Cursor::new(&recovery_sram))
+                        .expect("recovery load sram");
+                    self.bus.cartridge_mut().set_sram_dirty(recovery_sram_dirty);
+                }
+                LoadErrorPolicy::LeavePartial => {}
+            }
+
+            Err(err)
+        } else {
+            // the loaded state may be in the middle of a different frame
+            self.frame_ppu_cycles = 0;
+            self.input_frame_started = false;
+            self.cycles_overshoot = 0;
+
+            Ok(())
+        }
+    }
+}
+
+/// Migrates the components data (the data after the header) of a save state
+/// from `version` to [`SAVE_STATE_VERSION`], one version at a time.
+///
+/// When changing the structure of the saved components and bumping
+/// [`SAVE_STATE_VERSION`], a migration step should be added here that
+/// transforms the data of the previous version, e.g. by inserting the
+/// default values of new fields.
+fn migrate_save_state(version: usize, mut data: Vec<u8>) -> Result<Vec<u8>, SaveError> {
+    for from in version..SAVE_STATE_VERSION {
+        data = match from {
+            // version 2 only added compression, which is handled by the reader,
+            // the components data is the same
+            1 => data,
+            // version 3 stored the configuration in the components as a
+            // length prefixed block, and added new fields and components to
+            // the bus
+            2 => {
+                let mut migration = StateMigration::new(from, &data);
+                Cpu::migrate_state(&mut migration)?;
+                Bus::migrate_state(&mut migration)?;
+                migration.finish()?
+            }
+            _ => return Err(SaveError::UnsupportedMigration { from, to: from + 1 }),
+        };
+    }
+
+    Ok(data)
 }
@@ -0,0 +1,244 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::serial::SerialDevice;
+use crate::{GameBoy, PPU_CYCLES_PER_FRAME};
+
+/// The time to transfer one bit with the fastest serial clock, the CGB fast
+/// clock in double speed mode, in PPU cycles
+const LINK_STEP_CYCLES: u32 = 8;
+
+/// One end of the cable, as seen by the gameboy connected to it
+struct LinkPort {
+    /// The bit driven by the gameboy on its serial out line, which is
+    /// received by the other gameboy when it clocks a bit
+    out_bit: bool,
+    /// The bits clocked by the other gameboy, which are shifted into this
+    /// gameboy when it's clocked next, from the top bit
+    pending: u8,
+    pending_bits: u8,
+}
+
+impl LinkPort {
+    fn new() -> Self {
+        Self {
+            // the line is high until the gameboy drives it
+            out_bit: true,
+            pending: 0,
+            pending_bits: 0,
+        }
+    }
+
+    fn push(&mut self, bit: bool) {
+        // at most one byte is buffered, if the gameboy is too slow, the
+        // newer bits are dropped
+        if self.pending_bits < 8 {
+            self.pending |= (bit as u8) << (7 - self.pending_bits);
+            self.pending_bits += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<bool> {
+        if self.pending_bits == 0 {
+            return None;
+        }
+        let bit = self.pending & 0x80 != 0;
+        self.pending <<= 1;
+        self.pending_bits -= 1;
+        Some(bit)
+    }
+}
+
+/// The state of the cable shared by both ends.
+///
+/// When one gameboy clocks a bit as the master, it receives the bit the other
+/// gameboy is driving right away, and the sent bit waits until the other
+/// gameboy is clocked, so both gameboys swap their bytes in the same transfer
+/// as long as they are clocked alternately in steps shorter than one bit.
+struct LinkState {
+    ports: [LinkPort; 2],
+}
+
+impl LinkState {
+    fn new() -> Self {
+        Self {
+            ports: [LinkPort::new(), LinkPort::new()],
+        }
+    }
+
+    /// The gameboy at `port` clocks `bit` out with its internal clock
+    fn clock_bit(&mut self, port: usize, bit: bool) -> bool {
+        let other = &mut self.ports[1 - port];
+        other.push(bit);
+        other.out_bit
+    }
+
+    /// The gameboy at `port` waits for the clock of the other gameboy,
+    /// while driving `out_bit`
+    fn receive_bit(&mut self, port: usize, out_bit: bool) -> Option<bool> {
+        let port = &mut self.ports[port];
+        port.out_bit = out_bit;
+        port.pop()
+    }
+}
+
+/// One end of the cable, connected to one of the gameboys as a [`SerialDevice`]
+struct LinkEnd {
+    state: Rc<RefCell<LinkState>>,
+    port: usize,
+}
+
+impl SerialDevice for LinkEnd {
+    fn exchange_bit_external_clock(&mut self, bit: bool) -> bool {
+        self.state.borrow_mut().clock_bit(self.port, bit)
+    }
+
+    fn exchange_bit_internal_clock(&mut self, bit: bool) -> Option<bool> {
+        self.state.borrow_mut().receive_bit(self.port, bit)
+    }
+}
+
+/// A link cable connecting the serial ports of two [`GameBoy`]s.
+///
+/// Either gameboy can be the master of a transfer, the game selects it with
+/// the clock source bit of `SC`, see [`SerialRole`](crate::SerialRole).
+/// Each machine cycle, the pending serial bits are exchanged through the
+/// cable, so both gameboys should be clocked in small steps alternately,
+/// see [`LinkCable::clock_for_frame`].
+pub struct LinkCable {
+    state: Rc<RefCell<LinkState>>,
+}
+
+impl LinkCable {
+    /// Connects the two gameboys together, replacing any serial device
+    /// connected to them.
+    pub fn connect(a: &mut GameBoy, b: &mut GameBoy) -> Self {
+        let state = Rc::new(RefCell::new(LinkState::new()));
+
+        a.connect_device(Rc::new(RefCell::new(LinkEnd {
+            state: state.clone(),
+            port: 0,
+        })));
+        b.connect_device(Rc::new(RefCell::new(LinkEnd {
+            state: state.clone(),
+            port: 1,
+        })));
+
+        Self { state }
+    }
+
+    /// Disconnects the two gameboys, and clears any data in flight.
+    pub fn disconnect(self, a: &mut GameBoy, b: &mut GameBoy) {
+        a.disconnect_device();
+        b.disconnect_device();
+        *self.state.borrow_mut() = LinkState::new();
+    }
+
+    /// Clocks both gameboys for the duration of one PPU frame, alternating
+    /// between them every 8 PPU cycles, which is the time needed to transfer
+    /// one bit with the fastest serial clock (CGB fast clock in double speed).
+    ///
+    /// Instructions can't be split, so a master executing a long instruction
+    /// (i.e. `CALL`) while transferring with the fast clock may still clock
+    /// two bits in one step. Breakpoints do not stop the execution.
+    pub fn clock_for_frame(&self, a: &mut GameBoy, b: &mut GameBoy) {
+        for _ in 0..PPU_CYCLES_PER_FRAME / LINK_STEP_CYCLES {
+            a.clock_for_cycles(LINK_STEP_CYCLES as u64);
+            b.clock_for_cycles(LINK_STEP_CYCLES as u64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{test_cgb_gameboy, test_gameboy};
+    use crate::SerialStats;
+
+    /// Sends `first` then `second` with the serial `control`, and stores the
+    /// received bytes at `0xC000` and `0xC001`
+    #[rustfmt::skip]
+    fn exchange_program(first: u8, second: u8, control: u8) -> Vec<u8> {
+        let mut program = Vec::new();
+        for (i, byte) in [first, second].into_iter().enumerate() {
+            program.extend_from_slice(&[
+                0x3E, byte,             // LD A, byte
+                0xE0, 0x01,             // LDH (SB), A
+                0x3E, control,          // LD A, control
+                0xE0, 0x02,             // LDH (SC), A
+                0xF0, 0x02,             // LDH A, (SC)
+                0xCB, 0x7F,             // BIT 7, A
+                0x20, 0xFA,             // JR NZ, -6
+                0xF0, 0x01,             // LDH A, (SB)
+                0xEA, i as u8, 0xC0,    // LD (0xC000 + i), A
+            ]);
+        }
+        program.extend_from_slice(&[0x18, 0xFE]); // JR -2
+        program
+    }
+
+    #[test]
+    fn link_cable_handshake() {
+        // similar to the 2 player handshake of Tetris, the master sends `0x29`
+        // and the slave replies with `0x55`, then another byte is exchanged
+        let mut master = test_gameboy(&exchange_program(0x29, 0x31, 0x81));
+        let mut slave = test_gameboy(&exchange_program(0x55, 0x66, 0x80));
+
+        let cable = crate::LinkCable::connect(&mut master, &mut slave);
+        for _ in 0..10 {
+            cable.clock_for_frame(&mut master, &mut slave);
+        }
+
+        // both transfers are done, and the bytes are swapped in the same transfer
+        assert!(!master.serial_transfer_active());
+        assert!(!slave.serial_transfer_active());
+        assert_eq!(master.bus.read_not_ticked(0xC000, None), 0x55);
+        assert_eq!(master.bus.read_not_ticked(0xC001, None), 0x66);
+        assert_eq!(slave.bus.read_not_ticked(0xC000, None), 0x29);
+        assert_eq!(slave.bus.read_not_ticked(0xC001, None), 0x31);
+        // each side sent and received the two bytes
+        let stats = SerialStats {
+            bits_sent: 16,
+            bits_received: 16,
+            bytes_sent: 2,
+            bytes_received: 2,
+        };
+        assert_eq!(master.serial_stats(), stats);
+        assert_eq!(slave.serial_stats(), stats);
+
+        cable.disconnect(&mut master, &mut slave);
+    }
+
+    #[test]
+    fn link_cable_roles_follow_the_transfer() {
+        // the second gameboy connected is the master this time
+        let mut slave = test_gameboy(&exchange_program(0x12, 0x34, 0x80));
+        let mut master = test_gameboy(&exchange_program(0xAB, 0xCD, 0x81));
+
+        let cable = crate::LinkCable::connect(&mut slave, &mut master);
+        for _ in 0..10 {
+            cable.clock_for_frame(&mut slave, &mut master);
+        }
+
+        assert_eq!(master.bus.read_not_ticked(0xC000, None), 0x12);
+        assert_eq!(master.bus.read_not_ticked(0xC001, None), 0x34);
+        assert_eq!(slave.bus.read_not_ticked(0xC000, None), 0xAB);
+        assert_eq!(slave.bus.read_not_ticked(0xC001, None), 0xCD);
+    }
+
+    #[test]
+    fn link_cable_cgb_fast_clock() {
+        // one bit every 16 cycles
+        let mut master = test_cgb_gameboy(&exchange_program(0x29, 0x31, 0x83));
+        let mut slave = test_cgb_gameboy(&exchange_program(0x55, 0x66, 0x80));
+
+        let cable = crate::LinkCable::connect(&mut master, &mut slave);
+        for _ in 0..10 {
+            cable.clock_for_frame(&mut master, &mut slave);
+        }
+
+        assert_eq!(master.bus.read_not_ticked(0xC000, None), 0x55);
+        assert_eq!(master.bus.read_not_ticked(0xC001, None), 0x66);
+        assert_eq!(slave.bus.read_not_ticked(0xC000, None), 0x29);
+        assert_eq!(slave.bus.read_not_ticked(0xC001, None), 0x31);
+    }
+}
@@ -4,21 +4,84 @@ mod interrupts;
 use save_state::Savable;
 
 use std::cell::RefCell;
+use std::ops::RangeInclusive;
 use std::rc::Rc;
 
-pub use interrupts::{InterruptManager, InterruptType};
+pub use interrupts::{InterruptCounts, InterruptManager, InterruptType};
 
-use crate::apu::{Apu, AudioBuffers};
-use crate::cartridge::Cartridge;
+use crate::apu::{Apu, ApuChannelId, ApuRegisters, AudioBuffers, AudioCallback, AudioStemMode};
+use crate::cartridge::{Cartridge, CartridgeInterface};
 use crate::cpu::CpuBusProvider;
-use crate::joypad::{Joypad, JoypadButton};
-use crate::ppu::Ppu;
-use crate::serial::{Serial, SerialDevice};
-use crate::timer::Timer;
+use crate::infrared::{Infrared, IrLedCallback};
+use crate::joypad::{Joypad, JoypadButton, JoypadIntMode, JoypadState};
+use crate::ppu::{
+    FrameCallback, PipelineState, Ppu, PpuLayer, PpuQuirkReport, ScanlineCallback, TilemapRender,
+};
+use crate::save_migration::StateMigration;
+use crate::serial::{Serial, SerialByteCallback, SerialDevice, SerialRole, SerialStats};
+use crate::sgb::{Sgb, SgbAction, SgbBorder};
+use crate::timer::{Timer, TimerState};
 use crate::GameBoyConfig;
+pub use dma::DmaStatus;
 use dma::{BusType, Hdma, OamDma};
 use interrupts::Interrupts;
 
+/// The initial content of the RAM when the gameboy is powered on, applied
+/// to WRAM, HRAM, VRAM and OAM.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Savable)]
+pub enum RamFillPattern {
+    /// All bytes are `0x00`
+    #[default]
+    Zero,
+    /// All bytes are `0xFF`
+    Ones,
+    /// Pseudo random bytes, generated deterministically from the seed
+    Seeded(u64),
+}
+
+impl RamFillPattern {
+    /// Fills `data` with the pattern, `region` is used to generate different
+    /// data for each memory region when using [`RamFillPattern::Seeded`].
+    pub(crate) fn fill(self, region: u64, data: &mut [u8]) {
+        match self {
+            RamFillPattern::Zero => data.fill(0),
+            RamFillPattern::Ones => data.fill(0xFF),
+            RamFillPattern::Seeded(seed) => {
+                let mut state = seed ^ region.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+                for chunk in data.chunks_mut(8) {
+                    // splitmix64
+                    state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+                    let mut z = state;
+                    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+                    z ^= z >> 31;
+
+                    chunk.copy_from_slice(&z.to_le_bytes()[..chunk.len()]);
+                }
+            }
+        }
+    }
+}
+
+/// The number of memory accesses to each `256` byte page, see
+/// [`GameBoy::take_access_profile`](crate::GameBoy::take_access_profile).
+///
+/// Index `n` is for the addresses `n00-nFF`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessProfile {
+    pub reads: [u64; 256],
+    pub writes: [u64; 256],
+}
+
+impl Default for AccessProfile {
+    fn default() -> Self {
+        Self {
+            reads: [0; 256],
+            writes: [0; 256],
+        }
+    }
+}
+
 #[derive(Default, Savable)]
 struct BootRom {
     enabled: bool,
@@ -37,10 +100,15 @@ impl Default for Speed {
     }
 }
 
+/// The number of machine cycles the CPU is halted for during a speed switch
+const SPEED_SWITCH_CYCLES: u16 = 2050;
+
 #[derive(Default, Savable)]
 struct SpeedController {
     preparing_switch: bool,
     current_speed: Speed,
+    /// The remaining machine cycles of the current speed switch
+    switch_remaining_cycles: u16,
 }
 
 impl SpeedController {
@@ -67,6 +135,15 @@ impl SpeedController {
             Speed::Double => Speed::Normal,
         };
         self.preparing_switch = false;
+        self.switch_remaining_cycles = SPEED_SWITCH_CYCLES;
+    }
+
+    fn is_switching(&self) -> bool {
+        self.switch_remaining_cycles != 0
+    }
+
+    fn clock_switch(&mut self) {
+        self.switch_remaining_cycles = self.switch_remaining_cycles.saturating_sub(1);
     }
 }
 
@@ -76,16 +153,14 @@ struct Wram {
     bank: u8,
 }
 
-impl Default for Wram {
-    fn default() -> Self {
-        Self {
-            data: [0; 0x8000],
-            bank: 1,
-        }
+impl Wram {
+    fn new(config: GameBoyConfig) -> Self {
+        let mut data = [0; 0x8000];
+        config.ram_fill.fill(0, &mut data);
+
+        Self { data, bank: 1 }
     }
-}
 
-impl Wram {
     fn read_wram0(&self, addr: u16) -> u8 {
         self.data[addr as usize & 0xFFF]
     }
@@ -113,6 +188,10 @@ impl Wram {
     fn get_wram_bank(&self) -> u8 {
         0xF8 | self.bank
     }
+
+    fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
 }
 
 #[derive(Savable)]
@@ -208,13 +287,15 @@ impl std::ops::IndexMut<usize> for UnknownRegisters {
 
 #[derive(Savable)]
 pub struct Bus {
-    cartridge: Cartridge,
+    cartridge: Box<dyn CartridgeInterface>,
     ppu: Ppu,
     wram: Wram,
     interrupts: Interrupts,
     timer: Timer,
     joypad: Joypad,
     serial: Serial,
+    sgb: Sgb,
+    infrared: Infrared,
     oam_dma: OamDma,
     hdma: Hdma,
     apu: Apu,
@@ -227,18 +308,34 @@ pub struct Bus {
     #[savable(skip)]
     serial_device: Option<Rc<RefCell<dyn SerialDevice>>>,
 
+    /// Address ranges where CPU writes are reported as watchpoint hits
+    #[savable(skip)]
+    write_watchpoints: Vec<RangeInclusive<u16>>,
+    /// The first watched write `(addr, value)` since the last check by the CPU
+    #[savable(skip)]
+    watchpoint_hit: Option<(u16, u8)>,
+    /// Reads then writes counters for each page, `None` if profiling is disabled
+    #[savable(skip)]
+    access_profile: Option<Box<[u64]>>,
+
     stopped: bool,
 
     /// Used to track how many ppu cycles have elapsed
     /// when the frontend gets the elapsed value, its reset to 0
     elapsed_ppu_cycles: u32,
+    /// Total ppu cycles elapsed since power on, never reset
+    total_ppu_cycles: u64,
 
     config: GameBoyConfig,
 }
 
 impl Bus {
-    pub fn new_without_boot_rom(cartridge: Cartridge, config: GameBoyConfig) -> Self {
-        let cgb_mode = cartridge.is_cartridge_color();
+    pub fn new_without_boot_rom(
+        cartridge: Box<dyn CartridgeInterface>,
+        config: GameBoyConfig,
+    ) -> Self {
+        let cgb_mode = config.is_cartridge_cgb_mode(cartridge.as_ref());
+        let sgb_enabled = config.sgb && config.is_dmg && cartridge.header().sgb_supported;
         let mut lock = Lock::default();
 
         if !cgb_mode || config.is_dmg {
@@ -250,33 +347,42 @@ impl Bus {
 
         lock.finish_boot();
 
+        let mut hram = [0; 127];
+        config.ram_fill.fill(1, &mut hram);
+
         Self {
             cartridge,
             ppu: Ppu::new_skip_boot_rom(cgb_mode, config),
-            wram: Wram::default(),
+            wram: Wram::new(config),
             interrupts: Interrupts::default(),
             timer: Timer::new_skip_boot_rom(config),
             joypad: Joypad::default(),
             serial: Serial::new_skip_boot_rom(config),
+            sgb: Sgb::new(sgb_enabled),
+            infrared: Infrared::default(),
             oam_dma: OamDma::default(),
             hdma: Hdma::default(),
             apu: Apu::new_skip_boot_rom(config),
-            hram: [0; 127],
+            hram,
             boot_rom: BootRom::default(),
             speed_controller: SpeedController::default(),
             lock,
             unknown_registers: UnknownRegisters::new([0xFF, 0xFF, 0xFF, 0x70]),
             serial_device: None,
+            write_watchpoints: Vec::new(),
+            watchpoint_hit: None,
+            access_profile: None,
             stopped: false,
 
             elapsed_ppu_cycles: 0,
+            total_ppu_cycles: 0,
 
             config,
         }
     }
 
     pub fn new_with_boot_rom(
-        cartridge: Cartridge,
+        cartridge: Box<dyn CartridgeInterface>,
         boot_rom_data: Vec<u8>,
         config: GameBoyConfig,
     ) -> Self {
@@ -305,14 +411,235 @@ impl Bus {
         s
     }
 
-    pub fn cartridge(&self) -> &Cartridge {
-        &self.cartridge
+    /// Migrates the saved state of the bus to the next version, see
+    /// [`StateMigration`]
+    pub fn migrate_state(migration: &mut StateMigration) -> save_state::Result<()> {
+        let config = GameBoyConfig::default();
+
+        Cartridge::migrate_state(migration)?;
+        Ppu::migrate_state(migration)?;
+        migration.copy(Wram::new(config))?;
+        migration.copy(Interrupts::default())?;
+        migration.copy(Timer::new_skip_boot_rom(config))?;
+        migration.copy(Joypad::default())?;
+        Serial::migrate_state(migration)?;
+        migration.copy_since(3, Sgb::new(false))?;
+        migration.copy_since(3, Infrared::default())?;
+        migration.copy(OamDma::default())?;
+        migration.copy(Hdma::default())?;
+        Apu::migrate_state(migration)?;
+        migration.copy([0u8; 127])?;
+        migration.copy(BootRom::default())?;
+
+        migration.copy(false)?; // preparing_switch
+        migration.copy(Speed::Normal)?;
+        migration.copy_since(3, 0u16)?; // switch_remaining_cycles
+
+        migration.copy(Lock::default())?;
+        migration.copy(UnknownRegisters::new([0; 4]))?;
+        migration.copy(false)?; // stopped
+        migration.copy(0u32)?; // elapsed_ppu_cycles
+        migration.copy_since(3, 0u64)?; // total_ppu_cycles
+        migration.config()
+    }
+
+    pub fn wram_mut(&mut self) -> &mut [u8] {
+        self.wram.data_mut()
+    }
+
+    pub fn hram_mut(&mut self) -> &mut [u8] {
+        &mut self.hram
+    }
+
+    pub fn boot_rom_active(&self) -> bool {
+        self.boot_rom.enabled
+    }
+
+    pub fn has_boot_rom(&self) -> bool {
+        !self.boot_rom.data.is_empty()
+    }
+
+    pub fn cartridge(&self) -> &dyn CartridgeInterface {
+        self.cartridge.as_ref()
+    }
+
+    pub fn cartridge_mut(&mut self) -> &mut dyn CartridgeInterface {
+        self.cartridge.as_mut()
     }
 
     pub fn screen_buffer(&self) -> &[u8] {
         self.ppu.screen_buffer()
     }
 
+    pub fn screen_buffer_rgba(&self, out: &mut [u8]) {
+        self.ppu.screen_buffer_rgba(out);
+    }
+
+    pub fn screen_buffer_rgb565(&self, out: &mut [u16], big_endian: bool) {
+        self.ppu.screen_buffer_rgb565(out, big_endian);
+    }
+
+    pub fn frame_ready(&self) -> bool {
+        self.ppu.frame_ready()
+    }
+
+    pub fn take_frame_ready(&mut self) -> bool {
+        self.ppu.take_frame_ready()
+    }
+
+    pub fn frame_changed(&self) -> bool {
+        self.ppu.frame_changed()
+    }
+
+    pub fn screen_buffer_indices(&self) -> &[u8] {
+        self.ppu.screen_buffer_indices()
+    }
+
+    pub fn screen_buffer_palettes(&self) -> &[u8] {
+        self.ppu.screen_buffer_palettes()
+    }
+
+    pub fn vram(&self, bank: u8) -> &[u8] {
+        self.ppu.vram(bank)
+    }
+
+    pub fn take_vram_dirty_tiles(&mut self) -> Vec<u16> {
+        self.ppu.take_vram_dirty_tiles()
+    }
+
+    pub fn oam(&self) -> &[u8] {
+        self.ppu.oam()
+    }
+
+    pub fn ppu_scanline(&self) -> u8 {
+        self.ppu.scanline()
+    }
+
+    pub fn ppu_cycle(&self) -> u16 {
+        self.ppu.cycle()
+    }
+
+    pub fn ppu_window_line_counter(&self) -> u8 {
+        self.ppu.window_line_counter()
+    }
+
+    pub fn set_ppu_window_line_counter(&mut self, counter: u8) {
+        self.ppu.set_window_line_counter(counter);
+    }
+
+    pub fn ppu_pipeline_state(&self) -> PipelineState {
+        self.ppu.pipeline_state()
+    }
+
+    pub fn ppu_quirk_report(&self) -> PpuQuirkReport {
+        self.ppu.quirk_report()
+    }
+
+    pub fn ppu_ly(&self) -> u8 {
+        self.ppu.read_ly()
+    }
+
+    pub fn ppu_mode(&self) -> u8 {
+        self.ppu.get_current_mode()
+    }
+
+    pub fn is_lcd_on(&self) -> bool {
+        self.ppu.is_lcd_on()
+    }
+
+    pub fn take_frame_done(&mut self) -> bool {
+        self.ppu.take_frame_done()
+    }
+
+    pub fn set_frame_callback(&mut self, callback: Option<FrameCallback>) {
+        self.ppu.set_frame_callback(callback);
+    }
+
+    pub fn set_scanline_callback(&mut self, callback: Option<ScanlineCallback>) {
+        self.ppu.set_scanline_callback(callback);
+    }
+
+    pub fn set_layer_enabled(&mut self, layer: PpuLayer, enabled: bool) {
+        self.ppu.set_layer_enabled(layer, enabled);
+    }
+
+    pub fn set_sprite_limit(&mut self, limit: Option<u8>) {
+        self.ppu.set_sprite_limit(limit);
+    }
+
+    pub fn add_write_watchpoint_range(&mut self, range: RangeInclusive<u16>) {
+        self.write_watchpoints.push(range);
+    }
+
+    pub fn remove_write_watchpoint(&mut self, addr: u16) {
+        self.write_watchpoints
+            .retain(|range| !range.contains(&addr));
+    }
+
+    pub fn clear_write_watchpoints(&mut self) {
+        self.write_watchpoints.clear();
+        self.watchpoint_hit = None;
+    }
+
+    pub fn sgb_border(&self) -> Option<SgbBorder> {
+        self.sgb.border()
+    }
+
+    pub fn enable_access_profiling(&mut self) {
+        if self.access_profile.is_none() {
+            self.access_profile = Some(vec![0; 512].into_boxed_slice());
+        }
+    }
+
+    pub fn take_access_profile(&mut self) -> AccessProfile {
+        let mut profile = AccessProfile::default();
+        if let Some(counters) = self.access_profile.as_mut() {
+            profile.reads.copy_from_slice(&counters[..256]);
+            profile.writes.copy_from_slice(&counters[256..]);
+            counters.fill(0);
+        }
+        profile
+    }
+
+    pub fn request_interrupt(&mut self, interrupt: InterruptType) {
+        self.interrupts.request_interrupt(interrupt);
+    }
+
+    pub fn interrupt_counts(&self) -> InterruptCounts {
+        self.interrupts.counts()
+    }
+
+    pub fn pending_interrupts(&self) -> (u8, u8) {
+        (
+            self.interrupts.read_interrupt_flags(),
+            self.interrupts.read_interrupt_enable(),
+        )
+    }
+
+    pub fn bg_palettes_rgb(&self) -> [[(u8, u8, u8); 4]; 8] {
+        self.ppu.bg_palettes_rgb()
+    }
+
+    pub fn ppu_render_background(&self) -> TilemapRender {
+        self.ppu.render_background()
+    }
+
+    pub fn ppu_render_window(&self) -> TilemapRender {
+        self.ppu.render_window()
+    }
+
+    pub fn sprite_palettes_rgb(&self) -> [[(u8, u8, u8); 4]; 8] {
+        self.ppu.sprite_palettes_rgb()
+    }
+
+    pub fn set_lcd_ghosting_decay(&mut self, decay: Option<f32>) {
+        self.ppu.set_lcd_ghosting_decay(decay);
+    }
+
+    pub fn set_lcd_frame_blend(&mut self, weight: Option<f32>) {
+        self.ppu.set_lcd_frame_blend(weight);
+    }
+
     #[cfg(test)]
     pub(crate) fn raw_screen_buffer(&self) -> &[u8] {
         self.ppu.raw_screen_buffer()
@@ -322,6 +649,38 @@ impl Bus {
         self.apu.get_buffers()
     }
 
+    pub fn wave_ram(&self) -> [u8; 16] {
+        self.apu.wave_ram()
+    }
+
+    pub fn apu_registers(&self) -> ApuRegisters {
+        self.apu.registers()
+    }
+
+    pub fn apu_powered(&self) -> bool {
+        self.apu.is_powered()
+    }
+
+    pub fn apu_expected_samples(&self, cycles: u32) -> usize {
+        self.apu.expected_samples(cycles)
+    }
+
+    pub fn apu_channel_frequency(&self, channel: ApuChannelId) -> Option<f32> {
+        self.apu.channel_frequency(channel)
+    }
+
+    pub fn set_wave_ram(&mut self, data: [u8; 16]) {
+        self.apu.set_wave_ram(data);
+    }
+
+    pub fn drain_audio_into(&mut self, out: &mut Vec<f32>) {
+        self.apu.drain_buffer_into(out);
+    }
+
+    pub fn drain_audio_channel_into(&mut self, channel: ApuChannelId, out: &mut Vec<f32>) {
+        self.apu.drain_channel_buffer_into(channel, out);
+    }
+
     pub fn press_joypad(&mut self, button: JoypadButton) {
         self.joypad.press_joypad(button);
     }
@@ -330,20 +689,168 @@ impl Bus {
         self.joypad.release_joypad(button);
     }
 
+    pub fn joypad_state(&self) -> JoypadState {
+        self.joypad.state()
+    }
+
+    pub fn set_joypad_state(&mut self, state: JoypadState) {
+        self.joypad.set_state(state);
+    }
+
+    pub fn set_joypad_interrupt_mode(&mut self, mode: JoypadIntMode) {
+        self.joypad.set_interrupt_mode(mode);
+    }
+
+    pub fn set_joypad_autofire(&mut self, button: JoypadButton, rate_hz: Option<f32>) {
+        self.joypad.set_autofire(button, rate_hz);
+    }
+
+    pub fn clock_joypad_autofire(&mut self) {
+        self.joypad.clock_autofire();
+    }
+
+    pub fn serial_stats(&self) -> SerialStats {
+        self.serial.stats()
+    }
+
     pub fn connect_device(&mut self, device: Rc<RefCell<dyn SerialDevice>>) {
         self.serial_device = Some(device);
     }
 
     pub fn disconnect_device(&mut self) {
-        self.serial_device = None;
+        if self.serial_device.take().is_some() {
+            // the device may have been driving the clock, don't leave the
+            // game waiting for the rest of the byte
+            self.serial
+                .finish_external_clock_transfer(&mut self.interrupts);
+        }
+    }
+
+    pub fn serial_role(&self) -> SerialRole {
+        self.serial.role()
+    }
+
+    pub fn serial_transfer_active(&self) -> bool {
+        self.serial.transfer_active()
+    }
+
+    pub fn serial_data(&self) -> u8 {
+        self.serial.read_data()
+    }
+
+    pub fn serial_clock_hz(&self) -> u32 {
+        self.serial
+            .clock_hz(self.speed_controller.current_speed() == Speed::Double)
+    }
+
+    pub fn set_serial_byte_callback(&mut self, callback: Option<SerialByteCallback>) {
+        self.serial.set_byte_callback(callback);
+    }
+
+    pub fn set_ir_input(&mut self, lit: bool) {
+        self.infrared.set_input(lit);
+    }
+
+    pub fn set_ir_led_callback(&mut self, callback: Option<IrLedCallback>) {
+        self.infrared.set_led_callback(callback);
+    }
+
+    pub fn set_div(&mut self, value: u16) {
+        self.timer.set_divider(value);
+    }
+
+    pub fn timer_state(&self) -> TimerState {
+        self.timer.state()
+    }
+
+    pub fn set_timer_state(&mut self, state: TimerState) {
+        self.timer.set_state(state);
+    }
+
+    pub fn apu_frame_sequencer_step(&self) -> u8 {
+        self.apu.frame_sequencer_step()
+    }
+
+    pub fn set_apu_sequencer_callback(&mut self, callback: Option<Box<dyn FnMut(u8)>>) {
+        self.apu.set_sequencer_callback(callback);
+    }
+
+    pub fn set_audio_sample_callback(&mut self, callback: Option<Box<dyn FnMut(f32, f32)>>) {
+        self.apu.set_sample_callback(callback);
+    }
+
+    pub fn set_audio_callback(&mut self, callback: Option<AudioCallback>) {
+        self.apu.set_audio_callback(callback);
+    }
+
+    pub fn set_audio_channel_muted(&mut self, channel: ApuChannelId, muted: bool) {
+        self.apu.set_channel_muted(channel, muted);
+    }
+
+    pub fn set_audio_channel_solo(&mut self, channel: ApuChannelId, solo: bool) {
+        self.apu.set_channel_solo(channel, solo);
+    }
+
+    pub fn set_audio_stem_mode(&mut self, mode: AudioStemMode) {
+        self.apu.set_stem_mode(mode);
+    }
+
+    pub fn set_audio_master_volume(&mut self, volume: f32) {
+        self.apu.set_master_volume(volume);
+    }
+
+    pub fn set_audio_channel_volume(&mut self, channel: ApuChannelId, volume: f32) {
+        self.apu.set_channel_volume(channel, volume);
+    }
+
+    pub fn set_apu_output_enabled(&mut self, enabled: bool) {
+        self.apu.set_output_enabled(enabled);
+    }
+
+    pub fn set_audio_speed_multiplier(&mut self, multiplier: f32) {
+        self.apu.set_speed_multiplier(multiplier);
+    }
+
+    pub fn audio_speed_multiplier(&self) -> f32 {
+        self.apu.speed_multiplier()
+    }
+
+    pub fn audio_samples_per_second(&self) -> f64 {
+        self.apu.samples_per_second()
     }
 
     pub fn elapsed_ppu_cycles(&mut self) -> u32 {
         std::mem::replace(&mut self.elapsed_ppu_cycles, 0)
     }
+
+    pub fn dma_status(&self) -> DmaStatus {
+        DmaStatus {
+            oam_dma_active: self.oam_dma.in_transfer(),
+            oam_dma_source: self.oam_dma.get_next_address(),
+            hdma_active: self.hdma.is_active(),
+            hdma_hblank_mode: self.hdma.is_hblank_dma(),
+            hdma_remaining_length: self.hdma.remaining_length(),
+            hdma_source: self.hdma.source_addr(),
+            hdma_dest: self.hdma.dest_addr(),
+        }
+    }
+
+    pub fn total_ppu_cycles(&self) -> u64 {
+        self.total_ppu_cycles
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.ppu.frame_count()
+    }
 }
 
 impl Bus {
+    /// Clocks all the components for one machine cycle, without any CPU
+    /// memory access
+    pub fn clock_machine_cycle(&mut self) {
+        self.on_cpu_machine_cycle();
+    }
+
     fn on_cpu_machine_cycle(&mut self) {
         let double_speed = self.speed_controller.current_speed() == Speed::Double;
 
@@ -356,6 +863,7 @@ impl Bus {
         // In order to not crash if overflowed (in case the user is not taking the value
         // after every cpu exeution)
         self.elapsed_ppu_cycles = self.elapsed_ppu_cycles.saturating_add(t_clocks as u32);
+        self.total_ppu_cycles += t_clocks as u64;
 
         // we return after updating `elapsed_ppu_cycles` because frontend
         // depend on it
@@ -364,9 +872,16 @@ impl Bus {
                 self.stopped = false;
             }
 
+            // keep the audio output going, so that the frontend
+            // gets the same number of samples every frame
+            self.apu.clock_stopped(double_speed);
+
             return;
         }
 
+        // the CPU is halted during the switch, but everything else is running
+        self.speed_controller.clock_switch();
+
         // The mapper is independent of CPU clock speed, and a full second
         // for the mapper is 4194304/2 clocks
         self.cartridge.clock_mapper();
@@ -407,13 +922,24 @@ impl Bus {
 
         let serial_bit = self.serial.clock_for_bit(&mut self.interrupts);
 
-        // TODO: this design only support gameboy sending data as master clock.
-        //  Add support for gameboy as slave (maybe another gameboy as master).
-        if let Some(bit) = serial_bit {
-            if let Some(serial_device) = self.serial_device.as_mut() {
-                if let Ok(mut serial_device) = serial_device.try_borrow_mut() {
-                    let received_bit = serial_device.exchange_bit_external_clock(bit);
-                    self.serial.receive_bit(received_bit);
+        if let Some(serial_device) = self.serial_device.as_mut() {
+            if let Ok(mut serial_device) = serial_device.try_borrow_mut() {
+                match self.serial.role() {
+                    SerialRole::Master => {
+                        if let Some(bit) = serial_bit {
+                            let received_bit = serial_device.exchange_bit_external_clock(bit);
+                            self.serial.receive_bit(received_bit);
+                        }
+                    }
+                    SerialRole::Slave => {
+                        let out_bit = self.serial.external_clock_out_bit();
+                        if let Some(received_bit) =
+                            serial_device.exchange_bit_internal_clock(out_bit)
+                        {
+                            self.serial
+                                .receive_bit_external_clock(received_bit, &mut self.interrupts);
+                        }
+                    }
                 }
             }
         }
@@ -425,6 +951,27 @@ impl Bus {
     }
 
     pub(crate) fn read_not_ticked(&mut self, addr: u16, block_for_dma: Option<BusType>) -> u8 {
+        if let Some(counters) = self.access_profile.as_mut() {
+            counters[(addr >> 8) as usize] += 1;
+        }
+
+        self.read_not_profiled(addr, block_for_dma)
+    }
+
+    /// Reads the memory contents ignoring OAM locking and DMA conflicts
+    pub fn read_raw(&mut self, addr: u16) -> u8 {
+        match addr {
+            0xFE00..=0xFE9F => self.ppu.oam()[addr as usize & 0xFF],
+            _ => self.read_not_profiled(addr, None),
+        }
+    }
+
+    /// Reads the value the CPU would read at this cycle, without clocking
+    pub fn read_as_cpu(&mut self, addr: u16) -> u8 {
+        self.read_not_profiled(addr, self.oam_dma.conflicting_bus())
+    }
+
+    fn read_not_profiled(&mut self, addr: u16, block_for_dma: Option<BusType>) -> u8 {
         let dma_value = if block_for_dma.is_some() {
             self.oam_dma.current_value()
         } else {
@@ -435,12 +982,13 @@ impl Bus {
         let offset = addr as u8;
 
         match (page, block_for_dma) {
-            (0x00, _) | (0x02..=0x08, _) if self.boot_rom.enabled => {
-                self.boot_rom.data[addr as usize]
-            } // boot rom
+            (0x00, _) if self.boot_rom.enabled => self.boot_rom.data[addr as usize], // boot rom
             (0x02..=0x08, _) if self.boot_rom.enabled && !self.config.is_dmg => {
                 self.boot_rom.data[addr as usize]
             } // boot rom
+            (0x01, _) if self.boot_rom.enabled && self.config.force_dmg_mode_on_cgb => {
+                self.read_header_as_dmg(addr)
+            } // header seen by the boot rom
             (0x00..=0x7F, Some(BusType::External)) => dma_value, // external bus DMA conflict
             (0x00..=0x3F, _) => self.cartridge.read_rom0(addr),  // rom0
             (0x40..=0x7F, _) => self.cartridge.read_romx(addr),  // romx
@@ -450,7 +998,7 @@ impl Bus {
             (0xA0..=0xBF, _) => self.cartridge.read_ram(addr),                         // sram
             (0xC0..=0xCF, _) => self.wram.read_wram0(addr),                            // wram0
             (0xD0..=0xDF, _) => self.wram.read_wramx(addr),                            // wramx
-            (0xE0..=0xFD, _) => self.read_not_ticked(0xC000 | (addr & 0x1FFF), block_for_dma), // echo
+            (0xE0..=0xFD, _) => self.read_not_profiled(0xC000 | (addr & 0x1FFF), block_for_dma), // echo
             (0xFE, None) if offset <= 0x9F => self.ppu.read_oam(addr), // ppu oam
             (0xFE, _) if offset >= 0xA0 => 0,                          // unused
             (0xFF, _) => self.read_io(offset),                         // io registers
@@ -458,13 +1006,35 @@ impl Bus {
         }
     }
 
+    /// Reads the cartridge header with the CGB flag cleared, and the header
+    /// checksum fixed accordingly, used by
+    /// [`force_dmg_mode_on_cgb`](GameBoyConfig::force_dmg_mode_on_cgb)
+    fn read_header_as_dmg(&self, addr: u16) -> u8 {
+        let cgb_flag = self.cartridge.read_rom0(0x143) & 0x80;
+        let value = self.cartridge.read_rom0(addr);
+
+        match addr {
+            0x143 => value & !cgb_flag,
+            0x14D => value.wrapping_add(cgb_flag),
+            _ => value,
+        }
+    }
+
     fn write_not_ticked(&mut self, addr: u16, data: u8, block_for_dma: Option<BusType>) {
+        if let Some(counters) = self.access_profile.as_mut() {
+            counters[256 + (addr >> 8) as usize] += 1;
+        }
+
+        self.write_not_profiled(addr, data, block_for_dma)
+    }
+
+    fn write_not_profiled(&mut self, addr: u16, data: u8, block_for_dma: Option<BusType>) {
         let page = (addr >> 8) as u8;
         let offset = addr as u8;
 
         match (page, block_for_dma) {
             (0x00..=0x7F, Some(BusType::External)) => {} // ignore writes
-            (0x00..=0x7F, _) => self.cartridge.write_to_bank_controller(addr, data), // cart
+            (0x00..=0x7F, _) => self.write_to_bank_controller(addr, data), // cart
             (0x80..=0x9F, Some(BusType::Video)) => {}    // ignore writes
             (0x80..=0x9F, _) => self.ppu.write_vram(addr, data), // ppu vram
             (0xA0..=0xDF, Some(BusType::External)) if self.config.is_dmg => {} // ignore writes
@@ -472,7 +1042,7 @@ impl Bus {
             (0xC0..=0xCF, _) => self.wram.write_wram0(addr, data), // wram0
             (0xD0..=0xDF, _) => self.wram.write_wramx(addr, data), // wramx
             (0xE0..=0xFD, _) => {
-                self.write_not_ticked(0xC000 | (addr & 0x1FFF), data, block_for_dma)
+                self.write_not_profiled(0xC000 | (addr & 0x1FFF), data, block_for_dma)
             } // echo
             (0xFE, None) if offset <= 0x9F => {
                 self.ppu.write_oam(addr, data) // ppu oam
@@ -482,40 +1052,60 @@ impl Bus {
         }
     }
 
+    /// The ROM itself is written instead when
+    /// [`writable_rom`](GameBoyConfig::writable_rom) is set
+    fn write_to_bank_controller(&mut self, addr: u16, data: u8) {
+        if self.config.writable_rom {
+            self.cartridge.write_rom(addr, data);
+        } else {
+            self.cartridge.write_to_bank_controller(addr, data);
+        }
+    }
+
+    /// The SGB receives its packets through the joypad register as well
+    fn write_joypad(&mut self, data: u8) {
+        self.joypad.write_joypad(data);
+
+        match self.sgb.write_joypad(data) {
+            Some(SgbAction::SetScreenPalette(colors)) => self.ppu.set_dmg_shades(colors),
+            Some(SgbAction::VramTransfer) => {
+                self.sgb.vram_transfer(&self.ppu.sgb_transfer_data());
+            }
+            None => {}
+        }
+    }
+
     fn read_io(&mut self, offset: u8) -> u8 {
         let addr = 0xFF00 | (offset as u16);
         match offset {
-            0x00 => self.joypad.read_joypad(),              // joypad
-            0x01 => self.serial.read_data(),                // serial
-            0x02 => self.serial.read_control(),             // serial
-            0x04 => self.timer.read_div(),                  // timer
-            0x05 => self.timer.read_timer_counter(),        // timer
-            0x06 => self.timer.read_timer_reload(),         // timer
-            0x07 => self.timer.read_control(),              // timer
-            0x0F => self.interrupts.read_interrupt_flags(), // interrupts flags
-            0x10..=0x3F => self.apu.read_register(addr),    // apu
-            0x40 => self.ppu.read_lcd_control(),            // ppu
-            0x41 => self.ppu.read_lcd_status(),             // ppu
-            0x42 => self.ppu.read_scroll_y(),               // ppu
-            0x43 => self.ppu.read_scroll_x(),               // ppu
-            0x44 => self.ppu.read_ly(),                     // ppu
-            0x45 => self.ppu.read_lyc(),                    // ppu
-            0x46 => self.oam_dma.read_register(),           // oam dma
-            0x47 => self.ppu.read_dmg_bg_palette(),         // ppu
-            0x48 => self.ppu.read_dmg_sprite_palettes(0),   // ppu
-            0x49 => self.ppu.read_dmg_sprite_palettes(1),   // ppu
-            0x4A => self.ppu.read_window_y(),               // ppu
-            0x4B => self.ppu.read_window_x(),               // ppu
+            0x00 => self.sgb.read_joypad(self.joypad.read_joypad()), // joypad
+            0x01 => self.serial.read_data(),                         // serial
+            0x02 => self.serial.read_control(),                      // serial
+            0x04 => self.timer.read_div(),                           // timer
+            0x05 => self.timer.read_timer_counter(),                 // timer
+            0x06 => self.timer.read_timer_reload(),                  // timer
+            0x07 => self.timer.read_control(),                       // timer
+            0x0F => self.interrupts.read_interrupt_flags(),          // interrupts flags
+            0x10..=0x3F => self.apu.read_register(addr),             // apu
+            0x40 => self.ppu.read_lcd_control(),                     // ppu
+            0x41 => self.ppu.read_lcd_status(),                      // ppu
+            0x42 => self.ppu.read_scroll_y(),                        // ppu
+            0x43 => self.ppu.read_scroll_x(),                        // ppu
+            0x44 => self.ppu.read_ly(),                              // ppu
+            0x45 => self.ppu.read_lyc(),                             // ppu
+            0x46 => self.oam_dma.read_register(),                    // oam dma
+            0x47 => self.ppu.read_dmg_bg_palette(),                  // ppu
+            0x48 => self.ppu.read_dmg_sprite_palettes(0),            // ppu
+            0x49 => self.ppu.read_dmg_sprite_palettes(1),            // ppu
+            0x4A => self.ppu.read_window_y(),                        // ppu
+            0x4B => self.ppu.read_window_x(),                        // ppu
             0x4D if self.lock.is_cgb_mode() => self.speed_controller.read_key1(), // speed
             0x4F if !self.config.is_dmg => self.ppu.read_vram_bank(), // vram bank
-            0x50 => 0xFF,                                   // boot rom stop
+            0x50 => 0xFF,                                            // boot rom stop
             0x51..=0x55 if self.lock.is_cgb_mode() => self.hdma.read_register(addr), // hdma
-            0x56 if self.lock.is_cgb_mode() => {
-                // TODO: implement RP port
-                0xFF
-            }
+            0x56 if self.lock.is_cgb_mode() => self.infrared.read_register(), // infrared
             0x68 if !self.config.is_dmg => self.ppu.read_cgb_bg_palettes_index(), // ppu
-            0x69 if !self.config.is_dmg => self.ppu.read_cgb_bg_palettes_data(),  // ppu
+            0x69 if !self.config.is_dmg => self.ppu.read_cgb_bg_palettes_data(), // ppu
             0x6A if !self.config.is_dmg => self.ppu.read_cgb_sprite_palettes_index(), // ppu
             0x6B if self.lock.is_cgb_mode() => self.ppu.read_cgb_sprite_palettes_data(), // ppu
             0x6C if !self.config.is_dmg => self.ppu.read_sprite_priority_mode(),
@@ -536,27 +1126,27 @@ impl Bus {
         let addr = 0xFF00 | (offset as u16);
 
         match offset {
-            0x00 => self.joypad.write_joypad(data),              // joypad
-            0x01 => self.serial.write_data(data),                // serial
-            0x02 => self.serial.write_control(data),             // serial
-            0x04 => self.timer.write_div(data),                  // timer
-            0x05 => self.timer.write_timer_counter(data),        // timer
-            0x06 => self.timer.write_timer_reload(data),         // timer
-            0x07 => self.timer.write_control(data),              // timer
-            0x0F => self.interrupts.write_interrupt_flags(data), // interrupts flags
-            0x10..=0x3F => self.apu.write_register(addr, data),  // apu
-            0x40 => self.ppu.write_lcd_control(data),            // ppu
-            0x41 => self.ppu.write_lcd_status(data),             // ppu
-            0x42 => self.ppu.write_scroll_y(data),               // ppu
-            0x43 => self.ppu.write_scroll_x(data),               // ppu
-            0x44 => self.ppu.write_ly(data),                     // ppu
-            0x45 => self.ppu.write_lyc(data),                    // ppu
-            0x46 => self.oam_dma.write_register(data),           // dma start
-            0x47 => self.ppu.write_dmg_bg_palette(data),         // ppu
-            0x48 => self.ppu.write_dmg_sprite_palettes(0, data), // ppu
-            0x49 => self.ppu.write_dmg_sprite_palettes(1, data), // ppu
-            0x4A => self.ppu.write_window_y(data),               // ppu
-            0x4B => self.ppu.write_window_x(data),               // ppu
+            0x00 => self.write_joypad(data),                          // joypad
+            0x01 => self.serial.write_data(data),                     // serial
+            0x02 => self.serial.write_control(data),                  // serial
+            0x04 => self.timer.write_div(data),                       // timer
+            0x05 => self.timer.write_timer_counter(data),             // timer
+            0x06 => self.timer.write_timer_reload(data),              // timer
+            0x07 => self.timer.write_control(data),                   // timer
+            0x0F => self.interrupts.write_interrupt_flags(data),      // interrupts flags
+            0x10..=0x3F => self.apu.write_register(addr, data),       // apu
+            0x40 => self.ppu.write_lcd_control(data),                 // ppu
+            0x41 => self.ppu.write_lcd_status(data),                  // ppu
+            0x42 => self.ppu.write_scroll_y(data),                    // ppu
+            0x43 => self.ppu.write_scroll_x(data),                    // ppu
+            0x44 => self.ppu.write_ly(data),                          // ppu
+            0x45 => self.ppu.write_lyc(data),                         // ppu
+            0x46 => self.oam_dma.write_register(data),                // dma start
+            0x47 => self.ppu.write_dmg_bg_palette(data),              // ppu
+            0x48 => self.ppu.write_dmg_sprite_palettes(0, data),      // ppu
+            0x49 => self.ppu.write_dmg_sprite_palettes(1, data),      // ppu
+            0x4A => self.ppu.write_window_y(data),                    // ppu
+            0x4B => self.ppu.write_window_x(data),                    // ppu
             0x4C if self.lock.is_cgb_mode() => self.lock.write(data), // DMG/CGB lock register
             0x4D if self.lock.is_cgb_mode() => self.speed_controller.write_key1(data), // speed
             0x4F if self.lock.is_cgb_mode() => self.ppu.write_vram_bank(data), // vram bank
@@ -564,14 +1154,12 @@ impl Bus {
                 self.lock.finish_boot();
                 self.boot_rom.enabled = false;
                 self.ppu
-                    .update_cgb_mode(self.cartridge.is_cartridge_color());
+                    .update_cgb_mode(self.config.is_cartridge_cgb_mode(self.cartridge.as_ref()));
             } // boot rom stop
             0x51..=0x55 if self.lock.is_cgb_mode() => self.hdma.write_register(addr, data), // hdma
-            0x56 => {
-                //TODO: implement RP port
-            }
+            0x56 if self.lock.is_cgb_mode() => self.infrared.write_register(data), // infrared
             0x68 if self.lock.is_cgb_mode() => self.ppu.write_cgb_bg_palettes_index(data), // ppu
-            0x69 if self.lock.is_cgb_mode() => self.ppu.write_cgb_bg_palettes_data(data),  // ppu
+            0x69 if self.lock.is_cgb_mode() => self.ppu.write_cgb_bg_palettes_data(data), // ppu
             0x6A if self.lock.is_cgb_mode() => self.ppu.write_cgb_sprite_palettes_index(data), // ppu
             0x6B if self.lock.is_cgb_mode() => self.ppu.write_cgb_sprite_palettes_data(data), // ppu
             0x6C if self.lock.is_cgb_mode() => self.ppu.write_sprite_priority_mode(data),
@@ -601,6 +1189,15 @@ impl CpuBusProvider for Bus {
 
     /// each time the cpu writes, clock the components on the bus
     fn write(&mut self, addr: u16, data: u8) {
+        if self.watchpoint_hit.is_none()
+            && self
+                .write_watchpoints
+                .iter()
+                .any(|range| range.contains(&addr))
+        {
+            self.watchpoint_hit = Some((addr, data));
+        }
+
         self.write_not_ticked(addr, data, self.oam_dma.conflicting_bus());
         self.on_cpu_machine_cycle();
 
@@ -609,6 +1206,10 @@ impl CpuBusProvider for Bus {
         }
     }
 
+    fn peek(&mut self, addr: u16) -> u8 {
+        self.read_not_profiled(addr, None)
+    }
+
     // gets the interrupt type and remove it
     fn take_next_interrupt(&mut self) -> Option<InterruptType> {
         let int = self.interrupts.get_highest_interrupt();
@@ -622,6 +1223,10 @@ impl CpuBusProvider for Bus {
         self.interrupts.get_highest_interrupt()
     }
 
+    fn take_watchpoint_hit(&mut self) -> Option<(u16, u8)> {
+        self.watchpoint_hit.take()
+    }
+
     fn is_hdma_running(&mut self) -> bool {
         self.hdma.is_transferreing(&self.ppu)
     }
@@ -632,11 +1237,11 @@ impl CpuBusProvider for Bus {
             self.speed_controller.commit_speed_switch();
             self.timer.write_div(0);
         } else {
+            // the APU is not clocked while stopped, so the channels hold
+            // their output until a button is pressed, unlike the speed switch
+            // above, where the APU keeps running and only the CPU is halted
             self.stopped = true;
             self.ppu.enter_stop_mode();
-            // TODO: is there any special stuff to do with the apu?
-            //  for CGB, sounds still play?
-            // self.apu.enter_stop_mode(); ?
         }
     }
 
@@ -644,6 +1249,10 @@ impl CpuBusProvider for Bus {
         self.stopped
     }
 
+    fn switching_speed(&self) -> bool {
+        self.speed_controller.is_switching()
+    }
+
     fn trigger_write_oam_bug(&mut self, addr: u16) {
         if self.config.is_dmg && addr & 0xFF00 == 0xFE00 {
             self.ppu.oam_bug_write();
@@ -661,4 +1270,246 @@ impl CpuBusProvider for Bus {
         self.on_cpu_machine_cycle();
         result
     }
+
+    fn idle(&mut self) {
+        self.clock_machine_cycle();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::CpuState;
+    use crate::tests::{build_test_rom, test_cgb_gameboy, test_gameboy};
+    use crate::GameBoyConfig;
+
+    #[test]
+    fn dma_status() {
+        let mut gb = test_cgb_gameboy(&[
+            0x3E, 0xC1, // LD A, 0xC1
+            0xE0, 0x51, // LDH (HDMA1), A
+            0xAF, // XOR A
+            0xE0, 0x52, // LDH (HDMA2), A
+            0xE0, 0x53, // LDH (HDMA3), A
+            0xE0, 0x54, // LDH (HDMA4), A
+            0x3E, 0x83, // LD A, 0x83
+            0xE0, 0x55, // LDH (HDMA5), A
+            0x3E, 0xC0, // LD A, 0xC0
+            0xE0, 0x46, // LDH (DMA), A
+            0x18, 0xFE, // JR -2
+        ]);
+        assert_eq!(gb.dma_status(), crate::DmaStatus::default());
+
+        // run until HDMA5 is written
+        while gb.cpu.registers().pc != 0x15F {
+            gb.cpu.next_instruction(&mut gb.bus);
+        }
+        let status = gb.dma_status();
+        assert!(status.hdma_active);
+        assert!(status.hdma_hblank_mode);
+        assert!(!status.oam_dma_active);
+        // reading the status does not advance the transfer
+        assert_eq!(gb.dma_status(), status);
+        // 4 blocks, one may be transferred already if the PPU is in HBlank
+        let transferred = 0x40 - status.hdma_remaining_length;
+        assert!(transferred == 0 || transferred == 0x10);
+        assert_eq!(status.hdma_source, 0xC100 + transferred);
+        assert_eq!(status.hdma_dest, 0x8000 + transferred);
+
+        gb.cpu.next_instruction(&mut gb.bus);
+        gb.cpu.next_instruction(&mut gb.bus);
+        let status = gb.dma_status();
+        assert!(status.oam_dma_active);
+        assert_eq!(status.oam_dma_source & 0xFF00, 0xC000);
+
+        // OAM DMA takes 160 M-cycles, and HDMA transfers a block every HBlank
+        for _ in 0..4 * 160 {
+            gb.cpu.next_instruction(&mut gb.bus);
+        }
+        let status = gb.dma_status();
+        assert!(!status.oam_dma_active);
+        assert!(!status.hdma_active);
+        assert_eq!(status.hdma_remaining_length, 0);
+        assert_eq!(status.hdma_dest, 0x8040);
+    }
+
+    #[test]
+    fn access_profile() {
+        let mut gb = test_cgb_gameboy(&[
+            0x21, 0x00, 0xE0, // LD HL, 0xE000
+            0x77, // LD (HL), A
+            0x18, 0xFD, // JR -3
+        ]);
+
+        // disabled by default
+        for _ in 0..10 {
+            gb.cpu.next_instruction(&mut gb.bus);
+        }
+        assert_eq!(gb.take_access_profile(), crate::AccessProfile::default());
+
+        gb.enable_access_profiling();
+        while gb.cpu.registers().pc != 0x153 {
+            gb.cpu.next_instruction(&mut gb.bus);
+        }
+        gb.take_access_profile();
+
+        for _ in 0..100 {
+            // LD (HL), A and JR
+            gb.cpu.next_instruction(&mut gb.bus);
+            gb.cpu.next_instruction(&mut gb.bus);
+        }
+        let profile = gb.take_access_profile();
+        // 3 opcode bytes fetched per loop, the internal cycle of `JR` is not
+        // counted as a read
+        assert_eq!(profile.reads[0x01], 300);
+        assert_eq!(profile.writes[0xE0], 100);
+        // echo RAM is counted once in its own page
+        assert_eq!(profile.writes[0xC0], 0);
+        assert_eq!(profile.reads.iter().sum::<u64>(), 300);
+        assert_eq!(profile.writes.iter().sum::<u64>(), 100);
+
+        // counters are reset after taking them
+        assert_eq!(gb.take_access_profile(), crate::AccessProfile::default());
+
+        // inspecting the memory is not counted
+        gb.disassemble(0x150);
+        gb.disassemble_range(0x150, 3);
+        gb.dump_cpu_log_line();
+        gb.read_mem(0xC000);
+        gb.read_mem_as_cpu(0xC000);
+        assert_eq!(gb.take_access_profile(), crate::AccessProfile::default());
+    }
+
+    #[test]
+    fn boot_rom_active() {
+        use std::io::Cursor;
+
+        let rom = build_test_rom(0, 0, &[0x18, 0xFE]);
+        // `LD A,1`, `LDH (50),A`, `JR -2`
+        let mut boot_rom = vec![0x3E, 0x01, 0xE0, 0x50, 0x18, 0xFE];
+        boot_rom.resize(0x900, 0);
+
+        let mut gb = crate::GameBoy::builder_from_reader(Cursor::new(rom.clone()))
+            .boot_rom_bytes(boot_rom)
+            .build()
+            .unwrap();
+        assert!(gb.has_boot_rom());
+        assert!(gb.boot_rom_active());
+
+        gb.cpu.next_instruction(&mut gb.bus);
+        assert!(gb.boot_rom_active());
+        gb.cpu.next_instruction(&mut gb.bus);
+        assert!(!gb.boot_rom_active());
+        assert!(gb.has_boot_rom());
+
+        let gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+        assert!(!gb.has_boot_rom());
+        assert!(!gb.boot_rom_active());
+    }
+
+    #[test]
+    fn wram_and_hram_mut() {
+        use crate::cpu::CpuBusProvider;
+
+        let mut gb = test_cgb_gameboy(&[0x18, 0xFE]);
+        assert_eq!(gb.wram_mut().len(), 0x8000);
+        assert_eq!(gb.hram_mut().len(), 127);
+
+        gb.wram_mut()[0x0123] = 0x11;
+        gb.wram_mut()[0x1123] = 0x22;
+        gb.wram_mut()[0x3123] = 0x33;
+        gb.hram_mut()[0x05] = 0x44;
+        assert_eq!(gb.bus.read_not_ticked(0xC123, None), 0x11);
+        assert_eq!(gb.bus.read_not_ticked(0xD123, None), 0x22);
+        assert_eq!(gb.bus.read_not_ticked(0xFF85, None), 0x44);
+
+        // bank 3
+        gb.bus.write(0xFF70, 3);
+        assert_eq!(gb.bus.read_not_ticked(0xD123, None), 0x33);
+        gb.bus.write(0xD124, 0x55);
+        assert_eq!(gb.wram_mut()[0x3124], 0x55);
+    }
+
+    #[test]
+    fn read_mem_as_cpu() {
+        use crate::cpu::CpuBusProvider;
+
+        let mut gb = test_gameboy(&[0x18, 0xFE]);
+
+        while gb.ppu_mode() != 1 {
+            gb.cpu.next_instruction(&mut gb.bus);
+        }
+        gb.bus.write(0xFE00, 0x12);
+        assert_eq!(gb.read_mem_as_cpu(0xFE00), 0x12);
+
+        while !(2..=3).contains(&gb.ppu_mode()) {
+            gb.cpu.next_instruction(&mut gb.bus);
+        }
+        let hash = gb.state_hash();
+        assert_eq!(gb.read_mem(0xFE00), 0x12);
+        assert_eq!(gb.read_mem_as_cpu(0xFE00), 0xFF);
+        assert_eq!(gb.read_mem(0x0100), gb.read_mem_as_cpu(0x0100));
+        assert_eq!(gb.state_hash(), hash);
+    }
+
+    #[test]
+    fn ram_fill_pattern() {
+        use crate::RamFillPattern;
+
+        let rom = build_test_rom(0, 0, &[0x18, 0xFE]);
+        let ram_content = |ram_fill| {
+            let config = GameBoyConfig {
+                ram_fill,
+                ..GameBoyConfig::default()
+            };
+            let mut gb = crate::GameBoy::from_rom_bytes(rom.clone(), config).unwrap();
+            // VRAM, OAM, WRAM and HRAM
+            [
+                0x8000..0x8100,
+                0xFE00..0xFEA0,
+                0xC000..0xC100,
+                0xFF80..0xFFFF,
+            ]
+            .into_iter()
+            .flatten()
+            .map(|addr| gb.bus.read_not_ticked(addr, None))
+            .collect::<Vec<_>>()
+        };
+
+        assert!(ram_content(RamFillPattern::Zero).iter().all(|&b| b == 0));
+        assert!(ram_content(RamFillPattern::Ones).iter().all(|&b| b == 0xFF));
+
+        let seeded = ram_content(RamFillPattern::Seeded(1));
+        assert!(seeded.iter().any(|&b| b != 0 && b != 0xFF));
+        assert_eq!(seeded, ram_content(RamFillPattern::Seeded(1)));
+        assert_ne!(seeded, ram_content(RamFillPattern::Seeded(2)));
+    }
+
+    #[test]
+    fn speed_switch_wait_time() {
+        #[rustfmt::skip]
+        const PROGRAM: [u8; 11] = [
+            0x3E, 0x01,       // LD A, 1
+            0xE0, 0x4D,       // LDH (KEY1), A
+            0x10, 0x00,       // STOP (switch speed)
+            0xF0, 0x04,       // LDH A, (DIV)
+            0x40,             // LD B, B (breakpoint)
+            0x18, 0xFE,       // JR -2
+        ];
+        let mut gb = test_cgb_gameboy(&PROGRAM);
+
+        let mut switching_cycles = 0;
+        let regs = loop {
+            match gb.cpu.next_instruction(&mut gb.bus) {
+                CpuState::SpeedSwitching => switching_cycles += 1,
+                CpuState::Breakpoint(regs) => break regs,
+                _ => {}
+            }
+        };
+
+        assert_eq!(switching_cycles, 2050);
+        // DIV is reset at the switch, and keeps running during the switch,
+        // incrementing every 64 machine cycles
+        assert_eq!(regs.a as u32, 2050 / 64);
+        assert!(gb.bus.read_not_ticked(0xFF4D, None) & 0x80 != 0);
+    }
 }
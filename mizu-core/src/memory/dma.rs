@@ -1,6 +1,27 @@
 use crate::ppu::Ppu;
 use save_state::Savable;
 
+/// The state of the DMA transfers, see [`GameBoy::dma_status`](crate::GameBoy::dma_status).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DmaStatus {
+    /// OAM DMA is in transfer, including its starting delay.
+    pub oam_dma_active: bool,
+    /// The address of the next byte to be copied by OAM DMA.
+    pub oam_dma_source: u16,
+    /// CGB VRAM DMA (HDMA or GDMA) is active.
+    pub hdma_active: bool,
+    /// The VRAM DMA copies a block every HBlank (HDMA), otherwise, it
+    /// copies all the data at once (GDMA).
+    pub hdma_hblank_mode: bool,
+    /// The number of bytes remaining to be copied by VRAM DMA, `0` when
+    /// it is not active.
+    pub hdma_remaining_length: u16,
+    /// The address of the next byte to be copied by VRAM DMA.
+    pub hdma_source: u16,
+    /// The VRAM address the next byte will be copied to by VRAM DMA.
+    pub hdma_dest: u16,
+}
+
 #[derive(Default, Savable)]
 pub struct Hdma {
     source_addr: u16,
@@ -73,6 +94,31 @@ impl Hdma {
         }
     }
 
+    pub fn is_active(&self) -> bool {
+        self.master_dma_active
+    }
+
+    pub fn is_hblank_dma(&self) -> bool {
+        self.hblank_dma
+    }
+
+    /// The number of bytes remaining to be transferred, `0` if not active
+    pub fn remaining_length(&self) -> u16 {
+        if self.master_dma_active {
+            (self.length as u16 + 1) * 0x10 - (self.dest_addr & 0xF)
+        } else {
+            0
+        }
+    }
+
+    pub fn source_addr(&self) -> u16 {
+        self.source_addr
+    }
+
+    pub fn dest_addr(&self) -> u16 {
+        self.dest_addr
+    }
+
     pub fn get_next_src_address(&mut self) -> u16 {
         let result = self.source_addr;
         self.source_addr += 1;
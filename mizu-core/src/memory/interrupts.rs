@@ -2,6 +2,7 @@ use bitflags::bitflags;
 use save_state::Savable;
 use std::convert::{From, TryFrom};
 
+/// The interrupt sources, ordered from the highest priority.
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum InterruptType {
     Vblank,
@@ -26,6 +27,30 @@ impl TryFrom<u8> for InterruptType {
     }
 }
 
+/// The number of times each interrupt was requested since the last VBlank
+/// interrupt, see [`GameBoy::interrupts_fired_this_frame`](crate::GameBoy::interrupts_fired_this_frame).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptCounts {
+    pub vblank: u32,
+    pub lcd_stat: u32,
+    pub timer: u32,
+    pub serial: u32,
+    pub joypad: u32,
+}
+
+impl InterruptCounts {
+    fn increment(&mut self, interrupt: InterruptType) {
+        let counter = match interrupt {
+            InterruptType::Vblank => &mut self.vblank,
+            InterruptType::LcdStat => &mut self.lcd_stat,
+            InterruptType::Timer => &mut self.timer,
+            InterruptType::Serial => &mut self.serial,
+            InterruptType::Joypad => &mut self.joypad,
+        };
+        *counter = counter.saturating_add(1);
+    }
+}
+
 pub trait InterruptManager {
     fn request_interrupt(&mut self, interrupt: InterruptType);
 }
@@ -60,6 +85,9 @@ impl From<InterruptType> for InterruptsFlags {
 pub struct Interrupts {
     enabled: InterruptsFlags,
     requested: InterruptsFlags,
+
+    #[savable(skip)]
+    counts: InterruptCounts,
 }
 
 impl Default for Interrupts {
@@ -67,11 +95,17 @@ impl Default for Interrupts {
         Self {
             enabled: InterruptsFlags::from_bits_truncate(0),
             requested: InterruptsFlags::from_bits_truncate(1),
+            counts: InterruptCounts::default(),
         }
     }
 }
 
 impl Interrupts {
+    /// The interrupts requested since the last VBlank, including it
+    pub fn counts(&self) -> InterruptCounts {
+        self.counts
+    }
+
     pub fn write_interrupt_enable(&mut self, data: u8) {
         self.enabled = InterruptsFlags::from_bits_truncate(data);
     }
@@ -116,8 +150,46 @@ impl Interrupts {
 
 impl InterruptManager for Interrupts {
     fn request_interrupt(&mut self, interrupt: InterruptType) {
-        let interrupt = interrupt.into();
+        if interrupt == InterruptType::Vblank {
+            self.counts = InterruptCounts::default();
+        }
+        self.counts.increment(interrupt);
 
-        self.requested.insert(interrupt);
+        self.requested.insert(interrupt.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InterruptCounts, InterruptManager, InterruptType, Interrupts};
+
+    #[test]
+    fn counts_reset_on_vblank() {
+        let mut interrupts = Interrupts::default();
+
+        interrupts.request_interrupt(InterruptType::LcdStat);
+        interrupts.request_interrupt(InterruptType::LcdStat);
+        interrupts.request_interrupt(InterruptType::Timer);
+        assert_eq!(
+            interrupts.counts(),
+            InterruptCounts {
+                lcd_stat: 2,
+                timer: 1,
+                ..InterruptCounts::default()
+            }
+        );
+
+        interrupts.request_interrupt(InterruptType::Vblank);
+        interrupts.request_interrupt(InterruptType::Joypad);
+        interrupts.request_interrupt(InterruptType::Serial);
+        assert_eq!(
+            interrupts.counts(),
+            InterruptCounts {
+                vblank: 1,
+                serial: 1,
+                joypad: 1,
+                ..InterruptCounts::default()
+            }
+        );
     }
 }
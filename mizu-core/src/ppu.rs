@@ -9,14 +9,67 @@ use bitflags::bitflags;
 use save_state::Savable;
 
 use crate::memory::{InterruptManager, InterruptType};
+use crate::save_migration::StateMigration;
 use crate::GameBoyConfig;
 
 use bg_attribs::BgAttribute;
-use colors::{Color, ColorPalette, ColorPalettesCollection};
+use colors::{ColorPalette, ColorPalettesCollection};
 use fifo::{BgFifo, SpriteFifo, SpritePriorityMode};
 use lcd::Lcd;
 use sprite::{SelectedSprite, Sprite};
 
+pub use colors::{Color, ColorCorrection};
+
+/// The number of sprites that can be rendered per scanline by the hardware
+const HARDWARE_SPRITE_LIMIT: u8 = 10;
+/// Set in the palette of the indexed screen buffer for sprite palettes
+const SPRITE_PALETTE_FLAG: u8 = 0x08;
+/// The number of 16 byte tiles in a VRAM bank (`0x8000-0x97FF`)
+pub const VRAM_TILES_PER_BANK: usize = 384;
+
+/// Only the selected sprites that can be used by the hardware are saved,
+/// so that lifting the sprite limit does not change the save state layout
+mod selected_oam_codec {
+    use super::{SelectedSprite, HARDWARE_SPRITE_LIMIT};
+    use save_state::Savable;
+    use std::io::{Read, Write};
+
+    pub fn save<W: Write>(value: &[SelectedSprite; 40], writer: &mut W) -> save_state::Result<()> {
+        for sprite in &value[..HARDWARE_SPRITE_LIMIT as usize] {
+            sprite.save(writer)?;
+        }
+        Ok(())
+    }
+
+    pub fn load<R: Read>(
+        value: &mut [SelectedSprite; 40],
+        reader: &mut R,
+    ) -> save_state::Result<()> {
+        for sprite in &mut value[..HARDWARE_SPRITE_LIMIT as usize] {
+            sprite.load(reader)?;
+        }
+        Ok(())
+    }
+}
+
+/// The size of the saved selected sprites, it can't be larger than the
+/// number of sprites saved with [`selected_oam_codec`]
+mod selected_oam_size_codec {
+    use super::HARDWARE_SPRITE_LIMIT;
+    use save_state::Savable;
+    use std::io::{Read, Write};
+
+    pub fn save<W: Write>(value: &u8, writer: &mut W) -> save_state::Result<()> {
+        (*value).min(HARDWARE_SPRITE_LIMIT).save(writer)
+    }
+
+    pub fn load<R: Read>(value: &mut u8, reader: &mut R) -> save_state::Result<()> {
+        value.load(reader)?;
+        *value = (*value).min(HARDWARE_SPRITE_LIMIT);
+        Ok(())
+    }
+}
+
 bitflags! {
     #[derive(Savable)]
     #[savable(bitflags)]
@@ -167,6 +220,78 @@ impl Fetcher {
     }
 }
 
+/// A snapshot of the pixel pipeline of the PPU (the fetcher and the FIFOs),
+/// see [`GameBoy::ppu_pipeline_state`](crate::GameBoy::ppu_pipeline_state).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PipelineState {
+    /// The tile column the fetcher will fetch next, relative to the start
+    /// of the background or the window
+    pub fetcher_x: u8,
+    /// The dots left until the fetcher finishes fetching the current tile
+    pub fetcher_delay: u8,
+    /// The fetcher has a fetched tile row waiting to be pushed to the
+    /// background FIFO
+    pub fetcher_has_data: bool,
+    /// The color numbers (`0-3`) of the pixels in the background FIFO,
+    /// from the next pixel to be shifted out
+    pub bg_fifo: Vec<u8>,
+    /// The color numbers (`0-3`) of the pixels in the sprite FIFO,
+    /// from the next pixel to be shifted out
+    pub sprite_fifo: Vec<u8>,
+    /// The pixels left to be discarded at the start of the line because
+    /// of `SCX` fine scrolling (or `WX < 7` for the window)
+    pub fine_scroll_x_discard: u8,
+}
+
+/// The state of the window and fine scroll handling of the PPU for the
+/// current dot, see [`GameBoy::ppu_quirk_report`](crate::GameBoy::ppu_quirk_report).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PpuQuirkReport {
+    /// The pixels left to be discarded at the start of the line because
+    /// of `SCX` fine scrolling (or `WX < 7` for the window)
+    pub fine_scroll_x_discard: u8,
+    /// The window is being drawn in the current line
+    pub drawing_window: bool,
+    /// The window would start drawing at the current dot, i.e. the window is
+    /// enabled, `LY >= WY`, and the current x matches `WX - 7` (or is `0`
+    /// if `WX < 7`). Only `true` during mode 3.
+    pub window_trigger: bool,
+}
+
+/// The size in pixels of the square rendered by [`TilemapRender`]
+pub const TILEMAP_RENDER_SIZE: usize = 256;
+
+/// A whole 32x32 tiles map rendered to RGB, see
+/// [`GameBoy::render_background`](crate::GameBoy::render_background) and
+/// [`GameBoy::render_window`](crate::GameBoy::render_window).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TilemapRender {
+    /// `256x256` RGB pixels, 3 bytes per pixel, row by row
+    pub pixels: Vec<u8>,
+    /// The position of the map relative to the screen, (`SCX`, `SCY`) for
+    /// the background, which is the top left corner of the visible area in
+    /// the map, and (`WX`, `WY`) for the window, which is where the top
+    /// left corner of the map is drawn on the screen (`WX` is offset by `7`)
+    pub x: u8,
+    pub y: u8,
+}
+
+/// Callback called with the screen buffer when a frame is complete
+pub type FrameCallback = Box<dyn FnMut(&[u8])>;
+
+/// Callback called with the line number and its RGB pixels when the PPU
+/// completes drawing a scanline
+pub type ScanlineCallback = Box<dyn FnMut(u8, &[u8])>;
+
+/// A layer rendered by the PPU, used to toggle rendering of each layer
+/// for debugging.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PpuLayer {
+    Background,
+    Window,
+    Sprites,
+}
+
 #[derive(Savable)]
 pub struct Ppu {
     lcd_control: LcdControl,
@@ -186,9 +311,16 @@ pub struct Ppu {
 
     vram: [u8; 0x4000],
     vram_bank: u8,
-    oam: [Sprite; 40],
-    // the sprites that got selected
-    selected_oam: [SelectedSprite; 10],
+    /// A bit for every tile of both banks (`384 * 2`), set when the tile
+    /// data is written, see [`Ppu::take_vram_dirty_tiles`]
+    #[savable(skip)]
+    vram_dirty_tiles: [u64; VRAM_TILES_PER_BANK * 2 / 64],
+    oam: [u8; 0xA0],
+    // the sprites that got selected, only the first `HARDWARE_SPRITE_LIMIT`
+    // can be used by the hardware, the rest are used when the limit is lifted
+    #[savable(with = "selected_oam_codec")]
+    selected_oam: [SelectedSprite; 40],
+    #[savable(with = "selected_oam_size_codec")]
     selected_oam_size: u8,
 
     cgb_bg_palettes: ColorPalettesCollection,
@@ -206,6 +338,8 @@ pub struct Ppu {
 
     cycle: u16,
     scanline: u8,
+    /// Number of VBlanks entered since power on, never reset
+    frame_count: u64,
 
     mode_3_end_cycle: u16,
 
@@ -216,44 +350,44 @@ pub struct Ppu {
 
     is_cgb_mode: bool,
 
+    /// Debug override to hide layers, indexed by `PpuLayer`
+    #[savable(skip)]
+    layers_enabled: [bool; 3],
+    /// Debug override of the number of sprites per scanline, `None` for no limit
+    #[savable(skip)]
+    sprite_limit: Option<u8>,
+
+    /// Called with the screen buffer when a frame is complete
+    #[savable(skip)]
+    frame_callback: Option<FrameCallback>,
+    /// Called with each line when it is drawn (end of mode 3)
+    #[savable(skip)]
+    scanline_callback: Option<ScanlineCallback>,
+    /// Set when entering VBlank, until taken by [`Ppu::take_frame_done`]
+    #[savable(skip)]
+    frame_done: bool,
+    /// Set when entering VBlank, until taken by [`Ppu::take_frame_ready`]
+    #[savable(skip)]
+    frame_ready: bool,
+
     config: GameBoyConfig,
 }
 
+/// The palette of the DMG shades from the user configuration
+fn dmg_color_palette(config: GameBoyConfig) -> ColorPalette {
+    ColorPalette::new(config.dmg_palette)
+}
+
 impl Ppu {
     pub fn new(config: GameBoyConfig) -> Self {
         let mut cgb_bg_palettes = ColorPalettesCollection::default();
         let mut cgb_sprite_palettes = ColorPalettesCollection::default();
 
         if config.is_dmg {
-            cgb_bg_palettes.set_palette(
-                0,
-                ColorPalette::new([
-                    color!(31, 31, 31),
-                    color!(21, 21, 21),
-                    color!(10, 10, 10),
-                    color!(0, 0, 0),
-                ]),
-            );
-
-            cgb_sprite_palettes.set_palette(
-                0,
-                ColorPalette::new([
-                    color!(31, 31, 31),
-                    color!(21, 21, 21),
-                    color!(10, 10, 10),
-                    color!(0, 0, 0),
-                ]),
-            );
-
-            cgb_sprite_palettes.set_palette(
-                1,
-                ColorPalette::new([
-                    color!(31, 31, 31),
-                    color!(21, 21, 21),
-                    color!(10, 10, 10),
-                    color!(0, 0, 0),
-                ]),
-            );
+            let palette = dmg_color_palette(config);
+            cgb_bg_palettes.set_palette(0, palette);
+            cgb_sprite_palettes.set_palette(0, palette);
+            cgb_sprite_palettes.set_palette(1, palette);
         }
 
         let sprite_priority_mode = if config.is_dmg {
@@ -262,7 +396,10 @@ impl Ppu {
             SpritePriorityMode::ByIndex
         };
 
-        Self {
+        let mut vram = [0; 0x4000];
+        config.ram_fill.fill(2, &mut vram);
+
+        let mut s = Self {
             lcd_control: LcdControl::from_bits_truncate(0),
             // COINCIDENCE_FLAG flag set because LYC and LY are 0 at the beginning
             lcd_status: LcdStatus::from_bits_truncate(4),
@@ -275,10 +412,11 @@ impl Ppu {
             dmg_sprite_palettes: [0xFF; 2],
             windows_y: 0,
             windows_x: 0,
-            vram: [0; 0x4000],
+            vram,
             vram_bank: 0,
-            oam: [Sprite::default(); 40],
-            selected_oam: [SelectedSprite::default(); 10],
+            vram_dirty_tiles: Default::default(),
+            oam: [0; 0xA0],
+            selected_oam: [SelectedSprite::default(); 40],
             selected_oam_size: 0,
             cgb_bg_palettes,
             cgb_sprite_palettes,
@@ -288,9 +426,10 @@ impl Ppu {
             window_y_counter: 0,
             bg_fifo: BgFifo::default(),
             sprite_fifo: SpriteFifo::new(sprite_priority_mode),
-            lcd: Lcd::default(),
+            lcd: Lcd::new(config.color_correction, config.render_video),
             cycle: 4,
             scanline: 0,
+            frame_count: 0,
             mode_3_end_cycle: 0,
             lcd_turned_on: false,
             // CGB by default, the bootrom of the CGB will change
@@ -298,8 +437,23 @@ impl Ppu {
             sprite_priority_mode,
             is_cgb_mode: !config.is_dmg,
 
+            layers_enabled: [true; 3],
+            sprite_limit: Some(HARDWARE_SPRITE_LIMIT),
+            frame_callback: None,
+            scanline_callback: None,
+            frame_done: false,
+            frame_ready: false,
+
             config,
+        };
+
+        let mut oam = [0; 0xA0];
+        config.ram_fill.fill(3, &mut oam);
+        for (addr, &data) in oam.iter().enumerate() {
+            s.write_oam_no_lock(addr as u16, data);
         }
+
+        s
     }
     /// create a ppu instance that match the one the ppu would have when the
     /// boot_rom finishes execution
@@ -322,35 +476,10 @@ impl Ppu {
 
         // palettes for DMG only
         if !cgb_mode {
-            s.cgb_bg_palettes.set_palette(
-                0,
-                ColorPalette::new([
-                    color!(31, 31, 31),
-                    color!(21, 21, 21),
-                    color!(10, 10, 10),
-                    color!(0, 0, 0),
-                ]),
-            );
-
-            s.cgb_sprite_palettes.set_palette(
-                0,
-                ColorPalette::new([
-                    color!(31, 31, 31),
-                    color!(21, 21, 21),
-                    color!(10, 10, 10),
-                    color!(0, 0, 0),
-                ]),
-            );
-
-            s.cgb_sprite_palettes.set_palette(
-                1,
-                ColorPalette::new([
-                    color!(31, 31, 31),
-                    color!(21, 21, 21),
-                    color!(10, 10, 10),
-                    color!(0, 0, 0),
-                ]),
-            );
+            let palette = dmg_color_palette(config);
+            s.cgb_bg_palettes.set_palette(0, palette);
+            s.cgb_sprite_palettes.set_palette(0, palette);
+            s.cgb_sprite_palettes.set_palette(1, palette);
             s.sprite_priority_mode = SpritePriorityMode::ByCoord;
         }
 
@@ -367,6 +496,47 @@ impl Ppu {
         s
     }
 
+    /// Migrates the saved state of the PPU to the next version, see
+    /// [`StateMigration`]
+    pub fn migrate_state(migration: &mut StateMigration) -> save_state::Result<()> {
+        let ppu = Self::new(GameBoyConfig::default());
+
+        migration.copy(ppu.lcd_control)?;
+        migration.copy(ppu.lcd_status)?;
+        migration.copy(ppu.scroll_y)?;
+        migration.copy(ppu.scroll_x)?;
+        migration.copy(ppu.ly)?;
+        migration.copy(ppu.lyc)?;
+        migration.copy(ppu.stat_interrupt_line)?;
+        migration.copy(ppu.dmg_bg_palette)?;
+        migration.copy(ppu.dmg_sprite_palettes)?;
+        migration.copy(ppu.windows_y)?;
+        migration.copy(ppu.windows_x)?;
+        migration.copy(ppu.vram)?;
+        migration.copy(ppu.vram_bank)?;
+        migration.copy(ppu.oam)?;
+        // saved with `selected_oam_codec`
+        migration.copy([SelectedSprite::default(); HARDWARE_SPRITE_LIMIT as usize])?;
+        migration.copy(ppu.selected_oam_size)?;
+        migration.copy(ppu.cgb_bg_palettes)?;
+        migration.copy(ppu.cgb_sprite_palettes)?;
+        migration.copy(ppu.fine_scroll_x_discard)?;
+        migration.copy(ppu.fetcher)?;
+        migration.copy(ppu.is_drawing_window)?;
+        migration.copy(ppu.window_y_counter)?;
+        migration.copy(ppu.bg_fifo)?;
+        migration.copy(ppu.sprite_fifo)?;
+        migration.copy(ppu.lcd)?;
+        migration.copy(ppu.cycle)?;
+        migration.copy(ppu.scanline)?;
+        migration.copy_since(3, ppu.frame_count)?;
+        migration.copy(ppu.mode_3_end_cycle)?;
+        migration.copy(ppu.lcd_turned_on)?;
+        migration.copy(ppu.sprite_priority_mode)?;
+        migration.copy(ppu.is_cgb_mode)?;
+        migration.config()
+    }
+
     pub fn read_vram(&self, addr: u16) -> u8 {
         self.read_vram_banked(self.vram_bank, addr)
     }
@@ -377,6 +547,12 @@ impl Ppu {
         let offset = addr as usize & 0x1FFF;
         let bank_start = self.vram_bank as usize * 0x2000;
         self.vram[bank_start + offset] = data;
+
+        // only the tile data, not the tile maps
+        if offset < VRAM_TILES_PER_BANK * 16 {
+            let tile = self.vram_bank as usize * VRAM_TILES_PER_BANK + offset / 16;
+            self.vram_dirty_tiles[tile / 64] |= 1 << (tile % 64);
+        }
     }
 
     pub fn read_oam(&self, addr: u16) -> u8 {
@@ -396,7 +572,7 @@ impl Ppu {
     /// This is used for DMA only, as it can write when OAM is normally blocked
     pub fn write_oam_no_lock(&mut self, addr: u16, data: u8) {
         let addr = addr & 0xFF;
-        self.oam[addr as usize / 4].set_at_offset(addr as u8 % 4, data);
+        self.oam[addr as usize] = data;
     }
 
     /// In OAM bug on write:
@@ -540,6 +716,46 @@ impl Ppu {
         self.ly
     }
 
+    /// The scanline currently being processed, unlike `ly`, this is not
+    /// reset to 0 early in scanline 153
+    pub fn scanline(&self) -> u8 {
+        self.scanline
+    }
+
+    /// The dot (PPU cycle) within the current scanline
+    pub fn cycle(&self) -> u16 {
+        self.cycle
+    }
+
+    /// The internal line counter of the window, it is incremented only on
+    /// scanlines where the window was drawn, and reset on VBlank
+    pub fn window_line_counter(&self) -> u8 {
+        self.window_y_counter
+    }
+
+    pub fn set_window_line_counter(&mut self, counter: u8) {
+        self.window_y_counter = counter;
+    }
+
+    pub fn pipeline_state(&self) -> PipelineState {
+        PipelineState {
+            fetcher_x: self.fetcher.x,
+            fetcher_delay: self.fetcher.delay_counter,
+            fetcher_has_data: self.fetcher.data.is_some(),
+            bg_fifo: self.bg_fifo.colors(),
+            sprite_fifo: self.sprite_fifo.colors(),
+            fine_scroll_x_discard: self.fine_scroll_x_discard,
+        }
+    }
+
+    pub fn quirk_report(&self) -> PpuQuirkReport {
+        PpuQuirkReport {
+            fine_scroll_x_discard: self.fine_scroll_x_discard,
+            drawing_window: self.is_drawing_window,
+            window_trigger: self.get_current_mode() == 3 && self.window_trigger(),
+        }
+    }
+
     pub fn write_ly(&mut self, _data: u8) {}
 
     pub fn read_lyc(&self) -> u8 {
@@ -582,6 +798,42 @@ impl Ppu {
         self.windows_x = data;
     }
 
+    /// Returns the `256` tiles of the background tile data (selected by
+    /// `LCDC` bit 4) in tile number order, this is what the SGB receives in
+    /// VRAM transfers, assuming the game shows the tiles in order on the
+    /// screen like it should.
+    pub fn sgb_transfer_data(&self) -> [u8; 0x1000] {
+        let signed_tiles = self.lcd_control.bg_window_pattern_table_block_1();
+        std::array::from_fn(|i| {
+            // tiles `128-255` are always at `0x8800`
+            let addr = if signed_tiles && i < 0x800 {
+                0x1000 + i
+            } else {
+                i
+            };
+            self.read_vram_banked(0, addr as u16)
+        })
+    }
+
+    /// Sets the 4 DMG shades to the `RGB555` `colors`, used by the SGB
+    /// palettes. Has no effect in CGB mode.
+    pub fn set_dmg_shades(&mut self, colors: [u16; 4]) {
+        if self.is_cgb_mode {
+            return;
+        }
+
+        let palette = ColorPalette::new(colors.map(|c| {
+            color!(
+                c as u8 & 0x1F,
+                (c >> 5) as u8 & 0x1F,
+                (c >> 10) as u8 & 0x1F
+            )
+        }));
+        self.cgb_bg_palettes.set_palette(0, palette);
+        self.cgb_sprite_palettes.set_palette(0, palette);
+        self.cgb_sprite_palettes.set_palette(1, palette);
+    }
+
     pub fn read_vram_bank(&self) -> u8 {
         0xFE | self.vram_bank
     }
@@ -649,10 +901,132 @@ impl Ppu {
         self.lcd_status.current_mode()
     }
 
+    pub fn is_lcd_on(&self) -> bool {
+        self.lcd_control.display_enable()
+    }
+
     pub fn screen_buffer(&self) -> &[u8] {
         self.lcd.screen_buffer()
     }
 
+    pub fn screen_buffer_rgba(&self, out: &mut [u8]) {
+        self.lcd.screen_buffer_rgba(out);
+    }
+
+    pub fn screen_buffer_rgb565(&self, out: &mut [u16], big_endian: bool) {
+        self.lcd.screen_buffer_rgb565(out, big_endian);
+    }
+
+    /// A new frame was completed since the last [`Ppu::take_frame_ready`]
+    pub fn frame_ready(&self) -> bool {
+        self.frame_ready
+    }
+
+    pub fn take_frame_ready(&mut self) -> bool {
+        std::mem::take(&mut self.frame_ready)
+    }
+
+    pub fn frame_changed(&self) -> bool {
+        self.lcd.frame_changed()
+    }
+
+    pub fn screen_buffer_indices(&self) -> &[u8] {
+        self.lcd.screen_buffer_indices()
+    }
+
+    pub fn screen_buffer_palettes(&self) -> &[u8] {
+        self.lcd.screen_buffer_palettes()
+    }
+
+    /// The raw content of a VRAM bank, `bank` must be `0` or `1`
+    pub fn vram(&self, bank: u8) -> &[u8] {
+        assert!(bank < 2, "invalid VRAM bank {}", bank);
+        let bank_start = bank as usize * 0x2000;
+        &self.vram[bank_start..bank_start + 0x2000]
+    }
+
+    pub fn oam(&self) -> &[u8] {
+        &self.oam
+    }
+
+    /// The tiles written since the last call, bank `1` tiles are offset
+    /// by [`VRAM_TILES_PER_BANK`]
+    pub fn take_vram_dirty_tiles(&mut self) -> Vec<u16> {
+        let mut tiles = Vec::new();
+        for (i, word) in self.vram_dirty_tiles.iter_mut().enumerate() {
+            let mut bits = std::mem::take(word);
+            while bits != 0 {
+                tiles.push((i * 64) as u16 + bits.trailing_zeros() as u16);
+                bits &= bits - 1;
+            }
+        }
+        tiles
+    }
+
+    /// Returns `true` if a frame was completed (VBlank entered) since the
+    /// last call, and resets it
+    pub fn take_frame_done(&mut self) -> bool {
+        std::mem::take(&mut self.frame_done)
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn bg_palettes_rgb(&self) -> [[(u8, u8, u8); 4]; 8] {
+        self.palettes_rgb(&self.cgb_bg_palettes, &[self.dmg_bg_palette])
+    }
+
+    pub fn sprite_palettes_rgb(&self) -> [[(u8, u8, u8); 4]; 8] {
+        self.palettes_rgb(&self.cgb_sprite_palettes, &self.dmg_sprite_palettes)
+    }
+
+    /// Renders the whole background tile map selected by `LCDC`
+    pub fn render_background(&self) -> TilemapRender {
+        TilemapRender {
+            pixels: self.render_tilemap(self.lcd_control.bg_tilemap()),
+            x: self.scroll_x,
+            y: self.scroll_y,
+        }
+    }
+
+    /// Renders the whole window tile map selected by `LCDC`
+    pub fn render_window(&self) -> TilemapRender {
+        TilemapRender {
+            pixels: self.render_tilemap(self.lcd_control.window_tilemap()),
+            x: self.windows_x,
+            y: self.windows_y,
+        }
+    }
+
+    pub fn set_frame_callback(&mut self, callback: Option<FrameCallback>) {
+        self.frame_callback = callback;
+    }
+
+    pub fn set_scanline_callback(&mut self, callback: Option<ScanlineCallback>) {
+        self.scanline_callback = callback;
+    }
+
+    /// Hides/shows a layer, this is a debug override on top of the `LCDC`
+    /// register, a disabled layer is rendered as color 0 (transparent)
+    pub fn set_layer_enabled(&mut self, layer: PpuLayer, enabled: bool) {
+        self.layers_enabled[layer as usize] = enabled;
+    }
+
+    /// Sets the maximum number of sprites rendered per scanline, this is
+    /// a debug override, `None` removes the limit
+    pub fn set_sprite_limit(&mut self, limit: Option<u8>) {
+        self.sprite_limit = limit;
+    }
+
+    pub fn set_lcd_ghosting_decay(&mut self, decay: Option<f32>) {
+        self.lcd.set_ghosting_decay(decay);
+    }
+
+    pub fn set_lcd_frame_blend(&mut self, weight: Option<f32>) {
+        self.lcd.set_frame_blend(weight);
+    }
+
     pub fn enter_stop_mode(&mut self) {
         if self.config.is_dmg {
             self.lcd.clear();
@@ -672,9 +1046,54 @@ impl Ppu {
         self.lcd.raw_screen_buffer()
     }
 
-    pub fn clock<I: InterruptManager>(&mut self, interrupt_manager: &mut I, clocks: u8) {
-        let mut new_stat_int_happened = false;
+    /// The mode interrupt sources of the STAT interrupt line at the current
+    /// dot, following the hardware timing:
+    ///
+    /// - Mode 0 and mode 1 sources are high for the whole mode.
+    /// - The mode 2 source is high for a single dot at the start of the
+    ///   line, dot `0` on CGB and dot `4` on DMG. Line `0` is an exception
+    ///   where it happens at dot `4` on both, as the mode changes at dot `4`
+    ///   when coming from vblank.
+    /// - When entering vblank at line `144`, the mode 2 source also triggers
+    ///   together with the mode 1 source. On CGB this happens at dot `0`
+    ///   (before the vblank interrupt), and on DMG at dot `4`.
+    fn stat_mode_interrupt_accurate(&self) -> bool {
+        let status = &self.lcd_status;
+        let is_dmg = self.config.is_dmg;
+
+        let mode_int = match status.current_mode() {
+            0 => status.mode_0_hblank_interrupt(),
+            1 if self.scanline == 144 && self.cycle == 4 && is_dmg => {
+                status.mode_1_vblank_interrupt() || status.mode_2_oam_interrupt()
+            }
+            1 => status.mode_1_vblank_interrupt(),
+            2 if self.cycle == 0 => !is_dmg && status.mode_2_oam_interrupt(),
+            2 if self.cycle == 4 => (is_dmg || self.scanline == 0) && status.mode_2_oam_interrupt(),
+            _ => false,
+        };
+
+        let cgb_vblank_int = self.scanline == 144
+            && self.cycle == 0
+            && !is_dmg
+            && (status.mode_1_vblank_interrupt() || status.mode_2_oam_interrupt());
+
+        mode_int || cgb_vblank_int
+    }
+
+    /// The mode interrupt sources of the STAT interrupt line at the current
+    /// dot, where each source is high for the whole duration of its mode
+    fn stat_mode_interrupt_simple(&self) -> bool {
+        let status = &self.lcd_status;
+
+        match status.current_mode() {
+            0 => status.mode_0_hblank_interrupt(),
+            1 => status.mode_1_vblank_interrupt(),
+            2 => status.mode_2_oam_interrupt(),
+            _ => false,
+        }
+    }
 
+    pub fn clock<I: InterruptManager>(&mut self, interrupt_manager: &mut I, clocks: u8) {
         if !self.lcd_control.display_enable() {
             return;
         }
@@ -715,50 +1134,22 @@ impl Ppu {
                 self.lcd_status.current_mode_set(1);
                 self.enter_vblank();
                 self.mode_3_end_cycle = 0;
+                self.frame_count += 1;
 
                 interrupt_manager.request_interrupt(InterruptType::Vblank);
             }
             _ => {}
         }
 
+        let mut new_stat_int_happened = if self.config.stat_quirks {
+            self.stat_mode_interrupt_accurate()
+        } else {
+            self.stat_mode_interrupt_simple()
+        };
+
         match self.lcd_status.current_mode() {
-            0 => {
-                new_stat_int_happened =
-                    new_stat_int_happened || self.lcd_status.mode_0_hblank_interrupt();
-            }
-            // TODO: check if apply to 144 or to 144-153
-            1 if self.cycle == 4 && self.scanline == 144 && self.config.is_dmg => {
-                // special: also mode 2 interrupt if enabled
-                new_stat_int_happened = new_stat_int_happened
-                    || self.lcd_status.mode_1_vblank_interrupt()
-                    || self.lcd_status.mode_2_oam_interrupt();
-            }
-            1 => {
-                new_stat_int_happened =
-                    new_stat_int_happened || self.lcd_status.mode_1_vblank_interrupt();
-            }
-            2 if self.cycle == 0 => {
-                // FIXME: check mode 2 interrupt timing for DMG and CGB
-                if !self.config.is_dmg {
-                    new_stat_int_happened =
-                        new_stat_int_happened || self.lcd_status.mode_2_oam_interrupt();
-                }
-            }
             2 if self.cycle == 4 => {
                 self.load_selected_sprites_oam();
-
-                // FIXME: check mode 2 interrupt timing for DMG and CGB
-                if self.config.is_dmg {
-                    new_stat_int_happened =
-                        new_stat_int_happened || self.lcd_status.mode_2_oam_interrupt();
-                }
-
-                // execluded from the spcial case where mode2 interrupt happen
-                // at cycle 0, here it happens at cycle 4
-                if self.scanline == 0 {
-                    new_stat_int_happened =
-                        new_stat_int_happened || self.lcd_status.mode_2_oam_interrupt();
-                }
             }
             3 => {
                 for _ in 0..clocks {
@@ -774,14 +1165,6 @@ impl Ppu {
             _ => {}
         }
 
-        // In CGB, the mode2 interrupt happens before the vblank interrupt
-        if self.cycle == 0 && self.scanline == 144 && !self.config.is_dmg {
-            // special: also mode 2 interrupt if enabled
-            new_stat_int_happened = new_stat_int_happened
-                || self.lcd_status.mode_1_vblank_interrupt()
-                || self.lcd_status.mode_2_oam_interrupt();
-        }
-
         let new_coincidence = self.ly == self.lyc;
         self.lcd_status.coincidence_flag_set(new_coincidence);
 
@@ -804,7 +1187,6 @@ impl Ppu {
             self.cycle -= 456;
             self.scanline += 1;
             if self.scanline == 154 {
-                self.lcd.switch_buffers();
                 self.scanline = 0;
                 self.lcd.next_line();
             }
@@ -828,7 +1210,7 @@ impl Ppu {
 
     fn read_oam_no_lock(&self, addr: u16) -> u8 {
         let addr = addr & 0xFF;
-        self.oam[addr as usize / 4].get_at_offset(addr as u8 % 4)
+        self.oam[addr as usize]
     }
 
     fn read_oam_word_no_lock(&self, offset: u8) -> u16 {
@@ -889,6 +1271,7 @@ impl Ppu {
                 self.bg_fifo.push(
                     pixels,
                     self.cgb_bg_palettes.get_palette(attribs.palette()),
+                    attribs.palette(),
                     attribs.priority(),
                 );
             }
@@ -902,8 +1285,9 @@ impl Ppu {
             } else {
                 self.try_add_sprite();
 
-                let color = self.get_next_color();
-                self.lcd.push(color, self.scanline);
+                let (color, color_index, palette) = self.get_next_color();
+                self.lcd
+                    .push_indexed(color, color_index, palette, self.scanline);
 
                 if self.lcd.x() == 160 {
                     return true;
@@ -918,39 +1302,89 @@ impl Ppu {
     /// mixing here does not mean using the two pixels and output something in the middle
     /// mixing just means check priorities and all stuff and pick which should be
     /// rendered, the other is just discarded
-    fn get_next_color(&mut self) -> Color {
+    ///
+    /// Returns the color, and the color index and palette of the pixel for
+    /// the indexed screen buffer, see [`Lcd::push_indexed`]
+    fn get_next_color(&mut self) -> (Color, u8, u8) {
         let bg_pixel = self.bg_fifo.pop();
-        let sprite_pixel = self.sprite_fifo.pop();
+        // the sprite fifo must advance even if sprites are hidden
+        let sprite_pixel = self
+            .sprite_fifo
+            .pop()
+            .filter(|_| self.layers_enabled[PpuLayer::Sprites as usize]);
 
         // If we have a sprite, then mix, else just use the background
-        let (mut color_index, palette, dmg_palette) = if let Some(sprite_pixel) = sprite_pixel {
-            let master_priority = self.is_cgb_mode && !self.lcd_control.bg_window_priority();
-            let bg_priority = bg_pixel.bg_priority;
-            let oam_bg_priority = sprite_pixel.oam_bg_priority;
-
-            if (master_priority || bg_pixel.color == 0 || (!bg_priority && !oam_bg_priority))
-                && sprite_pixel.color != 0
-            {
-                // sprite wins
+        let (mut color_index, palette, palette_index, dmg_palette) =
+            if let Some(sprite_pixel) = sprite_pixel {
+                let master_priority = self.is_cgb_mode && !self.lcd_control.bg_window_priority();
+                let bg_priority = bg_pixel.bg_priority;
+                let oam_bg_priority = sprite_pixel.oam_bg_priority;
+
+                if (master_priority || bg_pixel.color == 0 || (!bg_priority && !oam_bg_priority))
+                    && sprite_pixel.color != 0
+                {
+                    // sprite wins
+                    (
+                        sprite_pixel.color,
+                        sprite_pixel.palette,
+                        SPRITE_PALETTE_FLAG | sprite_pixel.palette_index,
+                        self.dmg_sprite_palettes[sprite_pixel.dmg_palette as usize],
+                    )
+                } else {
+                    // background wins
+                    (
+                        bg_pixel.color,
+                        bg_pixel.palette,
+                        bg_pixel.palette_index,
+                        self.dmg_bg_palette,
+                    )
+                }
+            } else {
+                // there is no sprite pixel, so we just use the background pixel
                 (
-                    sprite_pixel.color,
-                    sprite_pixel.palette,
-                    self.dmg_sprite_palettes[sprite_pixel.dmg_palette as usize],
+                    bg_pixel.color,
+                    bg_pixel.palette,
+                    bg_pixel.palette_index,
+                    self.dmg_bg_palette,
                 )
-            } else {
-                // background wins
-                (bg_pixel.color, bg_pixel.palette, self.dmg_bg_palette)
-            }
-        } else {
-            // there is no sprite pixel, so we just use the background pixel
-            (bg_pixel.color, bg_pixel.palette, self.dmg_bg_palette)
-        };
+            };
+        let raw_color_index = color_index;
 
         if !self.is_cgb_mode {
             color_index = (dmg_palette >> (2 * color_index)) & 0b11;
         }
 
-        palette.get_color(color_index)
+        (
+            palette.get_color(color_index),
+            raw_color_index,
+            palette_index,
+        )
+    }
+
+    /// Converts the palettes to RGB, in DMG mode, the first palettes
+    /// are mapped by their `dmg_palettes` registers like when rendering
+    fn palettes_rgb(
+        &self,
+        palettes: &ColorPalettesCollection,
+        dmg_palettes: &[u8],
+    ) -> [[(u8, u8, u8); 4]; 8] {
+        std::array::from_fn(|palette_index| {
+            let palette = palettes.get_palette(palette_index as u8);
+            let dmg_palette = dmg_palettes
+                .get(palette_index)
+                .filter(|_| !self.is_cgb_mode);
+
+            std::array::from_fn(|color_index| {
+                let mut color_index = color_index as u8;
+                if let Some(dmg_palette) = dmg_palette {
+                    color_index = (dmg_palette >> (2 * color_index)) & 0b11;
+                }
+                let [r, g, b] = palette
+                    .get_color(color_index)
+                    .to_rgb(self.config.color_correction);
+                (r, g, b)
+            })
+        })
     }
 
     /// Gets the tile number, BgAttribute for that tile, and its y position
@@ -981,6 +1415,44 @@ impl Ppu {
         (tile, tile_attribs, tile_y)
     }
 
+    /// Renders the 32x32 tiles of `tile_map` with the current tile data
+    /// addressing and background palettes
+    fn render_tilemap(&self, tile_map: u16) -> Vec<u8> {
+        let palettes = self.bg_palettes_rgb();
+        let mut pixels = vec![0; TILEMAP_RENDER_SIZE * TILEMAP_RENDER_SIZE * 3];
+
+        for tile_y in 0..32 {
+            for tile_x in 0..32 {
+                let vram_index = tile_map + self.get_tile_index(tile_x, tile_y);
+                let tile = self.read_vram_banked(0, vram_index);
+                let attribs = if self.is_cgb_mode {
+                    BgAttribute::new(self.read_vram_banked(1, vram_index))
+                } else {
+                    BgAttribute::new(0)
+                };
+                let palette = &palettes[attribs.palette() as usize];
+
+                for y in 0..8 {
+                    let pattern_y = if attribs.is_vertical_flip() { 7 - y } else { y };
+                    let mut pattern = self.get_bg_pattern(tile, pattern_y, attribs.bank());
+                    if attribs.is_horizontal_flip() {
+                        pattern.reverse();
+                    }
+
+                    let row = tile_y as usize * 8 + y as usize;
+                    for (x, &color_index) in pattern.iter().enumerate() {
+                        let column = tile_x as usize * 8 + x;
+                        let index = (row * TILEMAP_RENDER_SIZE + column) * 3;
+                        let (r, g, b) = palette[color_index as usize];
+                        pixels[index..index + 3].copy_from_slice(&[r, g, b]);
+                    }
+                }
+            }
+        }
+
+        pixels
+    }
+
     fn get_tile_index(&self, tile_x: u8, tile_y: u8) -> u16 {
         tile_y as u16 * 32 + tile_x as u16
     }
@@ -1034,6 +1506,15 @@ impl Ppu {
                 y % 8
             };
 
+            let layer = if self.is_drawing_window {
+                PpuLayer::Window
+            } else {
+                PpuLayer::Background
+            };
+            if !self.layers_enabled[layer as usize] {
+                return ([0; 8], attribs);
+            }
+
             let mut pattern = self.get_bg_pattern(tile, y, attribs.bank());
 
             if attribs.is_horizontal_flip() {
@@ -1045,16 +1526,22 @@ impl Ppu {
     }
 
     fn load_selected_sprites_oam(&mut self) {
+        let limit = self
+            .sprite_limit
+            .map_or(self.selected_oam.len(), |limit| limit as usize)
+            .min(self.selected_oam.len());
+
         let mut count = 0;
-        for (i, &sprite) in self.oam.iter().enumerate() {
+        for (i, bytes) in self.oam.chunks_exact(4).enumerate() {
+            let sprite = Sprite::from_bytes(bytes);
             // in range
             if self.scanline.wrapping_sub(sprite.screen_y()) < self.lcd_control.sprite_size() {
-                self.selected_oam[count] = SelectedSprite::new(sprite, i as u8);
-                count += 1;
-
-                if count == 10 {
+                if count == limit {
                     break;
                 }
+
+                self.selected_oam[count] = SelectedSprite::new(sprite, i as u8);
+                count += 1;
             }
         }
 
@@ -1070,6 +1557,13 @@ impl Ppu {
             {
                 let sprite = selected_sprite.sprite();
 
+                // sprites at X=0 or X>=168 are fully off-screen, they are still
+                // selected in OAM scan (and count toward the 10 sprites limit),
+                // but they are never drawn
+                if sprite.x() == 0 || sprite.x() >= 168 {
+                    continue;
+                }
+
                 // the x index of the sprite is of the left of the display
                 let left_out_of_bounds = self.lcd.x() == 0 && sprite.x() < 8;
 
@@ -1113,19 +1607,23 @@ impl Ppu {
                         colors,
                         selected_sprite,
                         self.cgb_sprite_palettes.get_palette(palette_selector),
+                        palette_selector,
                     );
                 }
             }
         }
     }
 
-    fn try_enter_window(&mut self) {
-        if self.lcd_control.window_enable()
+    fn window_trigger(&self) -> bool {
+        self.lcd_control.window_enable()
             && !self.is_drawing_window
                 // handle if window's x is less than 7
             && (self.lcd.x() == self.windows_x.wrapping_sub(7) || (self.lcd.x() == 0 && self.windows_x < 7))
             && self.scanline >= self.windows_y
-        {
+    }
+
+    fn try_enter_window(&mut self) {
+        if self.window_trigger() {
             // override the scroll_x if:
             // - the window_x is lower than 7; to discard the bits *from* the window
             // - there is already fine scroll; to reset the scrolling and for the window
@@ -1144,12 +1642,16 @@ impl Ppu {
     /// Ending stuff for mode 3
     fn enter_hblank(&mut self) {
         self.lcd.next_line();
+        if let Some(callback) = self.scanline_callback.as_mut() {
+            callback(self.scanline, self.lcd.drawn_line(self.scanline));
+        }
         // clear for the next line
         self.bg_fifo.clear();
         self.sprite_fifo.clear();
         self.fetcher.x = 0;
         if self.is_drawing_window {
-            self.window_y_counter += 1;
+            // can only overflow if it was overridden for testing
+            self.window_y_counter = self.window_y_counter.wrapping_add(1);
         }
         self.is_drawing_window = false;
     }
@@ -1157,5 +1659,733 @@ impl Ppu {
     fn enter_vblank(&mut self) {
         // after drawing the screen reset the window y internal counter
         self.window_y_counter = 0;
+
+        // nothing is drawn during VBlank, so the frame is complete
+        self.lcd.switch_buffers();
+        if let Some(callback) = self.frame_callback.as_mut() {
+            callback(self.lcd.screen_buffer());
+        }
+        self.frame_done = true;
+        self.frame_ready = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::lcd::{LCD_HEIGHT, LCD_WIDTH};
+    use super::{Color, Ppu, PpuLayer, SelectedSprite, SPRITE_PALETTE_FLAG, VRAM_TILES_PER_BANK};
+    use crate::memory::{InterruptManager, InterruptType};
+    use crate::tests::{test_cgb_gameboy, test_gameboy};
+    use crate::GameBoyConfig;
+    use save_state::Savable;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    struct NoInterrupts;
+
+    impl InterruptManager for NoInterrupts {
+        fn request_interrupt(&mut self, _interrupt: InterruptType) {}
+    }
+
+    /// DMG ppu with sprites enabled and tile 1 filled with color 3
+    fn sprites_test_ppu() -> Ppu {
+        let config = GameBoyConfig {
+            is_dmg: true,
+            ..GameBoyConfig::default()
+        };
+        let mut ppu = Ppu::new_skip_boot_rom(false, config);
+        ppu.write_lcd_control(0x93);
+        for i in 0..16 {
+            ppu.write_vram(0x8010 + i, 0xFF);
+        }
+        ppu
+    }
+
+    fn set_sprite(ppu: &mut Ppu, index: u16, y: u8, x: u8, tile: u8) {
+        let addr = 0xFE00 + index * 4;
+        ppu.write_oam_no_lock(addr, y);
+        ppu.write_oam_no_lock(addr + 1, x);
+        ppu.write_oam_no_lock(addr + 2, tile);
+        ppu.write_oam_no_lock(addr + 3, 0);
+    }
+
+    /// clocks the ppu until the first scanline is fully drawn
+    fn render_first_line(ppu: &mut Ppu) {
+        while !(ppu.scanline == 1 && ppu.cycle == 0) {
+            ppu.clock(&mut NoInterrupts, 4);
+        }
+    }
+
+    /// returns which pixels of the first line are black (drawn by a sprite)
+    fn first_line_black_pixels(ppu: &Ppu) -> Vec<bool> {
+        ppu.raw_screen_buffer()[..LCD_WIDTH * 3]
+            .chunks(3)
+            .map(|pixel| pixel[0] == 0)
+            .collect()
+    }
+
+    #[test]
+    fn scanline_dot_and_window_line_counter() {
+        let mut ppu = sprites_test_ppu();
+        // window enabled from scanline 2, for the whole width
+        ppu.write_lcd_control(0xB3);
+        ppu.write_window_y(2);
+        ppu.write_window_x(7);
+
+        render_first_line(&mut ppu);
+        assert_eq!((ppu.scanline(), ppu.cycle()), (1, 0));
+        ppu.clock(&mut NoInterrupts, 4);
+        assert_eq!((ppu.scanline(), ppu.cycle()), (1, 4));
+
+        while ppu.scanline() != 6 {
+            ppu.clock(&mut NoInterrupts, 4);
+        }
+        assert_eq!(ppu.window_line_counter(), 4);
+
+        // disabling the window stops the counter, but does not reset it
+        ppu.write_lcd_control(0x93);
+        while ppu.scanline() != 10 {
+            ppu.clock(&mut NoInterrupts, 4);
+        }
+        assert_eq!(ppu.window_line_counter(), 4);
+
+        // continues from the overridden value
+        ppu.write_lcd_control(0xB3);
+        ppu.set_window_line_counter(100);
+        while ppu.scanline() != 12 {
+            ppu.clock(&mut NoInterrupts, 4);
+        }
+        assert_eq!(ppu.window_line_counter(), 102);
+
+        while ppu.scanline() != 145 {
+            ppu.clock(&mut NoInterrupts, 4);
+        }
+        assert_eq!(ppu.window_line_counter(), 0);
+    }
+
+    #[test]
+    fn offscreen_sprites_count_toward_limit() {
+        let mut ppu = sprites_test_ppu();
+
+        // 5 sprites at X=0 and 5 at X=168, all not visible
+        for i in 0..5 {
+            set_sprite(&mut ppu, i, 16, 0, 1);
+            set_sprite(&mut ppu, i + 5, 16, 168, 1);
+        }
+        // visible sprite, but after the 10 sprites limit
+        set_sprite(&mut ppu, 10, 16, 20, 1);
+
+        render_first_line(&mut ppu);
+
+        assert_eq!(ppu.selected_oam_size, 10);
+        assert!(first_line_black_pixels(&ppu).iter().all(|&black| !black));
+    }
+
+    #[test]
+    fn custom_sprite_limit() {
+        let mut ppu = sprites_test_ppu();
+
+        // 20 sprites on the same line, 8 pixels apart
+        for i in 0..20 {
+            set_sprite(&mut ppu, i, 16, (8 + i * 8) as u8, 1);
+        }
+
+        let visible_sprites = |ppu: &Ppu| {
+            first_line_black_pixels(ppu)
+                .chunks(8)
+                .filter(|pixels| pixels.iter().all(|&black| black))
+                .count()
+        };
+
+        render_first_line(&mut ppu);
+        assert_eq!(ppu.selected_oam_size, 10);
+        assert_eq!(visible_sprites(&ppu), 10);
+
+        for (limit, expected) in [(None, 20), (Some(15), 15), (Some(0), 0)] {
+            ppu.set_sprite_limit(limit);
+            // render the first line of the next frame
+            ppu.clock(&mut NoInterrupts, 4);
+            render_first_line(&mut ppu);
+            assert_eq!(ppu.selected_oam_size, expected);
+            assert_eq!(visible_sprites(&ppu), expected as usize);
+        }
+
+        // rendering does not break with all the sprites on the same position,
+        // even if the scanline takes longer than normal
+        ppu.set_sprite_limit(None);
+        for i in 0..40 {
+            set_sprite(&mut ppu, i, 16, 8, 1);
+        }
+        for _ in 0..3 {
+            ppu.clock(&mut NoInterrupts, 4);
+            render_first_line(&mut ppu);
+        }
+
+        // only the hardware selected sprites are saved
+        let mut data = Vec::new();
+        super::selected_oam_codec::save(&ppu.selected_oam, &mut data).unwrap();
+        let selected_sprite_size = SelectedSprite::default().save_size().unwrap();
+        assert_eq!(data.len() as u64, selected_sprite_size * 10);
+
+        // and the size is clamped to the saved sprites
+        assert_eq!(ppu.selected_oam_size, 40);
+        let mut data = Vec::new();
+        ppu.save(&mut data).unwrap();
+        let mut loaded = Ppu::new(GameBoyConfig::default());
+        loaded.load(&mut data.as_slice()).unwrap();
+        assert_eq!(loaded.selected_oam_size, 10);
+    }
+
+    #[test]
+    fn partially_visible_sprites_on_edges() {
+        let mut ppu = sprites_test_ppu();
+
+        set_sprite(&mut ppu, 0, 16, 4, 1);
+        set_sprite(&mut ppu, 1, 16, 164, 1);
+
+        render_first_line(&mut ppu);
+
+        assert_eq!(ppu.selected_oam_size, 2);
+
+        let black = first_line_black_pixels(&ppu);
+        for (x, &is_black) in black.iter().enumerate() {
+            assert_eq!(is_black, !(4..156).contains(&x), "pixel {}", x);
+        }
+    }
+
+    #[test]
+    fn indexed_screen_buffer() {
+        let mut ppu = sprites_test_ppu();
+        // OBP1 maps everything to white
+        ppu.write_dmg_sprite_palettes(1, 0x00);
+
+        set_sprite(&mut ppu, 0, 16, 8, 1);
+        set_sprite(&mut ppu, 1, 16, 24, 1);
+        // use OBP1
+        ppu.write_oam_no_lock(0xFE07, 0x10);
+
+        // render the whole frame until the buffers are switched
+        render_first_line(&mut ppu);
+        while ppu.scanline() != 0 {
+            ppu.clock(&mut NoInterrupts, 4);
+        }
+
+        let indices = &ppu.screen_buffer_indices()[..LCD_WIDTH];
+        let palettes = &ppu.screen_buffer_palettes()[..LCD_WIDTH];
+        for x in 0..LCD_WIDTH {
+            let (index, palette) = match x {
+                0..=7 => (3, SPRITE_PALETTE_FLAG),
+                16..=23 => (3, SPRITE_PALETTE_FLAG | 1),
+                _ => (0, 0),
+            };
+            assert_eq!((indices[x], palettes[x]), (index, palette), "pixel {}", x);
+        }
+
+        // the palette is applied only to the RGB buffer
+        let rgb = |x: usize| &ppu.screen_buffer()[x * 3..x * 3 + 3];
+        assert_eq!(rgb(0), &[0, 0, 0]);
+        // same as the white background
+        assert_eq!(rgb(16), rgb(100));
+    }
+
+    #[test]
+    fn hide_sprites_layer() {
+        let mut ppu = sprites_test_ppu();
+        set_sprite(&mut ppu, 0, 16, 8, 1);
+
+        ppu.set_layer_enabled(PpuLayer::Sprites, false);
+        render_first_line(&mut ppu);
+        assert!(first_line_black_pixels(&ppu).iter().all(|&black| !black));
+
+        ppu.set_layer_enabled(PpuLayer::Sprites, true);
+        ppu.scanline = 153;
+        ppu.cycle = 400;
+        render_first_line(&mut ppu);
+        let black = first_line_black_pixels(&ppu);
+        for (x, &is_black) in black.iter().enumerate() {
+            assert_eq!(is_black, x < 8, "pixel {}", x);
+        }
+    }
+
+    #[test]
+    fn palettes_rgb() {
+        let mut ppu = Ppu::new_skip_boot_rom(true, GameBoyConfig::default());
+        // bg palette 1, color 2: white
+        ppu.write_cgb_bg_palettes_index(0x80 | (8 + 4));
+        ppu.write_cgb_bg_palettes_data(0xFF);
+        ppu.write_cgb_bg_palettes_data(0x7F);
+        // sprite palette 7, color 3: pure red
+        ppu.write_cgb_sprite_palettes_index(0x80 | (7 * 8 + 6));
+        ppu.write_cgb_sprite_palettes_data(0x1F);
+        ppu.write_cgb_sprite_palettes_data(0x00);
+
+        let bg_index = ppu.read_cgb_bg_palettes_index();
+        let sprite_index = ppu.read_cgb_sprite_palettes_index();
+        assert_eq!(ppu.bg_palettes_rgb()[1][2], (240, 240, 240));
+        assert_eq!(ppu.sprite_palettes_rgb()[7][3], (201, 0, 46));
+        // reading does not affect the palettes index
+        assert_eq!(ppu.read_cgb_bg_palettes_index(), bg_index);
+        assert_eq!(ppu.read_cgb_sprite_palettes_index(), sprite_index);
+
+        // DMG mode, the palettes are mapped by the DMG palette registers
+        let mut ppu = sprites_test_ppu();
+        ppu.write_dmg_bg_palette(0xE4);
+        ppu.write_dmg_sprite_palettes(1, 0xE4);
+        let bg_palette = ppu.bg_palettes_rgb()[0];
+        let sprite_palette = ppu.sprite_palettes_rgb()[1];
+        assert_eq!(bg_palette, sprite_palette);
+
+        ppu.write_dmg_bg_palette(0x1B);
+        ppu.write_dmg_sprite_palettes(1, 0x00);
+        let mut reversed = bg_palette;
+        reversed.reverse();
+        assert_eq!(ppu.bg_palettes_rgb()[0], reversed);
+        assert_eq!(ppu.sprite_palettes_rgb()[1], [bg_palette[0]; 4]);
+    }
+
+    #[test]
+    fn hide_background_and_window_layers() {
+        let mut ppu = sprites_test_ppu();
+        // tile 0 is used for background and window, fill it with color 3
+        for i in 0..16 {
+            ppu.write_vram(0x8000 + i, 0xFF);
+        }
+        // window starts at x=80
+        ppu.write_lcd_control(0x91 | 0x20);
+        ppu.write_window_x(87);
+        ppu.write_window_y(0);
+        ppu.write_dmg_bg_palette(0xE4);
+
+        ppu.set_layer_enabled(PpuLayer::Background, false);
+        render_first_line(&mut ppu);
+        let black = first_line_black_pixels(&ppu);
+        for (x, &is_black) in black.iter().enumerate() {
+            assert_eq!(is_black, x >= 80, "pixel {}", x);
+        }
+
+        ppu.set_layer_enabled(PpuLayer::Background, true);
+        ppu.set_layer_enabled(PpuLayer::Window, false);
+        ppu.scanline = 153;
+        ppu.cycle = 400;
+        render_first_line(&mut ppu);
+        let black = first_line_black_pixels(&ppu);
+        for (x, &is_black) in black.iter().enumerate() {
+            assert_eq!(is_black, x < 80, "pixel {}", x);
+        }
+    }
+
+    #[test]
+    fn frame_callback_once_per_frame() {
+        let mut ppu = sprites_test_ppu();
+
+        let frames = Rc::new(Cell::new(0));
+        let frames_clone = frames.clone();
+        ppu.set_frame_callback(Some(Box::new(move |buffer| {
+            assert_eq!(buffer.len(), LCD_WIDTH * LCD_HEIGHT * 3);
+            frames_clone.set(frames_clone.get() + 1);
+        })));
+
+        const PPU_CYCLES_PER_FRAME: u32 = 456 * 154;
+        for _ in 0..PPU_CYCLES_PER_FRAME * 3 / 4 {
+            ppu.clock(&mut NoInterrupts, 4);
+        }
+        assert_eq!(frames.get(), 3);
+
+        // not called when the LCD is off
+        ppu.write_lcd_control(0x13);
+        for _ in 0..PPU_CYCLES_PER_FRAME * 2 / 4 {
+            ppu.clock(&mut NoInterrupts, 4);
+        }
+        assert_eq!(frames.get(), 3);
+    }
+
+    #[test]
+    fn scanline_callback_visible_lines() {
+        let mut ppu = sprites_test_ppu();
+
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let lines_clone = lines.clone();
+        ppu.set_scanline_callback(Some(Box::new(move |line, pixels| {
+            assert_eq!(pixels.len(), LCD_WIDTH * 3);
+            lines_clone.borrow_mut().push(line);
+        })));
+
+        // start from the beginning of a frame
+        while ppu.scanline() != 144 {
+            ppu.clock(&mut NoInterrupts, 4);
+        }
+        lines.borrow_mut().clear();
+
+        const PPU_CYCLES_PER_FRAME: u32 = 456 * 154;
+        for _ in 0..PPU_CYCLES_PER_FRAME * 2 / 4 {
+            ppu.clock(&mut NoInterrupts, 4);
+        }
+        let expected = (0..144).chain(0..144).collect::<Vec<u8>>();
+        assert_eq!(*lines.borrow(), expected);
+    }
+
+    #[test]
+    fn render_whole_background() {
+        let mut ppu = sprites_test_ppu();
+        ppu.write_dmg_bg_palette(0xE4);
+        ppu.write_scroll_x(0x12);
+        ppu.write_scroll_y(0x34);
+        // tile 2 is empty, tile 1 is full of color 3
+        for i in 0..16 {
+            ppu.write_vram(0x8020 + i, 0x00);
+        }
+        // bottom right corner of the map
+        ppu.write_vram(0x9800 + 31 * 32 + 30, 2);
+        ppu.write_vram(0x9800 + 31 * 32 + 31, 1);
+
+        let render = ppu.render_background();
+        assert_eq!((render.x, render.y), (0x12, 0x34));
+        assert_eq!(render.pixels.len(), 256 * 256 * 3);
+
+        let palette = ppu.bg_palettes_rgb()[0];
+        let pixel = |x: usize, y: usize| {
+            let index = (y * 256 + x) * 3;
+            let p = &render.pixels[index..index + 3];
+            (p[0], p[1], p[2])
+        };
+        for y in 248..256 {
+            for x in 240..248 {
+                assert_eq!(pixel(x, y), palette[0]);
+            }
+            for x in 248..256 {
+                assert_eq!(pixel(x, y), palette[3]);
+            }
+        }
+
+        // the window uses the other map
+        ppu.write_lcd_control(0x93 | 0x40);
+        ppu.write_window_x(0x56);
+        ppu.write_window_y(0x78);
+        ppu.write_vram(0x9C00, 1);
+        ppu.write_vram(0x9C00 + 31 * 32 + 31, 2);
+        let window = ppu.render_window();
+        assert_eq!((window.x, window.y), (0x56, 0x78));
+        assert_eq!(&window.pixels[..3], &render.pixels[(256 * 256 - 1) * 3..]);
+        assert_eq!(
+            &window.pixels[(256 * 256 - 1) * 3..],
+            &render.pixels[(256 * 248 + 240) * 3..][..3]
+        );
+        // the background is not affected by the window map
+        assert_eq!(ppu.render_background(), render);
+    }
+
+    #[test]
+    fn custom_dmg_palette() {
+        let green_config = GameBoyConfig {
+            is_dmg: true,
+            dmg_palette: [
+                Color::from_rgb(0xE0, 0xF8, 0xD0),
+                Color::from_rgb(0x88, 0xC0, 0x70),
+                Color::from_rgb(0x34, 0x68, 0x56),
+                Color::from_rgb(0x08, 0x18, 0x20),
+            ],
+            ..GameBoyConfig::default()
+        };
+        let mut ppu = Ppu::new_skip_boot_rom(false, green_config);
+        render_first_line(&mut ppu);
+        // background is shade 0 (lightest)
+        assert_eq!(
+            &ppu.raw_screen_buffer()[..3],
+            &[0xE0 >> 3, 0xF8 >> 3, 0xD0 >> 3]
+        );
+
+        // loading the state restores the palette
+        let data = save_state::save_object(&ppu).unwrap();
+        let default_config = GameBoyConfig {
+            is_dmg: true,
+            ..GameBoyConfig::default()
+        };
+        let mut ppu = Ppu::new_skip_boot_rom(false, default_config);
+        save_state::load_object(&mut ppu, &data).unwrap();
+        assert_eq!(ppu.config.dmg_palette, green_config.dmg_palette);
+
+        ppu.scanline = 153;
+        ppu.cycle = 400;
+        render_first_line(&mut ppu);
+        assert_eq!(
+            &ppu.raw_screen_buffer()[..3],
+            &[0xE0 >> 3, 0xF8 >> 3, 0xD0 >> 3]
+        );
+    }
+
+    #[test]
+    fn raw_vram_and_oam() {
+        let mut ppu = Ppu::new_skip_boot_rom(true, GameBoyConfig::default());
+        ppu.write_vram(0x8000, 0x12);
+        ppu.write_vram_bank(1);
+        ppu.write_vram(0x9FFF, 0x34);
+        set_sprite(&mut ppu, 39, 0x56, 0x78, 0x9A);
+
+        assert_eq!(ppu.vram(0).len(), 0x2000);
+        assert_eq!(ppu.vram(0)[0], 0x12);
+        assert_eq!(ppu.vram(1)[0x1FFF], 0x34);
+        assert_eq!(ppu.oam().len(), 0xA0);
+        assert_eq!(ppu.oam()[0x9C..], [0x56, 0x78, 0x9A, 0]);
+    }
+
+    #[test]
+    fn vram_dirty_tiles() {
+        let mut ppu = Ppu::new_skip_boot_rom(true, GameBoyConfig::default());
+        ppu.take_vram_dirty_tiles();
+
+        ppu.write_vram(0x8000, 0x12);
+        ppu.write_vram(0x800F, 0x12);
+        ppu.write_vram(0x97F0, 0x12);
+        // tile maps are not tracked
+        ppu.write_vram(0x9800, 0x12);
+        ppu.write_vram_bank(1);
+        ppu.write_vram(0x8010, 0x34);
+        ppu.write_vram(0x9FFF, 0x34);
+
+        assert_eq!(
+            ppu.take_vram_dirty_tiles(),
+            [0, 383, VRAM_TILES_PER_BANK as u16 + 1]
+        );
+        assert!(ppu.take_vram_dirty_tiles().is_empty());
+    }
+
+    #[derive(Default)]
+    struct StatInterrupts {
+        stat: bool,
+    }
+
+    impl InterruptManager for StatInterrupts {
+        fn request_interrupt(&mut self, interrupt: InterruptType) {
+            if interrupt == InterruptType::LcdStat {
+                self.stat = true;
+            }
+        }
+    }
+
+    /// returns the dot of scanline 1 where the mode 2 STAT interrupt triggers
+    fn mode_2_interrupt_dot(is_dmg: bool, stat_quirks: bool) -> u16 {
+        let config = GameBoyConfig {
+            is_dmg,
+            stat_quirks,
+            ..GameBoyConfig::default()
+        };
+        let mut ppu = Ppu::new_skip_boot_rom(!is_dmg, config);
+        ppu.write_lcd_status(0x20);
+        while ppu.scanline != 1 {
+            ppu.clock(&mut NoInterrupts, 4);
+        }
+
+        let mut interrupts = StatInterrupts::default();
+        loop {
+            let dot = ppu.cycle;
+            ppu.clock(&mut interrupts, 4);
+            if interrupts.stat {
+                return dot;
+            }
+        }
+    }
+
+    #[test]
+    fn stat_mode_2_interrupt_timing() {
+        assert_eq!(mode_2_interrupt_dot(true, true), 4);
+        assert_eq!(mode_2_interrupt_dot(false, true), 0);
+        assert_eq!(mode_2_interrupt_dot(true, false), 0);
+        assert_eq!(mode_2_interrupt_dot(false, false), 0);
+    }
+
+    /// returns the dot of scanline 1 where the mode 0 STAT interrupt
+    /// triggers, and the dot where mode 3 ended on that line
+    fn mode_0_interrupt_dot(stat_quirks: bool, scroll_x: u8) -> (u16, u16) {
+        let config = GameBoyConfig {
+            is_dmg: true,
+            stat_quirks,
+            ..GameBoyConfig::default()
+        };
+        let mut ppu = Ppu::new_skip_boot_rom(false, config);
+        ppu.write_scroll_x(scroll_x);
+        ppu.write_lcd_status(0x08);
+        while ppu.scanline != 1 {
+            ppu.clock(&mut NoInterrupts, 4);
+        }
+
+        let mut interrupts = StatInterrupts::default();
+        loop {
+            let dot = ppu.cycle;
+            ppu.clock(&mut interrupts, 4);
+            if interrupts.stat {
+                return (dot, ppu.mode_3_end_cycle);
+            }
+        }
+    }
+
+    #[test]
+    fn stat_mode_0_interrupt_timing() {
+        for stat_quirks in [true, false] {
+            // the mode 0 source is high for the whole mode, so the interrupt
+            // triggers on the first clock after mode 3 ends
+            assert_eq!(mode_0_interrupt_dot(stat_quirks, 0), (256, 252));
+            // the discarded fine scroll pixels extend mode 3
+            assert_eq!(mode_0_interrupt_dot(stat_quirks, 4), (260, 256));
+        }
+    }
+
+    #[test]
+    fn window_quirk_report() {
+        let mut ppu = sprites_test_ppu();
+        ppu.write_lcd_control(0xB3);
+        ppu.write_window_y(0);
+        ppu.write_window_x(50);
+        while ppu.lcd_status.current_mode() != 3 {
+            ppu.clock(&mut NoInterrupts, 1);
+        }
+
+        let mut triggered = false;
+        while ppu.lcd_status.current_mode() == 3 {
+            let report = ppu.quirk_report();
+            let x = ppu.lcd.x();
+            ppu.clock(&mut NoInterrupts, 1);
+            if report.window_trigger {
+                assert!(!report.drawing_window);
+                assert_eq!(x, 50 - 7);
+                triggered = true;
+
+                let report = ppu.quirk_report();
+                assert!(report.drawing_window);
+                assert!(!report.window_trigger);
+                assert_eq!(report.fine_scroll_x_discard, 0);
+            }
+        }
+        assert!(triggered);
+        assert!(!ppu.quirk_report().drawing_window);
+
+        // `WX < 7`, the window starts at the first dot and the pixels
+        // outside the screen are discarded
+        let mut ppu = sprites_test_ppu();
+        ppu.write_lcd_control(0xB3);
+        ppu.write_window_y(0);
+        ppu.write_window_x(3);
+        while ppu.lcd_status.current_mode() != 3 {
+            ppu.clock(&mut NoInterrupts, 1);
+        }
+        let report = ppu.quirk_report();
+        assert!(report.drawing_window);
+        assert!(!report.window_trigger);
+        assert_eq!(report.fine_scroll_x_discard, 4);
+    }
+
+    #[test]
+    fn pipeline_state_during_mode_3() {
+        let mut ppu = sprites_test_ppu();
+        ppu.write_scroll_x(3);
+        while ppu.lcd_status.current_mode() != 3 {
+            ppu.clock(&mut NoInterrupts, 4);
+        }
+
+        let state = ppu.pipeline_state();
+        assert_eq!(state.fine_scroll_x_discard, 3);
+        assert_eq!(state.fetcher_x, 0);
+        assert!(state.bg_fifo.is_empty());
+
+        let mut max_fetcher_x = 0;
+        while ppu.lcd_status.current_mode() == 3 {
+            ppu.clock(&mut NoInterrupts, 4);
+            let state = ppu.pipeline_state();
+            assert!(state.bg_fifo.len() <= 16);
+            assert!(state.sprite_fifo.len() <= 8);
+            max_fetcher_x = max_fetcher_x.max(state.fetcher_x);
+        }
+        // 160 pixels + the discarded fine scroll
+        assert!(max_fetcher_x >= 20);
+        assert_eq!(ppu.pipeline_state().fine_scroll_x_discard, 0);
+    }
+
+    #[test]
+    fn corrupt_fifo_size_fails_to_load() {
+        use super::fifo::BgFifo;
+
+        let data = save_state::save_object(&17usize).unwrap();
+        assert!(matches!(
+            save_state::load_object(&mut BgFifo::default(), &data),
+            Err(save_state::Error::CorruptField("BgFifo::pixels"))
+        ));
+    }
+
+    #[test]
+    fn frame_ready_until_taken() {
+        let mut gb = test_gameboy(&[0x18, 0xFE]);
+        assert!(!gb.frame_ready());
+
+        gb.clock_for_frame();
+        assert!(gb.frame_ready());
+        // reading the screen does not clear it
+        gb.screen_buffer();
+        gb.screen_diff(&[0xFF; 160 * 144 * 3], &mut Vec::new());
+        assert!(gb.frame_ready());
+        assert!(gb.take_frame_ready());
+        assert!(!gb.frame_ready());
+        assert!(!gb.take_frame_ready());
+
+        // polling mid frame, only ready when the next frame is complete
+        for _ in 0..100 {
+            gb.clock_for_scanline();
+            assert!(!gb.frame_ready());
+        }
+        while !gb.frame_ready() {
+            gb.clock_for_scanline();
+        }
+        assert!(gb.current_scanline() >= 144);
+        assert!(gb.take_frame_ready());
+    }
+
+    #[test]
+    fn screen_diff() {
+        let gb = test_gameboy(&[0x18, 0xFE]);
+
+        let mut diff = Vec::new();
+        let current = gb.screen_buffer().to_vec();
+        gb.screen_diff(&current, &mut diff);
+        assert!(diff.is_empty());
+
+        let mut prev = current.clone();
+        prev[0] ^= 0xFF;
+        prev[(160 * 10 + 5) * 3 + 2] ^= 0xFF;
+        gb.screen_diff(&prev, &mut diff);
+        assert_eq!(
+            diff,
+            vec![
+                (0, [current[0], current[1], current[2]]),
+                (
+                    160 * 10 + 5,
+                    current[(160 * 10 + 5) * 3..][..3].try_into().unwrap()
+                ),
+            ]
+        );
+
+        // applying the diff results in the current screen
+        for &(i, rgb) in &diff {
+            prev[i as usize * 3..][..3].copy_from_slice(&rgb);
+        }
+        assert_eq!(prev, current);
+    }
+
+    #[test]
+    fn lcd_on_and_ppu_mode() {
+        let mut gb = test_gameboy(&[0x18, 0xFE]);
+        assert!(gb.is_lcd_on());
+
+        let mut modes_seen = [false; 4];
+        for _ in 0..70224 / 12 {
+            let mode = gb.ppu_mode();
+            assert_eq!(mode, gb.bus.read_not_ticked(0xFF41, None) & 3);
+            modes_seen[mode as usize] = true;
+            gb.cpu.next_instruction(&mut gb.bus);
+        }
+        assert_eq!(modes_seen, [true; 4]);
+
+        // `XOR A`, `LDH (LCDC), A`, `JR -2`
+        let mut gb = test_cgb_gameboy(&[0xAF, 0xE0, 0x40, 0x18, 0xFE]);
+        gb.clock_for_frame();
+        assert!(!gb.is_lcd_on());
+        assert_eq!(gb.ppu_mode(), 0);
     }
 }
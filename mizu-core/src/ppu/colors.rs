@@ -10,7 +10,21 @@ macro_rules! color {
     };
 }
 
-#[derive(Clone, Copy)]
+/// The color correction applied when converting the 5 bits per channel
+/// colors of the PPU to the 8 bits RGB screen buffer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ColorCorrection {
+    /// The colors are scaled linearly, which looks oversaturated compared
+    /// to the real hardware
+    None,
+    /// Mixes the color channels to emulate the washed-out colors of the CGB LCD
+    #[default]
+    CgbLcd,
+}
+
+/// A color of the PPU, with 5 bits per channel (`0-31`) like the CGB
+/// palettes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Savable)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -18,7 +32,15 @@ pub struct Color {
 }
 
 impl Color {
-    pub fn from_raw(mut color: u16) -> Self {
+    /// Creates a color from 8 bits per channel RGB, the lower 3 bits of
+    /// each channel are dropped.
+    pub fn from_rgb(r: u8, g: u8, b: u8) -> Self {
+        color!(r >> 3, g >> 3, b >> 3)
+    }
+
+    /// Creates a color from the `RGB555` format of the CGB palettes, red
+    /// in the lowest bits
+    pub fn from_rgb555(mut color: u16) -> Self {
         let r = (color & 0x1F) as u8;
         color >>= 5;
         let g = (color & 0x1F) as u8;
@@ -28,7 +50,34 @@ impl Color {
         Self { r, g, b }
     }
 
-    pub fn to_raw(self) -> u16 {
+    /// Converts the color to 8 bits per channel RGB, scaled linearly
+    /// without color correction
+    pub fn rgb(&self) -> (u8, u8, u8) {
+        let [r, g, b] = self.to_rgb(ColorCorrection::None);
+        (r, g, b)
+    }
+
+    /// Converts the 5 bits per channel color to 8 bits RGB
+    #[allow(clippy::many_single_char_names)]
+    pub fn to_rgb(self, correction: ColorCorrection) -> [u8; 3] {
+        let r = (self.r & 0x1F) as u16;
+        let g = (self.g & 0x1F) as u16;
+        let b = (self.b & 0x1F) as u16;
+
+        match correction {
+            ColorCorrection::None => [r, g, b].map(|c| ((c << 3) | (c >> 2)) as u8),
+            ColorCorrection::CgbLcd => {
+                let rr = r * 26 + g * 4 + b * 2;
+                let gg = g * 24 + b * 8;
+                let bb = r * 6 + g * 4 + b * 22;
+
+                [rr, gg, bb].map(|c| (c.min(960) >> 2) as u8)
+            }
+        }
+    }
+
+    /// Converts the color to the `RGB555` format of the CGB palettes
+    pub fn rgb555(&self) -> u16 {
         let r = (self.r & 0x1F) as u16;
         let g = (self.g & 0x1F) as u16;
         let b = (self.b & 0x1F) as u16;
@@ -46,14 +95,14 @@ impl ColorPalette {
     pub fn new(colors: [Color; 4]) -> Self {
         let mut raw_colors = [0; 4];
         for (i, color) in colors.iter().enumerate() {
-            raw_colors[i] = color.to_raw();
+            raw_colors[i] = color.rgb555();
         }
         Self { data: raw_colors }
     }
 
     pub fn get_color(&self, color_index: u8) -> Color {
         let color = self.data[color_index as usize & 3];
-        Color::from_raw(color)
+        Color::from_rgb555(color)
     }
 }
 
@@ -132,3 +181,21 @@ impl ColorPalettesCollection {
         self.palettes[index as usize & 7] = palette;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Color;
+
+    #[test]
+    fn conversions() {
+        let color = Color::from_rgb555(0x7C1F);
+        assert_eq!(color, Color { r: 31, g: 0, b: 31 });
+        assert_eq!(color.rgb555(), 0x7C1F);
+        assert_eq!(color.rgb(), (0xFF, 0x00, 0xFF));
+
+        let color = Color::from_rgb(0x80, 0x40, 0x08);
+        assert_eq!(color, Color { r: 16, g: 8, b: 1 });
+        assert_eq!(color.rgb555(), 16 | (8 << 5) | (1 << 10));
+        assert_eq!(color.rgb(), (0x84, 0x42, 0x08));
+    }
+}
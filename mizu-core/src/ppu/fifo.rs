@@ -15,6 +15,9 @@ pub enum SpritePriorityMode {
 pub struct BgFifoPixel {
     pub color: u8,
     pub palette: ColorPalette,
+    /// The number of `palette`, used for the indexed screen buffer only
+    #[savable(skip)]
+    pub palette_index: u8,
     pub bg_priority: bool,
 }
 
@@ -24,6 +27,9 @@ pub struct BgFifoPixel {
 pub struct SpriteFifoPixel {
     pub color: u8,
     pub palette: ColorPalette,
+    /// The number of `palette`, used for the indexed screen buffer only
+    #[savable(skip)]
+    pub palette_index: u8,
     pub dmg_palette: u8,
     pub index: u8,
     pub oam_bg_priority: bool,
@@ -46,11 +52,18 @@ impl BgFifo {
         *self.pixels.pop_front().unwrap()
     }
 
-    pub fn push(&mut self, colors: [u8; 8], palette: ColorPalette, bg_priority: bool) {
+    pub fn push(
+        &mut self,
+        colors: [u8; 8],
+        palette: ColorPalette,
+        palette_index: u8,
+        bg_priority: bool,
+    ) {
         for &color in colors.iter() {
             *self.pixels.push_back() = BgFifoPixel {
                 color,
                 palette,
+                palette_index,
                 bg_priority,
             };
         }
@@ -63,6 +76,11 @@ impl BgFifo {
     pub fn clear(&mut self) {
         self.pixels.clear();
     }
+
+    /// The color numbers of the pixels, from the next pixel to be popped
+    pub fn colors(&self) -> Vec<u8> {
+        self.pixels.iter().map(|pixel| pixel.color).collect()
+    }
 }
 
 impl Savable for BgFifo {
@@ -79,7 +97,9 @@ impl Savable for BgFifo {
     fn load<R: std::io::Read>(&mut self, mut reader: &mut R) -> save_state::Result<()> {
         let mut size = 0;
         <usize as Savable>::load(&mut size, &mut reader)?;
-        assert!(size <= 16);
+        if size > 16 {
+            return Err(save_state::Error::CorruptField("BgFifo::pixels"));
+        }
 
         self.pixels.clear();
 
@@ -113,7 +133,13 @@ impl SpriteFifo {
         self.pixels.pop_front().map(|x| *x)
     }
 
-    pub fn push(&mut self, colors: [u8; 8], sprite: &SelectedSprite, palette: ColorPalette) {
+    pub fn push(
+        &mut self,
+        colors: [u8; 8],
+        sprite: &SelectedSprite,
+        palette: ColorPalette,
+        palette_index: u8,
+    ) {
         let dmg_palette = sprite.sprite().dmg_palette();
         let index = sprite.index();
         let oam_bg_priority = sprite.sprite().bg_priority();
@@ -126,6 +152,7 @@ impl SpriteFifo {
             let new_sprite_pixel = SpriteFifoPixel {
                 color: new_color,
                 palette,
+                palette_index,
                 dmg_palette,
                 index,
                 oam_bg_priority,
@@ -157,6 +184,11 @@ impl SpriteFifo {
     pub fn clear(&mut self) {
         self.pixels.clear();
     }
+
+    /// The color numbers of the pixels, from the next pixel to be popped
+    pub fn colors(&self) -> Vec<u8> {
+        self.pixels.iter().map(|pixel| pixel.color).collect()
+    }
 }
 
 impl Savable for SpriteFifo {
@@ -176,7 +208,9 @@ impl Savable for SpriteFifo {
         let mut size = 0;
         <usize as Savable>::load(&mut size, &mut reader)?;
 
-        assert!(size <= 8);
+        if size > 8 {
+            return Err(save_state::Error::CorruptField("SpriteFifo::pixels"));
+        }
 
         self.pixels.clear();
 
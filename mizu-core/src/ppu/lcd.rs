@@ -1,9 +1,26 @@
-use super::colors::Color;
+use super::colors::{Color, ColorCorrection};
 use save_state::Savable;
 
 pub const LCD_WIDTH: usize = 160;
 pub const LCD_HEIGHT: usize = 144;
 
+/// Emulates the slow response of the LCD pixels, each pixel decays
+/// from its old value toward the new one
+struct Ghosting {
+    /// How much of the old pixel value is kept every frame, in `0.0..=1.0`
+    decay: f32,
+    /// The current value of each pixel channel
+    accumulator: Box<[f32]>,
+}
+
+/// Blends every frame with the previous rendered frame
+struct FrameBlend {
+    /// How much of the previous frame is used, in `0.0..=1.0`
+    weight: f32,
+    /// The previous frame before blending
+    previous: Box<[u8]>,
+}
+
 #[derive(Savable)]
 pub struct Lcd {
     // x is the only attribute that should be saved, just to be in sync
@@ -14,8 +31,29 @@ pub struct Lcd {
     buf: Box<[[u8; LCD_WIDTH * LCD_HEIGHT * 3]; 2]>,
     #[savable(skip)]
     selected_buffer: usize,
+    /// The color index (before the palette) of each pixel
+    #[savable(skip)]
+    index_buf: Box<[[u8; LCD_WIDTH * LCD_HEIGHT]; 2]>,
+    /// The palette used by each pixel
+    #[savable(skip)]
+    palette_buf: Box<[[u8; LCD_WIDTH * LCD_HEIGHT]; 2]>,
     #[savable(skip)]
     raw_buf: Box<[u8; LCD_WIDTH * LCD_HEIGHT * 3]>,
+    #[savable(skip)]
+    frame_blend: Option<FrameBlend>,
+    #[savable(skip)]
+    ghosting: Option<Ghosting>,
+    #[savable(skip)]
+    color_correction: ColorCorrection,
+    /// If `false`, only the position is tracked and the buffers are not updated
+    #[savable(skip)]
+    render: bool,
+    /// A pixel pushed in the frame being drawn differs from the shown frame
+    #[savable(skip)]
+    pixels_changed: bool,
+    /// The shown frame differs from the one shown before it
+    #[savable(skip)]
+    frame_changed: bool,
 }
 
 impl Default for Lcd {
@@ -24,32 +62,52 @@ impl Default for Lcd {
             x: 0,
             buf: Box::new([[0xFF; LCD_WIDTH * LCD_HEIGHT * 3]; 2]),
             selected_buffer: 0,
+            index_buf: Box::new([[0; LCD_WIDTH * LCD_HEIGHT]; 2]),
+            palette_buf: Box::new([[0; LCD_WIDTH * LCD_HEIGHT]; 2]),
             raw_buf: Box::new([0x1F; LCD_WIDTH * LCD_HEIGHT * 3]),
+            frame_blend: None,
+            ghosting: None,
+            color_correction: ColorCorrection::default(),
+            render: true,
+            pixels_changed: false,
+            frame_changed: false,
         }
     }
 }
 
 impl Lcd {
-    #[allow(clippy::many_single_char_names, clippy::identity_op)]
-    pub fn push(&mut self, color: Color, y: u8) {
-        let index = (y as usize * LCD_WIDTH + self.x as usize) * 3;
+    pub fn new(color_correction: ColorCorrection, render: bool) -> Self {
+        Self {
+            color_correction,
+            render,
+            ..Self::default()
+        }
+    }
 
-        let r = color.r as u16;
-        let g = color.g as u16;
-        let b = color.b as u16;
+    pub fn push(&mut self, color: Color, y: u8) {
+        self.push_indexed(color, 0, 0, y);
+    }
 
-        let rr = r * 26 + g * 4 + b * 2;
-        let gg = g * 24 + b * 8;
-        let bb = r * 6 + g * 4 + b * 22;
+    /// Pushes a pixel with its `color_index` before applying the palette,
+    /// and the `palette` it used
+    #[allow(clippy::identity_op)]
+    pub fn push_indexed(&mut self, color: Color, color_index: u8, palette: u8, y: u8) {
+        if !self.render {
+            self.x += 1;
+            return;
+        }
 
-        let rr = rr.min(960) >> 2;
-        let gg = gg.min(960) >> 2;
-        let bb = bb.min(960) >> 2;
+        let pixel = y as usize * LCD_WIDTH + self.x as usize;
+        let index = pixel * 3;
 
         let i = self.next_buffer_index();
-        self.buf[i][index + 0] = rr as u8;
-        self.buf[i][index + 1] = gg as u8;
-        self.buf[i][index + 2] = bb as u8;
+        let rgb = color.to_rgb(self.color_correction);
+        if !self.pixels_changed && self.buf[self.selected_buffer][index..index + 3] != rgb {
+            self.pixels_changed = true;
+        }
+        self.buf[i][index..index + 3].copy_from_slice(&rgb);
+        self.index_buf[i][pixel] = color_index;
+        self.palette_buf[i][pixel] = palette;
 
         // used for testing
         self.raw_buf[index + 0] = color.r & 0x1F;
@@ -68,13 +126,146 @@ impl Lcd {
     }
 
     pub fn switch_buffers(&mut self) {
+        if !self.render {
+            return;
+        }
+
+        let previous_buffer = self.selected_buffer;
         self.selected_buffer = self.next_buffer_index();
+        self.frame_changed = std::mem::take(&mut self.pixels_changed);
+
+        if let Some(frame_blend) = self.frame_blend.as_mut() {
+            let weight = frame_blend.weight;
+            for (pixel, previous) in self.buf[self.selected_buffer]
+                .iter_mut()
+                .zip(frame_blend.previous.iter_mut())
+            {
+                let current = *pixel;
+                *pixel = (current as f32 * (1. - weight) + *previous as f32 * weight).round() as u8;
+                *previous = current;
+            }
+        }
+
+        if let Some(ghosting) = self.ghosting.as_mut() {
+            let decay = ghosting.decay;
+            for (pixel, acc) in self.buf[self.selected_buffer]
+                .iter_mut()
+                .zip(ghosting.accumulator.iter_mut())
+            {
+                *acc = *acc * decay + *pixel as f32 * (1. - decay);
+                *pixel = acc.round() as u8;
+            }
+        }
+
+        // the output is modified after pushing, so compare the final result
+        if self.frame_blend.is_some() || self.ghosting.is_some() {
+            self.frame_changed = self.buf[self.selected_buffer] != self.buf[previous_buffer];
+        }
+    }
+
+    /// The shown frame differs from the frame shown before it
+    pub fn frame_changed(&self) -> bool {
+        self.frame_changed
+    }
+
+    /// Enables LCD ghosting with `decay` being how much of the previous
+    /// value of each pixel is kept every frame (clamped to `0.0..=1.0`),
+    /// or disable it with `None`.
+    pub fn set_ghosting_decay(&mut self, decay: Option<f32>) {
+        self.ghosting = decay.map(|decay| {
+            let accumulator = match self.ghosting.take() {
+                Some(ghosting) => ghosting.accumulator,
+                // start from the current frame
+                None => self.buf[self.selected_buffer]
+                    .iter()
+                    .map(|&pixel| pixel as f32)
+                    .collect(),
+            };
+
+            Ghosting {
+                decay: decay.clamp(0., 1.),
+                accumulator,
+            }
+        });
+    }
+
+    /// Enables blending every frame with the previous rendered frame, with
+    /// `weight` being how much of the previous frame is used (clamped to
+    /// `0.0..=1.0`), or disable it with `None`.
+    ///
+    /// Unlike ghosting, only the last frame is used, so a pixel that
+    /// alternates every frame is shown as a constant blend of the two values.
+    pub fn set_frame_blend(&mut self, weight: Option<f32>) {
+        self.frame_blend = weight.map(|weight| {
+            let previous = match self.frame_blend.take() {
+                Some(frame_blend) => frame_blend.previous,
+                // start from the current frame
+                None => self.buf[self.selected_buffer].to_vec().into_boxed_slice(),
+            };
+
+            FrameBlend {
+                weight: weight.clamp(0., 1.),
+                previous,
+            }
+        });
     }
 
     pub fn screen_buffer(&self) -> &[u8] {
         &self.buf[self.selected_buffer]
     }
 
+    /// The RGB pixels of line `y` of the frame being drawn, before
+    /// frame blending and ghosting are applied
+    pub fn drawn_line(&self, y: u8) -> &[u8] {
+        let start = y as usize * LCD_WIDTH * 3;
+        &self.buf[self.next_buffer_index()][start..start + LCD_WIDTH * 3]
+    }
+
+    pub fn screen_buffer_indices(&self) -> &[u8] {
+        &self.index_buf[self.selected_buffer]
+    }
+
+    pub fn screen_buffer_palettes(&self) -> &[u8] {
+        &self.palette_buf[self.selected_buffer]
+    }
+
+    /// Writes the screen buffer as RGBA into `out`, with alpha set to `0xFF`
+    pub fn screen_buffer_rgba(&self, out: &mut [u8]) {
+        assert_eq!(
+            out.len(),
+            LCD_WIDTH * LCD_HEIGHT * 4,
+            "RGBA buffer must be of size 160*144*4"
+        );
+
+        for (dest, src) in out
+            .chunks_exact_mut(4)
+            .zip(self.screen_buffer().chunks_exact(3))
+        {
+            dest[..3].copy_from_slice(src);
+            dest[3] = 0xFF;
+        }
+    }
+
+    /// Writes the screen buffer as RGB565 into `out`, `big_endian` selects
+    /// the byte order of every pixel in memory
+    pub fn screen_buffer_rgb565(&self, out: &mut [u16], big_endian: bool) {
+        assert_eq!(
+            out.len(),
+            LCD_WIDTH * LCD_HEIGHT,
+            "RGB565 buffer must be of size 160*144"
+        );
+
+        for (dest, src) in out.iter_mut().zip(self.screen_buffer().chunks_exact(3)) {
+            let pixel =
+                ((src[0] as u16 >> 3) << 11) | ((src[1] as u16 >> 2) << 5) | (src[2] as u16 >> 3);
+            *dest = if big_endian {
+                pixel.to_be()
+            } else {
+                pixel.to_le()
+            };
+        }
+    }
+
     #[cfg(test)]
     pub fn raw_screen_buffer(&self) -> &[u8] {
         self.raw_buf.as_ref()
@@ -88,6 +279,10 @@ impl Lcd {
                 *raw_byte = 0x1F;
             }
         }
+        for buf in self.index_buf.iter_mut().chain(self.palette_buf.iter_mut()) {
+            buf.fill(0);
+        }
+        self.frame_changed = true;
     }
 
     pub fn fill(&mut self, color: Color) {
@@ -106,6 +301,7 @@ impl Lcd {
 
         self.x = saved_x;
         self.selected_buffer = saved_selected_buffer;
+        self.frame_changed = true;
     }
 }
 
@@ -114,3 +310,218 @@ impl Lcd {
         self.selected_buffer ^ 1
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Lcd, LCD_HEIGHT, LCD_WIDTH};
+    use crate::ppu::colors::{Color, ColorCorrection};
+
+    /// renders a checkerboard of white and black pixels, `inverted` swaps them
+    fn render_checkerboard(lcd: &mut Lcd, inverted: bool) {
+        for y in 0..LCD_HEIGHT {
+            for x in 0..LCD_WIDTH {
+                let white = ((x + y) % 2 == 0) ^ inverted;
+                let c = if white { 31 } else { 0 };
+                lcd.push(color!(c, c, c), y as u8);
+            }
+            lcd.next_line();
+        }
+        lcd.switch_buffers();
+    }
+
+    #[test]
+    fn rgba_screen_buffer() {
+        let mut lcd = Lcd::default();
+        render_checkerboard(&mut lcd, false);
+
+        let mut rgba = vec![0; LCD_WIDTH * LCD_HEIGHT * 4];
+        lcd.screen_buffer_rgba(&mut rgba);
+
+        for (rgba, rgb) in rgba.chunks(4).zip(lcd.screen_buffer().chunks(3)) {
+            assert_eq!(&rgba[..3], rgb);
+            assert_eq!(rgba[3], 0xFF);
+        }
+    }
+
+    #[test]
+    fn rgb565_screen_buffer() {
+        let mut lcd = Lcd::new(ColorCorrection::None, true);
+        lcd.push(color!(31, 0, 0), 0);
+        lcd.push(color!(0, 31, 0), 0);
+        lcd.push(color!(0, 0, 31), 0);
+        lcd.push(color!(31, 31, 31), 0);
+        lcd.push(color!(16, 8, 4), 0);
+        lcd.switch_buffers();
+
+        let mut out = vec![0; LCD_WIDTH * LCD_HEIGHT];
+        lcd.screen_buffer_rgb565(&mut out, false);
+        let rgb = lcd.screen_buffer();
+        let expected =
+            (rgb[12] as u16 >> 3) << 11 | (rgb[13] as u16 >> 2) << 5 | rgb[14] as u16 >> 3;
+        assert_eq!(
+            out[..5]
+                .iter()
+                .map(|&p| u16::from_le(p))
+                .collect::<Vec<_>>(),
+            [0xF800, 0x07E0, 0x001F, 0xFFFF, expected]
+        );
+
+        let mut out_be = vec![0; LCD_WIDTH * LCD_HEIGHT];
+        lcd.screen_buffer_rgb565(&mut out_be, true);
+        assert_eq!(u16::from_be(out_be[0]), 0xF800);
+        assert_eq!(out_be[0], out[0].swap_bytes());
+    }
+
+    #[test]
+    fn indexed_screen_buffer() {
+        let mut lcd = Lcd::default();
+        for y in 0..LCD_HEIGHT {
+            for x in 0..LCD_WIDTH {
+                lcd.push_indexed(color!(0, 0, 0), (x % 4) as u8, (y % 16) as u8, y as u8);
+            }
+            lcd.next_line();
+        }
+        // not visible until the buffers are switched
+        assert!(lcd.screen_buffer_indices().iter().all(|&i| i == 0));
+        lcd.switch_buffers();
+
+        let indices = lcd.screen_buffer_indices();
+        let palettes = lcd.screen_buffer_palettes();
+        assert_eq!(indices.len(), LCD_WIDTH * LCD_HEIGHT);
+        assert_eq!(&indices[..5], &[0, 1, 2, 3, 0]);
+        assert_eq!(palettes[LCD_WIDTH * 17], 1);
+
+        lcd.clear();
+        assert!(lcd.screen_buffer_indices().iter().all(|&i| i == 0));
+        assert!(lcd.screen_buffer_palettes().iter().all(|&p| p == 0));
+    }
+
+    #[test]
+    fn ghosting_converges() {
+        let mut lcd = Lcd::default();
+        render_checkerboard(&mut lcd, false);
+        let white = lcd.screen_buffer()[0] as f32;
+        assert_eq!(lcd.screen_buffer()[3], 0);
+
+        let decay = 0.5;
+        lcd.set_ghosting_decay(Some(decay));
+
+        // the checkerboard flickers every frame, so each pixel alternates
+        // between white and black, and converges to two values in between
+        let high = white / (1. + decay);
+        let low = white * decay / (1. + decay);
+
+        for i in 1..=20 {
+            render_checkerboard(&mut lcd, i % 2 == 1);
+
+            if i >= 10 {
+                let (first, second) = if i % 2 == 1 { (low, high) } else { (high, low) };
+                assert!((lcd.screen_buffer()[0] as f32 - first).abs() <= 1.);
+                assert!((lcd.screen_buffer()[3] as f32 - second).abs() <= 1.);
+            }
+        }
+
+        // disabling returns to the original output
+        lcd.set_ghosting_decay(None);
+        render_checkerboard(&mut lcd, false);
+        assert_eq!(lcd.screen_buffer()[0] as f32, white);
+        assert_eq!(lcd.screen_buffer()[3], 0);
+    }
+
+    #[test]
+    fn frame_blend() {
+        let mut lcd = Lcd::default();
+        render_checkerboard(&mut lcd, false);
+        let white = lcd.screen_buffer()[0];
+        let half = (white as f32 / 2.).round() as u8;
+
+        lcd.set_frame_blend(Some(0.5));
+        for i in 1..=4 {
+            render_checkerboard(&mut lcd, i % 2 == 1);
+            // flickering pixels are shown as a solid blend
+            assert_eq!(lcd.screen_buffer()[0], half);
+            assert_eq!(lcd.screen_buffer()[3], half);
+        }
+
+        // static pixels are not changed
+        render_checkerboard(&mut lcd, false);
+        render_checkerboard(&mut lcd, false);
+        assert_eq!(lcd.screen_buffer()[0], white);
+        assert_eq!(lcd.screen_buffer()[3], 0);
+
+        lcd.set_frame_blend(None);
+        render_checkerboard(&mut lcd, true);
+        assert_eq!(lcd.screen_buffer()[0], 0);
+        assert_eq!(lcd.screen_buffer()[3], white);
+    }
+
+    #[test]
+    fn frame_changed() {
+        let mut lcd = Lcd::default();
+        render_checkerboard(&mut lcd, false);
+        assert!(lcd.frame_changed());
+        render_checkerboard(&mut lcd, false);
+        assert!(!lcd.frame_changed());
+
+        // a single pixel change
+        for y in 0..LCD_HEIGHT {
+            for x in 0..LCD_WIDTH {
+                let white = (x + y) % 2 == 0 || (x, y) == (11, 20);
+                let c = if white { 31 } else { 0 };
+                lcd.push(color!(c, c, c), y as u8);
+            }
+            lcd.next_line();
+        }
+        lcd.switch_buffers();
+        assert!(lcd.frame_changed());
+        render_checkerboard(&mut lcd, false);
+        assert!(lcd.frame_changed());
+        render_checkerboard(&mut lcd, false);
+        assert!(!lcd.frame_changed());
+
+        // the ghosting output changes until it converges
+        lcd.set_ghosting_decay(Some(0.5));
+        render_checkerboard(&mut lcd, true);
+        assert!(lcd.frame_changed());
+        for _ in 0..20 {
+            render_checkerboard(&mut lcd, true);
+        }
+        assert!(!lcd.frame_changed());
+    }
+
+    #[test]
+    fn color_correction() {
+        let mut raw = Lcd::new(ColorCorrection::None, true);
+        let mut corrected = Lcd::new(ColorCorrection::CgbLcd, true);
+        for lcd in [&mut raw, &mut corrected] {
+            lcd.push(color!(31, 0, 0), 0);
+            lcd.push(color!(31, 31, 31), 0);
+            lcd.push(color!(0, 0, 0), 0);
+            lcd.switch_buffers();
+        }
+
+        assert_eq!(
+            &raw.screen_buffer()[..9],
+            &[255, 0, 0, 255, 255, 255, 0, 0, 0]
+        );
+        // the red is mixed into the other channels
+        assert_eq!(&corrected.screen_buffer()[..3], &[201, 0, 46]);
+        assert_eq!(&corrected.screen_buffer()[3..9], &[240, 240, 240, 0, 0, 0]);
+    }
+
+    #[test]
+    fn render_disabled() {
+        let mut lcd = Lcd::new(ColorCorrection::None, false);
+        let initial = lcd.screen_buffer().to_vec();
+
+        render_checkerboard(&mut lcd, false);
+        lcd.switch_buffers();
+        render_checkerboard(&mut lcd, true);
+        lcd.switch_buffers();
+
+        // the position is still tracked, but nothing is rendered
+        lcd.push(color!(0, 0, 0), 0);
+        assert_eq!(lcd.x(), 1);
+        assert_eq!(lcd.screen_buffer(), &initial[..]);
+    }
+}
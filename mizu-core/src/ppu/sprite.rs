@@ -43,23 +43,13 @@ pub struct Sprite {
 }
 
 impl Sprite {
-    pub fn get_at_offset(&self, offset: u8) -> u8 {
-        match offset {
-            0 => self.y,
-            1 => self.x,
-            2 => self.tile,
-            3 => self.flags.bits(),
-            _ => unreachable!(),
-        }
-    }
-
-    pub fn set_at_offset(&mut self, offset: u8, data: u8) {
-        match offset {
-            0 => self.y = data,
-            1 => self.x = data,
-            2 => self.tile = data,
-            3 => self.flags = SpriteFlags::from_bits_truncate(data),
-            _ => unreachable!(),
+    /// Decodes a sprite from its 4 bytes in OAM
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            y: bytes[0],
+            x: bytes[1],
+            tile: bytes[2],
+            flags: SpriteFlags::from_bits_truncate(bytes[3]),
         }
     }
 
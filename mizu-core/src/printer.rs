@@ -58,6 +58,18 @@ impl Packet {
     }
 }
 
+/// An image printed by the [`Printer`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PrintedImage {
+    pub width: u32,
+    pub height: u32,
+    /// The pixels in RGB format. i.e. 3 bytes per pixel.
+    pub data: Vec<u8>,
+}
+
+/// Callback called with the image printed by a print job, see [`Printer::set_on_print`]
+pub type PrintCallback = Box<dyn FnMut(&PrintedImage)>;
+
 /// A custom GameBoy [`SerialDevice`] that emulates
 /// how the [GameBoy printer](https://en.wikipedia.org/wiki/Game_Boy_Printer) operated.
 ///
@@ -82,6 +94,8 @@ pub struct Printer {
     /// trying to simulate the paper that the gameboy printer used.
     image_buffer: Vec<u8>,
     image_size: (u32, u32),
+
+    on_print: Option<PrintCallback>,
 }
 
 impl Default for Printer {
@@ -100,6 +114,7 @@ impl Default for Printer {
             received_bit_counter: 0,
             image_buffer: Vec::new(),
             image_size: (0, 0),
+            on_print: None,
         }
     }
 }
@@ -122,6 +137,33 @@ impl Printer {
         self.image_buffer.clear();
         self.image_size = (0, 0);
     }
+
+    /// Takes the whole printed image so far, leaving the image buffer of the
+    /// printer empty, returns `None` if nothing was printed.
+    pub fn take_image(&mut self) -> Option<PrintedImage> {
+        let (width, height) = self.image_size;
+        if height == 0 {
+            return None;
+        }
+        self.image_size = (0, 0);
+
+        Some(PrintedImage {
+            width,
+            height,
+            data: std::mem::take(&mut self.image_buffer),
+        })
+    }
+
+    /// Sets a callback to be called when a print job completes, with the
+    /// image printed by that job only (including the margins).
+    pub fn set_on_print(&mut self, callback: PrintCallback) {
+        self.on_print = Some(callback);
+    }
+
+    /// Removes the callback set by [`Printer::set_on_print`]
+    pub fn clear_on_print(&mut self) {
+        self.on_print = None;
+    }
 }
 
 impl Printer {
@@ -228,6 +270,9 @@ impl Printer {
                         let palette = self.current_packet.data[2];
                         let exposure = self.current_packet.data[3];
 
+                        let (_, old_height) = self.image_size;
+                        let old_len = self.image_buffer.len();
+
                         self.print(
                             number_of_sheets,
                             margins,
@@ -235,6 +280,15 @@ impl Printer {
                             exposure,
                             self.ram_next_write_pointer,
                         );
+
+                        if let Some(on_print) = self.on_print.as_mut() {
+                            let (width, height) = self.image_size;
+                            on_print(&PrintedImage {
+                                width,
+                                height: height - old_height,
+                                data: self.image_buffer[old_len..].to_vec(),
+                            });
+                        }
                     }
                     self.ready_to_print_next = false;
                 }
@@ -405,36 +459,6 @@ fn compute_exposure_multiply(exposure: u8) -> f64 {
     (100 + map_num((exposure & 0x7F) as i32, 0, 0x7F, -25, 25)) as f64 / 100.
 }
 
-#[test]
-fn map_num_test() {
-    let a = map_num(5, 0, 100, 1, 11);
-    assert_eq!(a, 1);
-}
-
-#[test]
-fn compute_exposure_multiply_test() {
-    let mut min = 200f64;
-    let mut max = -200f64;
-
-    for exposure in 0..=0x7F {
-        let a = compute_exposure_multiply(exposure);
-
-        // a should always increase, the first time we are just setting the numbers
-        // so we cannot compare in the first time
-        if exposure != 0 {
-            assert!(a >= min);
-            assert!(a >= max);
-        }
-
-        min = min.min(a);
-        max = max.max(a);
-    }
-
-    // these are the possible ranges of exposure
-    assert_eq!(min, 0.75);
-    assert_eq!(max, 1.25);
-}
-
 impl SerialDevice for Printer {
     fn exchange_bit_external_clock(&mut self, bit: bool) -> bool {
         self.received_bit_counter += 1;
@@ -454,3 +478,97 @@ impl SerialDevice for Printer {
         out
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{compute_exposure_multiply, map_num, Printer};
+    use crate::serial::SerialDevice;
+
+    #[test]
+    fn map_num_test() {
+        let a = map_num(5, 0, 100, 1, 11);
+        assert_eq!(a, 1);
+    }
+
+    #[test]
+    fn compute_exposure_multiply_test() {
+        let mut min = 200f64;
+        let mut max = -200f64;
+
+        for exposure in 0..=0x7F {
+            let a = compute_exposure_multiply(exposure);
+
+            // a should always increase, the first time we are just setting the numbers
+            // so we cannot compare in the first time
+            if exposure != 0 {
+                assert!(a >= min);
+                assert!(a >= max);
+            }
+
+            min = min.min(a);
+            max = max.max(a);
+        }
+
+        // these are the possible ranges of exposure
+        assert_eq!(min, 0.75);
+        assert_eq!(max, 1.25);
+    }
+
+    fn send_packet(printer: &mut Printer, command: u8, data: &[u8]) {
+        let mut packet = vec![
+            0x88,
+            0x33,
+            command,
+            0,
+            data.len() as u8,
+            (data.len() >> 8) as u8,
+        ];
+        packet.extend_from_slice(data);
+        let checksum = packet[2..]
+            .iter()
+            .fold(0u16, |sum, &x| sum.wrapping_add(x as u16));
+        packet.extend_from_slice(&checksum.to_le_bytes());
+        // alive indicator and status
+        packet.extend_from_slice(&[0, 0]);
+
+        for byte in packet {
+            for i in (0..8).rev() {
+                printer.exchange_bit_external_clock((byte >> i) & 1 == 1);
+            }
+        }
+    }
+
+    #[test]
+    fn take_printed_image() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut printer = Printer::default();
+        let printed = Rc::new(RefCell::new(Vec::new()));
+        let printed_clone = printed.clone();
+        printer.set_on_print(Box::new(move |image| {
+            printed_clone.borrow_mut().push(image.clone())
+        }));
+
+        assert_eq!(printer.take_image(), None);
+
+        // initialize, two tile rows of black pixels, then an empty data packet
+        send_packet(&mut printer, 1, &[]);
+        send_packet(&mut printer, 4, &[0xFF; 640]);
+        send_packet(&mut printer, 4, &[]);
+        // print one sheet, with one line margin after
+        send_packet(&mut printer, 2, &[1, 0x01, 0xE4, 0x40]);
+
+        let image = printer.take_image().unwrap();
+        assert_eq!((image.width, image.height), (160, 17));
+        assert_eq!(image.data.len(), 160 * 17 * 3);
+        assert_eq!(image.data[0], 0);
+        // margin
+        assert_eq!(image.data[160 * 16 * 3], 255);
+        assert_eq!(printed.borrow().as_slice(), &[image]);
+
+        // the buffer is now empty
+        assert_eq!(printer.take_image(), None);
+        assert_eq!(printer.get_image_size(), (0, 0));
+    }
+}
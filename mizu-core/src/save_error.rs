@@ -1,3 +1,4 @@
+use crate::cartridge::SramError;
 use crate::SAVE_STATE_VERSION;
 use save_state::Error as saveStateError;
 use std::io::Error as ioError;
@@ -25,6 +26,13 @@ pub enum SaveError {
     /// cartridge.
     #[error("This save_state file is not for this cartridge")]
     InvalidCartridgeHash,
+    /// The save state version is older than the current version, but
+    /// there is no migration from version `from` to version `to`.
+    #[error("Cannot migrate save_state from version {from} to version {to}")]
+    UnsupportedMigration { from: usize, to: usize },
+    /// Writing the cartridge SRAM file failed.
+    #[error("SramError: {0}")]
+    SramError(SramError),
 }
 
 impl From<save_state::Error> for SaveError {
@@ -33,6 +41,12 @@ impl From<save_state::Error> for SaveError {
     }
 }
 
+impl From<SramError> for SaveError {
+    fn from(e: SramError) -> Self {
+        Self::SramError(e)
+    }
+}
+
 impl From<ioError> for SaveError {
     fn from(e: ioError) -> Self {
         Self::SaveStateError(saveStateError::IoError(e))
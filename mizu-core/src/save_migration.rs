@@ -0,0 +1,86 @@
+use std::io::{Cursor, Read};
+
+use save_state::Savable;
+
+use crate::GameBoyConfig;
+
+/// Transforms the components data of a save state from the version `from`
+/// to the next version.
+///
+/// Each component walks its saved fields in order, copying the fields that
+/// did not change, and inserting or removing the fields that changed
+/// between the two versions.
+pub(crate) struct StateMigration<'a> {
+    from: usize,
+    reader: Cursor<&'a [u8]>,
+    writer: Vec<u8>,
+}
+
+impl<'a> StateMigration<'a> {
+    pub fn new(from: usize, data: &'a [u8]) -> Self {
+        Self {
+            from,
+            reader: Cursor::new(data),
+            writer: Vec::with_capacity(data.len()),
+        }
+    }
+
+    /// The version of the data being migrated
+    pub fn from(&self) -> usize {
+        self.from
+    }
+
+    /// Copies a field that did not change, `value` is only used to load
+    /// the field into, and is returned with the loaded data.
+    pub fn copy<T: Savable>(&mut self, mut value: T) -> save_state::Result<T> {
+        value.load(&mut self.reader)?;
+        value.save(&mut self.writer)?;
+        Ok(value)
+    }
+
+    /// Copies `len` bytes as is
+    pub fn copy_bytes(&mut self, len: u64) -> save_state::Result<()> {
+        let start = self.writer.len();
+        self.writer.resize(start + len as usize, 0);
+        self.reader.read_exact(&mut self.writer[start..])?;
+        Ok(())
+    }
+
+    /// Copies a field that was added in version `since`, or inserts `value`
+    /// if the data is older than that.
+    pub fn copy_since<T: Savable>(&mut self, since: usize, value: T) -> save_state::Result<T> {
+        if self.from >= since {
+            self.copy(value)
+        } else {
+            self.insert(&value)?;
+            Ok(value)
+        }
+    }
+
+    /// Writes a field that does not exist in the data being migrated
+    pub fn insert<T: Savable>(&mut self, value: &T) -> save_state::Result<()> {
+        value.save(&mut self.writer)
+    }
+
+    /// Reads a field that is removed or changed in the next version, to be
+    /// written again with [`insert`](Self::insert) if needed.
+    pub fn remove<T: Savable>(&mut self, mut value: T) -> save_state::Result<T> {
+        value.load(&mut self.reader)?;
+        Ok(value)
+    }
+
+    /// Migrates a [`GameBoyConfig`] stored in one of the components
+    pub fn config(&mut self) -> save_state::Result<()> {
+        GameBoyConfig::migrate_state(self)
+    }
+
+    /// Returns the migrated data, all the data must have been migrated
+    pub fn finish(self) -> save_state::Result<Vec<u8>> {
+        let remaining_data_len = self.reader.get_ref().len() as u64 - self.reader.position();
+        if remaining_data_len > 0 {
+            return Err(save_state::Error::TrailingData(remaining_data_len));
+        }
+
+        Ok(self.writer)
+    }
+}
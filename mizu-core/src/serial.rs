@@ -1,8 +1,13 @@
 use crate::memory::{InterruptManager, InterruptType};
+use crate::save_migration::StateMigration;
 use crate::GameBoyConfig;
 use bitflags::bitflags;
 use save_state::Savable;
 
+/// Callback called with every byte sent by the GameBoy as the master
+/// (internal clock)
+pub type SerialByteCallback = Box<dyn FnMut(u8)>;
+
 /// A [`GameBoy`](crate::GameBoy) custom serial device, which can be used to communicate
 /// between the GameBoy and any other device.
 ///
@@ -12,6 +17,95 @@ pub trait SerialDevice {
     /// A device implemnts this, when receiving a call from this function will
     /// send a bit (return) and get a bit from the sender (`bit` argument)
     fn exchange_bit_external_clock(&mut self, bit: bool) -> bool;
+
+    /// Used when the GameBoy is a slave (see [`SerialRole::Slave`]), this is
+    /// called on every machine cycle with the bit the GameBoy would send.
+    ///
+    /// If the device wants to clock a bit, it should take `bit` and return
+    /// `Some` with the bit to send to the GameBoy, else, it should return `None`
+    /// and the GameBoy will not shift any data.
+    fn exchange_bit_internal_clock(&mut self, _bit: bool) -> Option<bool> {
+        None
+    }
+}
+
+/// A [`SerialDevice`] that works with whole bytes instead of bits.
+///
+/// The callback is called with every byte received from the GameBoy, and
+/// returns the byte to send back. Since the reply is only known after the
+/// whole byte is received, it is sent during the next transfer, and the
+/// first transfer receives `0xFF`.
+///
+/// This is useful to capture the output of test roms, for example:
+/// ```no_run
+/// # use std::{cell::RefCell, rc::Rc};
+/// # use mizu_core::{ByteSerialDevice, GameBoy};
+/// # let mut gameboy = GameBoy::builder("rom.gb").build().unwrap();
+/// gameboy.connect_device(Rc::new(RefCell::new(ByteSerialDevice::new(|byte| {
+///     print!("{}", byte as char);
+///     0xFF
+/// }))));
+/// ```
+pub struct ByteSerialDevice {
+    callback: Box<dyn FnMut(u8) -> u8>,
+    received: u8,
+    received_bits: u8,
+    to_send: u8,
+}
+
+impl ByteSerialDevice {
+    pub fn new<F: FnMut(u8) -> u8 + 'static>(callback: F) -> Self {
+        Self {
+            callback: Box::new(callback),
+            received: 0,
+            received_bits: 0,
+            to_send: 0xFF,
+        }
+    }
+}
+
+impl SerialDevice for ByteSerialDevice {
+    fn exchange_bit_external_clock(&mut self, bit: bool) -> bool {
+        let out = self.to_send & 0x80 != 0;
+        self.to_send = self.to_send.wrapping_shl(1);
+
+        self.received = self.received.wrapping_shl(1) | bit as u8;
+        self.received_bits += 1;
+        if self.received_bits == 8 {
+            self.received_bits = 0;
+            self.to_send = (self.callback)(self.received);
+        }
+
+        out
+    }
+}
+
+/// The role of the GameBoy in the serial link with the connected [`SerialDevice`].
+///
+/// The game selects the role for each transfer with bit 0 of `SC`
+/// (the clock source).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialRole {
+    /// The GameBoy drives the serial clock, and the device is clocked
+    /// with [`SerialDevice::exchange_bit_external_clock`] when the GameBoy
+    /// transfers using the internal clock.
+    Master,
+    /// The device drives the serial clock with
+    /// [`SerialDevice::exchange_bit_internal_clock`], and the GameBoy
+    /// shifts data when it transfers using the external clock.
+    Slave,
+}
+
+/// Counters of the data exchanged with the connected [`SerialDevice`],
+/// can be used to verify a link is actually transferring data.
+///
+/// Only bits exchanged with a connected device are counted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SerialStats {
+    pub bits_sent: u64,
+    pub bits_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
 }
 
 bitflags! {
@@ -54,9 +148,16 @@ impl SerialControl {
 pub struct Serial {
     serial_control: SerialControl,
     transfere_data: u8,
+    /// The bits shifted out in the current internal clock transfer
+    sent_data: u8,
     bits_remaining: u8,
     pub internal_timer: u8,
     config: GameBoyConfig,
+
+    #[savable(skip)]
+    stats: SerialStats,
+    #[savable(skip)]
+    byte_callback: Option<SerialByteCallback>,
 }
 
 impl Serial {
@@ -64,12 +165,29 @@ impl Serial {
         Self {
             serial_control: SerialControl::from_bits_truncate(0),
             transfere_data: 0,
+            sent_data: 0,
             bits_remaining: 0,
             internal_timer: 2,
             config,
+
+            stats: SerialStats::default(),
+            byte_callback: None,
         }
     }
 
+    /// Migrates the saved state of the serial to the next version, see
+    /// [`StateMigration`]
+    pub fn migrate_state(migration: &mut StateMigration) -> save_state::Result<()> {
+        let serial = Self::new(GameBoyConfig::default());
+
+        migration.copy(serial.serial_control)?;
+        migration.copy(serial.transfere_data)?;
+        migration.copy_since(3, serial.sent_data)?;
+        migration.copy(serial.bits_remaining)?;
+        migration.copy(serial.internal_timer)?;
+        migration.config()
+    }
+
     pub fn new_skip_boot_rom(config: GameBoyConfig) -> Self {
         Self {
             // FIXME: the internal_timer is not constant for CGB games
@@ -105,6 +223,21 @@ impl Serial {
         }
     }
 
+    /// The frequency of the internal serial clock in Hz, this is `8192Hz`
+    /// normally, `262144Hz` with the CGB fast clock, and doubled in
+    /// double speed mode since the serial follows the CPU speed.
+    pub fn clock_hz(&self, double_speed: bool) -> u32 {
+        // `internal_timer` is incremented every machine cycle
+        let machine_cycles_per_bit = 2u32 << self.serial_control.clock_bit();
+        let clock_hz = 4194304 / 4 / machine_cycles_per_bit;
+
+        if double_speed {
+            clock_hz * 2
+        } else {
+            clock_hz
+        }
+    }
+
     /// Clocks the serial and will return `Some` if a bit should be sent,
     /// the bus must call `receive_bit` afterwards if there is a device.
     /// 1 is received automatically if `receive_bit` is not called
@@ -117,6 +250,7 @@ impl Serial {
         if can_clock && self.bits_remaining > 0 && self.serial_control.is_internal_clock() {
             let out = self.transfere_data & 0x80 != 0;
             self.transfere_data = self.transfere_data.wrapping_shl(1);
+            self.sent_data = self.sent_data.wrapping_shl(1) | out as u8;
 
             // data received from the other side, 1 for now meaning its
             // disconnected
@@ -127,6 +261,10 @@ impl Serial {
             if self.bits_remaining == 0 {
                 self.serial_control.end_transfere();
                 interrupt.request_interrupt(InterruptType::Serial);
+
+                if let Some(callback) = self.byte_callback.as_mut() {
+                    callback(self.sent_data);
+                }
             }
 
             Some(out)
@@ -146,5 +284,360 @@ impl Serial {
         // clear lowest bit
         self.transfere_data &= !1;
         self.transfere_data |= bit as u8;
+
+        self.count_exchanged_bit();
+    }
+
+    /// The bit that will be sent on the next external clock, if the GameBoy
+    /// is not transferring with external clock, the line is high (`1`)
+    pub fn external_clock_out_bit(&self) -> bool {
+        if self.is_external_clock_transfer() {
+            self.transfere_data & 0x80 != 0
+        } else {
+            true
+        }
+    }
+
+    /// Shifts one bit of the data with an external clock, which is driven by
+    /// the connected device, only works when the GameBoy is transferring
+    /// with external clock, otherwise the bit is ignored.
+    pub fn receive_bit_external_clock<I: InterruptManager>(
+        &mut self,
+        bit: bool,
+        interrupt: &mut I,
+    ) {
+        if !self.is_external_clock_transfer() {
+            return;
+        }
+
+        self.transfere_data = self.transfere_data.wrapping_shl(1) | bit as u8;
+        self.bits_remaining -= 1;
+        self.count_exchanged_bit();
+
+        if self.bits_remaining == 0 {
+            self.serial_control.end_transfere();
+            interrupt.request_interrupt(InterruptType::Serial);
+        }
+    }
+
+    /// Completes a pending external clock transfer when the device driving
+    /// the clock is disconnected, the line is high without a device, so the
+    /// remaining bits are received as `1`, like a transfer with nothing
+    /// connected as the master.
+    pub fn finish_external_clock_transfer<I: InterruptManager>(&mut self, interrupt: &mut I) {
+        if !self.is_external_clock_transfer() {
+            return;
+        }
+
+        let remaining = self.bits_remaining as u16;
+        self.transfere_data =
+            (((self.transfere_data as u16) << remaining) | ((1 << remaining) - 1)) as u8;
+        self.bits_remaining = 0;
+
+        self.serial_control.end_transfere();
+        interrupt.request_interrupt(InterruptType::Serial);
+    }
+
+    fn is_external_clock_transfer(&self) -> bool {
+        self.serial_control.in_transfer()
+            && !self.serial_control.is_internal_clock()
+            && self.bits_remaining > 0
+    }
+
+    fn count_exchanged_bit(&mut self) {
+        // a bit is sent and received in the same clock
+        self.stats.bits_sent += 1;
+        self.stats.bits_received += 1;
+        self.stats.bytes_sent = self.stats.bits_sent / 8;
+        self.stats.bytes_received = self.stats.bits_received / 8;
+    }
+
+    /// The role selected by the clock source bit of `SC`
+    pub fn role(&self) -> SerialRole {
+        if self.serial_control.is_internal_clock() {
+            SerialRole::Master
+        } else {
+            SerialRole::Slave
+        }
+    }
+
+    pub fn stats(&self) -> SerialStats {
+        self.stats
+    }
+
+    pub fn transfer_active(&self) -> bool {
+        self.serial_control.in_transfer()
+    }
+
+    pub fn set_byte_callback(&mut self, callback: Option<SerialByteCallback>) {
+        self.byte_callback = callback;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ByteSerialDevice, Serial, SerialDevice, SerialRole, SerialStats};
+    use crate::memory::{InterruptManager, InterruptType};
+    use crate::tests::test_cgb_gameboy;
+    use crate::GameBoyConfig;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Default)]
+    struct SerialInterrupt(bool);
+
+    impl InterruptManager for SerialInterrupt {
+        fn request_interrupt(&mut self, interrupt: InterruptType) {
+            self.0 |= interrupt == InterruptType::Serial;
+        }
+    }
+
+    /// Sends `data` and counts the bits exchanged
+    struct TestDevice {
+        data: u8,
+        received: u8,
+        bits: u64,
+    }
+
+    impl SerialDevice for TestDevice {
+        fn exchange_bit_external_clock(&mut self, bit: bool) -> bool {
+            let out = self.data & 0x80 != 0;
+            self.data <<= 1;
+            self.received = (self.received << 1) | bit as u8;
+            self.bits += 1;
+            out
+        }
+    }
+
+    #[test]
+    fn exchange_byte_stats() {
+        let mut serial = Serial::new(GameBoyConfig::default());
+        let mut interrupt = SerialInterrupt::default();
+        let mut device = TestDevice {
+            data: 0x3C,
+            received: 0,
+            bits: 0,
+        };
+
+        serial.write_data(0xA5);
+        // start transfer with internal clock
+        serial.write_control(0x81);
+
+        while !interrupt.0 {
+            if let Some(bit) = serial.clock_for_bit(&mut interrupt) {
+                let received = device.exchange_bit_external_clock(bit);
+                serial.receive_bit(received);
+            }
+        }
+
+        assert_eq!(serial.read_data(), 0x3C);
+        assert_eq!(device.received, 0xA5);
+        assert_eq!(device.bits, 8);
+        assert_eq!(
+            serial.stats(),
+            SerialStats {
+                bits_sent: 8,
+                bits_received: 8,
+                bytes_sent: 1,
+                bytes_received: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn byte_callback_as_master() {
+        let mut serial = Serial::new(GameBoyConfig::default());
+        let mut interrupt = SerialInterrupt::default();
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let sent_clone = sent.clone();
+        serial.set_byte_callback(Some(Box::new(move |byte| {
+            sent_clone.borrow_mut().push(byte)
+        })));
+
+        for byte in [b'O', b'K'] {
+            interrupt.0 = false;
+            serial.write_data(byte);
+            serial.write_control(0x81);
+            assert!(serial.transfer_active());
+
+            while !interrupt.0 {
+                serial.clock_for_bit(&mut interrupt);
+            }
+            assert!(!serial.transfer_active());
+            // nothing connected, received `0xFF`
+            assert_eq!(serial.read_data(), 0xFF);
+        }
+
+        assert_eq!(*sent.borrow(), b"OK");
+
+        // external clock transfers are not reported
+        serial.write_control(0x80);
+        for _ in 0..0x1000 {
+            serial.clock_for_bit(&mut interrupt);
+        }
+        assert_eq!(sent.borrow().len(), 2);
+    }
+
+    #[test]
+    fn fast_clock_cadence() {
+        let mut serial = Serial::new(GameBoyConfig::default());
+        let mut interrupt = SerialInterrupt::default();
+
+        // machine cycles between two shifted bits
+        let mut bit_period = |serial: &mut Serial, control: u8| {
+            serial.write_control(control);
+            let mut cycles = 0;
+            let mut bits = 0;
+            // skip until the first bit to align with the clock
+            while serial.clock_for_bit(&mut interrupt).is_none() {}
+            while bits < 2 {
+                cycles += 1;
+                if serial.clock_for_bit(&mut interrupt).is_some() {
+                    bits += 1;
+                }
+            }
+            cycles / 2
+        };
+
+        assert_eq!(bit_period(&mut serial, 0x81), 128);
+        assert_eq!(serial.clock_hz(false), 8192);
+        assert_eq!(serial.clock_hz(true), 16384);
+
+        assert_eq!(bit_period(&mut serial, 0x83), 4);
+        assert_eq!(serial.clock_hz(false), 262144);
+        assert_eq!(serial.clock_hz(true), 524288);
+
+        // the fast clock is not available in DMG
+        let mut serial = Serial::new(GameBoyConfig {
+            is_dmg: true,
+            ..GameBoyConfig::default()
+        });
+        assert_eq!(bit_period(&mut serial, 0x83), 128);
+        assert_eq!(serial.clock_hz(false), 8192);
+    }
+
+    #[test]
+    fn slave_exchange_byte() {
+        let mut serial = Serial::new(GameBoyConfig::default());
+        let mut interrupt = SerialInterrupt::default();
+        let mut device = TestDevice {
+            data: 0x3C,
+            received: 0,
+            bits: 0,
+        };
+
+        serial.write_data(0xA5);
+        // not in transfer yet, so clocks are ignored and the line is high
+        assert!(serial.external_clock_out_bit());
+        serial.receive_bit_external_clock(false, &mut interrupt);
+        assert_eq!(serial.read_data(), 0xA5);
+
+        // the role follows the clock source of the transfer
+        serial.write_control(0x81);
+        assert_eq!(serial.role(), SerialRole::Master);
+        // start transfer with external clock
+        serial.write_control(0x80);
+        assert_eq!(serial.role(), SerialRole::Slave);
+
+        // the internal clock does not shift the data
+        for _ in 0..0x1000 {
+            assert_eq!(serial.clock_for_bit(&mut interrupt), None);
+        }
+
+        // the device is the master
+        while !interrupt.0 {
+            let out = serial.external_clock_out_bit();
+            let received = device.exchange_bit_external_clock(out);
+            serial.receive_bit_external_clock(received, &mut interrupt);
+        }
+
+        assert_eq!(serial.read_data(), 0x3C);
+        assert_eq!(serial.read_control() & 0x80, 0);
+        assert_eq!(device.received, 0xA5);
+        assert_eq!(device.bits, 8);
+        assert_eq!(serial.stats().bytes_received, 1);
+    }
+
+    #[test]
+    fn byte_serial_device() {
+        let mut serial = Serial::new(GameBoyConfig::default());
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let received_clone = received.clone();
+        let mut device = ByteSerialDevice::new(move |byte| {
+            received_clone.borrow_mut().push(byte);
+            byte.wrapping_add(1)
+        });
+
+        let mut replies = Vec::new();
+        for &byte in b"OK" {
+            let mut interrupt = SerialInterrupt::default();
+            serial.write_data(byte);
+            serial.write_control(0x81);
+
+            while !interrupt.0 {
+                if let Some(bit) = serial.clock_for_bit(&mut interrupt) {
+                    let received = device.exchange_bit_external_clock(bit);
+                    serial.receive_bit(received);
+                }
+            }
+            replies.push(serial.read_data());
+        }
+
+        assert_eq!(received.borrow().as_slice(), b"OK");
+        // the reply is delayed by one byte
+        assert_eq!(replies, [0xFF, b'O' + 1]);
+    }
+
+    #[test]
+    fn disconnect_during_external_clock_transfer() {
+        use crate::SerialDevice;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        /// Clocks 3 `0` bits after the transfer starts, then stops
+        struct StallingDevice {
+            cycles: u32,
+        }
+
+        impl SerialDevice for StallingDevice {
+            fn exchange_bit_external_clock(&mut self, _bit: bool) -> bool {
+                true
+            }
+
+            fn exchange_bit_internal_clock(&mut self, _bit: bool) -> Option<bool> {
+                self.cycles += 1;
+                (self.cycles % 1000 == 0 && self.cycles <= 3000).then_some(false)
+            }
+        }
+
+        #[rustfmt::skip]
+        let mut gb = test_cgb_gameboy(&[
+            0x3E, 0x55, // LD A, 0x55
+            0xE0, 0x01, // LDH (SB), A
+            0x3E, 0x80, // LD A, 0x80 (start, external clock)
+            0xE0, 0x02, // LDH (SC), A
+            0xF0, 0x02, // LDH A, (SC)
+            0xCB, 0x7F, // BIT 7, A
+            0x20, 0xFA, // JR NZ, -6
+            0x06, 0x42, // LD B, 0x42
+            0x18, 0xFE, // JR -2
+        ]);
+        gb.connect_device(Rc::new(RefCell::new(StallingDevice { cycles: 0 })));
+
+        for _ in 0..3 {
+            gb.clock_for_frame();
+        }
+        assert!(gb.serial_transfer_active());
+        assert_eq!(gb.serial_stats().bits_received, 3);
+        assert_ne!(gb.cpu.registers().b, 0x42);
+
+        gb.disconnect_device();
+        assert!(!gb.serial_transfer_active());
+        // the received `0` bits, then `1` for the rest
+        assert_eq!(gb.serial_data(), 0x1F);
+        assert_eq!(gb.pending_interrupts().0 & 0x08, 0x08);
+
+        gb.clock_for_frame();
+        assert_eq!(gb.cpu.registers().b, 0x42);
     }
 }
@@ -0,0 +1,443 @@
+use save_state::Savable;
+
+/// The width of the SGB border image, see [`SgbBorder`]
+pub const SGB_BORDER_WIDTH: usize = 256;
+/// The height of the SGB border image, see [`SgbBorder`]
+pub const SGB_BORDER_HEIGHT: usize = 224;
+
+/// The size of the data sent with `CHR_TRN` and `PCT_TRN`
+pub const SGB_VRAM_TRANSFER_SIZE: usize = 0x1000;
+
+const PACKET_SIZE: usize = 16;
+/// The offset of the border palettes `4-7` in the `PCT_TRN` data
+const BORDER_PALETTES_OFFSET: usize = 0x800;
+
+const CMD_PAL01: u8 = 0x00;
+const CMD_PAL23: u8 = 0x01;
+const CMD_PAL03: u8 = 0x02;
+const CMD_PAL12: u8 = 0x03;
+const CMD_MLT_REQ: u8 = 0x11;
+const CMD_CHR_TRN: u8 = 0x13;
+const CMD_PCT_TRN: u8 = 0x14;
+
+/// The border drawn by the Super GameBoy around the screen, see
+/// [`GameBoy::sgb_border`](crate::GameBoy::sgb_border).
+///
+/// The GameBoy screen is placed at `(48, 40)` in the border, which is
+/// normally transparent there, transparent pixels are filled with the
+/// background color (color `0` of the SGB palettes).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SgbBorder {
+    /// The pixels in RGB format. i.e. 3 bytes per pixel,
+    /// [`SGB_BORDER_WIDTH`]x[`SGB_BORDER_HEIGHT`] pixels.
+    pub data: Vec<u8>,
+}
+
+/// What should be done by the [`Bus`](crate::memory::Bus) after a command
+/// packet is received, since the [`Sgb`] does not have access to the PPU
+pub enum SgbAction {
+    /// The SGB palette `0` changed, and should be used to color the screen,
+    /// the colors are in the `RGB555` format
+    SetScreenPalette([u16; 4]),
+    /// `CHR_TRN` or `PCT_TRN` is waiting for the VRAM data,
+    /// see [`Sgb::vram_transfer`]
+    VramTransfer,
+}
+
+#[derive(Savable, Default, Clone, Copy, PartialEq, Eq)]
+enum VramTransfer {
+    #[default]
+    None,
+    /// `CHR_TRN` to the lower (`false`) or upper (`true`) half of the tiles
+    Tiles(bool),
+    /// `PCT_TRN`
+    Map,
+}
+
+/// A minimal emulation of the Super GameBoy, which receives command
+/// packets through the joypad register `P1`.
+///
+/// Only the palette commands `PAL01`, `PAL23`, `PAL03` and `PAL12`, the
+/// multiplayer `MLT_REQ`, and the border transfers `CHR_TRN` and `PCT_TRN`
+/// are supported, other commands are ignored.
+#[derive(Savable)]
+pub struct Sgb {
+    enabled: bool,
+
+    /// Waiting for `P14` and `P15` to be high before the next bit
+    ready_for_pulse: bool,
+    /// Currently receiving a packet, started by the reset pulse
+    receiving: bool,
+    received_bits: u8,
+    packet: [u8; PACKET_SIZE],
+    /// The packets of the current command, can be up to 7
+    command: Vec<u8>,
+
+    old_p1: u8,
+    player_count: u8,
+    current_player: u8,
+
+    /// The 4 system palettes, in `RGB555` format
+    palettes: [[u16; 4]; 4],
+    pending_transfer: VramTransfer,
+    /// The border tiles in 4bpp SNES format, `256` tiles
+    border_tiles: Vec<u8>,
+    /// The border tile map followed by the border palettes
+    border_map: Vec<u8>,
+}
+
+impl Sgb {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            ready_for_pulse: false,
+            receiving: false,
+            received_bits: 0,
+            packet: [0; PACKET_SIZE],
+            command: Vec::new(),
+            old_p1: 0x30,
+            player_count: 1,
+            current_player: 0,
+            palettes: [[0; 4]; 4],
+            pending_transfer: VramTransfer::None,
+            border_tiles: Vec::new(),
+            border_map: Vec::new(),
+        }
+    }
+
+    /// Returns the value of `P1` to be read with the multiplayer joypads,
+    /// `value` is the value from the [`Joypad`](crate::joypad::Joypad).
+    ///
+    /// Only the first joypad has buttons, the others are never pressed.
+    pub fn read_joypad(&self, value: u8) -> u8 {
+        if !self.enabled || self.player_count == 1 {
+            return value;
+        }
+
+        if value & 0x30 == 0x30 {
+            (value & 0xF0) | (0xF - self.current_player)
+        } else if self.current_player != 0 {
+            value | 0xF
+        } else {
+            value
+        }
+    }
+
+    /// Handles the pulses written to `P1`, returns `Some` if the
+    /// [`Bus`](crate::memory::Bus) needs to act on a received command.
+    pub fn write_joypad(&mut self, data: u8) -> Option<SgbAction> {
+        if !self.enabled {
+            return None;
+        }
+
+        let p1 = data & 0x30;
+        let old_p1 = std::mem::replace(&mut self.old_p1, p1);
+
+        match p1 {
+            0x00 => {
+                // reset pulse, start of a packet
+                self.receiving = true;
+                self.received_bits = 0;
+                self.packet = [0; PACKET_SIZE];
+                self.ready_for_pulse = false;
+                None
+            }
+            0x30 => {
+                self.ready_for_pulse = true;
+
+                // the next joypad is selected on the rising edge of `P15`
+                if old_p1 & 0x20 == 0 && self.player_count > 1 {
+                    self.current_player = (self.current_player + 1) % self.player_count;
+                }
+                None
+            }
+            _ if !self.receiving || !self.ready_for_pulse => None,
+            _ => {
+                self.ready_for_pulse = false;
+                // `P14` low is `0`, `P15` low is `1`
+                let bit = p1 == 0x10;
+                self.receive_bit(bit)
+            }
+        }
+    }
+
+    /// Takes the pending VRAM transfer data, `data` is the `4KB` of tile
+    /// data shown on the screen.
+    pub fn vram_transfer(&mut self, data: &[u8; SGB_VRAM_TRANSFER_SIZE]) {
+        match std::mem::take(&mut self.pending_transfer) {
+            VramTransfer::None => {}
+            VramTransfer::Tiles(upper) => {
+                self.border_tiles.resize(SGB_VRAM_TRANSFER_SIZE * 2, 0);
+                let start = upper as usize * SGB_VRAM_TRANSFER_SIZE;
+                self.border_tiles[start..start + SGB_VRAM_TRANSFER_SIZE].copy_from_slice(data);
+            }
+            VramTransfer::Map => {
+                self.border_map = data.to_vec();
+            }
+        }
+    }
+
+    /// Renders the border, `None` if the tiles or the map are not
+    /// transferred yet
+    pub fn border(&self) -> Option<SgbBorder> {
+        if self.border_tiles.is_empty() || self.border_map.is_empty() {
+            return None;
+        }
+
+        let mut data = vec![0; SGB_BORDER_WIDTH * SGB_BORDER_HEIGHT * 3];
+        let background = rgb555_to_rgb(self.palettes[0][0]);
+
+        for y in 0..SGB_BORDER_HEIGHT {
+            for x in 0..SGB_BORDER_WIDTH {
+                let map_index = ((y / 8) * 32 + x / 8) * 2;
+                let entry = u16::from_le_bytes([
+                    self.border_map[map_index],
+                    self.border_map[map_index + 1],
+                ]);
+                let tile = (entry & 0xFF) as usize;
+                let palette = ((entry >> 10) & 3) as usize;
+                let x_flip = entry & 0x4000 != 0;
+                let y_flip = entry & 0x8000 != 0;
+
+                let mut tile_x = x % 8;
+                let mut tile_y = y % 8;
+                if x_flip {
+                    tile_x = 7 - tile_x;
+                }
+                if y_flip {
+                    tile_y = 7 - tile_y;
+                }
+
+                // 4 bitplanes, the first two interleaved, then the other two
+                let tile_data = &self.border_tiles[tile * 32..tile * 32 + 32];
+                let bit = 7 - tile_x;
+                let color_index = (0..4).fold(0, |acc, plane| {
+                    let byte = tile_data[(plane / 2) * 16 + tile_y * 2 + (plane % 2)];
+                    acc | (((byte >> bit) & 1) << plane)
+                }) as usize;
+
+                let color = if color_index == 0 {
+                    background
+                } else {
+                    let offset = BORDER_PALETTES_OFFSET + (palette * 16 + color_index) * 2;
+                    rgb555_to_rgb(u16::from_le_bytes([
+                        self.border_map[offset],
+                        self.border_map[offset + 1],
+                    ]))
+                };
+
+                let i = (y * SGB_BORDER_WIDTH + x) * 3;
+                data[i..i + 3].copy_from_slice(&color);
+            }
+        }
+
+        Some(SgbBorder { data })
+    }
+}
+
+impl Sgb {
+    fn receive_bit(&mut self, bit: bool) -> Option<SgbAction> {
+        let index = self.received_bits as usize;
+
+        if index == PACKET_SIZE * 8 {
+            // the stop bit must be `0`
+            self.receiving = false;
+            if bit {
+                self.command.clear();
+                return None;
+            }
+            return self.receive_packet();
+        }
+
+        self.packet[index / 8] |= (bit as u8) << (index % 8);
+        self.received_bits += 1;
+
+        None
+    }
+
+    fn receive_packet(&mut self) -> Option<SgbAction> {
+        self.command.extend_from_slice(&self.packet);
+
+        let length = (self.command[0] & 7).max(1) as usize;
+        if self.command.len() < length * PACKET_SIZE {
+            return None;
+        }
+
+        let command = std::mem::take(&mut self.command);
+        self.run_command(&command)
+    }
+
+    fn run_command(&mut self, data: &[u8]) -> Option<SgbAction> {
+        match data[0] >> 3 {
+            cmd @ (CMD_PAL01 | CMD_PAL23 | CMD_PAL03 | CMD_PAL12) => {
+                let (first, second) = match cmd {
+                    CMD_PAL01 => (0, 1),
+                    CMD_PAL23 => (2, 3),
+                    CMD_PAL03 => (0, 3),
+                    _ => (1, 2),
+                };
+                let color = |i: usize| u16::from_le_bytes([data[1 + i * 2], data[2 + i * 2]]);
+
+                // color 0 is shared by all palettes
+                for palette in self.palettes.iter_mut() {
+                    palette[0] = color(0);
+                }
+                for i in 1..4 {
+                    self.palettes[first][i] = color(i);
+                    self.palettes[second][i] = color(i + 3);
+                }
+
+                Some(SgbAction::SetScreenPalette(self.palettes[0]))
+            }
+            CMD_MLT_REQ => {
+                self.player_count = match data[1] & 3 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.current_player = 0;
+                None
+            }
+            CMD_CHR_TRN => {
+                self.pending_transfer = VramTransfer::Tiles(data[1] & 1 != 0);
+                Some(SgbAction::VramTransfer)
+            }
+            CMD_PCT_TRN => {
+                self.pending_transfer = VramTransfer::Map;
+                Some(SgbAction::VramTransfer)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn rgb555_to_rgb(color: u16) -> [u8; 3] {
+    let expand = |c: u16| {
+        let c = (c & 0x1F) as u8;
+        (c << 3) | (c >> 2)
+    };
+    [expand(color), expand(color >> 5), expand(color >> 10)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Sgb, SgbAction, SGB_BORDER_WIDTH, SGB_VRAM_TRANSFER_SIZE};
+
+    /// Sends a packet through `P1` pulses, and returns the action of the
+    /// last bit
+    fn send_packet(sgb: &mut Sgb, packet: &[u8; 16]) -> Option<SgbAction> {
+        sgb.write_joypad(0x00);
+        sgb.write_joypad(0x30);
+        for i in 0..128 {
+            let bit = (packet[i / 8] >> (i % 8)) & 1 != 0;
+            assert!(sgb.write_joypad(if bit { 0x10 } else { 0x20 }).is_none());
+            sgb.write_joypad(0x30);
+        }
+        // stop bit
+        let action = sgb.write_joypad(0x20);
+        sgb.write_joypad(0x30);
+        action
+    }
+
+    #[test]
+    fn palette_packet() {
+        let mut sgb = Sgb::new(true);
+        let mut packet = [0; 16];
+        // PAL01, 1 packet
+        packet[0] = 0x01;
+        for (i, color) in [0x7FFF, 0x001F, 0x03E0, 0x7C00, 0x1111, 0x2222, 0x3333]
+            .iter()
+            .enumerate()
+        {
+            packet[1 + i * 2..3 + i * 2].copy_from_slice(&u16::to_le_bytes(*color));
+        }
+
+        let action = send_packet(&mut sgb, &packet);
+        assert!(matches!(
+            action,
+            Some(SgbAction::SetScreenPalette([
+                0x7FFF, 0x001F, 0x03E0, 0x7C00
+            ]))
+        ));
+        assert_eq!(sgb.palettes[1], [0x7FFF, 0x1111, 0x2222, 0x3333]);
+
+        // invalid stop bit
+        sgb.write_joypad(0x00);
+        sgb.write_joypad(0x30);
+        for _ in 0..128 {
+            sgb.write_joypad(0x20);
+            sgb.write_joypad(0x30);
+        }
+        assert!(sgb.write_joypad(0x10).is_none());
+
+        // disabled
+        let mut sgb = Sgb::new(false);
+        assert!(send_packet(&mut sgb, &packet).is_none());
+    }
+
+    #[test]
+    fn multiplayer() {
+        let mut sgb = Sgb::new(true);
+        let mut packet = [0; 16];
+        // MLT_REQ, 4 players
+        packet[0] = (0x11 << 3) | 1;
+        packet[1] = 3;
+        assert!(send_packet(&mut sgb, &packet).is_none());
+
+        let mut ids = Vec::new();
+        for _ in 0..5 {
+            ids.push(sgb.read_joypad(0xFF) & 0xF);
+            // normal joypad read, rising edge of `P15` selects the next joypad
+            sgb.write_joypad(0x20);
+            sgb.write_joypad(0x10);
+            sgb.write_joypad(0x30);
+        }
+        assert_eq!(ids, [0xF, 0xE, 0xD, 0xC, 0xF]);
+
+        // the other joypads have no buttons pressed
+        sgb.write_joypad(0x20);
+        assert_eq!(sgb.read_joypad(0xE0), 0xEF);
+    }
+
+    #[test]
+    fn border_transfer() {
+        let mut sgb = Sgb::new(true);
+        assert!(sgb.border().is_none());
+
+        let mut packet = [0; 16];
+        // CHR_TRN, lower tiles
+        packet[0] = (0x13 << 3) | 1;
+        assert!(matches!(
+            send_packet(&mut sgb, &packet),
+            Some(SgbAction::VramTransfer)
+        ));
+        let mut tiles = [0; SGB_VRAM_TRANSFER_SIZE];
+        // tile 1, is filled with color 15
+        tiles[32..64].fill(0xFF);
+        sgb.vram_transfer(&tiles);
+        assert!(sgb.border().is_none());
+
+        // PCT_TRN
+        packet[0] = (0x14 << 3) | 1;
+        assert!(matches!(
+            send_packet(&mut sgb, &packet),
+            Some(SgbAction::VramTransfer)
+        ));
+        let mut map = [0; SGB_VRAM_TRANSFER_SIZE];
+        // second tile of the first row uses tile 1 with palette 5
+        map[2..4].copy_from_slice(&u16::to_le_bytes(1 | (5 << 10)));
+        // palette 5, color 15 is red
+        let offset = 0x800 + (16 + 15) * 2;
+        map[offset..offset + 2].copy_from_slice(&u16::to_le_bytes(0x001F));
+        sgb.vram_transfer(&map);
+
+        let border = sgb.border().unwrap();
+        assert_eq!(border.data.len(), SGB_BORDER_WIDTH * 224 * 3);
+        // transparent is the background color
+        assert_eq!(border.data[0..3], [0, 0, 0]);
+        assert_eq!(border.data[8 * 3..9 * 3], [0xFF, 0, 0]);
+        let second_row = (SGB_BORDER_WIDTH * 7 + 15) * 3;
+        assert_eq!(border.data[second_row..second_row + 3], [0xFF, 0, 0]);
+        assert_eq!(border.data[second_row + 3..second_row + 6], [0, 0, 0]);
+    }
+}
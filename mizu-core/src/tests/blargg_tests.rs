@@ -75,3 +75,36 @@ fn blargg_oam_bug_all() {
         );
     }
 }
+
+/// Blargg tests also write the result to the cartridge RAM, the signature
+/// `DE B0 61` is at `0xA001-0xA003` and the result code is at `0xA000`,
+/// `0` when the test passes
+fn blargg_memory_test(file_path: &str, is_dmg: bool) {
+    let mut gb = crate::tests::TestingGameBoy::new(file_path, is_dmg).unwrap();
+
+    gb.clock_until_infinte_loop();
+
+    let mut read = |addr| gb.bus.read_not_ticked(addr, None);
+    assert_eq!(
+        [read(0xA001), read(0xA002), read(0xA003)],
+        [0xDE, 0xB0, 0x61]
+    );
+    assert_eq!(read(0xA000), 0);
+}
+
+// the length registers can be written while the APU is off only on DMG
+#[test]
+fn blargg_dmg_sound_regs_after_power() {
+    blargg_memory_test(
+        "../test_roms/game-boy-test-roms/blargg/dmg_sound/rom_singles/11-regs after power.gb",
+        true,
+    );
+}
+
+#[test]
+fn blargg_cgb_sound_regs_after_power() {
+    blargg_memory_test(
+        "../test_roms/game-boy-test-roms/blargg/cgb_sound/rom_singles/11-regs after power.gb",
+        false,
+    );
+}
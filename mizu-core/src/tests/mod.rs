@@ -1,11 +1,21 @@
 #![cfg(test)]
 
-use super::cartridge::{Cartridge, CartridgeError};
+use super::cartridge::{Cartridge, CartridgeError, RtcSaveFormat};
 use super::cpu::{Cpu, CpuRegisters, CpuState};
 use super::memory::Bus;
-use super::GameBoyConfig;
+use super::{GameBoy, GameBoyConfig};
 
-use std::path::Path;
+use sha2::{Digest, Sha256};
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// When this environment variable is set, tests that fail the screen buffer
+/// checksum will write the actual screen as a PNG next to the test ROM file
+/// (which is where reference images of most test suites are) so it can be
+/// compared visually.
+const WRITE_FAILED_PNG_ENV: &str = "MIZU_WRITE_FAILED_PNG";
 
 enum ClockType {
     InfiniteLoop,
@@ -53,10 +63,15 @@ macro_rules! gb_tests {
                         }
                     }
 
-                    let screen_buffer = gb.raw_screen_buffer();
                     gb.print_screen_buffer();
 
-                    assert_eq!(crc::Crc::<u64>::new(&crc::CRC_64_XZ).checksum(screen_buffer), crc_checksum);
+                    let png_path = std::env::var_os(crate::tests::WRITE_FAILED_PNG_ENV)
+                        .map(|_| crate::tests::failed_png_path(file_path, is_dmg));
+                    crate::tests::assert_screen_checksum(
+                        gb.raw_screen_buffer(),
+                        crc_checksum,
+                        png_path,
+                    );
                 }
 
 
@@ -91,6 +106,498 @@ mod save_state_tests;
 mod scribbltests;
 mod small_tests;
 
+/// `<rom file name>-<dmg/cgb>-actual.png` next to the ROM file
+fn failed_png_path(rom_path: &str, is_dmg: bool) -> PathBuf {
+    let rom_path = Path::new(rom_path);
+    let stem = rom_path.file_stem().unwrap().to_string_lossy();
+    let emu = if is_dmg { "dmg" } else { "cgb" };
+
+    rom_path.with_file_name(format!("{}-{}-actual.png", stem, emu))
+}
+
+/// Checks the checksum of the screen buffer, and if it does not match and
+/// `png_path` is provided, the screen buffer is written there as PNG before
+/// failing.
+fn assert_screen_checksum(screen_buffer: &[u8], crc_checksum: u64, png_path: Option<PathBuf>) {
+    let actual_checksum = crc::Crc::<u64>::new(&crc::CRC_64_XZ).checksum(screen_buffer);
+
+    if actual_checksum != crc_checksum {
+        if let Some(png_path) = png_path {
+            write_screen_png(&png_path, screen_buffer).unwrap();
+            println!("Actual screen written to {}", png_path.display());
+        }
+    }
+
+    assert_eq!(actual_checksum, crc_checksum);
+}
+
+/// Writes the raw screen buffer (5 bits per color) as an 8 bit RGB PNG image.
+///
+/// The image data is stored without compression, so there is no need for
+/// an external crate.
+fn write_screen_png(path: &Path, screen_buffer: &[u8]) -> std::io::Result<()> {
+    const TV_WIDTH: u32 = 160;
+    const TV_HEIGHT: u32 = 144;
+
+    fn write_chunk(file: &mut File, ty: &[u8; 4], data: &[u8]) -> std::io::Result<()> {
+        let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+        let mut digest = crc.digest();
+        digest.update(ty);
+        digest.update(data);
+
+        file.write_all(&(data.len() as u32).to_be_bytes())?;
+        file.write_all(ty)?;
+        file.write_all(data)?;
+        file.write_all(&digest.finalize().to_be_bytes())
+    }
+
+    // each scanline starts with filter type `0` (None)
+    let mut raw_data = Vec::with_capacity(((TV_WIDTH * 3 + 1) * TV_HEIGHT) as usize);
+    for line in screen_buffer.chunks((TV_WIDTH * 3) as usize) {
+        raw_data.push(0);
+        raw_data.extend(line.iter().map(|&c| (c << 3) | (c >> 2)));
+    }
+
+    // zlib stream with stored (uncompressed) deflate blocks
+    let mut zlib_data = vec![0x78, 0x01];
+    let blocks_count = raw_data.chunks(0xFFFF).count();
+    for (i, block) in raw_data.chunks(0xFFFF).enumerate() {
+        let len = block.len() as u16;
+        zlib_data.push((i == blocks_count - 1) as u8);
+        zlib_data.extend_from_slice(&len.to_le_bytes());
+        zlib_data.extend_from_slice(&(!len).to_le_bytes());
+        zlib_data.extend_from_slice(block);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in &raw_data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    zlib_data.extend_from_slice(&((b << 16) | a).to_be_bytes());
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&TV_WIDTH.to_be_bytes());
+    header.extend_from_slice(&TV_HEIGHT.to_be_bytes());
+    // 8 bit depth, RGB, default compression, filter and no interlace
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut file = File::create(path)?;
+    file.write_all(b"\x89PNG\r\n\x1a\n")?;
+    write_chunk(&mut file, b"IHDR", &header)?;
+    write_chunk(&mut file, b"IDAT", &zlib_data)?;
+    write_chunk(&mut file, b"IEND", &[])
+}
+
+#[test]
+fn failed_checksum_writes_png() {
+    // a checkerboard screen, with an intentionally wrong checksum
+    let screen_buffer = (0..160 * 144)
+        .flat_map(|i| {
+            let c = if (i % 160 / 8 + i / 160 / 8) % 2 == 0 {
+                31
+            } else {
+                0
+            };
+            [c, c, c]
+        })
+        .collect::<Vec<u8>>();
+    let png_path = std::env::temp_dir().join("mizu-failed-checksum-actual.png");
+    let _ = std::fs::remove_file(&png_path);
+
+    let result = std::panic::catch_unwind(|| {
+        assert_screen_checksum(&screen_buffer, 0, Some(png_path.clone()));
+    });
+    assert!(result.is_err());
+
+    let png = std::fs::read(&png_path).unwrap();
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(&png[12..16], b"IHDR");
+    // header + IHDR + IDAT (2 stored blocks) + IEND
+    assert_eq!(
+        png.len(),
+        8 + 25 + (12 + 2 + 5 * 2 + (160 * 3 + 1) * 144 + 4) + 12
+    );
+
+    std::fs::remove_file(&png_path).unwrap();
+}
+
+/// Builds a valid ROM in memory, with `JP 0x150` at the entry point `0x100`
+/// and `program` placed at `0x150`, right after the header.
+pub(crate) fn build_test_rom(cartridge_type: u8, ram_size_index: u8, program: &[u8]) -> Vec<u8> {
+    const NINTENDO_LOGO_DATA: [u8; 48] = [
+        0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0c, 0x00,
+        0x0d, 0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e, 0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd,
+        0xd9, 0x99, 0xbb, 0xbb, 0x67, 0x63, 0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb,
+        0xb9, 0x33, 0x3e,
+    ];
+
+    let mut data = vec![0; 0x8000];
+    data[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+    data[0x104..=0x133].copy_from_slice(&NINTENDO_LOGO_DATA);
+    data[0x134..0x134 + 4].copy_from_slice(b"TEST");
+    data[0x147] = cartridge_type;
+    data[0x148] = 0;
+    data[0x149] = ram_size_index;
+    data[0x150..0x150 + program.len()].copy_from_slice(program);
+    fix_header_checksum(&mut data);
+
+    data
+}
+
+/// Builds a CGB only test rom, see [`build_test_rom`]
+pub(crate) fn build_cgb_test_rom(program: &[u8]) -> Vec<u8> {
+    let mut data = build_test_rom(0, 0, program);
+    data[0x143] = 0xC0;
+    fix_header_checksum(&mut data);
+
+    data
+}
+
+/// Recomputes the header checksum of `rom`, after modifying the header
+pub(crate) fn fix_header_checksum(rom: &mut [u8]) {
+    rom[0x14D] = rom[0x134..=0x14C]
+        .iter()
+        .fold(0u8, |acc, &b| acc.wrapping_sub(b).wrapping_sub(1));
+}
+
+/// Creates a [`GameBoy`] with the default configuration, running `program`
+/// from a ROM built with [`build_test_rom`]
+pub(crate) fn test_gameboy(program: &[u8]) -> GameBoy {
+    GameBoy::from_rom_bytes(build_test_rom(0, 0, program), GameBoyConfig::default()).unwrap()
+}
+
+/// Same as [`test_gameboy`], but with a CGB only ROM, to run in CGB mode
+pub(crate) fn test_cgb_gameboy(program: &[u8]) -> GameBoy {
+    GameBoy::from_rom_bytes(build_cgb_test_rom(program), GameBoyConfig::default()).unwrap()
+}
+
+#[test]
+fn gameboy_from_rom_bytes() {
+    // `JR -2` (infinite loop), 2 bytes only to not overwrite the header
+    let rom = build_test_rom(0, 0, &[0x18, 0xFE]);
+    let hash: [u8; 32] = Sha256::digest(&rom).into();
+
+    let mut gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+    assert_eq!(gb.game_title(), "TEST");
+    assert_eq!(gb.file_path(), None);
+    assert_eq!(gb.bus.cartridge().hash(), &hash);
+    assert_eq!(gb.clock_for_frame(), CpuState::InfiniteLoop);
+
+    // invalid roms are still rejected
+    assert!(matches!(
+        crate::GameBoy::from_rom_bytes(vec![0; 0x8000], GameBoyConfig::default()),
+        Err(CartridgeError::InvalidNintendoLogo)
+    ));
+}
+
+#[test]
+fn cgb_only_rom_on_dmg() {
+    // `JR -2` (infinite loop)
+    let rom = build_cgb_test_rom(&[0x18, 0xFE]);
+    let dmg_config = GameBoyConfig {
+        is_dmg: true,
+        ..GameBoyConfig::default()
+    };
+
+    assert!(matches!(
+        crate::GameBoy::from_rom_bytes(rom.clone(), dmg_config),
+        Err(CartridgeError::RequiresCgb)
+    ));
+    assert!(matches!(
+        crate::GameBoy::builder_from_reader(std::io::Cursor::new(rom.clone()))
+            .config(dmg_config)
+            .build(),
+        Err(CartridgeError::RequiresCgb)
+    ));
+
+    // switched to CGB
+    let gb = crate::GameBoy::from_rom_bytes(
+        rom.clone(),
+        GameBoyConfig {
+            auto_detect_mode: true,
+            ..dmg_config
+        },
+    )
+    .unwrap();
+    assert!(gb.cartridge_header().cgb_only());
+    assert_eq!(gb.cpu.registers().a, 0x11);
+
+    // the DMG boot rom selects DMG, which can't switch to CGB
+    let auto_detect_config = GameBoyConfig {
+        auto_detect_mode: true,
+        ..dmg_config
+    };
+    let builder = || {
+        crate::GameBoy::builder_from_reader(std::io::Cursor::new(rom.clone()))
+            .config(auto_detect_config)
+    };
+    assert!(matches!(
+        builder().boot_rom_bytes(vec![0; 0x100]).build(),
+        Err(CartridgeError::BootRomModelMismatch)
+    ));
+    assert!(builder().boot_rom_bytes(vec![0; 0x900]).build().is_ok());
+    assert!(builder()
+        .boot_rom_bytes(vec![0; 0x100])
+        .allow_any_boot_rom_size(true)
+        .build()
+        .is_ok());
+
+    // DMG games are not affected
+    let rom = build_test_rom(0, 0, &[0x18, 0xFE]);
+    let gb = crate::GameBoy::from_rom_bytes(
+        rom,
+        GameBoyConfig {
+            auto_detect_mode: true,
+            ..dmg_config
+        },
+    )
+    .unwrap();
+    assert_eq!(gb.cpu.registers().a, 0x01);
+}
+
+#[test]
+fn force_dmg_mode_on_cgb() {
+    let rom = build_cgb_test_rom(&[0x18, 0xFE]);
+    let forced_config = GameBoyConfig {
+        force_dmg_mode_on_cgb: true,
+        ..GameBoyConfig::default()
+    };
+
+    let mut gb = crate::GameBoy::from_rom_bytes(rom.clone(), GameBoyConfig::default()).unwrap();
+    assert_eq!(gb.bus.read_not_ticked(0xFF70, None), 0xF9);
+    assert_eq!(gb.cpu.registers().e, 0x56);
+
+    // CGB registers are locked, like DMG games
+    let mut gb = crate::GameBoy::from_rom_bytes(rom.clone(), forced_config).unwrap();
+    assert_eq!(gb.bus.read_not_ticked(0xFF70, None), 0xFF);
+    assert_eq!(gb.cpu.registers().e, 0x08);
+
+    // the boot rom sees a DMG cartridge, with a valid header checksum
+    let cartridge = Box::new(Cartridge::from_bytes(rom.clone()).unwrap());
+    let mut bus = Bus::new_with_boot_rom(cartridge, vec![0; 0x900], forced_config);
+    assert_eq!(bus.read_not_ticked(0x143, None), 0x40);
+    let header = (0x134..=0x14C).map(|addr| bus.read_not_ticked(addr, None));
+    let checksum = header.fold(0u8, |acc, b| acc.wrapping_sub(b).wrapping_sub(1));
+    assert_eq!(bus.read_not_ticked(0x14D, None), checksum);
+    assert_ne!(checksum, rom[0x14D]);
+}
+
+#[test]
+fn step_cycle() {
+    let mut gb = test_gameboy(&[0x18, 0xFE]);
+    // move to the start of a visible scanline
+    while gb.current_scanline() != 10 || gb.current_dot() != 0 {
+        gb.step_cycle();
+    }
+    let scanline = gb.current_scanline();
+    let pc = gb.cpu.registers().pc;
+
+    let mut modes = Vec::new();
+    for dot in (0..456).step_by(4) {
+        assert_eq!(gb.current_dot(), dot);
+        modes.push(gb.bus.read_not_ticked(0xFF41, None) & 3);
+        gb.step_cycle();
+    }
+    assert_eq!(gb.current_scanline(), scanline + 1);
+    // the CPU did not execute anything
+    assert_eq!(gb.cpu.registers().pc, pc);
+
+    // OAM scan (mode 0 is read at the first cycle), then drawing, then HBlank
+    assert_eq!(modes[0], 0);
+    assert!(modes[1..=20].iter().all(|&mode| mode == 2));
+    assert_eq!(modes[21], 3);
+    assert_eq!(modes[modes.len() - 1], 0);
+}
+
+#[test]
+fn clock_for_frame_ends_on_vblank() {
+    let mut gb = test_gameboy(&[0x18, 0xFE]);
+    for _ in 0..3 {
+        let frames = gb.frame_count();
+        gb.clock_for_frame();
+        assert_eq!(gb.frame_count(), frames + 1);
+        assert_eq!(gb.current_scanline(), 144);
+        // the last instruction may run past the start of VBlank
+        assert!(gb.current_dot() <= 16);
+    }
+
+    // with the LCD off, a frame is a fixed number of cycles
+    #[rustfmt::skip]
+    let mut gb = test_cgb_gameboy(&[
+        0x3E, 0x11, // LD A, 0x11
+        0xE0, 0x40, // LDH (LCDC), A
+        0x18, 0xFE, // JR -2
+    ]);
+    gb.clock_for_frame();
+    assert!(!gb.is_lcd_on());
+    for _ in 0..3 {
+        let cycles = gb.total_cycles();
+        gb.clock_for_frame();
+        let frame_cycles = gb.total_cycles() - cycles;
+        assert!((456 * 154..456 * 154 + 12).contains(&frame_cycles));
+    }
+}
+
+#[test]
+fn clock_for_duration_budget() {
+    use std::time::Duration;
+
+    let mut gb = test_gameboy(&[0x18, 0xFE]);
+    gb.clock_for_frame();
+
+    // the frame is interrupted, and completed by the next call
+    assert_eq!(
+        gb.clock_for_duration(Duration::ZERO),
+        (CpuState::InfiniteLoop, true)
+    );
+    assert_ne!(gb.current_scanline(), 144);
+    let frames = gb.frame_count();
+    assert_eq!(gb.clock_for_frame(), CpuState::InfiniteLoop);
+    assert_eq!(gb.frame_count(), frames + 1);
+    assert_eq!(gb.current_scanline(), 144);
+
+    assert_eq!(
+        gb.clock_for_duration(Duration::from_secs(10)),
+        (CpuState::InfiniteLoop, false)
+    );
+    assert_eq!(gb.frame_count(), frames + 2);
+}
+
+#[test]
+fn clock_for_scanline() {
+    // `INC A`, `JR -3`, 16 dots per loop, which does not divide the 456
+    // dots of a scanline, so the last instruction runs past its end
+    let mut gb = test_gameboy(&[0x3C, 0x18, 0xFD]);
+    gb.clock_for_frame();
+    assert_eq!(gb.current_scanline(), 144);
+
+    let frames = gb.frame_count();
+    for line in (145..=153).chain(0..=144) {
+        assert_eq!(gb.clock_for_scanline(), line);
+        assert_eq!(gb.current_scanline(), line);
+        // only the overshoot of the last instruction, the next line is
+        // not skipped
+        assert!(gb.current_dot() < 16, "dot {}", gb.current_dot());
+    }
+    assert_eq!(gb.frame_count(), frames + 1);
+
+    // stops at the breakpoint before the end of the line
+    gb.add_breakpoint(0x151);
+    assert_eq!(gb.clock_for_scanline(), 144);
+    assert_eq!(gb.cpu.registers().pc, 0x151);
+    assert_eq!(gb.current_scanline(), 144);
+
+    // and continues the same line after it
+    gb.clear_breakpoints();
+    assert_eq!(gb.clock_for_scanline(), 145);
+    assert!(gb.current_dot() < 16);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn dump_state_json() {
+    let rom = build_test_rom(1, 0, &[0x00, 0x18, 0xFE]);
+    let mut gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+    // `JP 0x150` and `NOP`
+    gb.cpu.next_instruction(&mut gb.bus);
+    gb.cpu.next_instruction(&mut gb.bus);
+    let regs = gb.cpu.registers();
+
+    let state: serde_json::Value = serde_json::from_str(&gb.dump_state_json()).unwrap();
+    assert_eq!(state["cpu"]["pc"], 0x151);
+    assert_eq!(state["cpu"]["a"], regs.a);
+    assert_eq!(state["cpu"]["sp"], regs.sp);
+    assert_eq!(state["cpu"]["ime"], false);
+    assert_eq!(state["interrupts"]["if"], gb.pending_interrupts().0);
+    assert_eq!(state["ppu"]["scanline"], gb.current_scanline());
+    assert_eq!(state["timer"]["divider"], gb.timer_state().divider);
+    assert_eq!(state["cartridge"]["rom0_bank"], 0);
+    assert_eq!(state["cartridge"]["romx_bank"], 1);
+}
+
+#[test]
+fn builder_from_reader() {
+    use std::io::{Cursor, Seek, SeekFrom};
+
+    let rom = build_test_rom(0, 0, &[0x18, 0xFE]);
+    let mut gb = crate::GameBoy::from_rom_bytes(rom.clone(), GameBoyConfig::default()).unwrap();
+    gb.clock_for_frame();
+    let mut state = Vec::new();
+    gb.save_state(&mut state).unwrap();
+
+    // the ROM is read from the current position of the reader
+    let mut archive = vec![0xAA; 0x20];
+    archive.extend_from_slice(&rom);
+    let mut reader = Cursor::new(archive);
+    reader.seek(SeekFrom::Start(0x20)).unwrap();
+
+    let mut reader_gb = crate::GameBoy::builder_from_reader(reader).build().unwrap();
+    assert_eq!(reader_gb.file_path(), None);
+    // same cartridge hash
+    reader_gb.load_state(Cursor::new(&state)).unwrap();
+
+    let reader_gb = crate::GameBoy::builder("missing.gb")
+        .rom_reader(Cursor::new(rom))
+        .build()
+        .unwrap();
+    assert_eq!(reader_gb.file_path(), None);
+}
+
+#[test]
+fn builder_boot_rom_bytes() {
+    use std::io::Cursor;
+
+    let rom = build_test_rom(0, 0, &[0x18, 0xFE]);
+    let builder = || crate::GameBoy::builder_from_reader(Cursor::new(rom.clone()));
+
+    let gb = builder().boot_rom_bytes(vec![0; 0x900]).build().unwrap();
+    assert_eq!(gb.cpu.registers().pc, 0x0000);
+
+    let gb = builder()
+        .boot_rom_bytes(vec![0; 0x900])
+        .skip_boot_rom(true)
+        .build()
+        .unwrap();
+    assert_eq!(gb.cpu.registers().pc, 0x0100);
+
+    // a DMG boot rom runs in DMG mode, where only `0x000-0x0FF` is mapped
+    let mut gb = builder().boot_rom_bytes(vec![0x18; 0x100]).build().unwrap();
+    assert_eq!(gb.bus.read_not_ticked(0x00FF, None), 0x18);
+    assert_eq!(gb.bus.read_not_ticked(0x0200, None), 0x00);
+
+    match builder().boot_rom_bytes(vec![0; 0x200]).build() {
+        Err(err @ crate::CartridgeError::InvalidBootRomSize { .. }) => {
+            assert_eq!(
+                err.to_string(),
+                "The boot rom size 0x200 does not match the expected 0x100 or 0x900"
+            );
+        }
+        _ => panic!("expected `InvalidBootRomSize`"),
+    }
+
+    // a DMG boot rom can't run CGB only games
+    let cgb_rom = build_cgb_test_rom(&[0x18, 0xFE]);
+    assert!(matches!(
+        crate::GameBoy::builder_from_reader(Cursor::new(cgb_rom))
+            .boot_rom_bytes(vec![0; 0x100])
+            .build(),
+        Err(crate::CartridgeError::RequiresCgb)
+    ));
+
+    // padded or truncated
+    for len in [0x100, 0xA00] {
+        let mut gb = builder()
+            .boot_rom_bytes(vec![0x18; len])
+            .allow_any_boot_rom_size(true)
+            .build()
+            .unwrap();
+        assert_eq!(gb.cpu.registers().pc, 0x0000);
+        assert_eq!(gb.bus.read_not_ticked(0x00FF, None), 0x18);
+        let expected = if len == 0x100 { 0xFF } else { 0x18 };
+        assert_eq!(gb.bus.read_not_ticked(0x08FF, None), expected);
+    }
+}
+
 #[derive(save_state::Savable)]
 struct TestingGameBoy {
     cpu: Cpu,
@@ -99,11 +606,26 @@ struct TestingGameBoy {
 
 impl TestingGameBoy {
     pub fn new<P: AsRef<Path>>(file_path: P, is_dmg: bool) -> Result<Self, CartridgeError> {
-        let cartridge = Cartridge::from_file::<_, String>(file_path, None, false)?;
+        let config = GameBoyConfig {
+            is_dmg,
+            ..GameBoyConfig::default()
+        };
 
-        let config = GameBoyConfig { is_dmg };
+        Self::with_config(file_path, config)
+    }
 
-        let is_cartridge_color = cartridge.is_cartridge_color();
+    pub fn with_config<P: AsRef<Path>>(
+        file_path: P,
+        config: GameBoyConfig,
+    ) -> Result<Self, CartridgeError> {
+        let cartridge = Box::new(Cartridge::from_file::<_, String>(
+            file_path,
+            None,
+            false,
+            RtcSaveFormat::Native,
+        )?);
+
+        let is_cartridge_color = config.is_cartridge_cgb_mode(cartridge.as_ref());
         Ok(Self {
             bus: Bus::new_without_boot_rom(cartridge, config),
             cpu: Cpu::new_without_boot_rom(config, is_cartridge_color),
@@ -169,3 +691,191 @@ impl TestingGameBoy {
         }
     }
 }
+
+#[test]
+fn input_recording_and_playback() {
+    use crate::JoypadState;
+
+    let rom = build_test_rom(0, 0, &[0x18, 0xFE]);
+    let inputs = [
+        JoypadState::empty(),
+        JoypadState::A,
+        JoypadState::A,
+        JoypadState::A | JoypadState::RIGHT,
+        JoypadState::empty(),
+    ];
+
+    let mut gb = crate::GameBoy::from_rom_bytes(rom.clone(), GameBoyConfig::default()).unwrap();
+    // frames before the recording are not counted
+    gb.clock_for_frame();
+    gb.start_input_recording();
+    for &state in &inputs {
+        gb.set_joypad_state(state);
+        gb.clock_for_frame();
+    }
+    let log = gb.take_input_log();
+    assert_eq!(
+        log,
+        [
+            (0, JoypadState::empty()),
+            (1, JoypadState::A),
+            (3, JoypadState::A | JoypadState::RIGHT),
+            (4, JoypadState::empty()),
+        ]
+    );
+    // recording stopped
+    assert!(gb.take_input_log().is_empty());
+
+    let mut gb = crate::GameBoy::from_rom_bytes(rom.clone(), GameBoyConfig::default()).unwrap();
+    gb.clock_for_frame();
+    gb.play_input_log(log.clone());
+    for &state in &inputs {
+        assert!(gb.is_playing_input_log());
+        gb.clock_for_frame();
+        assert_eq!(gb.joypad_state(), state);
+    }
+    assert!(!gb.is_playing_input_log());
+
+    // frames clocked by scanlines play the inputs the same way
+    let mut gb = crate::GameBoy::from_rom_bytes(rom.clone(), GameBoyConfig::default()).unwrap();
+    gb.clock_for_frame();
+    gb.play_input_log(log);
+    for &state in &inputs {
+        // the frame is complete when entering VBlank
+        while gb.clock_for_scanline() != 144 {}
+        assert_eq!(gb.joypad_state(), state);
+    }
+    assert!(!gb.is_playing_input_log());
+
+    // resuming from a breakpoint hit before the first cycle of the frame
+    // continues the same frame
+    let mut gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+    gb.add_breakpoint(0x100);
+    gb.start_input_recording();
+    gb.set_joypad_state(JoypadState::A);
+    assert!(matches!(gb.clock_for_frame(), CpuState::Breakpoint(_)));
+    gb.clock_for_frame();
+    gb.set_joypad_state(JoypadState::B);
+    gb.clock_for_frame();
+    assert_eq!(
+        gb.take_input_log(),
+        [(0, JoypadState::A), (1, JoypadState::B)]
+    );
+}
+
+#[test]
+fn run_frames_and_run_until() {
+    // `INC A`, `JR -3` (loop incrementing A)
+    let mut gb = test_gameboy(&[0x3C, 0x18, 0xFD]);
+
+    let screen_len = gb.screen_buffer().len();
+    assert_eq!(gb.run_frames(2).len(), screen_len);
+
+    // A wraps many times in a frame
+    assert_eq!(gb.run_until(|regs| regs.a == 0x42, 10), 1);
+    // the predicate is checked after every instruction
+    let mut instructions = 0;
+    assert_eq!(
+        gb.run_until(
+            |_| {
+                instructions += 1;
+                instructions == 3
+            },
+            10
+        ),
+        1
+    );
+    assert_eq!(gb.run_until(|_| false, 3), 3);
+}
+
+#[test]
+fn clock_for_cycles_carries_overshoot() {
+    // `JR -2` (loop of 12 cycles)
+    let mut gb = test_gameboy(&[0x18, 0xFE]);
+
+    // `JP 0x150` (16 cycles) and 7 instructions
+    assert_eq!(gb.clock_for_cycles(100), 100);
+    // 9 instructions, 8 cycles carried
+    assert_eq!(gb.clock_for_cycles(100), 108);
+    // 8 instructions, 4 cycles carried
+    assert_eq!(gb.clock_for_cycles(100), 96);
+    // fully covered by the carried cycles
+    assert_eq!(gb.clock_for_cycles(3), 0);
+    assert_eq!(gb.clock_for_cycles(1), 0);
+    assert_eq!(gb.clock_for_cycles(1), 12);
+
+    // stays in sync with the requested total across frames
+    let mut requested = 305;
+    let mut executed = 304 + 12;
+    for _ in 0..1000 {
+        requested += 1234;
+        executed += gb.clock_for_cycles(1234);
+        assert!(executed >= requested && executed - requested < 12);
+    }
+}
+
+#[test]
+fn total_cycles_and_frame_count() {
+    let mut gb = test_gameboy(&[0x18, 0xFE]);
+    assert_eq!(gb.total_cycles(), 0);
+
+    let executed = gb.clock_for_cycles(1000);
+    assert_eq!(gb.total_cycles(), executed);
+
+    let frames = gb.frame_count();
+    for _ in 0..5 {
+        gb.clock_for_frame();
+    }
+    assert_eq!(gb.frame_count(), frames + 5);
+
+    let mut state = Vec::new();
+    gb.save_state(&mut state).unwrap();
+    let total_cycles = gb.total_cycles();
+    let frame_count = gb.frame_count();
+
+    gb.run_frames(3);
+    assert_eq!(gb.frame_count(), frame_count + 3);
+    assert_eq!(gb.total_cycles(), total_cycles + 3 * 70224);
+
+    gb.load_state(std::io::Cursor::new(&state)).unwrap();
+    assert_eq!(gb.total_cycles(), total_cycles);
+    assert_eq!(gb.frame_count(), frame_count);
+}
+
+#[test]
+fn try_clock_for_frame_errors() {
+    use crate::EmulationError;
+
+    // illegal opcode
+    let mut gb = test_gameboy(&[0x00, 0xD3]);
+    assert_eq!(
+        gb.try_clock_for_frame(),
+        Err(EmulationError::IllegalInstruction {
+            opcode: 0xD3,
+            pc: 0x151
+        })
+    );
+
+    // `DI`, `JR -2`
+    let mut gb = test_gameboy(&[0xF3, 0x18, 0xFE]);
+    assert_eq!(
+        gb.try_clock_for_frame(),
+        Err(EmulationError::Deadlock { pc: 0x151 })
+    );
+
+    // `EI`, `JP 0x151`, `IE` is `0` on power on
+    let mut gb = test_gameboy(&[0xFB, 0xC3, 0x51, 0x01]);
+    assert_eq!(
+        gb.try_clock_for_frame(),
+        Err(EmulationError::Deadlock { pc: 0x151 })
+    );
+
+    // `LD A, 1`, `LDH (IE), A`, `EI`, `JR -2`, waiting for VBlank is fine
+    let mut rom = build_test_rom(0, 0, &[0x3E, 0x01, 0xE0, 0xFF, 0xFB, 0x18, 0xFE]);
+    // VBlank handler: `RETI`
+    rom[0x40] = 0xD9;
+    let mut gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+    for _ in 0..5 {
+        assert_eq!(gb.try_clock_for_frame(), Ok(()));
+    }
+}
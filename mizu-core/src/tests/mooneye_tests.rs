@@ -1,7 +1,17 @@
 use std::error::Error;
 
-fn mooneye_test(file_path: &str, is_dmg: bool) -> Result<(), Box<dyn Error>> {
-    let mut gb = crate::tests::TestingGameBoy::new(file_path, is_dmg).unwrap();
+/// Runs the test until the `LD B, B` breakpoint and checks the result in the
+/// registers, this is also used by other test suites following the same
+/// convention
+pub(super) fn mooneye_test(file_path: &str, is_dmg: bool) -> Result<(), Box<dyn Error>> {
+    // the STAT timing tests depend on the accurate STAT interrupt timing,
+    // so don't rely on the default
+    let config = crate::GameBoyConfig {
+        is_dmg,
+        stat_quirks: true,
+        ..crate::GameBoyConfig::default()
+    };
+    let mut gb = crate::tests::TestingGameBoy::with_config(file_path, config).unwrap();
 
     let regs = gb.clock_until_breakpoint();
 
@@ -168,6 +178,8 @@ mod acceptance {
         );
     }
 
+    // the STAT timing tests require `GameBoyConfig::stat_quirks`,
+    // which `mooneye_test` always enables
     mod ppu {
         mooneye_tests!("acceptance/ppu";
             //hblank_ly_scx_timing-GS,
@@ -1,3 +1,7 @@
+use std::error::Error;
+
+use super::mooneye_tests::mooneye_test;
+
 gb_tests!(
     brk; // clock until break
 
@@ -51,3 +55,38 @@ gb_tests!(
     0,
     4353720675538315229;
 );
+
+/// These tests report the result in the registers, in the same way as the
+/// mooneye tests, and they target CGB
+fn samesuite_registers_test(file_path: &str) -> Result<(), Box<dyn Error>> {
+    let file_path = format!("../test_roms/game-boy-test-roms/same-suite/{}", file_path);
+    mooneye_test(&file_path, false)
+}
+
+// writing to DIV resets the frame sequencer clock, delaying the next step
+#[test]
+fn apu_channel_1_stop_div() -> Result<(), Box<dyn Error>> {
+    samesuite_registers_test("apu/channel_1/channel_1_stop_div.gb")
+}
+
+#[test]
+fn apu_channel_1_volume_div() -> Result<(), Box<dyn Error>> {
+    samesuite_registers_test("apu/channel_1/channel_1_volume_div.gb")
+}
+
+#[test]
+fn apu_channel_3_stop_div() -> Result<(), Box<dyn Error>> {
+    samesuite_registers_test("apu/channel_3/channel_3_stop_div.gb")
+}
+
+// register accesses of the wave channel while it is playing and while the
+// DAC is on
+#[test]
+fn apu_channel_3_wave_ram_dac_on_rw() -> Result<(), Box<dyn Error>> {
+    samesuite_registers_test("apu/channel_3/channel_3_wave_ram_dac_on_rw.gb")
+}
+
+#[test]
+fn apu_channel_3_wave_ram_locked_write() -> Result<(), Box<dyn Error>> {
+    samesuite_registers_test("apu/channel_3/channel_3_wave_ram_locked_write.gb")
+}
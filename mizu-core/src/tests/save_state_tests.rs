@@ -1,3 +1,6 @@
+use super::{build_test_rom, test_gameboy};
+use crate::GameBoyConfig;
+
 /// We load the acid test, run it until the test finish, and we make sure it passes
 /// then we save the state, create a new emulation and load the state, the test
 /// should be passing
@@ -34,3 +37,377 @@ fn load_state() {
     let screen_buffer = gb.raw_screen_buffer();
     assert_eq!(crc.checksum(screen_buffer), CGB_CRC);
 }
+
+#[test]
+fn state_hash_detects_divergence() {
+    use crate::JoypadButton;
+
+    let rom = build_test_rom(0, 0, &[0x18, 0xFE]);
+    let mut gb1 = crate::GameBoy::from_rom_bytes(rom.clone(), GameBoyConfig::default()).unwrap();
+    let mut gb2 = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+    assert_eq!(gb1.state_hash(), gb2.state_hash());
+
+    for _ in 0..3 {
+        gb1.clock_for_frame();
+        gb2.clock_for_frame();
+        assert_eq!(gb1.state_hash(), gb2.state_hash());
+    }
+    // hashing does not change the state
+    assert_eq!(gb1.state_hash(), gb1.state_hash());
+
+    gb2.press_joypad(JoypadButton::A);
+    gb1.clock_for_frame();
+    gb2.clock_for_frame();
+    assert_ne!(gb1.state_hash(), gb2.state_hash());
+}
+
+#[test]
+fn load_state_error_policy() {
+    use crate::cpu::CpuBusProvider;
+    use crate::LoadErrorPolicy;
+
+    // MBC5+RAM+BATTERY
+    let rom = build_test_rom(0x1B, 2, &[0x18, 0xFE]);
+    let mut source_gb =
+        crate::GameBoy::from_rom_bytes(rom.clone(), GameBoyConfig::default()).unwrap();
+    for _ in 0..5 {
+        source_gb.clock_for_frame();
+    }
+    let mut state = Vec::new();
+    source_gb.save_state_uncompressed(&mut state).unwrap();
+    // cut in the middle of the bus data
+    state.truncate(state.len() / 2);
+
+    let new_gb = || {
+        let mut gb = crate::GameBoy::from_rom_bytes(rom.clone(), GameBoyConfig::default()).unwrap();
+        gb.bus.write(0x0000, 0x0A);
+        gb.bus.write(0xA000, 0x42);
+        gb.clock_for_frame();
+        gb
+    };
+
+    let mut gb = new_gb();
+    let hash_before = gb.state_hash();
+    assert!(gb
+        .load_state_with(std::io::Cursor::new(&state), LoadErrorPolicy::RollBack)
+        .is_err());
+    assert_eq!(gb.state_hash(), hash_before);
+    assert!(gb.sram_is_dirty());
+
+    let mut gb = new_gb();
+    assert!(gb
+        .load_state_with(std::io::Cursor::new(&state), LoadErrorPolicy::LeavePartial)
+        .is_err());
+    assert_ne!(gb.state_hash(), hash_before);
+
+    let mut gb = new_gb();
+    assert!(gb.sram_is_dirty());
+    assert!(gb
+        .load_state_with(std::io::Cursor::new(&state), LoadErrorPolicy::Reset)
+        .is_err());
+    assert_eq!(gb.cpu.registers().pc, 0x0100);
+    // the written SRAM is still not saved
+    assert!(gb.sram_is_dirty());
+    // the cartridge RAM is kept
+    gb.bus.write(0x0000, 0x0A);
+    assert_eq!(gb.bus.read_not_ticked(0xA000, None), 0x42);
+    // same as the power on state, other than the cartridge RAM
+    gb.bus.write(0xA000, 0x00);
+    gb.bus.write(0x0000, 0x00);
+    let fresh_gb = crate::GameBoy::from_rom_bytes(rom.clone(), GameBoyConfig::default()).unwrap();
+    assert_eq!(
+        save_state::save_object(&gb.cpu).unwrap(),
+        save_state::save_object(&fresh_gb.cpu).unwrap()
+    );
+}
+
+#[test]
+fn load_state_forced() {
+    let rom = build_test_rom(0, 0, &[0x18, 0xFE]);
+    let mut patched_rom = rom.clone();
+    // different title, same code
+    patched_rom[0x134] = b'B';
+    patched_rom[0x14d] = patched_rom[0x14d].wrapping_add(b'T' - b'B');
+
+    let mut gb = crate::GameBoy::from_rom_bytes(rom, GameBoyConfig::default()).unwrap();
+    let mut patched_gb =
+        crate::GameBoy::from_rom_bytes(patched_rom, GameBoyConfig::default()).unwrap();
+    gb.clock_for_frame();
+
+    let mut state = Vec::new();
+    gb.save_state(&mut state).unwrap();
+
+    assert!(matches!(
+        patched_gb.load_state(std::io::Cursor::new(&state)),
+        Err(crate::SaveError::InvalidCartridgeHash)
+    ));
+    patched_gb
+        .load_state_forced(std::io::Cursor::new(&state))
+        .unwrap();
+    assert_eq!(
+        save_state::save_object(&patched_gb.cpu).unwrap(),
+        save_state::save_object(&gb.cpu).unwrap()
+    );
+
+    // the magic is still checked
+    let mut invalid_state = state.clone();
+    invalid_state[0] = 0;
+    assert!(matches!(
+        patched_gb.load_state_forced(std::io::Cursor::new(&invalid_state)),
+        Err(crate::SaveError::InvalidSaveStateHeader)
+    ));
+
+    // normal loading is not affected after forced loading
+    assert!(matches!(
+        patched_gb.load_state(std::io::Cursor::new(&state)),
+        Err(crate::SaveError::InvalidCartridgeHash)
+    ));
+
+    // a different mapper fails to load, and the state is restored
+    let mbc1_rom = build_test_rom(1, 0, &[0x18, 0xFE]);
+    let mut mbc1_gb = crate::GameBoy::from_rom_bytes(mbc1_rom, GameBoyConfig::default()).unwrap();
+    let cpu_before = save_state::save_object(&mbc1_gb.cpu).unwrap();
+    assert!(matches!(
+        mbc1_gb.load_state_forced(std::io::Cursor::new(&state)),
+        Err(crate::SaveError::SaveStateError(
+            save_state::Error::CorruptField("Cartridge::cartridge_type")
+        ))
+    ));
+    assert_eq!(save_state::save_object(&mbc1_gb.cpu).unwrap(), cpu_before);
+}
+
+#[test]
+fn save_state_migration() {
+    use crate::{migrate_save_state, SaveError, SAVE_STATE_VERSION};
+
+    let data = vec![1, 2, 3];
+    assert_eq!(
+        migrate_save_state(SAVE_STATE_VERSION, data.clone()).unwrap(),
+        data
+    );
+
+    // 1 -> 2 does not change the data, which is not valid for 2 -> 3
+    assert!(matches!(
+        migrate_save_state(1, data.clone()),
+        Err(SaveError::SaveStateError(_))
+    ));
+    assert!(matches!(
+        migrate_save_state(2, data),
+        Err(SaveError::SaveStateError(_))
+    ));
+}
+
+#[test]
+fn config_save_block() {
+    use crate::{ApuQuality, RamFillPattern};
+
+    // all the fields are kept, except the settings of the host
+    let config = GameBoyConfig {
+        is_dmg: true,
+        apu_quality: ApuQuality::Fast,
+        sample_rate: 48000,
+        ram_fill: RamFillPattern::Seeded(5),
+        sgb: true,
+        force_dmg_mode_on_cgb: true,
+        stat_quirks: false,
+        writable_rom: true,
+        ..GameBoyConfig::default()
+    };
+    let data = save_state::save_object(&config).unwrap();
+    let mut loaded = GameBoyConfig {
+        sample_rate: 22050,
+        ..GameBoyConfig::default()
+    };
+    save_state::load_object(&mut loaded, &data).unwrap();
+    assert_eq!(
+        format!("{:?}", loaded),
+        format!(
+            "{:?}",
+            GameBoyConfig {
+                apu_quality: ApuQuality::Accurate,
+                sample_rate: 22050,
+                ..config
+            }
+        )
+    );
+
+    // data of a newer configuration is rejected
+    let mut newer = data.clone();
+    newer.push(0);
+    let len = u32::from_le_bytes(newer[..4].try_into().unwrap()) + 1;
+    newer[..4].copy_from_slice(&len.to_le_bytes());
+    assert!(matches!(
+        save_state::load_object(&mut loaded, &newer),
+        Err(save_state::Error::TrailingData(1))
+    ));
+}
+
+#[test]
+fn load_state_older_and_newer_versions() {
+    let mut gb = test_gameboy(&[0x18, 0xFE]);
+
+    let mut state = Vec::new();
+    gb.save_state(&mut state).unwrap();
+
+    // the version is stored as `u64` after the magic
+    let with_version = |version: usize| {
+        let mut state = state.clone();
+        state[4..12].copy_from_slice(&(version as u64).to_le_bytes());
+        state
+    };
+
+    assert!(matches!(
+        gb.load_state(std::io::Cursor::new(with_version(
+            crate::SAVE_STATE_VERSION + 1
+        ))),
+        Err(crate::SaveError::UnmatchedSaveErrorVersion(_))
+    ));
+    gb.load_state(std::io::Cursor::new(state)).unwrap();
+}
+
+/// Saved by `mizu-core` 1.0.0 (save state version 2) after running 5 frames
+/// of `build_test_rom(0x1A, 2, VERSION_2_PROGRAM)` with `is_dmg`
+const VERSION_2_STATE: &[u8] = include_bytes!("save_state_v2.state");
+/// Enables the cartridge RAM, writes `0x42` to `0xA010` and `0x99` to `0xC123`
+/// then increments `B` in a loop
+const VERSION_2_PROGRAM: &[u8] = &[
+    0x3E, 0x0A, 0xEA, 0x00, 0x00, 0x3E, 0x42, 0xEA, 0x10, 0xA0, 0x3E, 0x99, 0xEA, 0x23, 0xC1, 0x06,
+    0x00, 0x04, 0x18, 0xFD,
+];
+
+#[test]
+fn load_state_version_2() {
+    let rom = build_test_rom(0x1A, 2, VERSION_2_PROGRAM);
+    let config = GameBoyConfig {
+        is_dmg: true,
+        ..GameBoyConfig::default()
+    };
+    let mut gb = crate::GameBoy::from_rom_bytes(rom, config).unwrap();
+
+    gb.load_state(std::io::Cursor::new(VERSION_2_STATE))
+        .unwrap();
+    assert_eq!(gb.bus.read_not_ticked(0xA010, None), 0x42);
+    assert_eq!(gb.bus.read_not_ticked(0xC123, None), 0x99);
+    // in the `INC B` loop
+    assert!((0x161..=0x163).contains(&gb.cpu.registers().pc));
+
+    // the state continues running, and can be saved in the current version
+    let b = gb.cpu.registers().b;
+    gb.clock_for_frame();
+    assert_ne!(gb.cpu.registers().b, b);
+    let mut state = Vec::new();
+    gb.save_state(&mut state).unwrap();
+    gb.load_state(std::io::Cursor::new(state)).unwrap();
+}
+
+#[test]
+fn quick_save_slots() {
+    // increment `A` and write it to the background tile map in a loop
+    let mut gb = test_gameboy(&[0x3C, 0xEA, 0x00, 0x98, 0x18, 0xFA]);
+    gb.clock_for_frame();
+
+    // empty slot, nothing changes
+    let a = gb.cpu.registers().a;
+    assert!(!gb.quick_load(3).unwrap());
+    assert_eq!(gb.cpu.registers().a, a);
+
+    gb.quick_save(3);
+    let saved_a = gb.cpu.registers().a;
+    let saved_tile = gb.bus.read_not_ticked(0x9800, None);
+
+    gb.clock_for_frame();
+    gb.quick_save(crate::QUICK_SAVE_SLOTS as u8 - 1);
+    let last_a = gb.cpu.registers().a;
+    gb.clock_for_frame();
+    assert_ne!(gb.cpu.registers().a, saved_a);
+
+    // can be loaded multiple times
+    for _ in 0..2 {
+        assert!(gb.quick_load(3).unwrap());
+        assert_eq!(gb.cpu.registers().a, saved_a);
+        assert_eq!(gb.bus.read_not_ticked(0x9800, None), saved_tile);
+        gb.clock_for_frame();
+    }
+
+    assert!(gb.quick_load(crate::QUICK_SAVE_SLOTS as u8 - 1).unwrap());
+    assert_eq!(gb.cpu.registers().a, last_a);
+}
+
+#[test]
+fn save_state_uncompressed() {
+    // increment `A` and write it to the background tile map in a loop
+    let mut gb = test_gameboy(&[0x3C, 0xEA, 0x00, 0x98, 0x18, 0xFA]);
+    gb.clock_for_frame();
+
+    let mut compressed = Vec::new();
+    gb.save_state(&mut compressed).unwrap();
+    let mut uncompressed = Vec::new();
+    gb.save_state_uncompressed(&mut uncompressed).unwrap();
+    assert!(uncompressed.len() > compressed.len());
+
+    // both contain the same state
+    let mut other = crate::GameBoy::from_rom_bytes(
+        build_test_rom(0, 0, &[0x3C, 0xEA, 0x00, 0x98, 0x18, 0xFA]),
+        GameBoyConfig::default(),
+    )
+    .unwrap();
+    other
+        .load_state(std::io::Cursor::new(&uncompressed))
+        .unwrap();
+    assert_eq!(other.snapshot().data, gb.snapshot().data);
+    other.load_state(std::io::Cursor::new(&compressed)).unwrap();
+    assert_eq!(other.snapshot().data, gb.snapshot().data);
+
+    // trailing data is still detected without the compression stream
+    let mut invalid_state = uncompressed;
+    invalid_state.push(0);
+    assert!(matches!(
+        other.load_state(std::io::Cursor::new(&invalid_state)),
+        Err(crate::SaveError::SaveStateError(
+            save_state::Error::TrailingData(1)
+        ))
+    ));
+}
+
+#[test]
+fn snapshot_rewind() {
+    // increment `A` and write it to the background tile map in a loop
+    //   loop: INC A
+    //         LD (0x9800), A
+    //         JR loop
+    let mut gb = test_gameboy(&[0x3C, 0xEA, 0x00, 0x98, 0x18, 0xFA]);
+
+    // ring of snapshots, reusing the buffers
+    let mut ring = vec![crate::Snapshot::default(); 60];
+    for snapshot in ring.iter_mut() {
+        gb.snapshot_into(snapshot);
+        gb.clock_for_frame();
+    }
+    let end_screen = gb.screen_buffer().to_vec();
+
+    let mut restored = crate::Snapshot::default();
+    for snapshot in ring.iter().rev() {
+        gb.restore(snapshot);
+        gb.snapshot_into(&mut restored);
+        assert_eq!(restored.data, snapshot.data);
+    }
+
+    // replaying from the first snapshot reaches the same state
+    gb.restore(&ring[0]);
+    for snapshot in ring.iter().skip(1) {
+        gb.clock_for_frame();
+        assert_eq!(gb.snapshot().data, snapshot.data);
+    }
+    gb.clock_for_frame();
+    assert_eq!(gb.screen_buffer(), end_screen);
+}
+
+#[test]
+fn savable_components_round_trip() {
+    use crate::{Color, RamFillPattern};
+    use save_state::assert_round_trip;
+
+    assert_round_trip(&Color::from_rgb(0xF8, 0x80, 0x08));
+    assert_round_trip(&RamFillPattern::Ones);
+    assert_round_trip(&RamFillPattern::Seeded(0x1234_5678));
+}
@@ -29,6 +29,19 @@ impl TimerControl {
     }
 }
 
+/// The state of the timer registers, see [`GameBoy::timer_state`](crate::GameBoy::timer_state).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TimerState {
+    /// The full 16 bit internal divider, `DIV` is the top 8 bits of it.
+    pub divider: u16,
+    /// The timer counter `TIMA`.
+    pub tima: u8,
+    /// The timer modulo (reload value) `TMA`.
+    pub tma: u8,
+    /// The timer control `TAC`, only the lower 3 bits are used.
+    pub tac: u8,
+}
+
 #[derive(Savable)]
 pub struct Timer {
     divider: u16,
@@ -74,6 +87,42 @@ impl Timer {
         }
     }
 
+    /// Returns the full 16 bit internal divider
+    #[cfg(test)]
+    pub fn divider(&self) -> u16 {
+        self.divider
+    }
+
+    /// Sets the full 16 bit internal divider directly, unlike [`write_div`]
+    /// which resets it, this does not trigger a timer increment on the
+    /// falling edge of the divider bit.
+    ///
+    /// [`write_div`]: Self::write_div
+    pub fn set_divider(&mut self, value: u16) {
+        self.divider = value;
+    }
+
+    pub fn state(&self) -> TimerState {
+        TimerState {
+            divider: self.divider,
+            tima: self.timer_counter,
+            tma: self.timer_reload,
+            tac: self.timer_control.bits(),
+        }
+    }
+
+    /// Sets all the timer registers directly, without triggering timer
+    /// increments from the divider bit changes, any pending overflow
+    /// reload and interrupt is cancelled.
+    pub fn set_state(&mut self, state: TimerState) {
+        self.divider = state.divider;
+        self.timer_counter = state.tima;
+        self.timer_reload = state.tma;
+        self.timer_control = TimerControl::from_bits_truncate(state.tac);
+        self.interrupt_next = false;
+        self.during_interrupt = false;
+    }
+
     pub fn read_timer_counter(&self) -> u8 {
         self.timer_counter
     }
@@ -160,3 +209,74 @@ impl Timer {
         (self.divider >> bit) & 1 == 1
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Timer, TimerState};
+    use crate::memory::{InterruptManager, InterruptType};
+
+    struct NoInterrupts;
+
+    impl InterruptManager for NoInterrupts {
+        fn request_interrupt(&mut self, _interrupt: InterruptType) {}
+    }
+
+    #[derive(Default)]
+    struct TimerInterrupt(bool);
+
+    impl InterruptManager for TimerInterrupt {
+        fn request_interrupt(&mut self, interrupt: InterruptType) {
+            self.0 |= interrupt == InterruptType::Timer;
+        }
+    }
+
+    #[test]
+    fn set_full_divider() {
+        let mut timer = Timer::default();
+
+        timer.set_divider(0x1234);
+        assert_eq!(timer.divider(), 0x1234);
+        assert_eq!(timer.read_div(), 0x12);
+
+        timer.clock_divider(&mut NoInterrupts);
+        assert_eq!(timer.divider(), 0x1238);
+
+        timer.write_div(0x55);
+        assert_eq!(timer.divider(), 0);
+    }
+
+    #[test]
+    fn get_and_set_state() {
+        let mut timer = Timer::default();
+        let state = TimerState {
+            divider: 0x12FC,
+            tima: 0xFF,
+            tma: 0x80,
+            // enabled, increment every 16 clocks
+            tac: 0x05,
+        };
+
+        timer.set_state(state);
+        assert_eq!(timer.state(), state);
+        assert_eq!(timer.read_div(), 0x12);
+        assert_eq!(timer.read_control(), 0xFD);
+
+        // the divider bit 3 falls, so TIMA overflows
+        let mut interrupt = TimerInterrupt::default();
+        timer.clock_divider(&mut interrupt);
+        assert_eq!(timer.read_timer_counter(), 0x00);
+        assert!(!interrupt.0);
+        timer.clock_divider(&mut interrupt);
+        assert!(interrupt.0);
+        assert_eq!(timer.read_timer_counter(), 0x80);
+
+        // restoring the same state gives the same result
+        let mut other = Timer::default();
+        other.set_state(state);
+        let mut interrupt = TimerInterrupt::default();
+        other.clock_divider(&mut interrupt);
+        other.clock_divider(&mut interrupt);
+        assert!(interrupt.0);
+        assert_eq!(other.state(), timer.state());
+    }
+}
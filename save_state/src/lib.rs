@@ -2,10 +2,14 @@ pub use save_state_derive::*;
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use paste::paste;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::From;
+use std::hash::{BuildHasher, Hash};
 use std::io::{
     self, Cursor, Error as ioError, ErrorKind as ioErrorKind, Read, Result as ioResult, Write,
 };
+use std::rc::Rc;
 
 type CiboriumSerIoError = ciborium::ser::Error<io::Error>;
 type CiboriumDeIoError = ciborium::de::Error<io::Error>;
@@ -27,6 +31,17 @@ where
     ciborium::de::from_reader(reader).map_err(|e| e.into())
 }
 
+/// Returns the number of bytes written by `save`, used by the derive macro
+/// to compute the size of fields with custom `#[savable(with = "path")]` functions
+pub fn save_size_with<F>(save: F) -> Result<u64>
+where
+    F: FnOnce(&mut dyn Write) -> Result<()>,
+{
+    let mut counter = Counter::default();
+    save(&mut counter)?;
+    Ok(counter.counter)
+}
+
 pub fn serialized_size<T>(value: &T) -> Result<u64>
 where
     T: serde::Serialize,
@@ -121,6 +136,28 @@ pub fn load_object<T: Savable>(object: &mut T, data: &[u8]) -> Result<()> {
     }
 }
 
+/// Saves `value`, loads it into a fresh [`Default`] object and asserts that
+/// both are equal, and that [`Savable::save_size`] matches the number of
+/// bytes written.
+///
+/// Useful in tests to make sure the `save` and `load` of an object stay in sync.
+#[cfg(any(test, feature = "test-util"))]
+pub fn assert_round_trip<T: Savable + PartialEq + Default>(value: &T) {
+    let data = save_object(value).expect("failed to save the object");
+    assert_eq!(
+        value.save_size().expect("failed to compute the save size"),
+        data.len() as u64,
+        "`save_size` does not match the number of bytes written"
+    );
+
+    let mut loaded = T::default();
+    load_object(&mut loaded, &data).expect("failed to load the object");
+    assert!(
+        loaded == *value,
+        "the loaded object is not equal to the saved one"
+    );
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Io Eror: {0}")]
@@ -133,6 +170,8 @@ pub enum Error {
     TrailingData(u64),
     #[error("Enum could not be loaded correctly due to corrupted data ({0})")]
     InvalidEnumVariant(usize),
+    #[error("Field `{0}` could not be loaded correctly due to corrupted data")]
+    CorruptField(&'static str),
 }
 
 impl From<ioError> for Error {
@@ -331,6 +370,132 @@ where
     }
 }
 
+// the length is saved first, then the entries in iteration order, since the
+// order is not deterministic, loading inserts the entries one by one and does
+// not depend on it
+impl<K, V, S> Savable for HashMap<K, V, S>
+where
+    K: Savable + Default + Eq + Hash,
+    V: Savable + Default,
+    S: BuildHasher,
+{
+    fn save<W: Write>(&self, mut writer: &mut W) -> Result<()> {
+        self.len().save(&mut writer)?;
+        for (key, value) in self {
+            key.save(&mut writer)?;
+            value.save(&mut writer)?;
+        }
+        Ok(())
+    }
+
+    fn load<R: Read>(&mut self, mut reader: &mut R) -> Result<()> {
+        let mut len = 0usize;
+        len.load(&mut reader)?;
+
+        self.clear();
+        for _ in 0..len {
+            let mut key = K::default();
+            let mut value = V::default();
+            key.load(&mut reader)?;
+            value.load(&mut reader)?;
+            self.insert(key, value);
+        }
+        Ok(())
+    }
+}
+
+impl<T, S> Savable for HashSet<T, S>
+where
+    T: Savable + Default + Eq + Hash,
+    S: BuildHasher,
+{
+    fn save<W: Write>(&self, mut writer: &mut W) -> Result<()> {
+        self.len().save(&mut writer)?;
+        for element in self {
+            element.save(&mut writer)?;
+        }
+        Ok(())
+    }
+
+    fn load<R: Read>(&mut self, mut reader: &mut R) -> Result<()> {
+        let mut len = 0usize;
+        len.load(&mut reader)?;
+
+        self.clear();
+        for _ in 0..len {
+            let mut element = T::default();
+            element.load(&mut reader)?;
+            self.insert(element);
+        }
+        Ok(())
+    }
+}
+
+impl<T> Savable for VecDeque<T>
+where
+    T: Savable + Default,
+{
+    fn save<W: Write>(&self, mut writer: &mut W) -> Result<()> {
+        self.len().save(&mut writer)?;
+        for element in self {
+            element.save(&mut writer)?;
+        }
+        Ok(())
+    }
+
+    fn load<R: Read>(&mut self, mut reader: &mut R) -> Result<()> {
+        let mut len = 0usize;
+        len.load(&mut reader)?;
+
+        self.clear();
+        for _ in 0..len {
+            let mut element = T::default();
+            element.load(&mut reader)?;
+            self.push_back(element);
+        }
+        Ok(())
+    }
+}
+
+// transparent, the same as saving `T` directly
+impl<T> Savable for Box<T>
+where
+    T: Savable,
+{
+    fn save<W: Write>(&self, writer: &mut W) -> Result<()> {
+        self.as_ref().save(writer)
+    }
+
+    fn load<R: Read>(&mut self, reader: &mut R) -> Result<()> {
+        self.as_mut().load(reader)
+    }
+
+    fn save_size(&self) -> Result<u64> {
+        self.as_ref().save_size()
+    }
+}
+
+// the inner value is saved for every `Rc`, so sharing is not preserved, a
+// value shared by multiple saved `Rc`s is saved (and loaded) multiple times.
+// Loading writes into the existing `RefCell`, so other clones of the `Rc`
+// see the loaded value.
+impl<T> Savable for Rc<RefCell<T>>
+where
+    T: Savable,
+{
+    fn save<W: Write>(&self, writer: &mut W) -> Result<()> {
+        self.borrow().save(writer)
+    }
+
+    fn load<R: Read>(&mut self, reader: &mut R) -> Result<()> {
+        self.borrow_mut().load(reader)
+    }
+
+    fn save_size(&self) -> Result<u64> {
+        self.borrow().save_size()
+    }
+}
+
 impl<T> Savable for std::marker::PhantomData<T> {
     fn save<W: Write>(&self, _writer: &mut W) -> Result<()> {
         Ok(())
@@ -350,3 +515,138 @@ impl Savable for () {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{assert_round_trip, load_object, save_object};
+    use std::cell::RefCell;
+    use std::collections::{HashMap, HashSet, VecDeque};
+    use std::rc::Rc;
+
+    #[test]
+    fn hash_map_round_trip() {
+        let map = (0..100u16)
+            .map(|i| (i, (i as u32 * 3, i % 2 == 0)))
+            .collect::<HashMap<_, _>>();
+
+        let data = save_object(&map).unwrap();
+        // some data in the map before loading should be removed
+        let mut loaded = HashMap::from([(1000, (0, false))]);
+        load_object(&mut loaded, &data).unwrap();
+        assert_eq!(loaded, map);
+
+        let empty = HashMap::<u8, u8>::new();
+        let data = save_object(&empty).unwrap();
+        let mut loaded = HashMap::from([(1, 2)]);
+        load_object(&mut loaded, &data).unwrap();
+        assert!(loaded.is_empty());
+    }
+
+    #[test]
+    fn hash_set_round_trip() {
+        let set = (0..100u64).map(|i| i * 7).collect::<HashSet<_>>();
+
+        let data = save_object(&set).unwrap();
+        let mut loaded = HashSet::from([1]);
+        load_object(&mut loaded, &data).unwrap();
+        assert_eq!(loaded, set);
+
+        let empty = HashSet::<u8>::new();
+        let data = save_object(&empty).unwrap();
+        let mut loaded = HashSet::from([1]);
+        load_object(&mut loaded, &data).unwrap();
+        assert!(loaded.is_empty());
+    }
+
+    #[test]
+    fn vec_deque_round_trip() {
+        let mut deque = VecDeque::new();
+        for i in 0..50u32 {
+            deque.push_back(i);
+            deque.push_front(1000 + i);
+        }
+
+        let data = save_object(&deque).unwrap();
+        let mut loaded = VecDeque::new();
+        load_object(&mut loaded, &data).unwrap();
+        assert_eq!(loaded, deque);
+        assert_eq!(loaded.front(), Some(&1049));
+        assert_eq!(loaded.back(), Some(&49));
+
+        let empty = VecDeque::<u8>::new();
+        let data = save_object(&empty).unwrap();
+        let mut loaded = VecDeque::from([1]);
+        load_object(&mut loaded, &data).unwrap();
+        assert!(loaded.is_empty());
+    }
+
+    #[test]
+    fn box_round_trip() {
+        let boxed = Box::new((5u32, [1u8, 2, 3]));
+
+        let data = save_object(&boxed).unwrap();
+        // transparent
+        assert_eq!(data, save_object(&(5u32, [1u8, 2, 3])).unwrap());
+
+        let mut loaded = Box::new((0u32, [0u8; 3]));
+        load_object(&mut loaded, &data).unwrap();
+        assert_eq!(loaded, boxed);
+    }
+
+    #[test]
+    fn rc_ref_cell_round_trip() {
+        let shared = Rc::new(RefCell::new(vec![1u16, 2, 3]));
+        let data = save_object(&shared).unwrap();
+        assert_eq!(data, save_object(&vec![1u16, 2, 3]).unwrap());
+
+        let mut loaded = Rc::new(RefCell::new(Vec::<u16>::new()));
+        let other = loaded.clone();
+        load_object(&mut loaded, &data).unwrap();
+        assert_eq!(*loaded.borrow(), [1, 2, 3]);
+        // the existing value is updated
+        assert_eq!(*other.borrow(), [1, 2, 3]);
+
+        // sharing is not preserved, each `Rc` is saved separately
+        let pair = (shared.clone(), shared);
+        let data = save_object(&pair).unwrap();
+        let mut loaded = (
+            Rc::new(RefCell::new(Vec::<u16>::new())),
+            Rc::new(RefCell::new(Vec::new())),
+        );
+        load_object(&mut loaded, &data).unwrap();
+        assert!(!Rc::ptr_eq(&loaded.0, &loaded.1));
+        assert_eq!(loaded.0, loaded.1);
+    }
+
+    #[test]
+    fn assert_round_trip_values() {
+        assert_round_trip(&(1u8, 0x1234u16, true));
+        assert_round_trip(&vec![1u32, 2, 3]);
+        assert_round_trip(&HashMap::from([(1u8, 2u64), (3, 4)]));
+    }
+
+    #[test]
+    #[should_panic(expected = "the loaded object is not equal to the saved one")]
+    fn assert_round_trip_detects_lost_field() {
+        use super::{Result, Savable};
+
+        /// Only saves `a`, `b` is lost on load
+        #[derive(Default, PartialEq)]
+        struct Partial {
+            a: u8,
+            b: u8,
+        }
+
+        impl Savable for Partial {
+            fn save<W: std::io::Write>(&self, writer: &mut W) -> Result<()> {
+                self.a.save(writer)
+            }
+
+            fn load<R: std::io::Read>(&mut self, reader: &mut R) -> Result<()> {
+                self.a.load(reader)
+            }
+        }
+
+        assert_round_trip(&Partial { a: 1, b: 2 });
+    }
+}
@@ -1,4 +1,4 @@
-use syn::{Attribute, DeriveInput, Meta, NestedMeta, Result};
+use syn::{Attribute, DeriveInput, Lit, Meta, NestedMeta, Path, Result};
 
 fn parse_savable_attr(attr: &Attribute) -> Result<Vec<NestedMeta>> {
     if !attr.path.is_ident("savable") {
@@ -60,37 +60,67 @@ impl ContainerAttrs {
 pub struct FieldAttrs {
     pub use_serde: bool,
     pub skip: bool,
+    /// Reset the skipped field to `Default::default()` on load, instead of
+    /// keeping its current value
+    pub default: bool,
+    /// A module path containing `save` and `load` functions to use
+    /// for this field instead of `Savable`
+    pub with: Option<Path>,
 }
 
 impl FieldAttrs {
     pub fn new(input: &syn::Field) -> Result<Self> {
         let mut use_serde = false;
         let mut skip = false;
+        let mut default = false;
+        let mut with = None;
 
         for meta_item in input.attrs.iter().flat_map(parse_savable_attr).flatten() {
             match &meta_item {
                 NestedMeta::Meta(Meta::Path(path)) if path.is_ident("serde") => {
-                    if skip {
+                    if skip || with.is_some() {
                         return Err(syn::Error::new_spanned(
                             meta_item,
-                            "cannot have `skip` and `serde` at the same time",
+                            "only one of `skip`, `serde` and `with` can be used at the same time",
                         ));
                     }
                     use_serde = true;
                 }
                 NestedMeta::Meta(Meta::Path(path)) if path.is_ident("skip") => {
-                    if use_serde {
+                    if use_serde || with.is_some() {
                         return Err(syn::Error::new_spanned(
                             meta_item,
-                            "cannot have `skip` and `serde` at the same time",
+                            "only one of `skip`, `serde` and `with` can be used at the same time",
                         ));
                     }
                     skip = true;
                 }
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("default") => {
+                    default = true;
+                }
+                NestedMeta::Meta(Meta::NameValue(name_value))
+                    if name_value.path.is_ident("with") =>
+                {
+                    if use_serde || skip || with.is_some() {
+                        return Err(syn::Error::new_spanned(
+                            meta_item,
+                            "only one of `skip`, `serde` and `with` can be used at the same time",
+                        ));
+                    }
+                    match &name_value.lit {
+                        Lit::Str(lit) => with = Some(lit.parse::<Path>()?),
+                        other => {
+                            return Err(syn::Error::new_spanned(
+                                other,
+                                "expected a module path string #[savable(with = \"path\")]",
+                            ))
+                        }
+                    }
+                }
                 NestedMeta::Meta(other) => {
                     return Err(syn::Error::new_spanned(
                         other,
-                        "exected #[savable(serde)] or #[savable(skip)]",
+                        "exected #[savable(serde)], #[savable(skip)], #[savable(skip, default)] or #[savable(with = \"path\")]",
                     ));
                 }
                 NestedMeta::Lit(lit) => {
@@ -102,6 +132,18 @@ impl FieldAttrs {
             }
         }
 
-        Ok(Self { use_serde, skip })
+        if default && !skip {
+            return Err(syn::Error::new_spanned(
+                input,
+                "`default` can only be used with `skip`, #[savable(skip, default)]",
+            ));
+        }
+
+        Ok(Self {
+            use_serde,
+            skip,
+            default,
+            with,
+        })
     }
 }
@@ -70,7 +70,9 @@ fn impl_fields_for_save(
         .iter()
         .zip(idents)
         .map(|(f, ident)| {
-            if f.attrs.use_serde {
+            if let Some(with) = &f.attrs.with {
+                quote!(#with::save(#ident_prefix #ident, &mut writer)?;)
+            } else if f.attrs.use_serde {
                 quote!(::save_state::serialize_into(&mut writer, #ident_prefix #ident)?;)
             } else {
                 // bitflags Flag is in the form of `Flag(InternalFlag)` and `InternalFlag` is the integer inside
@@ -92,7 +94,9 @@ fn impl_fields_for_load(
         .iter()
         .zip(idents)
         .map(|(f, ident)| {
-            if f.attrs.use_serde {
+            if let Some(with) = &f.attrs.with {
+                quote!(#with::load(#ident_prefix #ident, &mut reader)?;)
+            } else if f.attrs.use_serde {
                 quote!(let _ = ::std::mem::replace(#ident_prefix #ident, ::save_state::deserialize_from(&mut reader)?);)
             } else {
                 // bitflags Flag is in the form of `Flag(InternalFlag)` and `InternalFlag` is the integer inside
@@ -119,7 +123,9 @@ fn get_fields_impl_size_sum(
         .iter()
         .zip(idents.iter())
         .map(|(f, ident)| {
-            if f.attrs.use_serde {
+            if let Some(with) = &f.attrs.with {
+                quote!(::save_state::save_size_with(|mut writer| #with::save(#ident_prefix #ident, &mut writer))?)
+            } else if f.attrs.use_serde {
                 quote!(::save_state::serialized_size(#ident_prefix #ident)?)
             } else {
                 // bitflags Flag is in the form of `Flag(InternalFlag)` and `InternalFlag` is the integer inside
@@ -147,6 +153,7 @@ fn impl_for_struct(container: &Container, fields: &Fields) -> Result<TokenStream
 
     let save_fields = impl_fields_for_save(fields, quote!(&self.), container.attrs.bitflags);
     let load_fields = impl_fields_for_load(fields, quote!(&mut self.), container.attrs.bitflags);
+    let default_idents = fields.default_idents();
     let size_sum = get_fields_impl_size_sum(fields, quote!(&self.), container.attrs.bitflags);
     let (impl_generics, ty_generics, where_clause) = container.generics.split_for_impl();
 
@@ -169,6 +176,7 @@ fn impl_for_struct(container: &Container, fields: &Fields) -> Result<TokenStream
                 mut reader: &mut R,
             ) -> ::save_state::Result<()> {
                 #(#load_fields)*
+                #(self.#default_idents = ::std::default::Default::default();)*
                 ::std::result::Result::Ok(())
             }
 
@@ -22,6 +22,8 @@ pub enum FieldsLocation {
 pub struct Fields {
     pub all_fields: Vec<Field>,
     pub unskipped_fields: Vec<Field>,
+    /// Skipped fields that are reset to `Default::default()` on load
+    pub default_fields: Vec<Field>,
     pub fields_type: FieldsType,
     pub location: FieldsLocation,
 }
@@ -47,9 +49,16 @@ impl Fields {
             .cloned()
             .collect();
 
+        let default_fields = all_fields
+            .iter()
+            .filter(|&f| f.attrs.default)
+            .cloned()
+            .collect();
+
         Ok(Self {
             all_fields,
             unskipped_fields,
+            default_fields,
             fields_type,
             location,
         })
@@ -59,6 +68,10 @@ impl Fields {
         self.formatted_idents(&self.unskipped_fields)
     }
 
+    pub fn default_idents(&self) -> Vec<TokenStream2> {
+        self.formatted_idents(&self.default_fields)
+    }
+
     pub fn all_idents(&self) -> Vec<TokenStream2> {
         self.formatted_idents(&self.all_fields)
     }
@@ -328,6 +341,11 @@ impl Container {
             relevant_type_params: HashSet::new(),
             associated_type_usage: Vec::new(),
         };
+        let mut default_visitor = FindTyParams {
+            all_type_params,
+            relevant_type_params: HashSet::new(),
+            associated_type_usage: Vec::new(),
+        };
 
         match &data {
             ContainerData::Struct(fields) => {
@@ -340,7 +358,7 @@ impl Container {
                     fields
                         .unskipped_fields
                         .iter()
-                        .filter(|f| !f.attrs.use_serde)
+                        .filter(|f| !f.attrs.use_serde && f.attrs.with.is_none())
                         .for_each(|f| savable_visitor.visit_type(&f.ty));
 
                     fields
@@ -349,6 +367,11 @@ impl Container {
                         .filter(|f| f.attrs.use_serde)
                         .for_each(|f| serde_visitor.visit_type(&f.ty));
                 }
+
+                fields
+                    .default_fields
+                    .iter()
+                    .for_each(|f| default_visitor.visit_type(&f.ty));
             }
             ContainerData::Enum(variants) => {
                 let all_fields = variants.iter().flat_map(|v| &v.fields.unskipped_fields);
@@ -358,7 +381,7 @@ impl Container {
                 } else {
                     all_fields
                         .clone()
-                        .filter(|f| !f.attrs.use_serde)
+                        .filter(|f| !f.attrs.use_serde && f.attrs.with.is_none())
                         .for_each(|f| savable_visitor.visit_type(&f.ty));
 
                     all_fields
@@ -376,6 +399,8 @@ impl Container {
             parse_quote!(::serde::Serialize),
             parse_quote!(::serde::de::DeserializeOwned),
         ]));
+        predicates
+            .extend(default_visitor.generate_bounds(vec![parse_quote!(::std::default::Default)]));
         generics
     }
 }
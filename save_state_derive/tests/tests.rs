@@ -2,3 +2,98 @@
 fn a() {
     println!("TODO: add tests")
 }
+
+mod duration_codec {
+    use save_state::Savable;
+    use std::io::{Read, Write};
+    use std::time::Duration;
+
+    pub fn save<W: Write>(value: &Duration, writer: &mut W) -> save_state::Result<()> {
+        value.as_secs().save(writer)?;
+        value.subsec_nanos().save(writer)
+    }
+
+    pub fn load<R: Read>(value: &mut Duration, reader: &mut R) -> save_state::Result<()> {
+        let mut secs = 0u64;
+        let mut nanos = 0u32;
+        secs.load(reader)?;
+        nanos.load(reader)?;
+        *value = Duration::new(secs, nanos);
+        Ok(())
+    }
+}
+
+#[test]
+fn custom_with_functions() {
+    use save_state::Savable;
+    use std::time::Duration;
+
+    #[derive(Savable, Default, Debug, PartialEq)]
+    struct Timer {
+        counter: u8,
+        #[savable(with = "duration_codec")]
+        elapsed: Duration,
+    }
+
+    #[derive(Savable, Debug, PartialEq)]
+    enum State {
+        Idle,
+        Running(#[savable(with = "duration_codec")] Duration),
+    }
+
+    let timer = Timer {
+        counter: 5,
+        elapsed: Duration::new(10, 20),
+    };
+    let data = save_state::save_object(&timer).unwrap();
+    assert_eq!(timer.save_size().unwrap(), 1 + 8 + 4);
+    assert_eq!(data.len(), 1 + 8 + 4);
+
+    let mut loaded = Timer::default();
+    save_state::load_object(&mut loaded, &data).unwrap();
+    assert_eq!(loaded, timer);
+
+    let state = State::Running(Duration::from_millis(1500));
+    let data = save_state::save_object(&state).unwrap();
+    let mut loaded = State::Idle;
+    save_state::load_object(&mut loaded, &data).unwrap();
+    assert_eq!(loaded, state);
+}
+
+#[test]
+fn skipped_fields_reset_to_default() {
+    use save_state::Savable;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[derive(Savable)]
+    struct Player<T, H> {
+        score: T,
+        /// not `Savable`, reset on load
+        #[savable(skip, default)]
+        handle: H,
+        /// kept on load
+        #[savable(skip)]
+        volume: u8,
+    }
+
+    let player = Player {
+        score: 10u32,
+        handle: Rc::new(Cell::new(5u8)),
+        volume: 3,
+    };
+    let data = save_state::save_object(&player).unwrap();
+    assert_eq!(player.save_size().unwrap(), 4);
+    assert_eq!(data.len(), 4);
+
+    let mut loaded = Player {
+        score: 0u32,
+        handle: player.handle.clone(),
+        volume: 7,
+    };
+    save_state::load_object(&mut loaded, &data).unwrap();
+    assert_eq!(loaded.score, 10);
+    assert!(!Rc::ptr_eq(&loaded.handle, &player.handle));
+    assert_eq!(loaded.handle.get(), 0);
+    assert_eq!(loaded.volume, 7);
+}
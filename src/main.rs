@@ -196,7 +196,7 @@ impl GameboyFront {
 
             self.window.clear(Color::BLACK);
 
-            convert_to_rgba(self.gameboy.screen_buffer(), &mut self.pixels_buffer);
+            self.gameboy.screen_buffer_rgba(&mut self.pixels_buffer);
 
             unsafe {
                 // Safety: we know the `pixels_buffer` is valid for the width and height of the image.
@@ -245,7 +245,7 @@ impl GameboyFront {
     }
 
     fn save_state_file(&self, slot: u8) -> Option<Box<Path>> {
-        let cartridge_path = self.gameboy.file_path();
+        let cartridge_path = self.gameboy.file_path()?;
 
         if let Some(base_saved_states_dir) = self.base_save_state_folder() {
             // we use the cartridge path and replace all `.` with `_` to remove
@@ -566,7 +566,10 @@ fn main() {
     let scale = *scale.unwrap_or(&DEFAULT_SCALE);
     let fps = *fps.unwrap_or(&DEFAULT_FPS);
 
-    let config = GameBoyConfig { is_dmg };
+    let config = GameBoyConfig {
+        is_dmg,
+        ..GameBoyConfig::default()
+    };
 
     let mut builder = GameBoy::builder(rom_file)
         .config(config)